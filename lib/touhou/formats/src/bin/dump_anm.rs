@@ -0,0 +1,95 @@
+use touhou_formats::th06::anm0::{Anm0, Script};
+use touhou_formats::th06::texture::{load_anm_image, save_sprite};
+use std::env;
+use std::path::Path;
+use std::fs::{File, create_dir_all};
+use std::io::{self, BufReader, Read, Write};
+
+pub fn load_file_into_vec<P: AsRef<Path>>(filename: P) -> io::Result<Vec<u8>> {
+    let file = File::open(filename)?;
+    let mut file = BufReader::new(file);
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+fn escape_json(string: &str) -> String {
+    string.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn write_script<W: Write>(output: &mut W, index: u8, script: &Script) -> io::Result<()> {
+    writeln!(output, "Script {} {{", index)?;
+    for (ip, call) in script.instructions.iter().enumerate() {
+        writeln!(output, "    {:>3}: {:>5}: {:?}", ip, call.time, call.instr)?;
+    }
+    for (label, ip) in script.interrupts.iter() {
+        writeln!(output, "    interrupt {} => {}", label, ip)?;
+    }
+    writeln!(output, "}}")?;
+    writeln!(output)
+}
+
+fn main() -> io::Result<()> {
+    // Parse arguments.
+    let args: Vec<_> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("Usage: {} <ANM file> <output dir>", args[0]);
+        std::process::exit(1);
+    }
+    let anm_filename = Path::new(&args[1]);
+    let output_dir = Path::new(&args[2]);
+
+    // Open the ANM file.
+    let buf = load_file_into_vec(anm_filename)?;
+    let (_, anms) = Anm0::from_slice(&buf).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err)))?;
+
+    create_dir_all(output_dir)?;
+    let mut atlas = File::create(output_dir.join("atlas.json"))?;
+    let mut scripts = File::create(output_dir.join("scripts.txt"))?;
+
+    writeln!(atlas, "[")?;
+    for (i, anm0) in anms.iter().enumerate() {
+        // Sprites are still listed in the atlas if the image can’t be found.
+        let image = load_anm_image(anm0, anm_filename)
+            .map_err(|err| eprintln!("Couldn’t load {}: {}", anm0.png_filename, err))
+            .ok();
+
+        writeln!(atlas, "  {{")?;
+        writeln!(atlas, "    \"png_filename\": \"{}\",", escape_json(&anm0.png_filename))?;
+        match anm0.alpha_filename {
+            Some(ref filename) => writeln!(atlas, "    \"alpha_filename\": \"{}\",", escape_json(filename))?,
+            None => writeln!(atlas, "    \"alpha_filename\": null,")?,
+        }
+        writeln!(atlas, "    \"size\": [{}, {}],", anm0.size.0, anm0.size.1)?;
+        writeln!(atlas, "    \"format\": {},", anm0.format)?;
+        writeln!(atlas, "    \"sprites\": [")?;
+        for (j, sprite) in anm0.sprites.iter().enumerate() {
+            let filename = format!("sprite-{}-{}.png", i, sprite.index);
+            let filename = match image {
+                Some(ref image) => match save_sprite(image, anm0, sprite, output_dir.join(&filename)) {
+                    Ok(()) => format!("\"{}\"", filename),
+                    Err(err) => {
+                        eprintln!("Couldn’t save {}: {}", filename, err);
+                        String::from("null")
+                    }
+                },
+                None => String::from("null"),
+            };
+            writeln!(atlas, "      {{\"index\": {}, \"x\": {}, \"y\": {}, \"width\": {}, \"height\": {}, \"file\": {}}}{}",
+                     sprite.index, sprite.x, sprite.y, sprite.width, sprite.height, filename,
+                     if j + 1 < anm0.sprites.len() { "," } else { "" })?;
+        }
+        writeln!(atlas, "    ]")?;
+        writeln!(atlas, "  }}{}", if i + 1 < anms.len() { "," } else { "" })?;
+
+        writeln!(scripts, "Anm {} ({}) {{", i, anm0.png_filename)?;
+        for (index, script) in anm0.scripts.iter() {
+            write_script(&mut scripts, *index, script)?;
+        }
+        writeln!(scripts, "}}")?;
+        writeln!(scripts)?;
+    }
+    writeln!(atlas, "]")?;
+
+    Ok(())
+}
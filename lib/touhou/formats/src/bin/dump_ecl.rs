@@ -1,4 +1,4 @@
-use touhou_formats::th06::ecl::{Ecl, CallMain, CallSub, Rank};
+use touhou_formats::th06::ecl::{Ecl, CallMain, CallSub, Rank, ParamMask};
 use std::env;
 use std::path::Path;
 use std::fs::File;
@@ -19,9 +19,17 @@ fn format_rank(rank: &Rank) -> String {
                         if rank.contains(Rank::LUNATIC) { 'L' } else { ' ' })
 }
 
+fn format_param_mask(param_mask: &ParamMask) -> String {
+    if param_mask.is_empty() {
+        String::new()
+    } else {
+        format!("[{:?}] ", param_mask)
+    }
+}
+
 fn print_sub_instruction(call: &CallSub) {
-    let CallSub { time, rank_mask, param_mask: _, instr } = call;
-    println!("    {:>5}: {}: {:?}", time, format_rank(rank_mask), instr);
+    let CallSub { time, rank_mask, param_mask, instr } = call;
+    println!("    {:>5}: {}: {}{:?}", time, format_rank(rank_mask), format_param_mask(param_mask), instr);
 }
 
 fn print_main_instruction(call: &CallMain) {
@@ -3,4 +3,9 @@
 
 //! Touhou formats.
 
+#[macro_use]
+mod macros;
+
 pub mod th06;
+#[cfg(feature = "th07")]
+pub mod th07;
@@ -0,0 +1,158 @@
+//! Macros shared between the formats of the various games.
+//!
+//! Each of them declares an `Instruction`-like enum from a list of opcodes,
+//! along with the function parsing the arguments of a given opcode, using the
+//! `le_*` parsers in scope at the call site for each argument type.  An opcode
+//! of `_` can be used as the last entry to catch every unknown opcode, its
+//! variant then being declared as `_ => fn Unknown(opcode: u16, args: RawArgs)`
+//! to keep the opcode and the raw bytes of its arguments.
+
+/// Raw bytes of the arguments of an unknown instruction.
+// The th06 formats don’t have any catch-all.
+#[allow(dead_code)]
+pub type RawArgs = Vec<u8>;
+
+/// Value of an opcode once parsed, the `_` catch-all returning the one it kept.
+macro_rules! opcode_value {
+    (_, $instr:expr, $name:path) => {
+        match $instr {
+            $name(opcode, _) => *opcode,
+            _ => unreachable!(),
+        }
+    };
+    ($opcode:tt, $instr:expr, $name:path) => { $opcode };
+}
+
+/// Parse the arguments of a single opcode from `$i`, the `_` catch-all keeping
+/// all of them as raw bytes.
+macro_rules! parse_args {
+    (_, $i:ident, $opcode:ident, $name:path, $($arg:ident: $arg_type:ident),*) => {{
+        let instr = $name($opcode, $i.to_vec());
+        $i = &$i[$i.len()..];
+        instr
+    }};
+    ($value:tt, $i:ident, $opcode:ident, $name:path, $($arg:ident: $arg_type:ident),*) => {{
+        $(
+            let (i2, $arg) = concat_idents!(le_, $arg_type)($i)?;
+            $i = i2;
+        )*
+        $name($($arg),*)
+    }};
+}
+
+macro_rules! declare_anm_instructions {
+    ($($opcode:tt => fn $name:ident($($arg:ident: $arg_type:ident),*)),*,) => {
+        /// Available instructions in an `Anm0`.
+        #[allow(missing_docs)]
+        #[derive(Debug, Clone)]
+        pub enum Instruction {
+            $(
+                $name($($arg_type),*)
+            ),*
+        }
+
+        #[allow(unreachable_patterns)]
+        fn parse_instruction_args(mut i: &[u8], opcode: u16) -> IResult<&[u8], Instruction> {
+            let instr = match opcode {
+                $(
+                    $opcode => parse_args!($opcode, i, opcode, Instruction::$name, $($arg: $arg_type),*),
+                )*
+                // XXX: use a more specific error instead.
+                _ => return Err(nom::Err::Failure(nom::error::Error::new(i, nom::error::ErrorKind::Eof)))
+            };
+            Ok((i, instr))
+        }
+    };
+}
+
+macro_rules! declare_main_instructions {
+    ($($opcode:tt => fn $name:ident($($arg:ident: $arg_type:ident),*)),*,) => {
+        /// Available instructions in an `Ecl`.
+        #[allow(missing_docs)]
+        #[derive(Debug, Clone)]
+        pub enum MainInstruction {
+            $(
+                $name($($arg_type),*)
+            ),*
+        }
+
+        #[allow(unreachable_patterns)]
+        fn parse_main_instruction_args(input: &[u8], opcode: u16) -> IResult<&[u8], MainInstruction> {
+            let mut i = &input[..];
+            let instr = match opcode {
+                $(
+                    $opcode => parse_args!($opcode, i, opcode, MainInstruction::$name, $($arg: $arg_type),*),
+                )*
+                _ => unreachable!()
+            };
+            Ok((i, instr))
+        }
+    };
+}
+
+macro_rules! declare_sub_instructions {
+    ($($opcode:tt => fn $name:ident($($arg:ident: $arg_type:ident),*)),*,) => {
+        /// Available instructions in an `Ecl`.
+        #[allow(missing_docs)]
+        #[derive(Debug, Clone)]
+        pub enum SubInstruction {
+            $(
+                $name($($arg_type),*)
+            ),*
+        }
+
+        impl SubInstruction {
+            /// Returns the opcode of this instruction.
+            pub fn opcode(&self) -> u16 {
+                match self {
+                    $(
+                        SubInstruction::$name(..) => opcode_value!($opcode, self, SubInstruction::$name)
+                    ),*
+                }
+            }
+        }
+
+        #[allow(unreachable_patterns)]
+        fn parse_sub_instruction_args(input: &[u8], opcode: u16) -> IResult<&[u8], SubInstruction> {
+            let mut i = &input[..];
+            let instr = match opcode {
+                $(
+                    $opcode => parse_args!($opcode, i, opcode, SubInstruction::$name, $($arg: $arg_type),*),
+                )*
+                _ => unreachable!()
+            };
+            Ok((i, instr))
+        }
+    };
+}
+
+macro_rules! declare_stage_instructions {
+    ($($opcode:tt => fn $name:ident($($arg:ident: $arg_type:ident),*)),*,) => {
+        /// Available instructions in an `Stage`.
+        #[allow(missing_docs)]
+        #[derive(Debug, Clone, Copy)]
+        pub enum Instruction {
+            $(
+                $name($($arg_type),*)
+            ),*
+        }
+
+        #[allow(unreachable_patterns)]
+        fn parse_instruction_args(input: &[u8], opcode: u16) -> IResult<&[u8], Instruction> {
+            let mut i = &input[..];
+            let instr = match opcode {
+                $(
+                    $opcode => {
+                        $(
+                            let (i2, $arg) = concat_idents!(le_, $arg_type)(i)?;
+                            i = i2;
+                        )*
+                        Instruction::$name($($arg),*)
+                    }
+                )*
+                _ => unreachable!()
+            };
+            Ok((i, instr))
+        }
+    };
+}
@@ -49,7 +49,7 @@ pub struct Script {
 }
 
 /// Main struct of the ANM0 animation format.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Anm0 {
     /// Resolution of the image used by this ANM.
     pub size: (u32, u32),
@@ -87,7 +87,7 @@ impl Anm0 {
     }
 }
 
-fn parse_name(i: &[u8]) -> IResult<&[u8], String> {
+pub(crate) fn parse_name(i: &[u8]) -> IResult<&[u8], String> {
     let (_, slice) = take_while_m_n(0, 32, |c| c != 0)(i)?;
     let string = match String::from_utf8(slice.to_vec()) {
         Ok(string) => string,
@@ -97,7 +97,7 @@ fn parse_name(i: &[u8]) -> IResult<&[u8], String> {
     Ok((i, string))
 }
 
-fn parse_sprite(i: &[u8]) -> IResult<&[u8], Sprite> {
+pub(crate) fn parse_sprite(i: &[u8]) -> IResult<&[u8], Sprite> {
     let (i, (index, x, y, width, height)) = tuple((le_u32, le_f32, le_f32, le_f32, le_f32))(i)?;
     Ok((i, Sprite {
         index,
@@ -108,36 +108,6 @@ fn parse_sprite(i: &[u8]) -> IResult<&[u8], Sprite> {
     }))
 }
 
-macro_rules! declare_anm_instructions {
-    ($($opcode:tt => fn $name:ident($($arg:ident: $arg_type:ident),*)),*,) => {
-        /// Available instructions in an `Anm0`.
-        #[allow(missing_docs)]
-        #[derive(Debug, Clone, Copy)]
-        pub enum Instruction {
-            $(
-                $name($($arg_type),*)
-            ),*
-        }
-
-        fn parse_instruction_args(mut i: &[u8], opcode: u8) -> IResult<&[u8], Instruction> {
-            let instr = match opcode {
-                $(
-                    $opcode => {
-                        $(
-                            let (i2, $arg) = concat_idents!(le_, $arg_type)(i)?;
-                            i = i2;
-                        )*
-                        Instruction::$name($($arg),*)
-                    }
-                )*
-                // XXX: use a more specific error instead.
-                _ => return Err(nom::Err::Failure(nom::error::Error::new(i, nom::error::ErrorKind::Eof)))
-            };
-            Ok((i, instr))
-        }
-    };
-}
-
 declare_anm_instructions!{
     0 => fn Delete(),
     1 => fn LoadSprite(sprite_number: u32),
@@ -171,6 +141,9 @@ declare_anm_instructions!{
     31 => fn Todo(todo: u32),
 }
 
+// No instruction keeps raw arguments here, so they can be copied around.
+impl Copy for Instruction {}
+
 fn parse_anm0(input: &[u8]) -> IResult<&[u8], Anm0> {
     let (i, (num_sprites, num_scripts, _, width, height, format, color_key,
              first_name_offset, _, second_name_offset, version, _,
@@ -234,7 +207,7 @@ fn parse_anm0(input: &[u8]) -> IResult<&[u8], Anm0> {
             instruction_offsets.push(tell - offset);
             // TODO: maybe check against the size of parsed data?
             let (i2, (time, opcode, _size)) = tuple((le_u16, le_u8, le_u8))(i)?;
-            let (i2, instr) = parse_instruction_args(i2, opcode)?;
+            let (i2, instr) = parse_instruction_args(i2, opcode as u16)?;
             instructions.push(Call { time, instr });
             i = i2;
             if opcode == 0 {
@@ -10,6 +10,7 @@ use nom::{
 };
 use encoding_rs::SHIFT_JIS;
 use bitflags::bitflags;
+use touhou_utils::impl_snapshot;
 
 bitflags! {
     /// Bit flags describing the current difficulty level.
@@ -31,6 +32,8 @@ bitflags! {
     }
 }
 
+impl_snapshot!(bits Rank);
+
 impl std::str::FromStr for Rank {
     type Err = String;
 
@@ -45,6 +48,143 @@ impl std::str::FromStr for Rank {
     }
 }
 
+bitflags! {
+    /// Bit flags telling which parameters of an instruction are variables.
+    ///
+    /// Bit n is set when the n-th parameter is a reference to a variable
+    /// (-10001 to -10025) instead of an immediate value.
+    pub struct ParamMask: u16 {
+        /// First parameter.
+        const PARAM_0 = 0x0001;
+        /// Second parameter.
+        const PARAM_1 = 0x0002;
+        /// Third parameter.
+        const PARAM_2 = 0x0004;
+        /// Fourth parameter.
+        const PARAM_3 = 0x0008;
+        /// Fifth parameter.
+        const PARAM_4 = 0x0010;
+        /// Sixth parameter.
+        const PARAM_5 = 0x0020;
+        /// Seventh parameter.
+        const PARAM_6 = 0x0040;
+        /// Eighth parameter.
+        const PARAM_7 = 0x0080;
+        /// Ninth parameter.
+        const PARAM_8 = 0x0100;
+        /// Tenth parameter.
+        const PARAM_9 = 0x0200;
+        /// Eleventh parameter.
+        const PARAM_10 = 0x0400;
+        /// Twelfth parameter.
+        const PARAM_11 = 0x0800;
+        /// Thirteenth parameter.
+        const PARAM_12 = 0x1000;
+        /// Fourteenth parameter.
+        const PARAM_13 = 0x2000;
+        /// Fifteenth parameter.
+        const PARAM_14 = 0x4000;
+        /// Sixteenth parameter.
+        const PARAM_15 = 0x8000;
+    }
+}
+
+impl ParamMask {
+    /// Returns whether the parameter at this index is a variable.
+    pub fn is_variable(&self, index: usize) -> bool {
+        index < 16 && self.bits() & (1 << index) != 0
+    }
+}
+
+bitflags! {
+    /// Bit flags changing the behaviour of the bullets fired by an enemy.
+    #[derive(Default)]
+    pub struct BulletFlags: u32 {
+        /// Start five pixels per frame faster, then slow down to the requested speed in 16 frames.
+        const SPEED_BURST = 0x1;
+
+        /// Play the second launch animation before being fired.
+        const LAUNCH_ANIM2 = 0x2;
+
+        /// Play the fourth launch animation before being fired.
+        const LAUNCH_ANIM4 = 0x4;
+
+        /// Play the eighth launch animation before being fired.
+        const LAUNCH_ANIM8 = 0x8;
+
+        /// Add a vector to the speed every frame, for a given amount of frames.
+        const ACCELERATION = 0x10;
+
+        /// Accelerate and rotate every frame, for a given amount of frames.
+        const ACCELERATION_ROTATION = 0x20;
+
+        /// Periodically stop, then rotate relatively to the current angle.
+        const CHANGE_ANGLE_RELATIVE = 0x40;
+
+        /// Periodically stop, then aim at the player.
+        const CHANGE_ANGLE_AIMED = 0x80;
+
+        /// Periodically stop, then go towards an absolute angle.
+        const CHANGE_ANGLE_ABSOLUTE = 0x100;
+
+        /// Play the sound set by `SetBulletSound` when fired.
+        const PLAY_SOUND = 0x200;
+
+        /// Bounce on all four edges of the screen.
+        const BOUNCE = 0x400;
+
+        /// Bounce on all edges of the screen except the bottom one.
+        const BOUNCE_EXCEPT_BOTTOM = 0x800;
+    }
+}
+
+impl_snapshot!(raw bits BulletFlags);
+
+bitflags! {
+    /// Bit flags describing what happens to an enemy once its life reaches zero.
+    ///
+    /// When neither of the two lower bits is set the enemy gets removed, and
+    /// when both are set it stays alive with one life point but can’t be
+    /// damaged anymore.
+    #[derive(Default)]
+    pub struct DeathFlags: u32 {
+        /// Stay on screen, but become untouchable.
+        const STAY_UNTOUCHABLE = 0x1;
+
+        /// Stay on screen.
+        const STAY = 0x2;
+
+        /// Don’t drop any bonus nor particle, and stay on screen.
+        const NO_BONUS = 0x4;
+    }
+}
+
+impl_snapshot!(raw bits DeathFlags);
+
+// Unknown bits are kept as is, so that no data gets lost; bitflags only marks
+// from_bits_unchecked() unsafe for historical reasons, and handles them fine.
+
+/// Parse a little-endian u16 into a ParamMask.
+#[allow(non_snake_case)]
+pub fn le_ParamMask(i: &[u8]) -> IResult<&[u8], ParamMask> {
+    let (i, mask) = le_u16(i)?;
+    Ok((i, unsafe { ParamMask::from_bits_unchecked(mask) }))
+}
+
+/// Parse a little-endian u32 into BulletFlags, keeping unknown bits.
+#[allow(non_snake_case)]
+pub fn le_BulletFlags(i: &[u8]) -> IResult<&[u8], BulletFlags> {
+    let (i, flags) = le_u32(i)?;
+    Ok((i, unsafe { BulletFlags::from_bits_unchecked(flags) }))
+}
+
+/// Parse a little-endian u32 into DeathFlags, keeping unknown bits.
+#[allow(non_snake_case)]
+pub fn le_DeathFlags(i: &[u8]) -> IResult<&[u8], DeathFlags> {
+    let (i, flags) = le_u32(i)?;
+    Ok((i, unsafe { DeathFlags::from_bits_unchecked(flags) }))
+}
+
 /// A single instruction, part of a `Script`.
 #[derive(Debug, Clone)]
 pub struct CallSub {
@@ -54,8 +194,8 @@ pub struct CallSub {
     /// The difficulty level(s) this instruction will be called at.
     pub rank_mask: Rank,
 
-    /// TODO
-    pub param_mask: u16,
+    /// Which parameters of this instruction are variables.
+    pub param_mask: ParamMask,
 
     /// The instruction to call.
     pub instr: SubInstruction,
@@ -67,7 +207,7 @@ impl CallSub {
         CallSub {
             time,
             rank_mask,
-            param_mask: 0,
+            param_mask: ParamMask::empty(),
             instr,
         }
     }
@@ -115,36 +255,142 @@ impl Ecl {
     pub fn from_slice(data: &[u8]) -> IResult<&[u8], Ecl> {
         parse_ecl(data)
     }
+
+    /// Project every sub onto a single rank and difficulty value (0 to 32).
+    ///
+    /// Instructions not called at this rank are removed, jump targets are
+    /// adjusted accordingly, and `SetDifficultyCoeffs` gets folded into the
+    /// bullet attributes following it in the same sub, the way
+    /// `Enemy::set_bullet_attributes` would scale them.  Coefficients are
+    /// tracked linearly through each sub, so those inherited from a caller
+    /// aren’t taken into account.
+    pub fn project(&self, rank: Rank, difficulty: i32) -> Vec<Sub> {
+        self.subs.iter().map(|sub| sub.project(rank, difficulty)).collect()
+    }
 }
 
-macro_rules! declare_main_instructions {
-    ($($opcode:tt => fn $name:ident($($arg:ident: $arg_type:ident),*)),*,) => {
-        /// Available instructions in an `Ecl`.
-        #[allow(missing_docs)]
-        #[derive(Debug, Clone, Copy)]
-        pub enum MainInstruction {
-            $(
-                $name($($arg_type),*)
-            ),*
+/// Coefficients set by `SetDifficultyCoeffs`, scaling the bullets fired with the difficulty.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DifficultyCoeffs {
+    /// Speed added at difficulty 0.
+    pub speed_a: f32,
+
+    /// Speed added at difficulty 32.
+    pub speed_b: f32,
+
+    /// Bullets per shot added at difficulty 0.
+    pub nb_a: i32,
+
+    /// Bullets per shot added at difficulty 32.
+    pub nb_b: i32,
+
+    /// Number of shots added at difficulty 0.
+    pub shots_a: i32,
+
+    /// Number of shots added at difficulty 32.
+    pub shots_b: i32,
+}
+
+impl_snapshot!(DifficultyCoeffs { speed_a, speed_b, nb_a, nb_b, shots_a, shots_b });
+
+impl Default for DifficultyCoeffs {
+    fn default() -> DifficultyCoeffs {
+        DifficultyCoeffs {
+            speed_a: -0.5,
+            speed_b: 0.5,
+            nb_a: 0,
+            nb_b: 0,
+            shots_a: 0,
+            shots_b: 0,
         }
+    }
+}
 
-        fn parse_main_instruction_args(input: &[u8], opcode: u16) -> IResult<&[u8], MainInstruction> {
-            let mut i = &input[..];
-            let instr = match opcode {
-                $(
-                    $opcode => {
-                        $(
-                            let (i2, $arg) = concat_idents!(le_, $arg_type)(i)?;
-                            i = i2;
-                        )*
-                        MainInstruction::$name($($arg),*)
-                    }
-                )*
-                _ => unreachable!()
+impl Sub {
+    fn project(&self, rank: Rank, difficulty: i32) -> Sub {
+        // Map every original ip to the first kept instruction at or after it.
+        let mut ips = Vec::with_capacity(self.instructions.len() + 1);
+        let mut kept = 0;
+        for call in self.instructions.iter() {
+            ips.push(kept);
+            let removed = (call.rank_mask & rank).is_empty()
+                || matches!(call.instr, SubInstruction::SetDifficultyCoeffs(..));
+            if !removed {
+                kept += 1;
+            }
+        }
+        ips.push(kept);
+        let remap = |ip: i32| ips.get(ip as usize).copied().unwrap_or(ip);
+
+        let mut coeffs = DifficultyCoeffs::default();
+        let mut instructions = Vec::with_capacity(kept as usize);
+        for call in self.instructions.iter() {
+            if (call.rank_mask & rank).is_empty() {
+                continue;
+            }
+            let instr = match call.instr {
+                SubInstruction::SetDifficultyCoeffs(speed_a, speed_b, nb_a, nb_b, shots_a, shots_b) => {
+                    coeffs = DifficultyCoeffs { speed_a, speed_b, nb_a, nb_b, shots_a, shots_b };
+                    continue;
+                }
+                SubInstruction::SetBulletAttributes1(..)
+                | SubInstruction::SetBulletAttributes2(..)
+                | SubInstruction::SetBulletAttributes3(..)
+                | SubInstruction::SetBulletAttributes4(..)
+                | SubInstruction::SetBulletAttributes5(..)
+                | SubInstruction::SetBulletAttributes6(..)
+                | SubInstruction::SetBulletAttributes7(..) => coeffs.scale(call.instr.clone(), call.param_mask, difficulty),
+                SubInstruction::RelativeJump(frame, ip) => SubInstruction::RelativeJump(frame, remap(ip)),
+                SubInstruction::RelativeJumpEx(frame, ip, var) => SubInstruction::RelativeJumpEx(frame, remap(ip), var),
+                SubInstruction::RelativeJumpIfLowerThan(frame, ip) => SubInstruction::RelativeJumpIfLowerThan(frame, remap(ip)),
+                SubInstruction::RelativeJumpIfLowerOrEqual(frame, ip) => SubInstruction::RelativeJumpIfLowerOrEqual(frame, remap(ip)),
+                SubInstruction::RelativeJumpIfEqual(frame, ip) => SubInstruction::RelativeJumpIfEqual(frame, remap(ip)),
+                SubInstruction::RelativeJumpIfGreaterThan(frame, ip) => SubInstruction::RelativeJumpIfGreaterThan(frame, remap(ip)),
+                SubInstruction::RelativeJumpIfGreaterOrEqual(frame, ip) => SubInstruction::RelativeJumpIfGreaterOrEqual(frame, remap(ip)),
+                SubInstruction::RelativeJumpIfNotEqual(frame, ip) => SubInstruction::RelativeJumpIfNotEqual(frame, remap(ip)),
+                ref instr => instr.clone(),
             };
-            Ok((i, instr))
+            instructions.push(CallSub { instr, ..call.clone() });
         }
-    };
+        Sub { instructions }
+    }
+}
+
+impl DifficultyCoeffs {
+    /// Returns the bullets per shot, number of shots and speed to add at this difficulty (0 to 32).
+    pub fn at(&self, difficulty: i32) -> (i32, i32, f32) {
+        let nb = self.nb_a + (self.nb_b - self.nb_a) * difficulty / 32;
+        let shots = self.shots_a + (self.shots_b - self.shots_a) * difficulty / 32;
+        let speed = self.speed_a + (self.speed_b - self.speed_a) * difficulty as f32 / 32.;
+        (nb, shots, speed)
+    }
+
+    fn scale(&self, instr: SubInstruction, param_mask: ParamMask, difficulty: i32) -> SubInstruction {
+        let (coeff_nb, coeff_shots, coeff_speed) = self.at(difficulty);
+
+        // Variables can only be resolved at runtime, so they are kept as is.
+        let is_variable = |index| param_mask.is_variable(index);
+        let nb = |nb: i32| if is_variable(2) { nb } else { (nb + coeff_nb).max(1) };
+        let shots = |shots: i32| if is_variable(3) { shots } else { (shots + coeff_shots).max(1) };
+        let speed = |speed: f32, index, coeff: f32| if is_variable(index) { speed } else { (speed + coeff).max(0.3) };
+
+        macro_rules! scale {
+            ($($name:ident),*) => {
+                match instr {
+                    $(
+                        SubInstruction::$name(anim, offset, bullets_per_shot, number_of_shots, speed1, speed2, launch_angle, angle, flags) => {
+                            SubInstruction::$name(anim, offset, nb(bullets_per_shot), shots(number_of_shots),
+                                                  speed(speed1, 4, coeff_speed), speed(speed2, 5, coeff_speed / 2.),
+                                                  launch_angle, angle, flags)
+                        }
+                    )*
+                    instr => instr,
+                }
+            };
+        }
+        scale!(SetBulletAttributes1, SetBulletAttributes2, SetBulletAttributes3, SetBulletAttributes4,
+               SetBulletAttributes5, SetBulletAttributes6, SetBulletAttributes7)
+    }
 }
 
 /// Parse a SHIFT_JIS byte string of length 34 into a String.
@@ -155,36 +401,6 @@ pub fn le_String(i: &[u8]) -> IResult<&[u8], String> {
     Ok((&i[34..], string.into_owned()))
 }
 
-macro_rules! declare_sub_instructions {
-    ($($opcode:tt => fn $name:ident($($arg:ident: $arg_type:ident),*)),*,) => {
-        /// Available instructions in an `Ecl`.
-        #[allow(missing_docs)]
-        #[derive(Debug, Clone)]
-        pub enum SubInstruction {
-            $(
-                $name($($arg_type),*)
-            ),*
-        }
-
-        fn parse_sub_instruction_args(input: &[u8], opcode: u16) -> IResult<&[u8], SubInstruction> {
-            let mut i = &input[..];
-            let instr = match opcode {
-                $(
-                    $opcode => {
-                        $(
-                            let (i2, $arg) = concat_idents!(le_, $arg_type)(i)?;
-                            i = i2;
-                        )*
-                        SubInstruction::$name($($arg),*)
-                    }
-                )*
-                _ => unreachable!()
-            };
-            Ok((i, instr))
-        }
-    };
-}
-
 declare_main_instructions!{
     0 => fn SpawnEnemy(x: f32, y: f32, z: f32, life: i16, bonus_dropped: i16, die_score: u32),
     2 => fn SpawnEnemyMirrored(x: f32, y: f32, z: f32, life: i16, bonus_dropped: i16, die_score: u32),
@@ -196,6 +412,9 @@ declare_main_instructions!{
     12 => fn WaitForBossDeath(),
 }
 
+// No instruction keeps raw arguments here, so they can be copied around.
+impl Copy for MainInstruction {}
+
 declare_sub_instructions!{
     0 => fn Noop(),
     1 => fn Destroy(unused: u32),
@@ -256,13 +475,13 @@ declare_sub_instructions!{
     63 => fn StopInAccel(duration: i32),
     65 => fn SetScreenBox(xmin: f32, ymin: f32, xmax: f32, ymax: f32),
     66 => fn ClearScreenBox(),
-    67 => fn SetBulletAttributes1(anim: i16, sprite_index_offset: i16, bullets_per_shot: i32, number_of_shots: i32, speed: f32, speed2: f32, launch_angle: f32, angle: f32, flags: u32),
-    68 => fn SetBulletAttributes2(anim: i16, sprite_index_offset: i16, bullets_per_shot: i32, number_of_shots: i32, speed: f32, speed2: f32, launch_angle: f32, angle: f32, flags: u32),
-    69 => fn SetBulletAttributes3(anim: i16, sprite_index_offset: i16, bullets_per_shot: i32, number_of_shots: i32, speed: f32, speed2: f32, launch_angle: f32, angle: f32, flags: u32),
-    70 => fn SetBulletAttributes4(anim: i16, sprite_index_offset: i16, bullets_per_shot: i32, number_of_shots: i32, speed: f32, speed2: f32, launch_angle: f32, angle: f32, flags: u32),
-    71 => fn SetBulletAttributes5(anim: i16, sprite_index_offset: i16, bullets_per_shot: i32, number_of_shots: i32, speed: f32, speed2: f32, launch_angle: f32, angle: f32, flags: u32),
-    74 => fn SetBulletAttributes6(anim: i16, sprite_index_offset: i16, bullets_per_shot: i32, number_of_shots: i32, speed: f32, speed2: f32, launch_angle: f32, angle: f32, flags: u32),
-    75 => fn SetBulletAttributes7(anim: i16, sprite_index_offset: i16, bullets_per_shot: i32, number_of_shots: i32, speed: f32, speed2: f32, launch_angle: f32, angle: f32, flags: u32),
+    67 => fn SetBulletAttributes1(anim: i16, sprite_index_offset: i16, bullets_per_shot: i32, number_of_shots: i32, speed: f32, speed2: f32, launch_angle: f32, angle: f32, flags: BulletFlags),
+    68 => fn SetBulletAttributes2(anim: i16, sprite_index_offset: i16, bullets_per_shot: i32, number_of_shots: i32, speed: f32, speed2: f32, launch_angle: f32, angle: f32, flags: BulletFlags),
+    69 => fn SetBulletAttributes3(anim: i16, sprite_index_offset: i16, bullets_per_shot: i32, number_of_shots: i32, speed: f32, speed2: f32, launch_angle: f32, angle: f32, flags: BulletFlags),
+    70 => fn SetBulletAttributes4(anim: i16, sprite_index_offset: i16, bullets_per_shot: i32, number_of_shots: i32, speed: f32, speed2: f32, launch_angle: f32, angle: f32, flags: BulletFlags),
+    71 => fn SetBulletAttributes5(anim: i16, sprite_index_offset: i16, bullets_per_shot: i32, number_of_shots: i32, speed: f32, speed2: f32, launch_angle: f32, angle: f32, flags: BulletFlags),
+    74 => fn SetBulletAttributes6(anim: i16, sprite_index_offset: i16, bullets_per_shot: i32, number_of_shots: i32, speed: f32, speed2: f32, launch_angle: f32, angle: f32, flags: BulletFlags),
+    75 => fn SetBulletAttributes7(anim: i16, sprite_index_offset: i16, bullets_per_shot: i32, number_of_shots: i32, speed: f32, speed2: f32, launch_angle: f32, angle: f32, flags: BulletFlags),
     76 => fn SetBulletInterval(interval: i32),
     77 => fn SetBulletIntervalEx(interval: i32),
     78 => fn DelayAttack(),
@@ -286,14 +505,14 @@ declare_sub_instructions!{
     97 => fn SetAnim(script: i32),
     98 => fn SetMultipleAnims(default: i16, end_left: i16, end_right: i16, left: i16, right: i16, _unused: i16),
     99 => fn SetAuxAnm(number: i32, script: i32),
-    100 => fn SetDeathAnim(sprite_index: i32),
+    100 => fn SetDeathAnim(anim: u8, UNK1: u8, UNK2: u8, _unused: u8),
     101 => fn SetBossMode(value: i32),
     102 => fn CreateSquares(UNK1: i32, UNK2: f32, UNK3: f32, UNK4: f32, UNK5: f32),
     103 => fn SetHitbox(width: f32, height: f32, depth: f32),
     104 => fn SetCollidable(collidable: i32),
     105 => fn SetDamageable(damageable: i32),
     106 => fn PlaySound(index: i32),
-    107 => fn SetDeathFlags(death_flags: u32),
+    107 => fn SetDeathFlags(death_flags: DeathFlags),
     108 => fn SetDeathCallback(sub: i32),
     109 => fn MemoryWriteInt(value: i32, index: i32),
     111 => fn SetLife(life: i32),
@@ -334,7 +553,7 @@ fn parse_sub_instruction(input: &[u8]) -> IResult<&[u8], CallSub> {
         return Err(Err::Error(nom::error::Error::new(i, ErrorKind::Eof)));
     }
 
-    let (i, (size, rank_mask, param_mask)) = tuple((le_u16, le_u16, le_u16))(i)?;
+    let (i, (size, rank_mask, param_mask)) = tuple((le_u16, le_u16, le_ParamMask))(i)?;
     let rank_mask = Rank::from_bits(rank_mask).unwrap();
     let (i, instr) = parse_sub_instruction_args(i, opcode)?;
     assert_eq!(input.len() - i.len(), size as usize);
@@ -412,6 +631,7 @@ fn parse_ecl(input: &[u8]) -> IResult<&[u8], Ecl> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use touhou_utils::snapshot::{Save, Load, Reader};
     use std::io::{self, Read};
     use std::fs::File;
 
@@ -425,4 +645,90 @@ mod tests {
         assert_eq!(ecl.subs.len(), 24);
         assert_eq!(ecl.mains.len(), 1);
     }
+
+    #[test]
+    fn project() {
+        let attributes = SubInstruction::SetBulletAttributes1(0, 0, 3, 1, 2., 1., 0., 0., BulletFlags::empty());
+        let ecl = Ecl {
+            mains: vec![],
+            subs: vec![Sub { instructions: vec![
+                CallSub::new(0, Rank::EASY, SubInstruction::SetLife(100)),
+                CallSub::new(0, Rank::ALL, SubInstruction::SetDifficultyCoeffs(-1., 1., -2, 2, 0, 0)),
+                CallSub::new(0, Rank::ALL, attributes),
+                CallSub::new(10, Rank::ALL, SubInstruction::RelativeJump(0, 2)),
+            ]}],
+        };
+
+        let subs = ecl.project(Rank::LUNATIC, 32);
+        assert_eq!(subs[0].instructions.len(), 2);
+        match subs[0].instructions[0].instr {
+            SubInstruction::SetBulletAttributes1(_, _, 5, 1, speed, speed2, _, _, _) => {
+                assert_eq!(speed, 3.);
+                assert_eq!(speed2, 1.5);
+            }
+            ref instr => panic!("unexpected {:?}", instr),
+        }
+        match subs[0].instructions[1].instr {
+            SubInstruction::RelativeJump(0, 0) => (),
+            ref instr => panic!("unexpected {:?}", instr),
+        }
+
+        let subs = ecl.project(Rank::EASY, 0);
+        assert_eq!(subs[0].instructions.len(), 3);
+        match subs[0].instructions[1].instr {
+            SubInstruction::SetBulletAttributes1(_, _, 1, 1, speed, speed2, _, _, _) => {
+                assert_eq!(speed, 1.);
+                assert_eq!(speed2, 0.5);
+            }
+            ref instr => panic!("unexpected {:?}", instr),
+        }
+
+        // Variables are only known at runtime.
+        let mut call = CallSub::new(0, Rank::ALL, SubInstruction::SetBulletAttributes1(0, 0, -10001, 1, -10005., 1., 0., 0., BulletFlags::empty()));
+        call.param_mask = ParamMask::PARAM_2 | ParamMask::PARAM_4;
+        let ecl = Ecl { mains: vec![], subs: vec![Sub { instructions: vec![call] }] };
+        match ecl.project(Rank::LUNATIC, 32)[0].instructions[0].instr {
+            SubInstruction::SetBulletAttributes1(_, _, -10001, 1, speed, speed2, _, _, _) => {
+                assert_eq!(speed, -10005.);
+                assert_eq!(speed2, 1.25);
+            }
+            ref instr => panic!("unexpected {:?}", instr),
+        }
+    }
+
+    #[test]
+    fn bullet_flags() {
+        let mut buf = vec![];
+        buf.extend_from_slice(&12i32.to_le_bytes());
+        buf.extend_from_slice(&67u16.to_le_bytes());
+        buf.extend_from_slice(&44u16.to_le_bytes());
+        buf.extend_from_slice(&0xff00u16.to_le_bytes());
+        buf.extend_from_slice(&0x0004u16.to_le_bytes());
+        buf.extend_from_slice(&[1, 0, 2, 0]);
+        buf.extend_from_slice(&3i32.to_le_bytes());
+        buf.extend_from_slice(&1i32.to_le_bytes());
+        for value in [2f32, 1., 0., 0.].iter() {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf.extend_from_slice(&0x1000_0402u32.to_le_bytes());
+        let (i, call) = parse_sub_instruction(&buf).unwrap();
+        assert!(i.is_empty());
+        assert_eq!(call.time, 12);
+        assert_eq!(call.rank_mask, Rank::ALL);
+        assert!(call.param_mask.is_variable(2));
+        assert!(!call.param_mask.is_variable(0));
+        match call.instr {
+            SubInstruction::SetBulletAttributes1(1, 2, 3, 1, _, _, _, _, flags) => {
+                assert_eq!(flags.bits(), 0x1000_0402);
+                assert!(flags.contains(BulletFlags::LAUNCH_ANIM2 | BulletFlags::BOUNCE));
+                assert_eq!(format!("{:?}", flags), "LAUNCH_ANIM2 | BOUNCE | 0x10000000");
+
+                let mut data = Vec::new();
+                flags.save(&mut data);
+                let mut reader = Reader::new(&data);
+                assert_eq!(BulletFlags::load(&mut reader, &()).unwrap(), flags);
+            }
+            instr => panic!("wrong instruction {:?}", instr),
+        }
+    }
 }
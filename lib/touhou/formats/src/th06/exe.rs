@@ -0,0 +1,344 @@
+//! EoSD executable support, to extract the characteristics of its characters.
+//!
+//! EoSD doesn’t ship SHT files, so like pytouhou this searches the executable for the character
+//! definitions, without embedding any copyrighted data or offsets specific to a given build.
+
+use crate::th06::sht::{Sht, Shot};
+use nom::{
+    IResult,
+    number::complete::{le_u8, le_u16, le_i16, le_u32, le_f32},
+    sequence::tuple,
+};
+use std::collections::BTreeMap;
+
+/// Amount of characters, and shot types, in EoSD.
+const CHARACTER_COUNT: u32 = 4;
+
+/// Size of a character definition, four speeds and two function pointers.
+const CHARACTER_SIZE: u32 = 24;
+
+/// Size of a shot definition in the executable.
+const SHOT_SIZE: u32 = 36;
+
+/// Amount of power levels of each shot type.
+const LEVEL_COUNT: u32 = 9;
+
+/// Opcode of the x86 `push imm32` instruction.
+const PUSH: u8 = 0x68;
+
+#[derive(Debug)]
+struct Section {
+    name: [u8; 8],
+    virtual_address: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// The few bits of a PE file needed to map virtual addresses to the file.
+#[derive(Debug)]
+struct PeFile<'a> {
+    data: &'a [u8],
+    image_base: u32,
+    sections: Vec<Section>,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+impl<'a> PeFile<'a> {
+    fn new(data: &'a [u8]) -> Option<PeFile<'a>> {
+        let pe_offset = read_u32(data, 0x3c)? as usize;
+        if data.get(pe_offset..pe_offset + 4)? != b"PE\0\0" {
+            return None;
+        }
+        let file_header = pe_offset + 4;
+        let section_count = read_u16(data, file_header + 2)? as usize;
+        let optional_header_size = read_u16(data, file_header + 16)? as usize;
+        let optional_header = file_header + 20;
+        let image_base = read_u32(data, optional_header + 28)?;
+
+        let mut sections = Vec::with_capacity(section_count);
+        for i in 0..section_count {
+            let header = optional_header + optional_header_size + i * 40;
+            let mut name = [0; 8];
+            name.copy_from_slice(data.get(header..header + 8)?);
+            sections.push(Section {
+                name,
+                virtual_address: read_u32(data, header + 12)?,
+                size: read_u32(data, header + 16)?,
+                offset: read_u32(data, header + 20)?,
+            });
+        }
+        Some(PeFile { data, image_base, sections })
+    }
+
+    /// Returns the virtual address and size of the first section starting with this name.
+    fn section(&self, name: &[u8]) -> Option<(u32, u32)> {
+        self.sections
+            .iter()
+            .find(|section| section.name.starts_with(name))
+            .map(|section| (self.image_base.wrapping_add(section.virtual_address), section.size))
+    }
+
+    /// Returns the data starting at this virtual address, until the end of the file.
+    fn at(&self, va: u32) -> &'a [u8] {
+        let rva = va.wrapping_sub(self.image_base);
+        self.sections
+            .iter()
+            .find(|section| rva.wrapping_sub(section.virtual_address) < section.size)
+            .and_then(|section| self.data.get((rva - section.virtual_address + section.offset) as usize..))
+            .unwrap_or(&[])
+    }
+}
+
+/// Bounds of a section, as a virtual address and a size.
+#[derive(Debug, Clone, Copy)]
+struct Bounds(u32, u32);
+
+impl Bounds {
+    /// Whether `size` bytes at `va` fit in this section, with the same margins as pytouhou.
+    fn contains(&self, va: u32, size: u32) -> bool {
+        va.wrapping_sub(self.0) < self.1.saturating_sub(size)
+    }
+}
+
+fn parse_character(i: &[u8]) -> IResult<&[u8], ([f32; 4], [u32; 2])> {
+    let (i, (speed1, speed2, speed3, speed4, func1, func2)) =
+        tuple((le_f32, le_f32, le_f32, le_f32, le_u32, le_u32))(i)?;
+    Ok((i, ([speed1, speed2, speed3, speed4], [func1, func2])))
+}
+
+fn parse_level(i: &[u8]) -> IResult<&[u8], (u32, u32, u32)> {
+    tuple((le_u32, le_u32, le_u32))(i)
+}
+
+fn parse_shot(i: &[u8]) -> IResult<&[u8], Shot> {
+    let (i, (interval, delay, x, y, hitbox_x, hitbox_y, angle, speed, damage, orb, shot_type, sprite, _unknown)) =
+        tuple((le_u16, le_u16, le_f32, le_f32, le_f32, le_f32, le_f32, le_f32, le_u16, le_u8, le_u8, le_i16, le_i16))(i)?;
+    let shot = Shot {
+        interval,
+        delay,
+        pos: (x, y),
+        hitbox: (hitbox_x, hitbox_y),
+        angle,
+        speed,
+        damage,
+        orb,
+        shot_type,
+        sprite,
+    };
+    Ok((i, shot))
+}
+
+struct Finder<'a> {
+    pe: PeFile<'a>,
+    data: Bounds,
+    text: Bounds,
+}
+
+impl<'a> Finder<'a> {
+    /// Returns the address of the power levels pushed by this shooting function, if they look
+    /// valid.
+    fn find_levels(&self, func: u32) -> Option<u32> {
+        (0..20).find_map(|i| {
+            let push: IResult<&[u8], (u8, u32)> = tuple((le_u8, le_u32))(self.pe.at(func + i));
+            let (_, (instr, levels)) = push.ok()?;
+            if instr != PUSH || !self.data.contains(levels, 12) {
+                return None;
+            }
+            let (_, (count, power, shots)) = parse_level(self.pe.at(levels)).ok()?;
+            if (1..=1000).contains(&count) && power < 1000 && self.data.contains(shots, SHOT_SIZE * count) {
+                Some(levels)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns the speeds and power levels of a character, if its definition looks valid.
+    fn character(&self, va: u32) -> Option<([f32; 4], [u32; 2])> {
+        let (_, (speeds, funcs)) = parse_character(self.pe.at(va)).ok()?;
+        if !speeds.iter().all(|&speed| 0. < speed && speed < 10.) || speeds[1] > speeds[0] {
+            return None;
+        }
+        if !funcs.iter().all(|&func| self.text.contains(func, 8)) {
+            return None;
+        }
+        Some((speeds, [self.find_levels(funcs[0])?, self.find_levels(funcs[1])?]))
+    }
+
+    fn shots(&self, levels: u32) -> Option<BTreeMap<u32, Vec<Shot>>> {
+        let mut shots = BTreeMap::new();
+        let mut i = self.pe.at(levels);
+        for _ in 0..LEVEL_COUNT {
+            let (rest, (count, power, offset)) = parse_level(i).ok()?;
+            i = rest;
+            let mut data = self.pe.at(offset);
+            let mut level = Vec::with_capacity(count.min(1000) as usize);
+            for _ in 0..count {
+                let (rest, shot) = parse_shot(data).ok()?;
+                data = rest;
+                level.push(shot);
+            }
+            shots.insert(power, level);
+        }
+        Some(shots)
+    }
+}
+
+/// Returns the characteristics of the four characters of EoSD, unfocused and focused, read from
+/// its executable.
+pub fn read_characters(exe: &[u8]) -> Option<Vec<(Sht, Sht)>> {
+    let pe = PeFile::new(exe)?;
+    let (data_va, data_size) = pe.section(b".data")?;
+    let (text_va, text_size) = pe.section(b".text")?;
+    let finder = Finder {
+        pe,
+        data: Bounds(data_va, data_size),
+        text: Bounds(text_va, text_size),
+    };
+
+    // Search the whole data section for four successive character definitions.
+    let characters = (data_va..data_va.saturating_add(data_size)).step_by(4).find_map(|va| {
+        (0..CHARACTER_COUNT)
+            .map(|character| finder.character(va + character * CHARACTER_SIZE))
+            .collect::<Option<Vec<_>>>()
+    })?;
+
+    let mut shts = Vec::with_capacity(characters.len());
+    for (speeds, levels) in characters {
+        let sht = Sht {
+            horizontal_vertical_speed: speeds[0],
+            horizontal_vertical_focused_speed: speeds[1],
+            diagonal_speed: speeds[0] * std::f32::consts::FRAC_1_SQRT_2,
+            diagonal_focused_speed: speeds[1] * std::f32::consts::FRAC_1_SQRT_2,
+            ..Default::default()
+        };
+        // Both modes share the same speeds, only their shots differ.
+        let focused_sht = Sht { shots: finder.shots(levels[1])?, ..sht.clone() };
+        let sht = Sht { shots: finder.shots(levels[0])?, ..sht };
+        shts.push((sht, focused_sht));
+    }
+    Some(shts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE_BASE: u32 = 0x400000;
+    const TEXT_RVA: u32 = 0x1000;
+    const DATA_RVA: u32 = 0x2000;
+    const HEADERS_SIZE: u32 = 0x200;
+    const TEXT_SIZE: u32 = 0x100;
+    const DATA_SIZE: u32 = 0x400;
+
+    fn exe() -> Vec<u8> {
+        let mut exe = vec![0; (HEADERS_SIZE + TEXT_SIZE + DATA_SIZE) as usize];
+        let put = |exe: &mut Vec<u8>, offset: u32, bytes: &[u8]| {
+            exe[offset as usize..offset as usize + bytes.len()].copy_from_slice(bytes);
+        };
+        put(&mut exe, 0, b"MZ");
+        put(&mut exe, 0x3c, &0x40u32.to_le_bytes());
+        put(&mut exe, 0x40, b"PE\0\0");
+        put(&mut exe, 0x46, &2u16.to_le_bytes());
+        put(&mut exe, 0x54, &224u16.to_le_bytes());
+        put(&mut exe, 0x58 + 28, &IMAGE_BASE.to_le_bytes());
+        for (i, &(name, rva, size, offset)) in [
+            (b".text\0\0\0", TEXT_RVA, TEXT_SIZE, HEADERS_SIZE),
+            (b".data\0\0\0", DATA_RVA, DATA_SIZE, HEADERS_SIZE + TEXT_SIZE),
+        ].iter().enumerate() {
+            let header = 0x58 + 224 + i as u32 * 40;
+            put(&mut exe, header, &name[..]);
+            put(&mut exe, header + 12, &rva.to_le_bytes());
+            put(&mut exe, header + 16, &size.to_le_bytes());
+            put(&mut exe, header + 20, &offset.to_le_bytes());
+        }
+
+        let text = |rva: u32| HEADERS_SIZE + rva - TEXT_RVA;
+        let data = |rva: u32| HEADERS_SIZE + TEXT_SIZE + rva - DATA_RVA;
+        let va = |rva: u32| IMAGE_BASE + rva;
+
+        // Two shooting functions, pushing the unfocused and focused levels.
+        for (func, levels) in [(0x10, 0x2100), (0x30, 0x2200)] {
+            put(&mut exe, text(TEXT_RVA + func), &[0x55, 0x89, 0xe5, PUSH]);
+            put(&mut exe, text(TEXT_RVA + func + 4), &va(levels).to_le_bytes());
+        }
+
+        // Some garbage before the characters, which must be skipped.
+        put(&mut exe, data(DATA_RVA), &[0xff; 8]);
+        for character in 0..4 {
+            let mut buf = vec![];
+            for speed in [4f32, 2., 4., 2.] {
+                buf.extend_from_slice(&(speed + character as f32 / 2.).to_le_bytes());
+            }
+            buf.extend_from_slice(&va(TEXT_RVA + 0x10).to_le_bytes());
+            buf.extend_from_slice(&va(TEXT_RVA + 0x30).to_le_bytes());
+            put(&mut exe, data(DATA_RVA + 8 + character * CHARACTER_SIZE), &buf);
+        }
+
+        // Every level but the last one has a single shot, the last one has none.
+        for (levels, shots, damage) in [(0x2100, 0x2300, 12u16), (0x2200, 0x2380, 30)] {
+            for level in 0..LEVEL_COUNT {
+                let count = if level < 8 { 1u32 } else { 0 };
+                let power = [8u32, 16, 32, 48, 64, 80, 96, 128, 999][level as usize];
+                let mut buf = vec![];
+                buf.extend_from_slice(&count.to_le_bytes());
+                buf.extend_from_slice(&power.to_le_bytes());
+                buf.extend_from_slice(&va(shots).to_le_bytes());
+                put(&mut exe, data(levels + level * 12), &buf);
+            }
+            let mut buf = vec![];
+            buf.extend_from_slice(&5u16.to_le_bytes());
+            buf.extend_from_slice(&1u16.to_le_bytes());
+            for value in [0., -10., 4., 12., -2., 10f32] {
+                buf.extend_from_slice(&value.to_le_bytes());
+            }
+            buf.extend_from_slice(&damage.to_le_bytes());
+            buf.extend_from_slice(&[0, 3]);
+            buf.extend_from_slice(&7i16.to_le_bytes());
+            buf.extend_from_slice(&0i16.to_le_bytes());
+            put(&mut exe, data(shots), &buf);
+        }
+        exe
+    }
+
+    #[test]
+    fn characters() {
+        let characters = read_characters(&exe()).unwrap();
+        assert_eq!(characters.len(), 4);
+        let (sht, focused_sht) = &characters[1];
+        assert_eq!(sht.horizontal_vertical_speed, 4.5);
+        assert_eq!(sht.horizontal_vertical_focused_speed, 2.5);
+        assert_eq!(focused_sht.horizontal_vertical_speed, 4.5);
+        assert_eq!(sht.diagonal_speed, 4.5 * std::f32::consts::FRAC_1_SQRT_2);
+        assert_eq!(sht.hitbox, 2.);
+        assert_eq!(sht.shots.len(), 9);
+        let shots = sht.get_shots(0);
+        assert_eq!(shots.len(), 1);
+        assert_eq!((shots[0].interval, shots[0].delay), (5, 1));
+        assert_eq!(shots[0].pos, (0., -10.));
+        assert_eq!(shots[0].damage, 12);
+        assert_eq!(shots[0].shot_type, 3);
+        assert_eq!(shots[0].sprite, 7);
+        assert_eq!(focused_sht.get_shots(0)[0].damage, 30);
+        assert!(sht.get_shots(128).is_empty());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(read_characters(b"").is_none());
+        let mut exe = exe();
+        // Break the speeds of the last character.
+        let offset = (HEADERS_SIZE + TEXT_SIZE + 8 + 3 * CHARACTER_SIZE) as usize;
+        exe[offset..offset + 4].copy_from_slice(&20f32.to_le_bytes());
+        assert!(read_characters(&exe).is_none());
+    }
+}
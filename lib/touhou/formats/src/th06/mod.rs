@@ -2,5 +2,10 @@
 
 pub mod pbg3;
 pub mod anm0;
+#[cfg(feature = "image")]
+pub mod texture;
 pub mod ecl;
 pub mod std;
+pub mod msg;
+pub mod sht;
+pub mod exe;
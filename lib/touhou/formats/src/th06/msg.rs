@@ -0,0 +1,199 @@
+//! MSG dialogue format support.
+
+use nom::{
+    IResult,
+    Err,
+    error::ErrorKind,
+    bytes::complete::take,
+    number::complete::{le_u8, le_u16, le_i16, le_u32},
+    sequence::tuple,
+    multi::count,
+};
+use encoding_rs::SHIFT_JIS;
+use std::collections::BTreeMap;
+
+/// A single instruction of a dialogue.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    /// End the dialogue, the original game crashes if it can still be skipped.
+    End(),
+
+    /// Make a character appear on this side, with this animation.
+    Enter(i16, i16),
+
+    /// Change the face of the character on this side.
+    ChangeFace(i16, i16),
+
+    /// Display this line of text, 0 replacing both lines and 1 adding the second one.
+    DisplayText(i16, i16, String),
+
+    /// Wait this many frames, or until the player skips it.
+    Pause(u32),
+
+    /// Play this animation on the character on this side.
+    Animate(i16, i16),
+
+    /// Let the main timeline spawn the boss.
+    SpawnEnemySprite(),
+
+    /// Play this music track.
+    ChangeMusic(u32),
+
+    /// Display the name (0) or the title (1) of the character on this side.
+    DisplayDescription(i16, i16, String),
+
+    /// Display the score screen.
+    ShowScores(u32),
+
+    /// Stop advancing the dialogue, until it gets ended from outside.
+    Freeze(),
+
+    /// End the stage.
+    NextStage(),
+
+    /// Unknown instruction 12.
+    Unknown12(),
+
+    /// Whether the player can skip this dialogue.
+    SetAllowSkip(u32),
+
+    /// Unknown instruction 14.
+    Unknown14(),
+
+    /// Any other opcode, with its raw arguments.
+    Unknown(u8, Vec<u8>),
+}
+
+/// An instruction, along with the frame at which it gets run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    /// Frame of the dialogue at which this instruction gets run.
+    pub time: u16,
+
+    /// The instruction to run.
+    pub instr: Instruction,
+}
+
+/// Main struct of the MSG dialogue format.
+#[derive(Debug, Clone, Default)]
+pub struct Msg {
+    /// Dialogues, indexed by script number; the ones of the second character start at 10.
+    pub scripts: BTreeMap<u8, Vec<Call>>,
+}
+
+impl Msg {
+    /// Parse a slice of bytes into a `Msg` struct.
+    pub fn from_slice(data: &[u8]) -> IResult<&[u8], Msg> {
+        parse_msg(data)
+    }
+}
+
+/// Decode a null-terminated SHIFT_JIS string.
+fn decode_string(data: &[u8]) -> String {
+    let data = data.split(|c| *c == b'\0').next().unwrap();
+    let (string, _encoding, _replaced) = SHIFT_JIS.decode(data);
+    string.into_owned()
+}
+
+fn parse_text(i: &[u8]) -> IResult<&[u8], (i16, i16, String)> {
+    let (i, (side, index)) = tuple((le_i16, le_i16))(i)?;
+    Ok((&[], (side, index, decode_string(i))))
+}
+
+fn parse_instruction(opcode: u8, args: &[u8]) -> IResult<&[u8], Instruction> {
+    let instr = match opcode {
+        0 => Instruction::End(),
+        1 => tuple((le_i16, le_i16))(args).map(|(_, (side, effect))| Instruction::Enter(side, effect))?,
+        2 => tuple((le_i16, le_i16))(args).map(|(_, (side, index))| Instruction::ChangeFace(side, index))?,
+        3 => parse_text(args).map(|(_, (side, index, text))| Instruction::DisplayText(side, index, text))?,
+        4 => le_u32(args).map(|(_, duration)| Instruction::Pause(duration))?,
+        5 => tuple((le_i16, le_i16))(args).map(|(_, (side, effect))| Instruction::Animate(side, effect))?,
+        6 => Instruction::SpawnEnemySprite(),
+        7 => le_u32(args).map(|(_, track)| Instruction::ChangeMusic(track))?,
+        8 => parse_text(args).map(|(_, (side, index, text))| Instruction::DisplayDescription(side, index, text))?,
+        9 => le_u32(args).map(|(_, value)| Instruction::ShowScores(value))?,
+        10 => Instruction::Freeze(),
+        11 => Instruction::NextStage(),
+        12 => Instruction::Unknown12(),
+        13 => le_u32(args).map(|(_, allow)| Instruction::SetAllowSkip(allow))?,
+        14 => Instruction::Unknown14(),
+        _ => Instruction::Unknown(opcode, args.to_vec()),
+    };
+    Ok((&[], instr))
+}
+
+fn parse_script(mut i: &[u8]) -> IResult<&[u8], Vec<Call>> {
+    let mut calls = Vec::new();
+    loop {
+        let (i2, (time, opcode, size)) = tuple((le_u16, le_u8, le_u8))(i)?;
+        if time == 0 && opcode == 0 {
+            break;
+        }
+        let (i2, args) = take(size)(i2)?;
+        let (_, instr) = parse_instruction(opcode, args)?;
+        calls.push(Call { time, instr });
+        i = i2;
+    }
+    Ok((i, calls))
+}
+
+fn parse_msg(input: &[u8]) -> IResult<&[u8], Msg> {
+    let (i, entry_count) = le_u32(input)?;
+    let (_, offsets) = count(le_u32, entry_count as usize)(i)?;
+
+    let mut scripts = BTreeMap::new();
+    for (index, &offset) in offsets.iter().enumerate() {
+        // In EoSD, the scripts missing for the first character point to the first one.
+        if index > 0 && offset == offsets[0] {
+            continue;
+        }
+        let offset = offset as usize;
+        if input.len() < offset {
+            return Err(Err::Failure(nom::error::Error::new(input, ErrorKind::Eof)));
+        }
+        let (_, script) = parse_script(&input[offset..])?;
+        scripts.insert(index as u8, script);
+    }
+
+    Ok((b"", Msg { scripts }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn msg() {
+        let mut buf = vec![];
+        buf.extend_from_slice(&3u32.to_le_bytes());
+        for offset in [16u32, 16, 50] {
+            buf.extend_from_slice(&offset.to_le_bytes());
+        }
+        buf.extend_from_slice(&[0, 0, 1, 4, 0, 0, 1, 0]);
+        buf.extend_from_slice(&[10, 0, 3, 10, 1, 0, 0, 0]);
+        buf.extend_from_slice(b"Hi!\0\0\0");
+        buf.extend_from_slice(&[20, 0, 4, 4, 60, 0, 0, 0]);
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(&[0, 0, 42, 2, 1, 2]);
+        buf.extend_from_slice(&[0; 4]);
+
+        let (_, msg) = Msg::from_slice(&buf).unwrap();
+        assert_eq!(msg.scripts.keys().collect::<Vec<_>>(), [&0, &2]);
+        assert_eq!(msg.scripts[&0], [
+            Call { time: 0, instr: Instruction::Enter(0, 1) },
+            Call { time: 10, instr: Instruction::DisplayText(1, 0, String::from("Hi!")) },
+            Call { time: 20, instr: Instruction::Pause(60) },
+        ]);
+        assert_eq!(msg.scripts[&2], [Call { time: 0, instr: Instruction::Unknown(42, vec![1, 2]) }]);
+    }
+
+    #[test]
+    fn invalid_offset() {
+        let mut buf = vec![];
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        // A single script, pointing after the end of the file.
+        buf.extend_from_slice(&0x1000u32.to_le_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        assert!(Msg::from_slice(&buf).is_err());
+    }
+}
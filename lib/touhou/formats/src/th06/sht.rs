@@ -0,0 +1,227 @@
+//! SHT player shot format support.
+//!
+//! EoSD keeps this data in its executable, see the `exe` module, the file layout is the one used
+//! from PCB onwards, and by the files extracted by pytouhou.
+
+use nom::{
+    IResult,
+    number::complete::{le_u8, le_u16, le_i16, le_u32, le_f32},
+    sequence::tuple,
+    multi::{count, many0},
+    error::ErrorKind,
+    Err,
+};
+use std::collections::BTreeMap;
+
+/// A single kind of bullet fired by the player.
+#[derive(Debug, Clone, Default)]
+pub struct Shot {
+    /// Amount of frames between two of these shots.
+    pub interval: u16,
+
+    /// Offset of the first shot in the interval.
+    pub delay: u16,
+
+    /// Position relative to the player or the orb firing it.
+    pub pos: (f32, f32),
+
+    /// Size of the hitbox of this shot.
+    pub hitbox: (f32, f32),
+
+    /// Angle of this shot.
+    pub angle: f32,
+
+    /// Speed of this shot.
+    pub speed: f32,
+
+    /// Damage dealt to enemies.
+    pub damage: u16,
+
+    /// Which orb fires this shot, or 0 for the player itself.
+    pub orb: u8,
+
+    /// Behaviour of this shot: 1 homes onto enemies, 2 accelerates, 3 is a laser.
+    pub shot_type: u8,
+
+    /// Sprite used for this shot.
+    pub sprite: i16,
+}
+
+/// Main struct of the SHT player shot format.
+#[derive(Debug, Clone)]
+pub struct Sht {
+    /// Size of the hitbox of the player.
+    pub hitbox: f32,
+
+    /// Distance under which bullets get grazed.
+    pub graze_hitbox: f32,
+
+    /// Speed of the items being autocollected.
+    pub autocollection_speed: f32,
+
+    /// Distance under which items get collected.
+    pub item_hitbox: f32,
+
+    /// Height above which all items get autocollected.
+    pub point_of_collection: f32,
+
+    /// Speed when moving horizontally or vertically.
+    pub horizontal_vertical_speed: f32,
+
+    /// Speed when moving horizontally or vertically while focused.
+    pub horizontal_vertical_focused_speed: f32,
+
+    /// Speed when moving diagonally.
+    pub diagonal_speed: f32,
+
+    /// Speed when moving diagonally while focused.
+    pub diagonal_focused_speed: f32,
+
+    /// Shots for each power level, indexed by the power they stop being used at.
+    pub shots: BTreeMap<u32, Vec<Shot>>,
+}
+
+impl Default for Sht {
+    fn default() -> Sht {
+        // Those are the values hardcoded in EoSD, which doesn’t have SHT files.
+        Sht {
+            hitbox: 2.,
+            graze_hitbox: 21.,
+            autocollection_speed: 8.,
+            item_hitbox: 19.,
+            point_of_collection: 128.,
+            horizontal_vertical_speed: 0.,
+            horizontal_vertical_focused_speed: 0.,
+            diagonal_speed: 0.,
+            diagonal_focused_speed: 0.,
+            shots: BTreeMap::new(),
+        }
+    }
+}
+
+impl Sht {
+    /// Parse a slice of bytes into an `Sht` struct.
+    pub fn from_slice(data: &[u8]) -> IResult<&[u8], Sht> {
+        parse_sht(data)
+    }
+
+    /// Returns the shots to fire at this power level.
+    pub fn get_shots(&self, power: u32) -> &[Shot] {
+        self.shots.range(power + 1..).next().map(|(_, shots)| shots.as_slice()).unwrap_or(&[])
+    }
+}
+
+fn parse_shot(i: &[u8]) -> IResult<&[u8], Shot> {
+    let (i, (interval, delay)) = tuple((le_u16, le_u16))(i)?;
+    if interval == 0xffff && delay == 0xffff {
+        return Err(Err::Error(nom::error::Error::new(i, ErrorKind::Eof)));
+    }
+    let (i, (x, y, hitbox_x, hitbox_y, angle, speed, damage, orb, shot_type, sprite, _unknown)) =
+        tuple((le_f32, le_f32, le_f32, le_f32, le_f32, le_f32, le_u16, le_u8, le_u8, le_i16, le_i16))(i)?;
+    let (i, _) = count(le_u32, 4)(i)?;
+    let shot = Shot {
+        interval,
+        delay,
+        pos: (x, y),
+        hitbox: (hitbox_x, hitbox_y),
+        angle,
+        speed,
+        damage,
+        orb,
+        shot_type,
+        sprite,
+    };
+    Ok((i, shot))
+}
+
+fn parse_sht(input: &[u8]) -> IResult<&[u8], Sht> {
+    let i = input;
+
+    let (i, (_unknown1, level_count, _bombs, _unknown2)) = tuple((le_i16, le_u16, le_f32, le_u32))(i)?;
+    let (i, (hitbox, graze_hitbox, autocollection_speed, item_hitbox, _cherry_loss, point_of_collection)) =
+        tuple((le_f32, le_f32, le_f32, le_f32, le_f32, le_f32))(i)?;
+    let (i, (horizontal_vertical_speed, horizontal_vertical_focused_speed, diagonal_speed, diagonal_focused_speed)) =
+        tuple((le_f32, le_f32, le_f32, le_f32))(i)?;
+    let (_, levels) = count(tuple((le_u32, le_u32)), level_count as usize)(i)?;
+
+    let mut shots = BTreeMap::new();
+    for (offset, power) in levels {
+        let offset = offset as usize;
+        if input.len() < offset {
+            return Err(Err::Failure(nom::error::Error::new(input, ErrorKind::Eof)));
+        }
+        let (_, level) = many0(parse_shot)(&input[offset..])?;
+        shots.insert(power, level);
+    }
+
+    let sht = Sht {
+        hitbox,
+        graze_hitbox,
+        autocollection_speed,
+        item_hitbox,
+        point_of_collection,
+        horizontal_vertical_speed,
+        horizontal_vertical_focused_speed,
+        diagonal_speed,
+        diagonal_focused_speed,
+        shots,
+    };
+    Ok((b"", sht))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sht() {
+        let mut buf = vec![];
+        buf.extend_from_slice(&0i16.to_le_bytes());
+        buf.extend_from_slice(&2u16.to_le_bytes());
+        buf.extend_from_slice(&3f32.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        for value in [2., 21., 8., 19., 0., 128., 4., 2., 2.828, 1.414f32] {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        // Two levels, both pointing to the same list of shots.
+        let offset = buf.len() as u32 + 16;
+        for power in [8u32, 999] {
+            buf.extend_from_slice(&offset.to_le_bytes());
+            buf.extend_from_slice(&power.to_le_bytes());
+        }
+        buf.extend_from_slice(&[4, 0, 1, 0]);
+        for value in [0., -10., 4., 12., -2., 10f32] {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf.extend_from_slice(&[12, 0, 0, 2, 5, 0, 0, 0]);
+        buf.extend_from_slice(&[0; 16]);
+        buf.extend_from_slice(&[0xff; 4]);
+
+        let (_, sht) = Sht::from_slice(&buf).unwrap();
+        assert_eq!(sht.horizontal_vertical_speed, 4.);
+        assert_eq!(sht.point_of_collection, 128.);
+        assert_eq!(sht.shots.len(), 2);
+        let shots = sht.get_shots(0);
+        assert_eq!(shots.len(), 1);
+        assert_eq!(shots[0].delay, 1);
+        assert_eq!(shots[0].damage, 12);
+        assert_eq!(shots[0].shot_type, 2);
+        assert_eq!(shots[0].sprite, 5);
+        assert!(std::ptr::eq(sht.get_shots(8), sht.get_shots(998)));
+        assert!(sht.get_shots(999).is_empty());
+    }
+
+    #[test]
+    fn invalid_offset() {
+        let mut buf = vec![];
+        buf.extend_from_slice(&0i16.to_le_bytes());
+        buf.extend_from_slice(&1u16.to_le_bytes());
+        buf.extend_from_slice(&3f32.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&[0; 40]);
+        // A single level, pointing after the end of the file.
+        buf.extend_from_slice(&0x1000u32.to_le_bytes());
+        buf.extend_from_slice(&999u32.to_le_bytes());
+        assert!(Sht::from_slice(&buf).is_err());
+    }
+}
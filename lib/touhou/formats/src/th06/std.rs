@@ -124,36 +124,6 @@ impl Stage {
     }
 }
 
-macro_rules! declare_stage_instructions {
-    ($($opcode:tt => fn $name:ident($($arg:ident: $arg_type:ident),*)),*,) => {
-        /// Available instructions in an `Stage`.
-        #[allow(missing_docs)]
-        #[derive(Debug, Clone, Copy)]
-        pub enum Instruction {
-            $(
-                $name($($arg_type),*)
-            ),*
-        }
-
-        fn parse_instruction_args(input: &[u8], opcode: u16) -> IResult<&[u8], Instruction> {
-            let mut i = &input[..];
-            let instr = match opcode {
-                $(
-                    $opcode => {
-                        $(
-                            let (i2, $arg) = concat_idents!(le_, $arg_type)(i)?;
-                            i = i2;
-                        )*
-                        Instruction::$name($($arg),*)
-                    }
-                )*
-                _ => unreachable!()
-            };
-            Ok((i, instr))
-        }
-    };
-}
-
 declare_stage_instructions!{
     0 => fn SetViewpos(x: f32, y: f32, z: f32),
     1 => fn SetFog(r: u8, g: u8, b: u8, a: u8, near: f32, far: f32),
@@ -212,7 +182,6 @@ fn parse_instruction(i: &[u8]) -> IResult<&[u8], Call> {
     // TODO: replace this assert with a custom error.
     assert_eq!(size, 12);
     let (i, instr) = parse_instruction_args(i, opcode)?;
-    println!("{} {:?}", time, instr);
     let call = Call { time, instr };
     Ok((i, call))
 }
@@ -0,0 +1,90 @@
+//! Headless decoding of the images used by ANM0 files.
+
+use crate::th06::anm0::{Anm0, Sprite};
+use image::{DynamicImage, ImageError, ImageResult, RgbaImage};
+use image::error::{ParameterError, ParameterErrorKind};
+use image::imageops;
+use std::path::Path;
+
+/// Load the image of this ANM0, merging its alpha channel image if it has one.
+///
+/// Both files are looked up next to `anm_filename`, like the game does inside
+/// its archives.
+pub fn load_anm_image<P: AsRef<Path>>(anm0: &Anm0, anm_filename: P) -> ImageResult<RgbaImage> {
+    let anm_filename = anm_filename.as_ref();
+    let sibling = |filename: &str| anm_filename.with_file_name(Path::new(filename).file_name().unwrap_or_default());
+    let rgb = image::open(sibling(&anm0.png_filename))?;
+    match anm0.alpha_filename {
+        Some(ref filename) => merge_alpha(rgb, &image::open(sibling(filename))?),
+        None => Ok(rgb.into_rgba8()),
+    }
+}
+
+/// Replace the alpha channel of this image with the luminance of another one, of the same size.
+pub fn merge_alpha(rgb: DynamicImage, alpha: &DynamicImage) -> ImageResult<RgbaImage> {
+    let mut rgba = rgb.into_rgba8();
+    let alpha = alpha.to_luma8();
+    if alpha.dimensions() != rgba.dimensions() {
+        return Err(ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::DimensionMismatch)));
+    }
+    for (pixel, luma) in rgba.pixels_mut().zip(alpha.pixels()) {
+        pixel[3] = luma[0];
+    }
+    Ok(rgba)
+}
+
+/// Extract a single sprite out of the image of its ANM0.
+///
+/// Sprite coordinates are expressed in the ANM0 size, so they get scaled if
+/// the image has a different resolution, and clamped to its bounds.
+pub fn crop_sprite(image: &RgbaImage, anm0: &Anm0, sprite: &Sprite) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let scale_x = width as f32 / anm0.size.0 as f32;
+    let scale_y = height as f32 / anm0.size.1 as f32;
+    let x = ((sprite.x * scale_x) as u32).min(width);
+    let y = ((sprite.y * scale_y) as u32).min(height);
+    let w = ((sprite.width * scale_x) as u32).min(width - x);
+    let h = ((sprite.height * scale_y) as u32).min(height - y);
+    imageops::crop_imm(image, x, y, w, h).to_image()
+}
+
+/// Convenience wrapper to save a sprite as a PNG file.
+pub fn save_sprite<P: AsRef<Path>>(image: &RgbaImage, anm0: &Anm0, sprite: &Sprite, path: P) -> ImageResult<()> {
+    DynamicImage::ImageRgba8(crop_sprite(image, anm0, sprite)).save_with_format(path, image::ImageFormat::Png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn crop() {
+        let anm0 = Anm0 {
+            size: (128, 128),
+            format: 5,
+            color_key: 0,
+            png_filename: String::from("data/etama3.png"),
+            alpha_filename: None,
+            sprites: vec![],
+            scripts: BTreeMap::new(),
+        };
+        // Image at twice the resolution of the ANM0.
+        let image = RgbaImage::from_fn(256, 256, |x, y| image::Rgba([x as u8, y as u8, 0, 255]));
+        let sprite = Sprite { index: 0, x: 16., y: 32., width: 8., height: 200. };
+        let crop = crop_sprite(&image, &anm0, &sprite);
+        assert_eq!(crop.dimensions(), (16, 192));
+        assert_eq!(crop.get_pixel(0, 0), &image::Rgba([32, 64, 0, 255]));
+    }
+
+    #[test]
+    fn alpha() {
+        let rgb = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 4, image::Rgb([1, 2, 3])));
+        let alpha = DynamicImage::ImageLuma8(image::GrayImage::from_fn(4, 4, |x, _| image::Luma([x as u8 * 10])));
+        let rgba = merge_alpha(rgb.clone(), &alpha).unwrap();
+        assert_eq!(rgba.get_pixel(2, 1), &image::Rgba([1, 2, 3, 20]));
+
+        let small = DynamicImage::ImageLuma8(image::GrayImage::new(2, 2));
+        assert!(merge_alpha(rgb, &small).is_err());
+    }
+}
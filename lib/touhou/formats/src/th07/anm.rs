@@ -0,0 +1,236 @@
+//! ANM animation format support, version 2 as used in PCB.
+//!
+//! The header and sprites are unchanged since EoSD, but instructions now have
+//! a 16-bit opcode, their size and a parameter mask, and scripts end with an
+//! opcode of 0xffff.
+
+use nom::{
+    IResult,
+    bytes::complete::tag,
+    number::complete::{le_u8, le_u16, le_u32, le_i32, le_f32},
+    sequence::tuple,
+    multi::{many_m_n, many0},
+};
+use crate::th06::anm0::{Sprite, parse_name, parse_sprite};
+use crate::th06::ecl::{ParamMask, le_ParamMask};
+use crate::macros::RawArgs;
+use std::collections::BTreeMap;
+
+/// A single instruction, part of a `Script`.
+#[derive(Debug, Clone)]
+pub struct Call {
+    /// Time at which this instruction will be called.
+    pub time: u16,
+
+    /// Raw opcode of this instruction.
+    pub opcode: u16,
+
+    /// Which parameters of this instruction are variables.
+    pub param_mask: ParamMask,
+
+    /// The instruction to call.
+    pub instr: Instruction,
+}
+
+/// Script driving an animation.
+#[derive(Debug, Clone)]
+pub struct Script {
+    /// List of instructions in this script.
+    pub instructions: Vec<Call>,
+
+    /// List of interrupts in this script.
+    pub interrupts: BTreeMap<i32, u8>
+}
+
+/// Main struct of the ANM animation format.
+#[derive(Debug, Clone)]
+pub struct Anm {
+    /// Resolution of the image used by this ANM.
+    pub size: (u32, u32),
+
+    /// Format of this ANM.
+    pub format: u32,
+
+    /// Color key, probably used for transparency.
+    pub color_key: u32,
+
+    /// File name of the main image.
+    pub png_filename: String,
+
+    /// File name of an alpha channel image.
+    pub alpha_filename: Option<String>,
+
+    /// A list of sprites, coordinates into the attached image.
+    pub sprites: Vec<Sprite>,
+
+    /// A map of scripts.
+    pub scripts: BTreeMap<u8, Script>,
+}
+
+impl Anm {
+    /// Parse a slice of bytes into an `Anm` struct.
+    pub fn from_slice(data: &[u8]) -> IResult<&[u8], Vec<Anm>> {
+        many0(parse_anm)(data)
+    }
+}
+
+// TODO: only the opcodes whose meaning didn’t change since EoSD are known so
+// far, the other ones get parsed as Unknown, keeping their raw arguments.
+declare_anm_instructions!{
+    0 => fn Nop(),
+    1 => fn Delete(),
+    2 => fn KeepStill(),
+    3 => fn LoadSprite(sprite_number: u32),
+    4 => fn Jump(instruction: u32, time: u32),
+    6 => fn Move(x: f32, y: f32, z: f32),
+    7 => fn SetScale(sx: f32, sy: f32),
+    8 => fn SetAlpha(alpha: u32),
+    9 => fn SetColor(red: u8, green: u8, blue: u8),
+    12 => fn SetRotations3d(x: f32, y: f32, z: f32),
+    13 => fn SetRotationsSpeed3d(x: f32, y: f32, z: f32),
+    14 => fn SetScaleSpeed(sx: f32, sy: f32),
+    21 => fn InterruptLabel(label: i32),
+    _ => fn Unknown(opcode: u16, args: RawArgs),
+}
+
+fn error<T>(input: &[u8]) -> IResult<&[u8], T> {
+    // XXX: use a more specific error instead.
+    Err(nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Eof)))
+}
+
+fn parse_anm(input: &[u8]) -> IResult<&[u8], Anm> {
+    let (i, (num_sprites, num_scripts, _, width, height, format, color_key,
+             first_name_offset, _, second_name_offset, version, _,
+             _texture_offset, has_data, _next_offset, _)) =
+        tuple((le_u32, le_u32, tag(b"\0\0\0\0"), le_u32, le_u32, le_u32, le_u32, le_u32,
+               tag(b"\0\0\0\0"), le_u32, le_u32, tag(b"\0\0\0\0"), le_u32, le_u32, le_u32,
+               tag(b"\0\0\0\0")))(input)?;
+
+    if version != 2 || has_data != 0 {
+        return error(input);
+    }
+    let num_sprites = num_sprites as usize;
+    let num_scripts = num_scripts as usize;
+
+    let (i, sprite_offsets) = many_m_n(num_sprites, num_sprites, le_u32)(i)?;
+    let (_, script_offsets) = many_m_n(num_scripts, num_scripts, tuple((le_u32, le_u32)))(i)?;
+
+    let name_at = |offset: u32| {
+        if input.len() < offset as usize {
+            return error(input);
+        }
+        parse_name(&input[offset as usize..])
+    };
+    let png_filename = if first_name_offset > 0 { name_at(first_name_offset)?.1 } else { String::new() };
+    let alpha_filename = if second_name_offset > 0 { Some(name_at(second_name_offset)?.1) } else { None };
+
+    let mut sprites = vec![];
+    for offset in sprite_offsets.into_iter().map(|x| x as usize) {
+        if input.len() < offset {
+            return error(input);
+        }
+        let (_, sprite) = parse_sprite(&input[offset..])?;
+        sprites.push(sprite);
+    }
+
+    let mut i = input;
+    let mut scripts = BTreeMap::new();
+    for (index, offset) in script_offsets.into_iter().map(|(index, offset)| (index as u8, offset as usize)) {
+        if input.len() < offset {
+            return error(input);
+        }
+        i = &input[offset..];
+        let mut instruction_offsets = vec![];
+
+        let mut instructions = vec![];
+        loop {
+            let tell = input.len() - i.len();
+            let (i2, (opcode, size, time, param_mask)) = tuple((le_u16, le_u16, le_u16, le_ParamMask))(i)?;
+            if opcode == 0xffff {
+                i = i2;
+                break;
+            }
+            let size = size as usize;
+            if size < 8 || i.len() < size {
+                return error(input);
+            }
+            instruction_offsets.push(tell - offset);
+            // Unknown instructions don’t consume their arguments, so always skip the full size.
+            let (_, instr) = parse_instruction_args(&i2[..size - 8], opcode)?;
+            instructions.push(Call { time, opcode, param_mask, instr });
+            i = &i[size..];
+        }
+        let mut interrupts = BTreeMap::new();
+        for (j, Call { instr, .. }) in instructions.iter_mut().enumerate() {
+            match instr {
+                Instruction::Jump(ref mut offset, _) => {
+                    match instruction_offsets.binary_search(&(*offset as usize)) {
+                        Ok(ptr) => *offset = ptr as u32,
+                        Err(_) => return error(input),
+                    }
+                }
+                Instruction::InterruptLabel(interrupt) => {
+                    interrupts.insert(*interrupt, j as u8 + 1);
+                }
+                _ => ()
+            }
+        }
+        scripts.insert(index, Script {
+            instructions,
+            interrupts,
+        });
+    }
+
+    let anm = Anm {
+        size: (width, height),
+        format,
+        color_key,
+        png_filename,
+        alpha_filename,
+        sprites,
+        scripts,
+    };
+    Ok((i, anm))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script() {
+        let mut buf = vec![];
+        for value in [0u32, 1, 0, 256, 256, 1, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0].iter() {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        // Script 0 at offset 72.
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&72u32.to_le_bytes());
+        // LoadSprite(3) at time 0.
+        for value in [3u16, 12, 0, 0].iter() {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf.extend_from_slice(&3u32.to_le_bytes());
+        // Unknown opcode 200 with two arguments, then Jump back to the start.
+        for value in [200u16, 16, 5, 0].iter() {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf.extend_from_slice(&[0xaa; 8]);
+        for value in [4u16, 16, 10, 0].iter() {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&[0xff, 0xff, 0, 0, 0, 0, 0, 0]);
+
+        let (_, mut anms) = Anm::from_slice(&buf).unwrap();
+        assert_eq!(anms.len(), 1);
+        let anm = anms.pop().unwrap();
+        assert_eq!(anm.size, (256, 256));
+        let script = &anm.scripts[&0];
+        assert_eq!(script.instructions.len(), 3);
+        assert!(matches!(script.instructions[0].instr, Instruction::LoadSprite(3)));
+        assert!(matches!(script.instructions[1].instr, Instruction::Unknown(200, ref args) if args == &[0xaa; 8]));
+        assert!(matches!(script.instructions[2].instr, Instruction::Jump(0, 0)));
+    }
+}
@@ -0,0 +1,283 @@
+//! ECL enemy script format support, as used in PCB.
+//!
+//! The container and instruction headers are the same as in EoSD, but many
+//! opcodes got added or moved.
+
+use nom::{
+    IResult,
+    number::complete::{le_u16, le_u32, le_i16, le_i32, le_f32},
+    sequence::tuple,
+    multi::{count, many0},
+    error::ErrorKind,
+    Err,
+};
+use crate::th06::ecl::{Rank, ParamMask, le_ParamMask};
+use crate::macros::RawArgs;
+
+/// A single instruction, part of a `Sub`.
+#[derive(Debug, Clone)]
+pub struct CallSub {
+    /// Time at which this instruction will be called.
+    pub time: i32,
+
+    /// Raw opcode of this instruction.
+    pub opcode: u16,
+
+    /// The difficulty level(s) this instruction will be called at.
+    pub rank_mask: Rank,
+
+    /// Which parameters of this instruction are variables.
+    pub param_mask: ParamMask,
+
+    /// The instruction to call.
+    pub instr: SubInstruction,
+}
+
+/// Script driving an enemy.
+#[derive(Debug, Clone)]
+pub struct Sub {
+    /// List of instructions in this script.
+    pub instructions: Vec<CallSub>,
+}
+
+/// A single instruction, part of a `Main`.
+#[derive(Debug, Clone)]
+pub struct CallMain {
+    /// Time at which this instruction will be called.
+    pub time: u16,
+
+    /// Subroutine to call for this enemy.
+    pub sub: u16,
+
+    /// Raw opcode of this instruction.
+    pub opcode: u16,
+
+    /// The instruction to call.
+    pub instr: MainInstruction,
+}
+
+/// Script spawning the enemies of a stage.
+#[derive(Debug, Clone)]
+pub struct Main {
+    /// List of instructions in this script.
+    pub instructions: Vec<CallMain>,
+}
+
+/// Main struct of the ECL enemy script format.
+#[derive(Debug, Clone)]
+pub struct Ecl {
+    /// A list of subs.
+    pub subs: Vec<Sub>,
+
+    /// A list of mains.
+    pub mains: Vec<Main>,
+}
+
+impl Ecl {
+    /// Parse a slice of bytes into an `Ecl` struct.
+    pub fn from_slice(data: &[u8]) -> IResult<&[u8], Ecl> {
+        parse_ecl(data)
+    }
+}
+
+// TODO: check the spawn instructions against more stages.
+declare_main_instructions!{
+    0 => fn SpawnEnemy(x: f32, y: f32, z: f32, life: i16, bonus_dropped: i16, die_score: u32),
+    2 => fn SpawnEnemyMirrored(x: f32, y: f32, z: f32, life: i16, bonus_dropped: i16, die_score: u32),
+    4 => fn SpawnEnemyRandom(x: f32, y: f32, z: f32, life: i16, bonus_dropped: i16, die_score: u32),
+    6 => fn SpawnEnemyMirroredRandom(x: f32, y: f32, z: f32, life: i16, bonus_dropped: i16, die_score: u32),
+    _ => fn Unknown(opcode: u16, args: RawArgs),
+}
+
+// TODO: only the variable, arithmetic and control flow instructions are known
+// to be shared with EoSD, everything else (movement, bullets, lasers,
+// callbacks…) gets parsed as Unknown, keeping its raw arguments, until their
+// layout is documented.
+declare_sub_instructions!{
+    0 => fn Noop(),
+    1 => fn Destroy(unused: u32),
+    2 => fn RelativeJump(frame: i32, ip: i32),
+    3 => fn RelativeJumpEx(frame: i32, ip: i32, variable_id: i32),
+    4 => fn SetInt(var: i32, value: i32),
+    5 => fn SetFloat(var: i32, value: f32),
+    6 => fn SetRandomInt(var: i32, max: i32),
+    7 => fn SetRandomIntMin(var: i32, max: i32, min: i32),
+    8 => fn SetRandomFloat(var: i32, max: f32),
+    9 => fn SetRandomFloatMin(var: i32, amplitude: f32, min: f32),
+    10 => fn StoreX(var: i32),
+    11 => fn StoreY(var: i32),
+    12 => fn StoreZ(var: i32),
+    13 => fn AddInt(var: i32, a: i32, b: i32),
+    14 => fn SubstractInt(var: i32, a: i32, b: i32),
+    15 => fn MultiplyInt(var: i32, a: i32, b: i32),
+    16 => fn DivideInt(var: i32, a: i32, b: i32),
+    17 => fn ModuloInt(var: i32, a: i32, b: i32),
+    18 => fn Increment(var: i32),
+    19 => fn Decrement(var: i32),
+    20 => fn AddFloat(var: i32, a: f32, b: f32),
+    21 => fn SubstractFloat(var: i32, a: f32, b: f32),
+    22 => fn MultiplyFloat(var: i32, a: f32, b: f32),
+    23 => fn DivideFloat(var: i32, a: f32, b: f32),
+    24 => fn ModuloFloat(var: i32, a: f32, b: f32),
+    25 => fn GetDirection(var: i32, x1: f32, y1: f32, x2: f32, y2: f32),
+    26 => fn FloatToUnitCircle(var: i32),
+    27 => fn CompareInts(a: i32, b: i32),
+    28 => fn CompareFloats(a: f32, b: f32),
+    29 => fn RelativeJumpIfLowerThan(frame: i32, ip: i32),
+    30 => fn RelativeJumpIfLowerOrEqual(frame: i32, ip: i32),
+    31 => fn RelativeJumpIfEqual(frame: i32, ip: i32),
+    32 => fn RelativeJumpIfGreaterThan(frame: i32, ip: i32),
+    33 => fn RelativeJumpIfGreaterOrEqual(frame: i32, ip: i32),
+    34 => fn RelativeJumpIfNotEqual(frame: i32, ip: i32),
+    35 => fn Call(sub: i32, param1: i32, param2: f32),
+    36 => fn Return(),
+    _ => fn Unknown(opcode: u16, args: RawArgs),
+}
+
+fn parse_sub_instruction(input: &[u8]) -> IResult<&[u8], CallSub> {
+    let i = input;
+    let (i, (time, opcode)) = tuple((le_i32, le_u16))(i)?;
+    if time == -1 || opcode == 0xffff {
+        return Err(Err::Error(nom::error::Error::new(i, ErrorKind::Eof)));
+    }
+
+    let (i, (size, rank_mask, param_mask)) = tuple((le_u16, le_u16, le_ParamMask))(i)?;
+    let size = size as usize;
+    if size < 12 || input.len() < size {
+        return Err(Err::Failure(nom::error::Error::new(i, ErrorKind::Eof)));
+    }
+    let rank_mask = Rank::from_bits_truncate(rank_mask);
+    // Unknown instructions don’t consume their arguments, so always skip the full size.
+    let (_, instr) = parse_sub_instruction_args(&input[12..size], opcode)?;
+    let call = CallSub { time, opcode, rank_mask, param_mask, instr };
+    Ok((&input[size..], call))
+}
+
+fn parse_sub(i: &[u8]) -> IResult<&[u8], Sub> {
+    let (i, instructions) = many0(parse_sub_instruction)(i)?;
+    let sub = Sub { instructions };
+    Ok((i, sub))
+}
+
+fn parse_main_instruction(input: &[u8]) -> IResult<&[u8], CallMain> {
+    let i = input;
+    let (i, (time, sub)) = tuple((le_u16, le_u16))(i)?;
+    if time == 0xffff && sub == 4 {
+        return Err(Err::Error(nom::error::Error::new(i, ErrorKind::Eof)));
+    }
+
+    let (i, (opcode, size)) = tuple((le_u16, le_u16))(i)?;
+    let size = size as usize;
+    if size < 8 || input.len() < size {
+        return Err(Err::Failure(nom::error::Error::new(i, ErrorKind::Eof)));
+    }
+    let (_, instr) = parse_main_instruction_args(&input[8..size], opcode)?;
+    let call = CallMain { time, sub, opcode, instr };
+    Ok((&input[size..], call))
+}
+
+fn parse_main(i: &[u8]) -> IResult<&[u8], Main> {
+    let (i, instructions) = many0(parse_main_instruction)(i)?;
+    let main = Main { instructions };
+    Ok((i, main))
+}
+
+fn parse_ecl(input: &[u8]) -> IResult<&[u8], Ecl> {
+    let i = input;
+
+    // Unlike in EoSD, the main count isn’t always zero here.
+    let (i, (sub_count, _main_count)) = tuple((le_u16, le_u16))(i)?;
+    let sub_count = sub_count as usize;
+
+    let (_, (main_offsets, sub_offsets)) = tuple((
+        count(le_u32, 3),
+        count(le_u32, sub_count),
+    ))(i)?;
+
+    let at = |offset: usize| {
+        if input.len() < offset {
+            return Err(Err::Failure(nom::error::Error::new(input, ErrorKind::Eof)));
+        }
+        Ok(&input[offset..])
+    };
+
+    let mut subs = Vec::new();
+    for offset in sub_offsets.into_iter().map(|offset| offset as usize) {
+        let (_, sub) = parse_sub(at(offset)?)?;
+        subs.push(sub);
+    }
+
+    let mut mains = Vec::new();
+    for offset in main_offsets.into_iter().map(|offset| offset as usize) {
+        if offset == 0 {
+            break;
+        }
+        let (_, main) = parse_main(at(offset)?)?;
+        mains.push(main);
+    }
+
+    let ecl = Ecl {
+        subs,
+        mains,
+    };
+    Ok((b"", ecl))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_instruction() {
+        let mut buf = vec![];
+        // Header: one sub, one main.
+        buf.extend_from_slice(&1u16.to_le_bytes());
+        buf.extend_from_slice(&1u16.to_le_bytes());
+        for offset in [20u32, 0, 0, 20 + 48].iter() {
+            buf.extend_from_slice(&offset.to_le_bytes());
+        }
+        // Main: spawn sub 0, then terminate.
+        buf.extend_from_slice(&10u16.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&28u16.to_le_bytes());
+        for value in [192f32, 64., 0.].iter() {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf.extend_from_slice(&100i16.to_le_bytes());
+        buf.extend_from_slice(&(-1i16).to_le_bytes());
+        buf.extend_from_slice(&1000u32.to_le_bytes());
+        // Unknown main opcode 42 with one argument.
+        for value in [20u16, 0, 42, 12].iter() {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf.extend_from_slice(&[0x55; 4]);
+        buf.extend_from_slice(&[0xff, 0xff, 4, 0, 0, 0, 0, 0]);
+        assert_eq!(buf.len(), 20 + 48);
+        // Sub: an unknown opcode, then Return, then terminate.
+        buf.extend_from_slice(&0i32.to_le_bytes());
+        for value in [150u16, 20, 0xff00, 0].iter() {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf.extend_from_slice(&[0xaa; 8]);
+        buf.extend_from_slice(&5i32.to_le_bytes());
+        for value in [36u16, 12, 0xff00, 0].iter() {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf.extend_from_slice(&(-1i32).to_le_bytes());
+        buf.extend_from_slice(&[0xff; 8]);
+
+        let (_, ecl) = Ecl::from_slice(&buf).unwrap();
+        assert_eq!(ecl.mains.len(), 1);
+        assert_eq!(ecl.mains[0].instructions.len(), 2);
+        assert!(matches!(ecl.mains[0].instructions[0].instr, MainInstruction::SpawnEnemy(_, _, _, 100, -1, 1000)));
+        assert!(matches!(ecl.mains[0].instructions[1].instr, MainInstruction::Unknown(42, ref args) if args == &[0x55; 4]));
+        assert_eq!(ecl.subs.len(), 1);
+        let sub = &ecl.subs[0].instructions;
+        assert_eq!(sub.len(), 2);
+        assert!(matches!(sub[0].instr, SubInstruction::Unknown(150, ref args) if args == &[0xaa; 8]));
+        assert_eq!(sub[0].instr.opcode(), 150);
+        assert!(matches!(sub[1].instr, SubInstruction::Return()));
+        assert_eq!(sub[1].time, 5);
+    }
+}
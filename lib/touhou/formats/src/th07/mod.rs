@@ -0,0 +1,13 @@
+//! Touhou 7: PCB implementation.
+//!
+//! Most formats only changed slightly since EoSD, so this reuses as much as
+//! possible from the `th06` modules.
+//!
+//! This is enough to extract the files of the game and walk its scripts, not to
+//! run them: only the ANM and ECL opcodes shared with EoSD get decoded, the
+//! other ones are kept as `Unknown` with their raw arguments.
+
+pub mod pbg4;
+pub mod anm;
+pub mod ecl;
+pub mod std;
@@ -0,0 +1,137 @@
+//! PBG4 archive files handling.
+//!
+//! This module provides classes for handling the PBG4 file format.
+//! The PBG4 format is the archive format used by Touhou 7: PCB.
+//!
+//! PBG4 files are composed of a byte-aligned header, LZSS-compressed files,
+//! and a LZSS-compressed file table at the end.
+
+use touhou_utils::bitstream::BitStream;
+use touhou_utils::lzss;
+use std::fs::File;
+use std::io;
+use std::collections::hash_map::{self, HashMap};
+use std::path::Path;
+
+/// Offset and uncompressed size of a file.
+type Entry = (u32, u32);
+
+fn read_u32<R: io::Read>(file: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    file.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Handle PBG4 archive files.
+///
+/// PBG4 is a file archive format used in Touhou 7: PCB.
+/// This class provides a representation of such files, as well as functions to
+/// read and extract files from a PBG4 archive.
+pub struct PBG4<R: io::Read + io::Seek> {
+    /// List of entries describing files present in the archive.
+    entries: HashMap<String, Entry>,
+
+    /// Bitstream over the whole archive.
+    bitstream: BitStream<R>,
+}
+
+impl<R: io::Read + io::Seek> PBG4<R> {
+    /// Open a PBG4 archive.
+    pub fn from_file(mut file: R) -> io::Result<PBG4<R>> {
+        let mut magic = [0; 4];
+        file.read_exact(&mut magic)?;
+        if &magic != b"PBG4" {
+            return Err(io::Error::new(io::ErrorKind::Other, "Wrong magic!"));
+        }
+
+        let nb_entries = read_u32(&mut file)?;
+        let offset = read_u32(&mut file)?;
+        let size = read_u32(&mut file)?;
+
+        let mut bitstream = BitStream::new(file);
+        bitstream.seek(io::SeekFrom::Start(offset as u64))?;
+        let table = lzss::decompress(&mut bitstream, size as usize, 0x2000, 13, 4, 3)?;
+
+        let mut entries = HashMap::new();
+        let mut table = &table[..];
+        for _ in 0..nb_entries {
+            let length = table.iter().position(|&c| c == 0)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Unterminated file name"))?;
+            let name = String::from_utf8(table[..length].to_vec()).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            table = &table[length + 1..];
+            let offset = read_u32(&mut table)?;
+            let size = read_u32(&mut table)?;
+            // Always zero.
+            let _unknown = read_u32(&mut table)?;
+            entries.insert(name, (offset, size));
+        }
+
+        Ok(PBG4 {
+            entries,
+            bitstream,
+        })
+    }
+
+    /// List all file entries in this PBG4 archive.
+    pub fn list_files(&self) -> hash_map::Keys<'_, String, Entry> {
+        self.entries.keys()
+    }
+
+    /// Read a single file from this PBG4 archive.
+    pub fn get_file(&mut self, filename: &str) -> io::Result<Vec<u8>> {
+        let (offset, size) =
+            self.entries.get(filename)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("File not found in PBG4: {}", filename)))?;
+        self.bitstream.seek(io::SeekFrom::Start(*offset as u64))?;
+        lzss::decompress(&mut self.bitstream, *size as usize, 0x2000, 13, 4, 3)
+    }
+}
+
+/// Open a PBG4 archive from its path.
+pub fn from_path_buffered<P: AsRef<Path>>(path: P) -> io::Result<PBG4<io::BufReader<File>>> {
+    let file = File::open(path)?;
+    let buf_file = io::BufReader::new(file);
+    PBG4::from_file(buf_file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Compress using only literals, which is valid LZSS.
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut bits = vec![];
+        for &byte in data {
+            bits.push(true);
+            bits.extend((0..8).rev().map(|i| (byte >> i) & 1 != 0));
+        }
+        bits.chunks(8).map(|chunk| chunk.iter().enumerate().fold(0u8, |acc, (i, &bit)| acc | ((bit as u8) << (7 - i)))).collect()
+    }
+
+    #[test]
+    fn archive() {
+        let content = b"Perfect Cherry Blossom";
+        let compressed = compress(content);
+
+        let mut table = vec![];
+        table.extend_from_slice(b"th07logo.jpg\0");
+        table.extend_from_slice(&16u32.to_le_bytes());
+        table.extend_from_slice(&(content.len() as u32).to_le_bytes());
+        table.extend_from_slice(&0u32.to_le_bytes());
+
+        let mut data = vec![];
+        data.extend_from_slice(b"PBG4");
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&(16 + compressed.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(table.len() as u32).to_le_bytes());
+        data.extend_from_slice(&compressed);
+        data.extend_from_slice(&compress(&table));
+
+        let mut pbg4 = PBG4::from_file(Cursor::new(data)).unwrap();
+        let files = pbg4.list_files().cloned().collect::<Vec<String>>();
+        assert_eq!(files, vec![String::from("th07logo.jpg")]);
+        assert_eq!(pbg4.get_file("th07logo.jpg").unwrap(), content);
+        assert!(pbg4.get_file("th06logo.jpg").is_err());
+    }
+}
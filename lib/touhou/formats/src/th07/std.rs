@@ -0,0 +1,5 @@
+//! STD background format support, as used in PCB.
+//!
+//! The stage format didn’t change since EoSD, so this only re-exports it.
+
+pub use crate::th06::std::*;
@@ -0,0 +1,67 @@
+//! Benchmark of the simulation of whole stages, reported in frames per second.
+//!
+//! This uses the files from an unarchived ST.DAT, in EoSD/ST.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use touhou_formats::th06::anm0::Anm0;
+use touhou_formats::th06::ecl::{Ecl, Rank};
+use touhou_interpreters::th06::enemy::Game;
+use touhou_interpreters::th06::player::Keys;
+use touhou_utils::prng::Prng;
+use std::fs;
+use std::sync::Arc;
+
+/// Amount of frames simulated per iteration, a bit less than two minutes of game.
+const FRAMES: u64 = 6000;
+
+fn load_anm0(filename: &str) -> Anm0 {
+    let buf = fs::read(filename).unwrap();
+    let (_, mut anms) = Anm0::from_slice(&buf).unwrap();
+    anms.pop().unwrap()
+}
+
+fn load_game(stage: u8) -> Game {
+    let buf = fs::read(format!("EoSD/ST/ecldata{}.ecl", stage)).unwrap();
+    let (_, ecl) = Ecl::from_slice(&buf).unwrap();
+    let anm0 = load_anm0(&format!("EoSD/ST/stg{}enm.anm", stage));
+    let anm0_bis = load_anm0(&format!("EoSD/ST/stg{}enm2.anm", stage));
+
+    let mut game = Game::new(Prng::new(0), Rank::NORMAL);
+    game.load_stage(ecl, Arc::new([anm0, anm0_bis]));
+    game
+}
+
+fn stages(c: &mut Criterion) {
+    let mut group = c.benchmark_group("stage");
+    group.throughput(Throughput::Elements(FRAMES));
+    group.sample_size(10);
+    // Stage 7 is the Extra stage.
+    for stage in 1..=7 {
+        let game = load_game(stage);
+
+        // Script errors only stop the enemy which failed, so report them and keep going.
+        let mut errors = 0;
+        let mut clone = game.clone();
+        for _ in 0..FRAMES {
+            if clone.run_frame(Keys::SHOOT).is_err() {
+                errors += 1;
+            }
+        }
+        if errors > 0 {
+            eprintln!("Stage {}: ECL errors on {} frames out of {}.", stage, errors, FRAMES);
+        }
+
+        group.bench_with_input(BenchmarkId::from_parameter(stage), &game, |b, game| {
+            b.iter_batched(|| game.clone(), |mut game| {
+                for _ in 0..FRAMES {
+                    let _ = game.run_frame(Keys::SHOOT);
+                }
+                game
+            }, BatchSize::LargeInput);
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, stages);
+criterion_main!(benches);
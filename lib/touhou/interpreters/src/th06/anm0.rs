@@ -7,10 +7,12 @@ use touhou_formats::th06::anm0::{
     Instruction,
 };
 use crate::th06::interpolator::{Interpolator1, Interpolator2, Interpolator3, Formula};
+use crate::th06::enemy::Game;
+use touhou_utils::impl_snapshot;
 use touhou_utils::math::Mat4;
 use touhou_utils::prng::Prng;
-use std::cell::RefCell;
-use std::rc::{Rc, Weak};
+use touhou_utils::snapshot::{Save, Load, Reader, Error};
+use std::sync::Arc;
 
 /// TODO
 #[repr(C)]
@@ -51,7 +53,7 @@ pub struct Sprite {
     rotation_interpolator: Option<Interpolator3<f32>>,
     color_interpolator: Option<Interpolator3<f32>>, // XXX: should be u8!
 
-    anm: Option<Anm0>,
+    inv_size: (f32, f32),
 
     dest_offset: [f32; 3],
     texcoords: [f32; 4],
@@ -64,6 +66,14 @@ pub struct Sprite {
     layer: u16,
 }
 
+impl_snapshot!(Sprite {
+    blendfunc, frame, width_override, height_override, angle, removed, changed, visible,
+    force_rotation, automatic_orientation, allow_dest_offset, mirrored, corner_relative_placement,
+    scale_interpolator, fade_interpolator, offset_interpolator, rotation_interpolator,
+    color_interpolator, inv_size, dest_offset, texcoords, texoffsets, rescale, scale_speed,
+    rotations_3d, rotations_speed_3d, color, layer,
+});
+
 impl Sprite {
     /// Create a new sprite.
     pub fn new() -> Sprite {
@@ -89,6 +99,16 @@ impl Sprite {
         }
     }
 
+    /// Whether its script deleted this sprite.
+    pub fn is_removed(&self) -> bool {
+        self.removed
+    }
+
+    /// Size of the part of the texture used by this sprite.
+    pub fn size(&self) -> [f32; 2] {
+        [self.texcoords[2], self.texcoords[3]]
+    }
+
     /// TODO
     pub fn fill_vertices(&self, vertices: &mut [Vertex; 4], x: f32, y: f32, z: f32) {
         let mut mat = Mat4::new([[-0.5, 0.5, 0.5, -0.5],
@@ -146,8 +166,7 @@ impl Sprite {
         vertices[3].pos[1] = mat[1][3] as i16;
         vertices[3].pos[2] = mat[2][3] as i16;
 
-        // XXX: don’t clone here.
-        let (x_1, y_1) = self.anm.clone().unwrap().inv_size();
+        let (x_1, y_1) = self.inv_size;
         let [tox, toy] = self.texoffsets;
         let left = tx * x_1 + tox;
         let right = (tx + tw) * x_1 + tox;
@@ -222,33 +241,35 @@ impl Sprite {
     }
 }
 
+#[derive(Clone)]
 struct Anms {
-    inner: Rc<RefCell<[Anm0]>>,
+    inner: Arc<[Anm0]>,
 }
 
 impl Anms {
-    fn new(anms: Rc<RefCell<[Anm0]>>) -> Anms {
+    fn new(anms: Arc<[Anm0]>) -> Anms {
         Anms {
             inner: anms,
         }
     }
 
     fn load_sprite(&self, sprite: &mut Sprite, id: u8) {
-        let anms = self.inner.borrow();
         let mut anm = None;
         let mut texcoords = None;
         let mut layer = 0;
-        'anm: for anm0 in anms.iter() {
+        'anm: for anm0 in self.inner.iter() {
             for sp in anm0.sprites.iter() {
                 if sp.index == id as u32 {
                     texcoords = Some(sp);
-                    anm = Some(anm0.clone());
+                    anm = Some(anm0);
                     break 'anm;
                 }
             }
             layer += 1;
         }
-        sprite.anm = anm;
+        if let Some(anm) = anm {
+            sprite.inv_size = anm.inv_size();
+        }
         sprite.layer = layer;
         if let Some(texcoords) = texcoords {
             sprite.texcoords = [texcoords.x, texcoords.y, texcoords.width, texcoords.height];
@@ -256,23 +277,22 @@ impl Anms {
     }
 
     fn get_script(&self, id: u8) -> Script {
-        let anms = self.inner.borrow();
-        for anm0 in anms.iter() {
-            if anm0.scripts.contains_key(&id) {
-                return anm0.scripts[&id].clone();
-            }
-        }
-        unreachable!();
+        self.find_script(id).unwrap()
+    }
+
+    fn find_script(&self, id: u8) -> Option<Script> {
+        self.inner.iter().find_map(|anm0| anm0.scripts.get(&id)).cloned()
     }
 }
 
 /// Interpreter for `Anm0` instructions to update a `Sprite`.
+#[derive(Clone)]
 pub struct AnmRunner {
     anms: Anms,
-    sprite: Rc<RefCell<Sprite>>,
-    prng: Weak<RefCell<Prng>>,
+    sprite: Sprite,
     running: bool,
     sprite_index_offset: u32,
+    script_id: u8,
     script: Script,
     instruction_pointer: usize,
     frame: u16,
@@ -283,16 +303,16 @@ pub struct AnmRunner {
 
 impl AnmRunner {
     /// Create a new `AnmRunner`.
-    pub fn new(anms: Rc<RefCell<[Anm0]>>, script_id: u8, sprite: Rc<RefCell<Sprite>>, prng: Weak<RefCell<Prng>>, sprite_index_offset: u32) -> AnmRunner {
+    pub fn new(anms: Arc<[Anm0]>, script_id: u8, sprite: Sprite, prng: &mut Prng, sprite_index_offset: u32) -> AnmRunner {
         let anms = Anms::new(anms);
         let script = anms.get_script(script_id);
         let mut runner = AnmRunner {
             anms,
-            sprite: sprite,
-            prng,
+            sprite,
             running: true,
             waiting: false,
 
+            script_id,
             script,
             frame: 0,
             timeout: None,
@@ -303,14 +323,19 @@ impl AnmRunner {
 
             sprite_index_offset: sprite_index_offset,
         };
-        runner.run_frame();
+        runner.run_frame(prng);
         runner.sprite_index_offset = 0;
         runner
     }
 
-    /// Get a Rc from the inner Sprite.
-    pub fn get_sprite(&self) -> Rc<RefCell<Sprite>> {
-        self.sprite.clone()
+    /// Whether one of these ANMs contains this script.
+    pub(crate) fn has_script(anms: &Arc<[Anm0]>, script_id: u8) -> bool {
+        Anms::new(anms.clone()).find_script(script_id).is_some()
+    }
+
+    /// Get the Sprite animated by this runner.
+    pub fn get_sprite(&self) -> &Sprite {
+        &self.sprite
     }
 
     /// Trigger an interrupt.
@@ -328,12 +353,14 @@ impl AnmRunner {
         let Call { time: frame, instr: _ } = &self.script.instructions[self.instruction_pointer];
         self.frame = *frame;
         self.waiting = false;
-        self.sprite.borrow_mut().visible = true;
+        self.sprite.visible = true;
         true
     }
 
     /// Advance the Anm of a single frame.
-    pub fn run_frame(&mut self) -> bool {
+    ///
+    /// `prng` is the one of the game, used for random sprites.
+    pub fn run_frame(&mut self, prng: &mut Prng) -> bool {
         if !self.running {
             return false;
         }
@@ -349,33 +376,33 @@ impl AnmRunner {
             }
 
             if frame == self.frame {
-                self.run_instruction(instr);
-                self.sprite.borrow_mut().changed = true;
+                self.run_instruction(instr, prng);
+                self.sprite.changed = true;
             }
         }
 
         if !self.waiting {
             self.frame += 1;
         } else if let Some(timeout) = self.timeout {
-            if timeout == self.sprite.borrow().frame { // TODO: check if it’s happening at the correct frame.
+            if timeout == self.sprite.frame { // TODO: check if it’s happening at the correct frame.
                 self.waiting = false;
             }
         }
 
-        self.sprite.borrow_mut().update();
+        self.sprite.update();
 
         self.running
     }
 
-    fn run_instruction(&mut self, instruction: Instruction) {
-        let mut sprite = self.sprite.borrow_mut();
+    fn run_instruction(&mut self, instruction: Instruction, prng: &mut Prng) {
+        let sprite = &mut self.sprite;
         match instruction {
             Instruction::Delete() => {
                 sprite.removed = true;
                 self.running = false;
             }
             Instruction::LoadSprite(sprite_index) => {
-                self.anms.load_sprite(&mut sprite, (sprite_index + self.sprite_index_offset) as u8);
+                self.anms.load_sprite(sprite, (sprite_index + self.sprite_index_offset) as u8);
             }
             Instruction::SetScale(sx, sy) => {
                 sprite.rescale = [sx, sy];
@@ -422,12 +449,11 @@ impl AnmRunner {
             }
             Instruction::LoadRandomSprite(min_index, mut amplitude) => {
                 if amplitude > 0 {
-                    let prng = self.prng.upgrade().unwrap();
-                    let rand = prng.borrow_mut().get_u16();
+                    let rand = prng.get_u16();
                     amplitude = (rand as u32) % amplitude;
                 }
                 let sprite_index = min_index + amplitude;
-                self.anms.load_sprite(&mut sprite, (sprite_index + self.sprite_index_offset) as u8);
+                self.anms.load_sprite(sprite, (sprite_index + self.sprite_index_offset) as u8);
             }
             Instruction::Move(x, y, z) => {
                 sprite.dest_offset = [x, y, z];
@@ -480,6 +506,50 @@ impl AnmRunner {
     }
 }
 
+impl Save for AnmRunner {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.script_id.save(out);
+        self.sprite.save(out);
+        self.running.save(out);
+        self.sprite_index_offset.save(out);
+        self.instruction_pointer.save(out);
+        self.frame.save(out);
+        self.waiting.save(out);
+        self.variables.save(out);
+        self.timeout.save(out);
+    }
+}
+
+/// The scripts are taken from the enemy ANMs of the game.
+impl Load<Game> for AnmRunner {
+    fn load(reader: &mut Reader, game: &Game) -> Result<AnmRunner, Error> {
+        let anms = game.anm0.clone().ok_or(Error::MissingData("anm0"))?;
+        AnmRunner::load_with(reader, game, anms)
+    }
+}
+
+impl AnmRunner {
+    /// Load a runner whose scripts are taken from these ANMs instead of the enemy ones.
+    pub(crate) fn load_with(reader: &mut Reader, game: &Game, anms: Arc<[Anm0]>) -> Result<AnmRunner, Error> {
+        let anms = Anms::new(anms);
+        let script_id = u8::load(reader, game)?;
+        let script = anms.find_script(script_id).ok_or(Error::InvalidValue("script"))?;
+        Ok(AnmRunner {
+            anms,
+            sprite: Load::load(reader, game)?,
+            running: Load::load(reader, game)?,
+            sprite_index_offset: Load::load(reader, game)?,
+            script_id,
+            script,
+            instruction_pointer: Load::load(reader, game)?,
+            frame: Load::load(reader, game)?,
+            waiting: Load::load(reader, game)?,
+            variables: Load::load(reader, game)?,
+            timeout: Load::load(reader, game)?,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -496,11 +566,11 @@ mod tests {
         let anm0 = anms.pop().unwrap();
         assert_eq!(anm0.size, (256, 256));
         assert_eq!(anm0.format, 5);
-        let sprite = Rc::new(RefCell::new(Sprite::new()));
-        let prng = Rc::new(RefCell::new(Prng::new(0)));
-        let mut anm_runner = AnmRunner::new(&anm0, 1, sprite.clone(), Rc::downgrade(&prng), 0);
+        let anms: Arc<[Anm0]> = Arc::new([anm0]);
+        let mut prng = Prng::new(0);
+        let mut anm_runner = AnmRunner::new(anms, 1, Sprite::new(), &mut prng, 0);
         for _ in 0..50 {
-            anm_runner.run_frame();
+            anm_runner.run_frame(&mut prng);
         }
     }
 }
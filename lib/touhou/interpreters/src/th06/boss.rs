@@ -0,0 +1,151 @@
+//! Module providing the state of the boss being fought, as displayed above the game area.
+
+use crate::th06::enemy::{Enemy, Game, Position};
+use touhou_utils::arena::Id;
+
+/// Highest amount of seconds displayed by the timer.
+const MAX_TIMEOUT_SECONDS: u32 = 99;
+
+/// State of the current boss, as shown by its life bar.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BossStatus {
+    /// The enemy being the boss.
+    pub enemy: Id,
+
+    /// Current position of the boss.
+    pub pos: Position,
+
+    /// Life left before the end of this phase.
+    pub life: u32,
+
+    /// Life the boss had at the start of this phase, filling its whole life bar.
+    pub max_life: u32,
+
+    /// Life below which the boss switches to its spellcard, if it still has to.
+    pub spell_life: Option<u32>,
+
+    /// Phases left after this one, displayed next to the life bar.
+    pub remaining_lives: u32,
+
+    /// Frames left before this phase times out, if it does.
+    pub timeout: Option<u32>,
+}
+
+impl BossStatus {
+    /// Seconds left before this phase times out, as displayed by the timer.
+    pub fn timeout_seconds(&self) -> Option<u32> {
+        self.timeout.map(|frames| (frames / 60).min(MAX_TIMEOUT_SECONDS))
+    }
+}
+
+impl Enemy {
+    /// Make this enemy the boss displayed by the game, or a regular enemy again.
+    pub(crate) fn set_boss(&mut self, id: Id, enable: bool, game: &mut Game) {
+        self.boss = enable;
+        if enable {
+            // With multiple bosses, only the last one has its life displayed.
+            game.boss = Some(id);
+            game.boss_max_life = self.life.max(1);
+        } else if game.boss == Some(id) {
+            game.boss = None;
+        }
+    }
+}
+
+impl Game {
+    /// Refill the life bar, if this enemy is the boss.
+    pub(crate) fn set_boss_life(&mut self, id: Id, life: u32) {
+        if self.boss == Some(id) {
+            self.boss_max_life = life.max(1);
+        }
+    }
+
+    /// Forget the boss once it died or stopped being one, falling back to the latest other boss.
+    pub(crate) fn update_boss(&mut self) {
+        let enemies = &self.enemies;
+        let is_boss = |id| enemies.get(id).is_some_and(|enemy: &Enemy| enemy.boss);
+        if self.boss.is_some_and(is_boss) {
+            return;
+        }
+        self.boss = enemies.ids().rev().find(|&id| is_boss(id));
+        if let Some(id) = self.boss {
+            self.boss_max_life = enemies[id].life.max(1);
+        }
+    }
+
+    /// Returns the state of the current boss, if any.
+    pub fn boss(&self) -> Option<BossStatus> {
+        let enemy = self.enemies.get(self.boss?)?;
+        Some(BossStatus {
+            enemy: self.boss?,
+            pos: enemy.pos,
+            life: enemy.life,
+            max_life: self.boss_max_life,
+            spell_life: enemy.low_life_trigger.map(|trigger| trigger.min(enemy.life)),
+            remaining_lives: enemy.remaining_lives,
+            timeout: enemy.timeout.map(|timeout| timeout.saturating_sub(enemy.frame)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::th06::player::Keys;
+    use crate::th06::testing::load_enemy;
+    use touhou_formats::th06::ecl::{CallSub, Rank, SubInstruction};
+
+    #[test]
+    fn life_bar() {
+        let mut game = load_enemy(vec![vec![
+            CallSub::new(0, Rank::EASY, SubInstruction::SetBossMode(0)),
+            CallSub::new(0, Rank::EASY, SubInstruction::SetLife(1000)),
+            CallSub::new(0, Rank::EASY, SubInstruction::SetLowLifeTrigger(600)),
+            CallSub::new(0, Rank::EASY, SubInstruction::SetRemainingLives(2)),
+            CallSub::new(0, Rank::EASY, SubInstruction::SetTimeout(1200)),
+            CallSub::new(30, Rank::EASY, SubInstruction::SetBossMode(-1)),
+        ]]);
+        game.run_frame(Keys::empty()).unwrap();
+        let boss = game.boss().unwrap();
+        assert_eq!((boss.life, boss.max_life), (1000, 1000));
+        assert_eq!(boss.spell_life, Some(600));
+        assert_eq!(boss.remaining_lives, 2);
+        assert_eq!(boss.timeout_seconds(), Some(19));
+        let timeout = boss.timeout.unwrap();
+
+        game.enemies[boss.enemy].life = 500;
+        for _ in 0..10 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+        let boss = game.boss().unwrap();
+        assert_eq!((boss.life, boss.max_life), (500, 1000));
+        assert_eq!(boss.spell_life, Some(500));
+        assert_eq!(boss.timeout, Some(timeout - 10));
+        assert!(game.has_boss());
+
+        for _ in 0..20 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+        assert!(game.boss().is_none());
+        assert!(!game.has_boss());
+    }
+
+    #[test]
+    fn multiple_bosses() {
+        // Bosses block the main timeline, so the second one has to be spawned by the first.
+        let mut game = load_enemy(vec![vec![
+            CallSub::new(0, Rank::EASY, SubInstruction::SetBossMode(0)),
+            CallSub::new(0, Rank::EASY, SubInstruction::SpawnEnemy(1, 0., 0., 0., 200, -1, 100)),
+        ], vec![
+            CallSub::new(0, Rank::EASY, SubInstruction::SetBossMode(0)),
+        ]]);
+        game.run_frame(Keys::empty()).unwrap();
+        let last = game.boss().unwrap();
+        assert_eq!((last.life, last.max_life), (200, 200));
+
+        game.enemies[last.enemy].removed = true;
+        game.run_frame(Keys::empty()).unwrap();
+        let boss = game.boss().unwrap();
+        assert_ne!(boss.enemy, last.enemy);
+        assert_eq!((boss.life, boss.max_life), (100, 100));
+    }
+}
@@ -0,0 +1,424 @@
+//! Module providing a Bullet struct, and the patterns enemies can fire.
+
+use touhou_formats::th06::ecl::BulletFlags;
+use crate::th06::enemy::{Game, Position, BulletAttributes};
+use crate::th06::interpolator::{Interpolator1, Formula};
+use touhou_utils::impl_snapshot;
+use std::f32::consts::PI;
+
+/// Width of the game area.
+pub(crate) const GAME_WIDTH: f32 = 384.;
+
+/// Height of the game area.
+pub(crate) const GAME_HEIGHT: f32 = 448.;
+
+/// Maximum amount of bullets alive at the same time.
+pub(crate) const MAX_BULLETS: usize = 640;
+
+/// Hitbox size of each bullet type, as indexed by the anim of `SetBulletAttributes*`.
+const HITBOX_SIZES: [f32; 10] = [2., 3., 2., 3., 2.5, 2., 8., 5.5, 4.5, 16.];
+
+/// Speed multiplier while the launch animation is playing, for LAUNCH_ANIM2/4/8.
+const LAUNCH_PENALTIES: [f32; 3] = [0.5, 0.4, 1. / 3.];
+
+// TODO: these come from the launch and cancel anims of etama3.anm, use an
+// AnmRunner once bullets get their own sprite.
+const LAUNCH_DURATION: i32 = 16;
+const CANCEL_DURATION: i32 = 16;
+
+/// All three variants of the periodic angle change.
+const CHANGE_ANGLE: BulletFlags = BulletFlags::from_bits_truncate(
+    BulletFlags::CHANGE_ANGLE_RELATIVE.bits() | BulletFlags::CHANGE_ANGLE_AIMED.bits() | BulletFlags::CHANGE_ANGLE_ABSOLUTE.bits());
+
+// TODO: use the actual sprite size instead.
+const CULLING_MARGIN: f32 = 16.;
+
+/// Values set by `SetExtendedBulletAttributes`, four ints followed by four floats.
+pub type ExtendedAttributes = (i32, i32, i32, i32, f32, f32, f32, f32);
+
+/// Lifecycle of a bullet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BulletState {
+    /// The launch animation is still playing, the bullet moves slower.
+    Launching,
+
+    /// Normal state, this bullet can hit the player.
+    Launched,
+
+    /// The bullet got cancelled and is playing its disappearing animation.
+    Cancelled,
+}
+
+impl_snapshot!(enum BulletState { Launching, Launched, Cancelled });
+
+/// Struct representing an enemy bullet.
+#[derive(Debug, Clone)]
+pub struct Bullet {
+    /// Current position of the bullet.
+    pub pos: Position,
+
+    /// Current angle of the bullet.
+    pub angle: f32,
+
+    /// Current speed of the bullet.
+    pub speed: f32,
+
+    /// Current velocity of the bullet.
+    pub dpos: [f32; 3],
+
+    /// Current flags of the bullet.
+    pub flags: BulletFlags,
+
+    /// Current frame of the bullet.
+    pub frame: i32,
+
+    /// Current extended attributes of the bullet.
+    pub attributes: ExtendedAttributes,
+
+    /// Current state of the bullet.
+    pub state: BulletState,
+
+    /// Bullet type, used to select its sprite and hitbox.
+    pub anim: i16,
+
+    /// Offset into the sprites of this type, usually its colour.
+    pub sprite_index_offset: i16,
+
+    /// Half-size of the hitbox of this bullet.
+    pub hitbox: [f32; 2],
+
+    /// Whether the player already grazed this bullet.
+    pub grazed: bool,
+
+    /// Whether a special function already changed the course of this bullet.
+    pub(crate) manipulated: bool,
+
+    pub(crate) removed: bool,
+    was_visible: bool,
+    speed_interpolator: Option<Interpolator1<f32>>,
+}
+
+impl_snapshot!(Bullet {
+    pos, angle, speed, dpos, flags, frame, attributes, state, anim, sprite_index_offset, hitbox,
+    grazed, manipulated, removed, was_visible, speed_interpolator,
+});
+
+impl Bullet {
+    /// Create a new bullet, which will start its launch animation if its flags ask for it.
+    pub(crate) fn new(pos: Position, anim: i16, sprite_index_offset: i16, angle: f32, speed: f32,
+                      attributes: ExtendedAttributes, flags: BulletFlags) -> Bullet {
+        let hitbox_size = HITBOX_SIZES.get(anim as usize).copied().unwrap_or(HITBOX_SIZES[0]);
+        let mut bullet = Bullet {
+            pos,
+            angle,
+            speed,
+            dpos: [angle.cos() * speed, angle.sin() * speed, 0.],
+            flags,
+            frame: 0,
+            attributes,
+            state: BulletState::Launching,
+            anim,
+            sprite_index_offset,
+            hitbox: [hitbox_size, hitbox_size],
+            grazed: false,
+            manipulated: false,
+            removed: false,
+            was_visible: true,
+            speed_interpolator: None,
+        };
+
+        let penalty = if flags.contains(BulletFlags::LAUNCH_ANIM2) {
+            Some(LAUNCH_PENALTIES[0])
+        } else if flags.contains(BulletFlags::LAUNCH_ANIM4) {
+            Some(LAUNCH_PENALTIES[1])
+        } else if flags.contains(BulletFlags::LAUNCH_ANIM8) {
+            Some(LAUNCH_PENALTIES[2])
+        } else {
+            None
+        };
+        match penalty {
+            Some(penalty) => {
+                bullet.dpos[0] *= penalty;
+                bullet.dpos[1] *= penalty;
+            }
+            None => bullet.launch(),
+        }
+        bullet
+    }
+
+    fn launch(&mut self) {
+        self.state = BulletState::Launched;
+        self.frame = 0;
+        self.set_velocity();
+        if self.flags.contains(BulletFlags::SPEED_BURST) {
+            self.speed_interpolator = Some(Interpolator1::new([self.speed + 5.], 0, [self.speed], 16, Formula::Linear));
+        }
+    }
+
+    /// Cancel this bullet, it will disappear after its cancel animation.
+    pub(crate) fn cancel(&mut self) {
+        self.dpos[0] /= 2.;
+        self.dpos[1] /= 2.;
+        self.frame = 0;
+        self.state = BulletState::Cancelled;
+    }
+
+    /// Whether this bullet can still hit the player.
+    pub fn is_active(&self) -> bool {
+        self.state != BulletState::Cancelled && !self.removed
+    }
+
+    fn set_velocity(&mut self) {
+        self.dpos[0] = self.angle.cos() * self.speed;
+        self.dpos[1] = self.angle.sin() * self.speed;
+    }
+
+    fn is_visible(&self) -> bool {
+        let Position { x, y } = self.pos;
+        (-CULLING_MARGIN..=GAME_WIDTH + CULLING_MARGIN).contains(&x)
+            && (-CULLING_MARGIN..=GAME_HEIGHT + CULLING_MARGIN).contains(&y)
+    }
+
+    /// Run the flag-driven behaviours of this bullet and move it, for a single frame.
+    ///
+    /// `target` is the position aimed at by `CHANGE_ANGLE_AIMED`, usually the player.
+    pub(crate) fn update(&mut self, target: Position) {
+        match self.state {
+            BulletState::Launching if self.frame >= LAUNCH_DURATION => self.launch(),
+            BulletState::Cancelled if self.frame >= CANCEL_DURATION => self.removed = true,
+            _ => (),
+        }
+
+        if self.state == BulletState::Launched {
+            self.update_flags(target);
+        }
+
+        if let Some(ref interpolator) = self.speed_interpolator {
+            let [speed] = interpolator.values(self.frame as u32);
+            self.dpos[0] = self.angle.cos() * speed;
+            self.dpos[1] = self.angle.sin() * speed;
+        }
+
+        self.pos.x += self.dpos[0];
+        self.pos.y += self.dpos[1];
+        self.frame += 1;
+
+        self.cull();
+    }
+
+    fn update_flags(&mut self, target: Position) {
+        let (frame, count, _, _, a, b, _, _) = self.attributes;
+        if self.flags.contains(BulletFlags::SPEED_BURST) {
+            // The burst is over once the interpolator reached its end.
+            if self.frame >= 16 {
+                self.speed_interpolator = None;
+                self.flags.remove(BulletFlags::SPEED_BURST);
+            }
+        } else if self.flags.contains(BulletFlags::ACCELERATION) {
+            // Each frame, add a vector to the velocity.
+            let (length, angle) = (a, if b < -900. { self.angle } else { b });
+            self.dpos[0] += angle.cos() * length;
+            self.dpos[1] += angle.sin() * length;
+            self.speed = self.dpos[0].hypot(self.dpos[1]);
+            self.angle = self.dpos[1].atan2(self.dpos[0]);
+            if self.frame == frame {
+                self.flags.remove(BulletFlags::ACCELERATION);
+            }
+        } else if self.flags.contains(BulletFlags::ACCELERATION_ROTATION) {
+            // Each frame, accelerate and rotate.
+            self.speed += a;
+            self.angle += b;
+            self.set_velocity();
+            if self.frame == frame {
+                self.flags.remove(BulletFlags::ACCELERATION_ROTATION);
+            }
+        } else if self.flags.intersects(CHANGE_ANGLE) {
+            if frame <= 0 {
+                self.flags.remove(CHANGE_ANGLE);
+                return;
+            }
+            if self.frame % frame == 0 {
+                let count = count - 1;
+                if self.frame != 0 {
+                    if b >= -900. {
+                        self.speed = b;
+                    }
+                    if self.flags.contains(BulletFlags::CHANGE_ANGLE_RELATIVE) {
+                        self.angle += a;
+                    } else if self.flags.contains(BulletFlags::CHANGE_ANGLE_AIMED) {
+                        self.angle = (target.y - self.pos.y).atan2(target.x - self.pos.x) + a;
+                    } else {
+                        self.angle = a;
+                    }
+                    self.set_velocity();
+                }
+                if count >= 0 {
+                    // Decelerate until the next change.
+                    let start = self.frame as u32;
+                    self.speed_interpolator = Some(Interpolator1::new([self.speed], start, [0.], start + frame as u32 - 1, Formula::Linear));
+                } else {
+                    self.speed_interpolator = None;
+                    self.flags.remove(CHANGE_ANGLE);
+                }
+                self.attributes.1 = count;
+            }
+        }
+    }
+
+    fn cull(&mut self) {
+        if self.flags.intersects(CHANGE_ANGLE) {
+            self.was_visible = false;
+        } else if self.is_visible() {
+            self.was_visible = true;
+        } else if self.was_visible {
+            self.removed = true;
+            if self.flags.intersects(BulletFlags::BOUNCE | BulletFlags::BOUNCE_EXCEPT_BOTTOM) && self.attributes.0 > 0 {
+                let Position { x, y } = self.pos;
+                if !(0. ..=GAME_WIDTH).contains(&x) {
+                    self.angle = PI - self.angle;
+                    self.removed = false;
+                }
+                if y < 0. || (self.flags.contains(BulletFlags::BOUNCE) && y > GAME_HEIGHT) {
+                    self.angle = -self.angle;
+                    self.removed = false;
+                }
+                self.set_velocity();
+                self.attributes.0 -= 1;
+            }
+        }
+    }
+}
+
+impl BulletAttributes {
+    /// Fire the pattern described by these attributes, adding the new bullets to the game.
+    ///
+    /// `bullet_type` is the opcode of the `SetBulletAttributes*` instruction minus 67.
+    pub(crate) fn fire(&self, game: &mut Game) {
+        let bullets_per_shot = self.bullets_per_shot.max(1) as i32;
+        let number_of_shots = self.number_of_shots.max(1) as i32;
+        let angle = self.angle;
+        let (speed, speed2) = (self.speed, self.speed2);
+        let opcode = self.bullet_type + 67;
+        // Patterns spread over a full circle, instead of following `angle`.
+        let ring = matches!(opcode, 69 | 70 | 71 | 74);
+
+        let mut launch_angle = self.launch_angle;
+        if matches!(opcode, 67 | 69 | 71) {
+            let player = &game.player;
+            launch_angle += (player.pos.y - self.pos.y).atan2(player.pos.x - self.pos.x);
+        }
+        if (opcode == 71 && bullets_per_shot % 2 != 0) || (matches!(opcode, 69 | 70) && bullets_per_shot % 2 == 0) {
+            launch_angle += PI / bullets_per_shot as f32;
+        }
+        if opcode != 75 {
+            launch_angle -= angle * (bullets_per_shot - 1) as f32 / 2.;
+        }
+
+        for shot in 0..number_of_shots {
+            let mut shot_speed = if shot == 0 {
+                speed
+            } else {
+                speed + (speed2 - speed) * shot as f32 / number_of_shots as f32
+            };
+            let mut bullet_angle = launch_angle;
+            if ring {
+                launch_angle += angle;
+            }
+            for _ in 0..bullets_per_shot {
+                if game.bullets.len() >= MAX_BULLETS {
+                    return;
+                }
+                if opcode == 75 {
+                    bullet_angle = game.prng.get_f64() as f32 * (launch_angle - angle) + angle;
+                }
+                if matches!(opcode, 74 | 75) {
+                    shot_speed = game.prng.get_f64() as f32 * (speed - speed2) + speed2;
+                }
+                let bullet = Bullet::new(self.pos, self.anim, self.sprite_index_offset, bullet_angle,
+                                         shot_speed, self.extended_attributes, self.flags);
+                game.bullets.push(bullet);
+                if ring {
+                    bullet_angle += 2. * PI / bullets_per_shot as f32;
+                } else {
+                    bullet_angle += angle;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::th06::testing::new_game;
+
+    fn attributes(opcode: i16, bullets_per_shot: i16, number_of_shots: i16, angle: f32) -> BulletAttributes {
+        BulletAttributes {
+            pos: Position::new(192., 100.),
+            bullet_type: opcode - 67,
+            bullets_per_shot,
+            number_of_shots,
+            speed: 2.,
+            speed2: 1.,
+            angle,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn aimed_spread() {
+        let mut game = new_game();
+        attributes(67, 3, 2, 0.1).fire(&mut game);
+        assert_eq!(game.bullets.len(), 6);
+        // The player is right below, so the middle bullet goes straight down.
+        assert!((game.bullets[1].angle - PI / 2.).abs() < 1e-5);
+        assert!((game.bullets[0].angle - (PI / 2. - 0.1)).abs() < 1e-5);
+        assert_eq!(game.bullets[0].speed, 2.);
+        assert_eq!(game.bullets[3].speed, 1.5);
+    }
+
+    #[test]
+    fn ring() {
+        let mut game = new_game();
+        attributes(70, 8, 1, 0.).fire(&mut game);
+        assert_eq!(game.bullets.len(), 8);
+        for pair in game.bullets.windows(2) {
+            assert!((pair[1].angle - pair[0].angle - PI / 4.).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn bullet_limit() {
+        let mut game = new_game();
+        attributes(68, 100, 10, 0.).fire(&mut game);
+        assert_eq!(game.bullets.len(), MAX_BULLETS);
+    }
+
+    #[test]
+    fn launch_and_culling() {
+        let mut bullet = Bullet::new(Position::new(10., 10.), 0, 0, -PI / 2., 2., Default::default(), BulletFlags::LAUNCH_ANIM2);
+        assert_eq!(bullet.state, BulletState::Launching);
+        bullet.update(Position::new(192., 384.));
+        assert_eq!(bullet.pos.y, 9.);
+        for _ in 0..LAUNCH_DURATION {
+            bullet.update(Position::new(192., 384.));
+        }
+        assert_eq!(bullet.state, BulletState::Launched);
+        for _ in 0..20 {
+            bullet.update(Position::new(192., 384.));
+        }
+        assert!(bullet.removed);
+    }
+
+    #[test]
+    fn bounce() {
+        let attributes = (1, 0, 0, 0, 0., 0., 0., 0.);
+        let mut bullet = Bullet::new(Position::new(5., 200.), 0, 0, PI, 4., attributes, BulletFlags::BOUNCE);
+        for _ in 0..10 {
+            bullet.update(Position::new(192., 384.));
+        }
+        assert!(!bullet.removed);
+        assert_eq!(bullet.attributes.0, 0);
+        assert!(bullet.dpos[0] > 0.);
+    }
+}
@@ -0,0 +1,388 @@
+//! Collision detection between the player, the enemies and everything they fire.
+
+use crate::th06::bullet::BulletState;
+use crate::th06::enemy::{Game, Position};
+use crate::th06::player::MAX_POWER;
+
+/// Score gained for each bullet or laser grazed.
+const GRAZE_SCORE: u32 = 500;
+
+/// Maximum damages an enemy can take in a single frame.
+const MAX_DAMAGES: u32 = 70;
+
+/// Damages dealt to a non-boss enemy touching the player.
+const CONTACT_DAMAGES: u32 = 10;
+
+/// Damages are divided by this during a spellcard.
+// TODO: pytouhou mentions a division by 3 somewhere too.
+const SPELLCARD_DAMAGES_DIVISOR: u32 = 7;
+
+/// Whether two axis-aligned boxes, given by their center and half-size, overlap.
+fn overlaps(a: Position, a_half_size: [f32; 2], b: Position, b_half_size: [f32; 2]) -> bool {
+    (a.x - b.x).abs() <= a_half_size[0] + b_half_size[0]
+        && (a.y - b.y).abs() <= a_half_size[1] + b_half_size[1]
+}
+
+impl Game {
+    /// Check every collision of this frame, hitting the player, damaging enemies, counting
+    /// grazes and collecting items.
+    pub(crate) fn check_collisions(&mut self) {
+        let mut full_power = false;
+        if self.player.touchable {
+            full_power = self.check_player_collisions();
+        }
+        self.check_enemy_collisions();
+
+        if full_power {
+            // TODO: display “full power”.
+            self.change_bullets_into_star_items();
+        }
+    }
+
+    /// Returns whether the player just reached full power by collecting an item.
+    fn check_player_collisions(&mut self) -> bool {
+        let player = &mut self.player;
+        let pos = player.pos;
+        let half_size = player.sht().hitbox;
+        let graze_half_size = player.sht().graze_hitbox;
+
+        for laser in self.lasers.iter() {
+            if laser.is_lethal() && laser.collides(pos, half_size) {
+                player.hit();
+            } else if laser.is_grazable() && laser.collides(pos, graze_half_size) {
+                player.graze += 1;
+                player.score += GRAZE_SCORE;
+                self.difficulty.modify(6);
+            }
+        }
+
+        for bullet in self.bullets.iter_mut() {
+            if bullet.state != BulletState::Launched {
+                continue;
+            }
+            if overlaps(bullet.pos, bullet.hitbox, pos, [half_size, half_size]) {
+                bullet.cancel();
+                player.hit();
+            } else if !bullet.grazed && overlaps(bullet.pos, bullet.hitbox, pos, [graze_half_size, graze_half_size]) {
+                bullet.grazed = true;
+                player.graze += 1;
+                player.score += GRAZE_SCORE;
+                self.difficulty.modify(6);
+            }
+        }
+
+        // TODO: check the height.
+        if pos.y < player.sht().point_of_collection && player.power >= MAX_POWER {
+            let speed = player.sht().autocollection_speed;
+            self.autocollect(speed);
+        }
+
+        let player = &mut self.player;
+        let item_half_size = player.sht().item_hitbox;
+        let mut full_power = false;
+        for item in self.items.iter_mut() {
+            if !item.removed && overlaps(item.pos, [item_half_size, item_half_size], pos, [half_size, half_size]) {
+                full_power |= item.collect(player, &mut self.difficulty);
+            }
+        }
+        full_power
+    }
+
+    fn check_enemy_collisions(&mut self) {
+        let player = &mut self.player;
+        let player_half_size = player.sht().hitbox;
+        let spellcard = self.spellcard.is_some();
+        let mut deaths = vec![];
+        let mut bonuses = vec![];
+
+        for enemy in self.enemies.iter_mut() {
+            if enemy.removed {
+                continue;
+            }
+
+            if enemy.touchable {
+                let mut damages = 0;
+                for bullet in self.player_bullets.iter_mut() {
+                    if !bullet.removed && overlaps(bullet.pos, bullet.hitbox, enemy.pos, enemy.hitbox_half_size) {
+                        bullet.removed = true;
+                        damages += bullet.damage as u32;
+                    }
+                }
+                for laser in player.lasers() {
+                    if laser.collides(enemy.pos, enemy.hitbox_half_size) {
+                        damages += laser.damage as u32;
+                    }
+                }
+
+                // Only two thirds of the hitbox can hurt the player.
+                let [width, height] = enemy.hitbox_half_size;
+                let half_size = [width * 2. / 3., height * 2. / 3.];
+                if enemy.collidable && player.touchable && overlaps(enemy.pos, half_size, player.pos, [player_half_size, player_half_size]) {
+                    if !enemy.boss {
+                        damages += CONTACT_DAMAGES;
+                    }
+                    player.hit();
+                }
+
+                let damages = damages.min(MAX_DAMAGES);
+                player.score += damages / 5 * 10;
+                if enemy.damageable {
+                    // Any damage still removes at least one life point.
+                    let damages = if spellcard && damages > SPELLCARD_DAMAGES_DIVISOR {
+                        damages / SPELLCARD_DAMAGES_DIVISOR
+                    } else if spellcard {
+                        damages.min(1)
+                    } else {
+                        damages
+                    };
+                    enemy.life = enemy.life.saturating_sub(damages);
+                }
+            }
+
+            if enemy.life == 0 && enemy.touchable {
+                deaths.push((enemy.pos, enemy.death_anim));
+            }
+            if let Some(bonus) = enemy.handle_callbacks(player) {
+                bonuses.push((enemy.pos, bonus));
+            }
+        }
+
+        for (pos, death_anim) in deaths {
+            self.enemy_death_effect(pos, death_anim);
+        }
+        for (pos, bonus) in bonuses {
+            self.drop_enemy_bonus(pos, bonus);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::th06::bullet::Bullet;
+    use crate::th06::enemy::Enemy;
+    use crate::th06::item::{Item, ItemType};
+    use crate::th06::laser::{Laser, LaserAttributes};
+    use crate::th06::player::{Keys, Player, PlayerBullet};
+    use crate::th06::spellcard::Spellcard;
+    use crate::th06::testing::vulnerable_game;
+    use touhou_formats::th06::ecl::{BulletFlags, DeathFlags};
+    use touhou_formats::th06::sht::{Sht, Shot};
+
+    fn bullet(x: f32, y: f32) -> Bullet {
+        Bullet::new(Position::new(x, y), 0, 0, 0., 0., Default::default(), BulletFlags::empty())
+    }
+
+    #[test]
+    fn bullet_hits_player() {
+        let mut game = vulnerable_game();
+        game.bullets.push(bullet(193., 385.));
+        game.check_collisions();
+        assert_eq!(game.bullets[0].state, BulletState::Cancelled);
+        assert!(!game.player().is_vulnerable());
+    }
+
+    #[test]
+    fn graze() {
+        let mut game = vulnerable_game();
+        game.bullets.push(bullet(202., 384.));
+        game.check_collisions();
+        game.check_collisions();
+        assert!(game.bullets[0].grazed);
+        assert_eq!(game.player().graze, 1);
+        assert_eq!(game.player().score, GRAZE_SCORE);
+        assert!(game.player().is_vulnerable());
+    }
+
+    fn vertical_laser() -> LaserAttributes {
+        LaserAttributes {
+            angle: std::f32::consts::FRAC_PI_2,
+            end_offset: 500.,
+            max_length: 500.,
+            width: 16.,
+            duration: 100,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn laser_hits_player() {
+        let mut game = vulnerable_game();
+        let mut laser = Laser::new(Position::new(180., 0.), &vertical_laser());
+        laser.update();
+        let laser = game.lasers.insert(laser);
+        game.check_collisions();
+        assert!(game.player().is_vulnerable());
+
+        game.lasers[laser].set_base_pos(Position::new(190., 0.));
+        game.check_collisions();
+        assert!(!game.player().is_vulnerable());
+    }
+
+    #[test]
+    fn laser_uses_character_hitbox() {
+        let mut game = vulnerable_game();
+        let sht = Sht { hitbox: 10., ..Default::default() };
+        let mut player = Player::new(sht.clone(), sht);
+        player.invulnerable_time = 0;
+        game.set_player(player);
+        let mut laser = Laser::new(Position::new(180., 0.), &vertical_laser());
+        laser.update();
+        game.lasers.insert(laser);
+        game.check_collisions();
+        assert!(!game.player().is_vulnerable());
+    }
+
+    #[test]
+    fn shots_damage_enemy() {
+        let mut game = vulnerable_game();
+        let mut enemy = Enemy::new(Position::new(192., 100.), 100, 0, 1000, false, None);
+        enemy.set_hitbox(32., 32.);
+        enemy.low_life_trigger = Some(80);
+        enemy.low_life_callback = Some(2);
+        let enemy = game.enemies.insert(enemy);
+        let shot = Shot { hitbox: (16., 16.), damage: 12, ..Default::default() };
+
+        game.player_bullets.push(PlayerBullet::new(Position::new(200., 110.), &shot));
+        game.check_collisions();
+        assert!(game.player_bullets[0].removed);
+        assert_eq!(game.enemies[enemy].life, 88);
+        assert_eq!(game.enemies[enemy].pending_callback, None);
+
+        game.player_bullets.push(PlayerBullet::new(Position::new(180., 90.), &shot));
+        game.check_collisions();
+        assert_eq!(game.enemies[enemy].life, 76);
+        assert_eq!(game.player().score, 40);
+        assert_eq!(game.enemies[enemy].pending_callback, Some(2));
+        assert_eq!(game.enemies[enemy].low_life_trigger, None);
+    }
+
+    #[test]
+    fn spellcard_divides_damages() {
+        let mut game = vulnerable_game();
+        let enemy = game.enemies.insert(Enemy::new(Position::new(200., 100.), 100, 0, 1000, false, None));
+        game.enemies[enemy].set_hitbox(32., 32.);
+        game.spellcard = Some(Spellcard::new(0, 3, String::new()));
+
+        game.player_bullets.push(PlayerBullet::new(Position::new(200., 100.), &Shot { hitbox: (16., 16.), damage: 30, ..Default::default() }));
+        game.check_collisions();
+        assert_eq!(game.enemies[enemy].life, 96);
+        // The score doesn’t get divided.
+        assert_eq!(game.player().score, 60);
+
+        // Low damages still hurt.
+        game.player_bullets.push(PlayerBullet::new(Position::new(200., 100.), &Shot { hitbox: (16., 16.), damage: 3, ..Default::default() }));
+        game.check_collisions();
+        assert_eq!(game.enemies[enemy].life, 95);
+    }
+
+    #[test]
+    fn player_laser_damages_enemy() {
+        let mut game = vulnerable_game();
+        let above = game.enemies.insert(Enemy::new(Position::new(200., 100.), 100, 0, 1000, false, None));
+        let aside = game.enemies.insert(Enemy::new(Position::new(100., 100.), 100, 0, 1000, false, None));
+        let below = game.enemies.insert(Enemy::new(Position::new(192., 420.), 100, 0, 1000, false, None));
+        for enemy in game.enemies.iter_mut() {
+            enemy.set_hitbox(16., 16.);
+        }
+        let laser = Shot { interval: 40, hitbox: (8., 0.), angle: -std::f32::consts::FRAC_PI_2, damage: 3, shot_type: 3, ..Default::default() };
+        let mut sht = Sht::default();
+        sht.shots.insert(999, vec![laser]);
+        game.set_player(Player::new(sht.clone(), sht));
+        game.player.update(Keys::SHOOT, &mut game.player_bullets);
+
+        // It keeps hitting on every frame.
+        game.check_collisions();
+        game.check_collisions();
+        assert_eq!(game.enemies[above].life, 94);
+        assert_eq!(game.enemies[aside].life, 100);
+        assert_eq!(game.enemies[below].life, 100);
+    }
+
+    #[test]
+    fn enemy_touches_player() {
+        let mut game = vulnerable_game();
+        let mut enemy = Enemy::new(Position::new(192., 390.), 100, 0, 1000, false, None);
+        enemy.set_hitbox(32., 32.);
+        enemy.collidable = true;
+        let enemy = game.enemies.insert(enemy);
+        game.check_collisions();
+        assert_eq!(game.enemies[enemy].life, 100 - CONTACT_DAMAGES);
+        assert!(!game.player().is_vulnerable());
+    }
+
+    #[test]
+    fn boss_touches_player() {
+        let mut game = vulnerable_game();
+        let mut enemy = Enemy::new(Position::new(192., 390.), 100, 0, 1000, false, None);
+        enemy.set_hitbox(32., 32.);
+        enemy.collidable = true;
+        enemy.boss = true;
+        let enemy = game.enemies.insert(enemy);
+        game.check_collisions();
+        assert_eq!(game.enemies[enemy].life, 100);
+        assert!(!game.player().is_vulnerable());
+    }
+
+    #[test]
+    fn bomb() {
+        let mut game = vulnerable_game();
+        let inside = game.enemies.insert(Enemy::new(Position::new(100., 100.), 100, 0, 1000, false, None));
+        let outside = game.enemies.insert(Enemy::new(Position::new(100., -100.), 100, 0, 1000, false, None));
+        game.bullets.push(bullet(50., 50.));
+        game.run_frame(Keys::BOMB).unwrap();
+        assert!(game.player().is_bombing());
+        assert_eq!(game.bullets[0].state, BulletState::Cancelled);
+        // How much bombing lowers the difficulty isn’t known yet.
+        assert_eq!(game.difficulty(), 16);
+
+        // Bullets fired during the bomb get cancelled too.
+        game.bullets.push(bullet(50., 50.));
+        game.run_frame(Keys::empty()).unwrap();
+        assert_eq!(game.bullets[1].state, BulletState::Cancelled);
+
+        // The shape and damages of bombs aren’t known yet, so enemies don’t take any.
+        assert_eq!(game.enemies[inside].life, 100);
+        assert_eq!(game.enemies[outside].life, 100);
+    }
+
+    #[test]
+    fn enemy_death() {
+        let mut game = vulnerable_game();
+        let removed = Enemy::new(Position::new(50., 50.), 1, 0, 1000, false, None);
+        let mut staying = Enemy::new(Position::new(50., 50.), 1, 0, 200, false, None);
+        staying.death_flags = DeathFlags::STAY_UNTOUCHABLE;
+        staying.death_callback = Some(3);
+        let removed = game.enemies.insert(removed);
+        let staying = game.enemies.insert(staying);
+        for enemy in game.enemies.iter_mut() {
+            enemy.life = 0;
+        }
+        game.check_collisions();
+        assert!(game.enemies[removed].removed);
+        assert!(!game.enemies[staying].removed);
+        assert!(!game.enemies[staying].touchable);
+        assert_eq!(game.enemies[staying].pending_callback, Some(3));
+        assert_eq!(game.player().score, 1200);
+    }
+
+    #[test]
+    fn collect_items() {
+        let mut game = vulnerable_game();
+        game.player.power = MAX_POWER - 1;
+        game.items.push(Item::new(Position::new(200., 390.), ItemType::Power));
+        game.items.push(Item::new(Position::new(50., 50.), ItemType::Point));
+        game.bullets.push(bullet(50., 200.));
+        game.check_collisions();
+        assert!(game.items[0].removed);
+        assert_eq!(game.player().power, MAX_POWER);
+        assert!(game.bullets.is_empty());
+        assert_eq!(game.items.len(), 3);
+        assert_eq!(game.items[2].item_type, ItemType::Star);
+
+        game.player.pos = Position::new(50., 100.);
+        game.check_collisions();
+        game.items[1].update(Position::new(50., 100.));
+        assert_eq!(game.items[1].pos, Position::new(50., 58.));
+    }
+}
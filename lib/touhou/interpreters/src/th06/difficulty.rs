@@ -0,0 +1,97 @@
+//! Module providing the hidden difficulty of a game, rising while the player does well.
+
+use touhou_formats::th06::ecl::Rank;
+use touhou_utils::impl_snapshot;
+
+/// Difficulty at the start of a game, whatever the rank.
+const INITIAL_DIFFICULTY: i32 = 16;
+
+/// Progress needed to change the difficulty by one.
+const STEP: i32 = 100;
+
+/// Amount of frames after which the difficulty rises by one.
+const TIME_INTERVAL: u32 = 32 * 60;
+
+/// Difficulty value, from 10 to 32, scaling the bullets fired through their difficulty coefficients.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Difficulty {
+    value: i32,
+    counter: i32,
+    min: i32,
+    max: i32,
+    frame: u32,
+}
+
+impl_snapshot!(Difficulty { value, counter, min, max, frame });
+
+impl Difficulty {
+    /// Start at the initial difficulty, within the bounds of this rank.
+    pub(crate) fn new(rank: Rank) -> Difficulty {
+        let (min, max) = if rank == Rank::EASY { (12, 20) } else { (10, 32) };
+        Difficulty {
+            value: INITIAL_DIFFICULTY,
+            counter: 0,
+            min,
+            max,
+            frame: 0,
+        }
+    }
+
+    /// Returns the current difficulty.
+    pub(crate) fn value(&self) -> i32 {
+        self.value
+    }
+
+    /// Progress towards a higher (or lower if negative) difficulty, a hundred changing it by one.
+    pub(crate) fn modify(&mut self, diff: i32) {
+        self.counter += diff;
+        self.value += self.counter.div_euclid(STEP);
+        self.counter = self.counter.rem_euclid(STEP);
+        self.value = self.value.clamp(self.min, self.max);
+    }
+
+    /// Raise the difficulty over time, to be called once per frame.
+    pub(crate) fn update(&mut self) {
+        self.frame += 1;
+        if self.frame == TIME_INTERVAL {
+            self.frame = 0;
+            self.modify(STEP);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modify() {
+        let mut difficulty = Difficulty::new(Rank::NORMAL);
+        assert_eq!(difficulty.value(), 16);
+        difficulty.modify(250);
+        assert_eq!((difficulty.value(), difficulty.counter), (18, 50));
+        difficulty.modify(-51);
+        assert_eq!((difficulty.value(), difficulty.counter), (17, 99));
+        difficulty.modify(-1600);
+        assert_eq!(difficulty.value(), 10);
+        difficulty.modify(10000);
+        assert_eq!(difficulty.value(), 32);
+
+        let mut difficulty = Difficulty::new(Rank::EASY);
+        difficulty.modify(-1600);
+        assert_eq!(difficulty.value(), 12);
+        difficulty.modify(10000);
+        assert_eq!(difficulty.value(), 20);
+    }
+
+    #[test]
+    fn time() {
+        let mut difficulty = Difficulty::new(Rank::LUNATIC);
+        for _ in 0..TIME_INTERVAL * 3 - 1 {
+            difficulty.update();
+        }
+        assert_eq!(difficulty.value(), 18);
+        difficulty.update();
+        assert_eq!(difficulty.value(), 19);
+    }
+}
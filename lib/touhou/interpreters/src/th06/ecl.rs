@@ -1,29 +1,284 @@
 //! ECL runner.
 
-use touhou_formats::th06::ecl::{Ecl, SubInstruction};
-use crate::th06::enemy::{Enemy, Offset, BulletAttributes, Position};
-use touhou_utils::prng::Prng;
-use std::cell::RefCell;
-use std::rc::Rc;
+use touhou_formats::th06::ecl::{Ecl, Main, MainInstruction, SubInstruction, BulletFlags, DifficultyCoeffs};
+use crate::th06::enemy::{Enemy, Game, Offset, BulletAttributes, Position};
+use crate::th06::bullet::BulletState;
+use crate::th06::item::ItemType;
+use crate::th06::laser::{Laser, LaserAttributes};
+use crate::th06::player::MAX_POWER;
+use crate::th06::trace::{InstructionEvent, Value};
+use touhou_utils::arena::Id;
+use touhou_utils::impl_snapshot;
+use touhou_utils::snapshot::Error;
+use std::convert::TryFrom;
+use std::f32::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+
+/// Amount of frames which can be stacked by calls, as in the original engine.
+const MAX_STACK_DEPTH: usize = 8;
 
 macro_rules! gen_SetBulletAttributes {
-    ($self:ident, $opcode:tt, $anim:ident, $sprite_index_offset:ident, $bullets_per_shot:ident,
+    ($self:ident, $enemy:ident, $game:ident, $opcode:tt, $anim:ident, $sprite_index_offset:ident, $bullets_per_shot:ident,
      $number_of_shots:ident, $speed:ident, $speed2:ident, $launch_angle:ident, $angle:ident,
      $flags:ident) => {{
-        let sprite_index_offset = $self.get_i32($sprite_index_offset as i32) as i16;
-        let bullets_per_shot = $self.get_i32($bullets_per_shot) as i16;
-        let number_of_shots = $self.get_i32($number_of_shots) as i16;
-        let speed = $self.get_f32($speed);
-        let speed2 = $self.get_f32($speed2);
-        let launch_angle = $self.get_f32($launch_angle);
-        let angle = $self.get_f32($angle);
-
-        let mut enemy = $self.enemy.borrow_mut();
-        enemy.set_bullet_attributes($opcode, $anim, sprite_index_offset, bullets_per_shot,
-                                    number_of_shots, speed, speed2, launch_angle, angle, $flags);
+        let sprite_index_offset = $self.get_i32($sprite_index_offset as i32, $enemy, $game)? as i16;
+        let bullets_per_shot = $self.get_i32($bullets_per_shot, $enemy, $game)? as i16;
+        let number_of_shots = $self.get_i32($number_of_shots, $enemy, $game)? as i16;
+        let speed = $self.get_f32($speed, $enemy, $game)?;
+        let speed2 = $self.get_f32($speed2, $enemy, $game)?;
+        let launch_angle = $self.get_f32($launch_angle, $enemy, $game)?;
+        let angle = $self.get_f32($angle, $enemy, $game)?;
+
+        $enemy.set_bullet_attributes($opcode, $anim, sprite_index_offset, bullets_per_shot,
+                                    number_of_shots, speed, speed2, launch_angle, angle, $flags,
+                                    $game);
     }};
 }
 
+macro_rules! gen_NewLaser {
+    ($self:ident, $enemy:ident, $game:ident, $towards_player:expr, $laser_type:ident, $sprite_idx_offset:ident, $angle:ident,
+     $speed:ident, $start_offset:ident, $end_offset:ident, $max_length:ident, $width:ident,
+     $start_duration:ident, $duration:ident, $end_duration:ident, $grazing_delay:ident,
+     $grazing_extra_duration:ident) => {{
+        let attributes = LaserAttributes {
+            anim: $laser_type,
+            sprite_index_offset: $self.get_i32($sprite_idx_offset as i32, $enemy, $game)? as i16,
+            angle: $self.get_f32($angle, $enemy, $game)?,
+            speed: $self.get_f32($speed, $enemy, $game)?,
+            start_offset: $self.get_f32($start_offset, $enemy, $game)?,
+            end_offset: $self.get_f32($end_offset, $enemy, $game)?,
+            max_length: $self.get_f32($max_length, $enemy, $game)?,
+            width: $self.get_f32($width, $enemy, $game)?,
+            start_duration: $self.get_i32($start_duration, $enemy, $game)?,
+            duration: $self.get_i32($duration, $enemy, $game)?,
+            end_duration: $self.get_i32($end_duration, $enemy, $game)?,
+            grazing_delay: $self.get_i32($grazing_delay, $enemy, $game)?,
+            grazing_extra_duration: $self.get_i32($grazing_extra_duration, $enemy, $game)?,
+        };
+
+        $enemy.new_laser($towards_player, &attributes, $game);
+    }};
+}
+
+/// Kind of error which can happen while running an ECL script.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EclErrorKind {
+    /// This variable doesn’t exist.
+    UnknownVariable(i32),
+
+    /// This variable can only be read.
+    ReadOnlyVariable(i32),
+
+    /// The instruction with this opcode isn’t implemented.
+    UnknownOpcode(u16),
+
+    /// A call went deeper than the original engine allows.
+    StackOverflow,
+
+    /// A return happened outside of any call.
+    StackUnderflow,
+
+    /// This sub doesn’t exist.
+    InvalidSub(i32),
+
+    /// This instruction pointer is outside of the current sub.
+    InvalidJump(i32),
+
+    /// This special function doesn’t exist.
+    UnknownSpecialFunction(i32),
+
+    /// This memory write isn’t known to be done by any script.
+    UnknownMemoryWrite(i32),
+
+    /// There are only 8 auxiliary animations.
+    InvalidAuxAnm(i32),
+
+    /// The ANMs of this enemy don’t contain this script.
+    InvalidAnim(i32),
+
+    /// This sound doesn’t exist.
+    InvalidSound(i32),
+
+    /// The first frame of an enemy spawned by this instruction failed.
+    SpawnedEnemy(Box<EclError>),
+
+    /// Enemies can’t be spawned before a stage got loaded.
+    NoStage,
+
+    /// An integer division or modulo by zero.
+    DivisionByZero,
+}
+
+impl fmt::Display for EclErrorKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EclErrorKind::UnknownVariable(var) => write!(fmt, "unknown variable {}", var),
+            EclErrorKind::ReadOnlyVariable(var) => write!(fmt, "variable {} is read-only", var),
+            EclErrorKind::UnknownOpcode(opcode) => write!(fmt, "unknown opcode {}", opcode),
+            EclErrorKind::StackOverflow => write!(fmt, "more than {} nested calls", MAX_STACK_DEPTH),
+            EclErrorKind::StackUnderflow => write!(fmt, "return outside of any call"),
+            EclErrorKind::InvalidSub(sub) => write!(fmt, "invalid sub {}", sub),
+            EclErrorKind::InvalidJump(ip) => write!(fmt, "invalid jump to instruction {}", ip),
+            EclErrorKind::UnknownSpecialFunction(function) => write!(fmt, "unknown special function {}", function),
+            EclErrorKind::UnknownMemoryWrite(index) => write!(fmt, "unknown memory write at index {}", index),
+            EclErrorKind::InvalidAuxAnm(number) => write!(fmt, "invalid auxiliary animation {}", number),
+            EclErrorKind::InvalidAnim(script) => write!(fmt, "invalid animation {}", script),
+            EclErrorKind::InvalidSound(sound) => write!(fmt, "invalid sound {}", sound),
+            EclErrorKind::SpawnedEnemy(error) => write!(fmt, "spawned enemy failed: {}", error),
+            EclErrorKind::NoStage => write!(fmt, "no stage loaded"),
+            EclErrorKind::DivisionByZero => write!(fmt, "division by zero"),
+        }
+    }
+}
+
+/// Error which happened while running an ECL script, along with where it happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EclError {
+    /// Sub being run.
+    pub sub: u16,
+
+    /// Index of the instruction being run in this sub.
+    pub ip: i32,
+
+    /// Frame of this sub.
+    pub frame: i32,
+
+    /// What went wrong.
+    pub kind: EclErrorKind,
+}
+
+impl fmt::Display for EclError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{} (sub {}, instruction {}, frame {})", self.kind, self.sub, self.ip, self.frame)
+    }
+}
+
+impl std::error::Error for EclError {}
+
+/// Integer division which wraps on overflow, like the original engine.
+fn divide(a: i32, b: i32) -> Result<i32, EclErrorKind> {
+    if b == 0 {
+        return Err(EclErrorKind::DivisionByZero);
+    }
+    Ok(a.wrapping_div(b))
+}
+
+/// Integer modulo which wraps on overflow, like the original engine.
+fn modulo(a: i32, b: i32) -> Result<i32, EclErrorKind> {
+    if b == 0 {
+        return Err(EclErrorKind::DivisionByZero);
+    }
+    Ok(a.wrapping_rem(b))
+}
+
+/// Index of the rank of this game, from 0 for easy to 3 for lunatic.
+fn rank_index(game: &Game) -> u32 {
+    (game.get_rank().bits() >> 8).trailing_zeros()
+}
+
+/// Interpreter for the main timeline of a stage, spawning enemies.
+#[derive(Clone)]
+pub struct MainRunner {
+    main: Main,
+    frame: u16,
+    ip: usize,
+    boss_wait: bool,
+}
+
+impl_snapshot!(MainRunner, |game: Game| {
+    frame, ip, boss_wait,
+} {
+    main: game.ecl.as_ref().and_then(|ecl| ecl.mains.first()).cloned().ok_or(Error::MissingData("main"))?,
+});
+
+impl MainRunner {
+    /// Create a new main runner.
+    pub fn new(main: Main) -> MainRunner {
+        MainRunner {
+            main,
+            frame: 0,
+            ip: 0,
+            boss_wait: false,
+        }
+    }
+
+    /// Advance the main timeline of a single frame.
+    pub(crate) fn run_frame(&mut self, game: &mut Game) -> Result<(), EclError> {
+        if !game.has_boss() {
+            self.boss_wait = false;
+        }
+
+        while !self.boss_wait && !game.msg_wait {
+            let call = match self.main.instructions.get(self.ip) {
+                Some(call) => call.clone(),
+                None => break,
+            };
+            if call.time > self.frame {
+                break;
+            }
+            self.ip += 1;
+            if call.time == self.frame {
+                self.run_instruction(call.sub, call.instr, game)?;
+            }
+        }
+
+        if !self.boss_wait && !game.msg_wait {
+            self.frame += 1;
+        }
+        Ok(())
+    }
+
+    fn run_instruction(&mut self, sub: u16, instruction: MainInstruction, game: &mut Game) -> Result<(), EclError> {
+        let (x, y, life, bonus_dropped, die_score, mirror, random) = match instruction {
+            MainInstruction::SpawnEnemy(x, y, _z, life, bonus_dropped, die_score) =>
+                (x, y, life, bonus_dropped, die_score, false, false),
+            MainInstruction::SpawnEnemyMirrored(x, y, _z, life, bonus_dropped, die_score) =>
+                (x, y, life, bonus_dropped, die_score, true, false),
+            MainInstruction::SpawnEnemyRandom(x, y, _z, life, bonus_dropped, die_score) =>
+                (x, y, life, bonus_dropped, die_score, false, true),
+            MainInstruction::SpawnEnemyMirroredRandom(x, y, _z, life, bonus_dropped, die_score) =>
+                (x, y, life, bonus_dropped, die_score, true, true),
+            MainInstruction::WaitForBossDeath() => {
+                self.boss_wait = true;
+                return Ok(());
+            }
+            MainInstruction::CallMessage() => {
+                game.start_msg(sub);
+                return Ok(());
+            }
+            MainInstruction::WaitMessage() => {
+                game.wait_msg();
+                return Ok(());
+            }
+            MainInstruction::ResumeEcl(_, _) => {
+                // The boss fight starts, even if the dialogue got frozen instead of ended.
+                game.end_msg();
+                game.resume_boss();
+                return Ok(());
+            }
+        };
+
+        // Enemies aren’t spawned while a boss is alive.
+        if game.has_boss() {
+            return Ok(());
+        }
+
+        let mut pos = Position::new(x, y);
+        if random {
+            let prng = &mut game.prng;
+            if x < -990. {
+                pos.x = prng.get_f64() as f32 * 368.;
+            }
+            if y < -990. {
+                pos.y = prng.get_f64() as f32 * 416.;
+            }
+        }
+        game.spawn_enemy(sub, pos, life, bonus_dropped, die_score, mirror)?;
+        Ok(())
+    }
+}
+
 #[derive(Clone, Default)]
 struct StackFrame {
     frame: i32,
@@ -36,39 +291,108 @@ struct StackFrame {
     sub: u16,
 }
 
+impl_snapshot!(StackFrame { frame, ip, ints1, floats, ints2, comparison_reg, sub });
+
 /// Interpreter for enemy scripts.
-#[derive(Default)]
+#[derive(Clone)]
 pub struct EclRunner {
-    /// XXX
-    pub enemy: Rc<RefCell<Enemy>>,
+    /// The enemy driven by this script.
+    pub(crate) enemy: Id,
 
-    ecl: Option<Ecl>,
+    ecl: Arc<Ecl>,
     /// XXX
     pub running: bool,
     frame: StackFrame,
-    // TODO: there are only 8 of these.
+    // Never deeper than MAX_STACK_DEPTH.
     stack: Vec<StackFrame>,
+    // Variables written by the current instruction, only while traced.
+    writes: Option<Vec<(i32, Value)>>,
 }
 
+impl_snapshot!(EclRunner, |game: Game| {
+    enemy, running, frame, stack,
+} {
+    ecl: game.ecl.clone().ok_or(Error::MissingData("ecl"))?,
+    writes: None,
+});
+
 impl EclRunner {
     /// Create a new ECL runner.
-    pub fn new(ecl: &Ecl, enemy: Rc<RefCell<Enemy>>, sub: u16) -> EclRunner {
+    pub fn new(ecl: Arc<Ecl>, enemy: Id, sub: u16) -> EclRunner {
         let mut ecl_runner = EclRunner {
             enemy,
-            // XXX: no clone.
-            ecl: Some(ecl.clone()),
+            ecl,
             running: true,
-            ..Default::default()
+            frame: StackFrame::default(),
+            stack: Vec::new(),
+            writes: None,
         };
         ecl_runner.frame.sub = sub;
         ecl_runner
     }
 
+    /// Switch to another sub, keeping the current variables but dropping the call stack.
+    fn switch_to_sub(&mut self, sub: i32) -> Result<(), EclErrorKind> {
+        let sub = self.check_sub(sub)?;
+        self.stack.clear();
+        self.frame.sub = sub;
+        self.frame.ip = 0;
+        self.frame.frame = 0;
+        self.running = true;
+        Ok(())
+    }
+
+    /// Returns this sub index if it exists in the ECL.
+    fn check_sub(&self, sub: i32) -> Result<u16, EclErrorKind> {
+        if sub < 0 || sub as usize >= self.ecl.subs.len() {
+            return Err(EclErrorKind::InvalidSub(sub));
+        }
+        Ok(sub as u16)
+    }
+
+    /// Attach the current position in the script to this error.
+    fn error(&self, ip: i32, kind: EclErrorKind) -> EclError {
+        EclError {
+            sub: self.frame.sub,
+            ip,
+            frame: self.frame.frame,
+            kind,
+        }
+    }
+
     /// Advance the ECL of a single frame.
-    pub fn run_frame(&mut self) {
+    ///
+    /// The enemy is taken out of the game meanwhile, so that both can be modified.  On error, this
+    /// runner stops.
+    pub fn run_frame(&mut self, game: &mut Game) -> Result<(), EclError> {
+        if let Some(mut enemy) = game.enemies.take(self.enemy) {
+            let result = self.run_enemy_frame(&mut enemy, game);
+            game.enemies.put_back(self.enemy, enemy);
+            if let Err(error) = &result {
+                self.running = false;
+                if let Some(tracer) = &game.tracer {
+                    tracer.lock().unwrap().error(self.enemy, error);
+                }
+            }
+            return result;
+        }
+        Ok(())
+    }
+
+    fn run_enemy_frame(&mut self, enemy: &mut Enemy, game: &mut Game) -> Result<(), EclError> {
+        let callback = enemy.pending_callback.take();
+        if let Some(sub) = callback {
+            self.switch_to_sub(sub).map_err(|kind| self.error(self.frame.ip, kind))?;
+        }
+
+        // Only the Arcs get cloned, so that the instructions can be borrowed while running them.
+        let ecl = self.ecl.clone();
+        let tracer = game.tracer.clone();
         while self.running {
-            let ecl = self.ecl.clone().unwrap();
-            let sub = &ecl.subs[self.frame.sub as usize];
+            let sub = match ecl.subs.get(self.frame.sub as usize) {
+                Some(sub) => sub,
+                None => return Err(self.error(self.frame.ip, EclErrorKind::InvalidSub(self.frame.sub as i32))),
+            };
             let call = match sub.instructions.get(self.frame.ip as usize) {
                 Some(call) => call,
                 None => {
@@ -82,21 +406,79 @@ impl EclRunner {
             }
             self.frame.ip += 1;
 
-            let rank = self.enemy.borrow().get_rank();
+            let rank = game.get_rank();
             if (call.rank_mask & rank).is_empty() {
                 continue;
             }
 
             if call.time == self.frame.frame {
-                self.run_instruction(call.instr.clone());
+                // Calls replace the current frame, so the error is located beforehand.
+                let (sub, ip, frame) = (self.frame.sub, self.frame.ip - 1, self.frame.frame);
+                let mut event = InstructionEvent { enemy: self.enemy, sub, ip, frame, instruction: &call.instr, writes: &[] };
+                if let Some(tracer) = &tracer {
+                    tracer.lock().unwrap().before(&event, enemy, game);
+                    self.writes = Some(Vec::new());
+                }
+                let result = self.run_instruction(&call.instr, enemy, game);
+                if let Some(tracer) = &tracer {
+                    let writes = self.writes.take().unwrap_or_default();
+                    event.writes = &writes;
+                    tracer.lock().unwrap().after(&event);
+                }
+                result.map_err(|kind| EclError { sub, ip, frame, kind })?;
+            }
+        }
+        // Set by 122, it keeps running even once the sub is over.
+        if let Some(function) = enemy.special_callback {
+            // A failing callback is only reported once.
+            if let Err(kind) = self.call_special_function(function, 0, enemy, game) {
+                enemy.special_callback = None;
+                return Err(self.error(self.frame.ip, kind));
             }
         }
         self.frame.frame += 1;
+        Ok(())
+    }
+
+    /// Value of the EoSD global variables, the ones from -10013 to -10025.
+    ///
+    /// They are all read-only, except for the position, frame and life of the enemy, the last two
+    /// never going below zero.
+    fn get_global(var: i32, enemy: &Enemy, game: &Game) -> Result<f32, EclErrorKind> {
+        Ok(match var {
+            // Index of the rank, from 0 for easy to 3 for lunatic.
+            -10013 => rank_index(game) as f32,
+            -10014 => game.difficulty() as f32,
+            -10015 => enemy.pos.x,
+            -10016 => enemy.pos.y,
+            -10017 => enemy.z,
+            -10018 => game.player.pos.x,
+            -10019 => game.player.pos.y,
+            // The player always stays on the same plane.
+            -10020 => 0.,
+            -10021 => enemy.get_angle_to(game.player.pos),
+            -10022 => enemy.frame as f32,
+            -10024 => enemy.life as f32,
+            -10025 => game.player.character as f32,
+            _ => return Err(EclErrorKind::UnknownVariable(var)),
+        })
     }
 
-    fn get_i32(&self, var: i32) -> i32 {
-        let enemy = self.enemy.borrow();
+    fn set_global(var: i32, value: f32, enemy: &mut Enemy) -> Result<(), EclErrorKind> {
         match var {
+            -10015 => enemy.pos.x = value,
+            -10016 => enemy.pos.y = value,
+            -10017 => enemy.z = value,
+            -10022 => enemy.frame = value.max(0.) as u32,
+            -10024 => enemy.life = value.max(0.) as u32,
+            -10013 | -10014 | -10018 | -10019 | -10020 | -10021 | -10025 => return Err(EclErrorKind::ReadOnlyVariable(var)),
+            _ => return Err(EclErrorKind::UnknownVariable(var)),
+        }
+        Ok(())
+    }
+
+    fn get_i32(&self, var: i32, enemy: &Enemy, game: &Game) -> Result<i32, EclErrorKind> {
+        Ok(match var {
             -10001 => self.frame.ints1[0],
             -10002 => self.frame.ints1[1],
             -10003 => self.frame.ints1[2],
@@ -109,26 +491,15 @@ impl EclRunner {
             -10010 => self.frame.ints2[1],
             -10011 => self.frame.ints2[2],
             -10012 => self.frame.ints2[3],
-            -10013 => enemy.get_rank().bits() as i32,
-            -10014 => enemy.get_difficulty(),
-            -10015 => enemy.pos.x as i32,
-            -10016 => enemy.pos.y as i32,
-            -10017 => enemy.z as i32,
-            -10018 => unimplemented!(),
-            -10019 => unimplemented!(),
-            -10020 => unreachable!(),
-            -10021 => unimplemented!(),
             -10022 => enemy.frame as i32,
-            -10023 => unreachable!(),
             -10024 => enemy.life as i32,
-            -10025 => unimplemented!(),
+            -10025..=-10013 => EclRunner::get_global(var, enemy, game)? as i32,
             _ => var
-        }
+        })
     }
 
-    fn get_f32(&self, var: f32) -> f32 {
-        let enemy = self.enemy.borrow();
-        match var {
+    fn get_f32(&self, var: f32, enemy: &Enemy, game: &Game) -> Result<f32, EclErrorKind> {
+        Ok(match var {
             -10001.0 => self.frame.ints1[0] as f32,
             -10002.0 => self.frame.ints1[1] as f32,
             -10003.0 => self.frame.ints1[2] as f32,
@@ -141,105 +512,316 @@ impl EclRunner {
             -10010.0 => self.frame.ints2[1] as f32,
             -10011.0 => self.frame.ints2[2] as f32,
             -10012.0 => self.frame.ints2[3] as f32,
-            -10013.0 => enemy.get_rank().bits() as f32,
-            -10014.0 => enemy.get_difficulty() as f32,
-            -10015.0 => enemy.pos.x,
-            -10016.0 => enemy.pos.y,
-            -10017.0 => enemy.z,
-            -10018.0 => unimplemented!(),
-            -10019.0 => unimplemented!(),
-            -10020.0 => unreachable!(),
-            -10021.0 => unimplemented!(),
-            -10022.0 => enemy.frame as f32,
-            -10023.0 => unreachable!(),
-            -10024.0 => enemy.life as f32,
-            -10025.0 => unimplemented!(),
+            _ if (-10025.0..=-10013.0).contains(&var) && var.fract() == 0. => EclRunner::get_global(var as i32, enemy, game)?,
             _ => var
-        }
+        })
     }
 
-    fn set_i32(&mut self, var: i32, value: i32) {
-        let mut enemy = self.enemy.borrow_mut();
+    fn set_i32(&mut self, var: i32, value: i32, enemy: &mut Enemy) -> Result<(), EclErrorKind> {
         match var {
             -10001 => self.frame.ints1[0] = value,
             -10002 => self.frame.ints1[1] = value,
             -10003 => self.frame.ints1[2] = value,
             -10004 => self.frame.ints1[3] = value,
-            -10005 => unimplemented!(),
-            -10006 => unimplemented!(),
-            -10007 => unimplemented!(),
-            -10008 => unimplemented!(),
+            -10005 => self.frame.floats[0] = value as f32,
+            -10006 => self.frame.floats[1] = value as f32,
+            -10007 => self.frame.floats[2] = value as f32,
+            -10008 => self.frame.floats[3] = value as f32,
             -10009 => self.frame.ints2[0] = value,
             -10010 => self.frame.ints2[1] = value,
             -10011 => self.frame.ints2[2] = value,
             -10012 => self.frame.ints2[3] = value,
-            -10013 => unreachable!(),
-            -10014 => unreachable!(),
-            -10015 => unimplemented!(),
-            -10016 => unimplemented!(),
-            -10017 => unimplemented!(),
-            -10018 => unreachable!(),
-            -10019 => unreachable!(),
-            -10020 => unreachable!(),
-            -10021 => unreachable!(),
-            -10022 => enemy.frame = value as u32,
-            -10023 => unreachable!(),
-            -10024 => enemy.life = value as u32,
-            -10025 => unreachable!(),
-            _ => panic!("Unknown variable {}", var)
+            -10022 => enemy.frame = value.max(0) as u32,
+            -10024 => enemy.life = value.max(0) as u32,
+            _ => EclRunner::set_global(var, value as f32, enemy)?,
+        }
+        if let Some(writes) = self.writes.as_mut() {
+            writes.push((var, Value::Int(value)));
         }
+        Ok(())
     }
 
-    fn set_f32(&mut self, var: f32, value: f32) {
-        let mut enemy = self.enemy.borrow_mut();
+    fn set_f32(&mut self, var: f32, value: f32, enemy: &mut Enemy) -> Result<(), EclErrorKind> {
         match var {
-            -10001.0 => unimplemented!(),
-            -10002.0 => unimplemented!(),
-            -10003.0 => unimplemented!(),
-            -10004.0 => unimplemented!(),
+            -10001.0 => self.frame.ints1[0] = value as i32,
+            -10002.0 => self.frame.ints1[1] = value as i32,
+            -10003.0 => self.frame.ints1[2] = value as i32,
+            -10004.0 => self.frame.ints1[3] = value as i32,
             -10005.0 => self.frame.floats[0] = value,
             -10006.0 => self.frame.floats[1] = value,
             -10007.0 => self.frame.floats[2] = value,
             -10008.0 => self.frame.floats[3] = value,
-            -10009.0 => unimplemented!(),
-            -10010.0 => unimplemented!(),
-            -10011.0 => unimplemented!(),
-            -10012.0 => unimplemented!(),
-            -10013.0 => unreachable!(),
-            -10014.0 => unreachable!(),
-            -10015.0 => enemy.pos.x = value,
-            -10016.0 => enemy.pos.y = value,
-            -10017.0 => enemy.z = value,
-            -10018.0 => unreachable!(),
-            -10019.0 => unreachable!(),
-            -10020.0 => unreachable!(),
-            -10021.0 => unreachable!(),
-            -10022.0 => unimplemented!(),
-            -10023.0 => unreachable!(),
-            -10024.0 => unimplemented!(),
-            -10025.0 => unreachable!(),
-            _ => panic!("Unknown variable {}", var)
+            -10009.0 => self.frame.ints2[0] = value as i32,
+            -10010.0 => self.frame.ints2[1] = value as i32,
+            -10011.0 => self.frame.ints2[2] = value as i32,
+            -10012.0 => self.frame.ints2[3] = value as i32,
+            _ if var.fract() == 0. => EclRunner::set_global(var as i32, value, enemy)?,
+            _ => return Err(EclErrorKind::UnknownVariable(var as i32)),
+        }
+        if let Some(writes) = self.writes.as_mut() {
+            writes.push((var as i32, Value::Float(value)));
         }
+        Ok(())
     }
 
-    fn get_prng(&mut self) -> Rc<RefCell<Prng>> {
-        let enemy = self.enemy.borrow();
-        enemy.prng.upgrade().unwrap()
+    /// Run one of the special functions of EoSD, once from 121 or on each frame from 122.
+    ///
+    /// The game doesn’t pass any argument to the ones it runs on each frame, they get 0 instead.
+    fn call_special_function(&mut self, function: i32, arg: i32, enemy: &mut Enemy, game: &mut Game) -> Result<(), EclErrorKind> {
+        match function {
+            0 => { // Cirno’s frozen bullets
+                //game.drop_particle(12, enemy.pos, 1, 0xffffffff);
+                for bullet in game.bullets.iter_mut() {
+                    //game.new_effect(bullet.sprite, TODO);
+                    if arg == 0 {
+                        bullet.speed = 0.;
+                        bullet.dpos = [0., 0., 0.];
+                    } else if arg == 1 {
+                        bullet.flags |= BulletFlags::ACCELERATION;
+                        bullet.attributes.0 = bullet.frame + 220;
+                        let rand_angle = game.prng.get_f64() * 2. * std::f64::consts::PI - std::f64::consts::PI;
+                        bullet.attributes.4 = 0.01;
+                        bullet.attributes.5 = rand_angle as f32;
+                    }
+                }
+            }
+            1 => {
+                let range_x = arg as f64;
+                let range_y = (arg as f32 * 0.75) as f64;
+                let rand_x = game.prng.get_f64();
+                let rand_y = game.prng.get_f64();
+                let pos = [rand_x * range_x + enemy.pos.x as f64 - range_x / 2.,
+                           rand_y * range_y + enemy.pos.y as f64 - range_y / 2.];
+                enemy.bullet_attributes.pos = Position::new(pos[0] as f32, pos[1] as f32);
+                enemy.bullet_attributes.fire(game);
+            }
+            3 => { // Patchouli’s dual sign spellcard selector
+                const KNOWLEDGE: [[i32; 3]; 4] =
+                    [[0, 3, 1],
+                     [2, 3, 4],
+                     [1, 4, 0],
+                     [4, 2, 3]];
+
+                let character = game.player.character as usize % KNOWLEDGE.len();
+                self.frame.ints1[1..].copy_from_slice(&KNOWLEDGE[character]);
+            }
+            4 => { // Sakuya’s time stop and random daggers
+                if arg < 2 {
+                    //drop_particle(&PARTICLES_ARRAY,0xc,enemy->pos,1,0xffffffff);
+                    game.time_stop = arg == 1;
+                    return Ok(());
+                }
+                // This changes the orientation of random bullets.
+                let hard = rank_index(game) >= 2;
+                let mut max_bullets = if hard { 52 } else { 14 };
+                let player = game.player.pos;
+                for bullet in game.bullets.iter_mut() {
+                    // TODO: only the bullets at least 30 pixels tall, the daggers, get redirected.
+                    if !bullet.is_active() || bullet.manipulated || game.prng.get_u16() & 3 != 0 {
+                        continue;
+                    }
+                    bullet.manipulated = true;
+                    // Offset from the player to the bullet.
+                    let offset = player - bullet.pos;
+                    let rand = game.prng.get_f64() as f32;
+                    bullet.angle = if offset.dx.hypot(offset.dy) > 128. {
+                        if hard {
+                            rand * 2. * PI
+                        } else {
+                            rand * (PI / 8. * 6.) + PI / 4.
+                        }
+                    } else {
+                        // TODO: check player_get_angle_to, might be what ST0 is.
+                        offset.dy.atan2(offset.dx) + PI / 2. + rand * 2. * PI
+                    };
+                    bullet.dpos[0] = bullet.angle.cos() * bullet.speed;
+                    bullet.dpos[1] = bullet.angle.sin() * bullet.speed;
+                    max_bullets -= 1;
+                    if max_bullets == 0 {
+                        break;
+                    }
+                }
+                self.frame.ints1[2] = 0;
+            }
+            7 => { // Remilia’s laser webs
+                // 16 lines of three segments each, either lasers or bullets depending on the
+                // argument.
+                let base_angle = game.prng.get_f64() as f32 * 2. * PI;
+                for i in 0..16 {
+                    let delta = if i % 2 == 0 { PI / 4. } else { -PI / 4. };
+                    let mut angle = base_angle + i as f32 * PI / 8.;
+                    let mut pos = enemy.pos + enemy.bullet_offset + Offset::new(angle.cos() * 32., angle.sin() * 32.);
+                    // TODO: check the lengths, and the laser attributes.
+                    for (segment, &length) in [112., 112., 400.].iter().enumerate() {
+                        if segment > 0 {
+                            pos = pos + Offset::new(angle.cos() * 112., angle.sin() * 112.);
+                            angle += delta;
+                        }
+                        if arg == 0 {
+                            let attributes = LaserAttributes {
+                                anim: 1,
+                                sprite_index_offset: 1,
+                                angle,
+                                end_offset: length,
+                                max_length: length,
+                                width: 30.,
+                                start_duration: 100,
+                                duration: 80,
+                                end_duration: 15,
+                                ..Default::default()
+                            };
+                            enemy.new_laser_at(pos, false, &attributes, game);
+                        } else {
+                            enemy.bullet_attributes.pos = pos;
+                            enemy.bullet_attributes.fire(game);
+                        }
+                    }
+                }
+            }
+            8 => { // Vampire Fantasy
+                let n = {
+                    // TODO: also check for (30. <= (bullet.sprites[0].additional_infos).height).
+                    let positions: Vec<Position> = game.bullets.iter()
+                        .filter(|bullet| bullet.state != BulletState::Cancelled)
+                        .map(|bullet| bullet.pos)
+                        .collect();
+                    for &pos in positions.iter() {
+                        let random = game.prng.get_f64();
+                        let launch_angle = (random * (2. * std::f64::consts::PI) - std::f64::consts::PI) as f32;
+                        let attribs = BulletAttributes {
+                            // TODO: check if the z value of this pos is really used.
+                            pos,
+                            anim: 3,
+                            sprite_index_offset: 1,
+                            launch_angle,
+                            speed: 0.,
+                            angle: 0.,
+                            speed2: 0.,
+                            bullets_per_shot: 1,
+                            number_of_shots: 1,
+                            flags: BulletFlags::LAUNCH_ANIM8,
+                            bullet_type: 1,
+                            extended_attributes: Default::default(),
+                            sound: None,
+                        };
+                        attribs.fire(game);
+                    }
+                    positions.len()
+                };
+                //TODO: this variable might not always be correct! it uses the argument in
+                //th06: *(int *)(param_1 + 0x9b0) = local_60;
+                self.set_i32(-10004, n as i32, enemy)?;
+            }
+            9 | 11 => { // Remilia’s stopped bullets starting again
+                //TODO: the game does that
+                //drop_particle(&PARTICLES_ARRAY,0xc,enemy->pos,1,0xffffffff);
+                //self._game.new_effect((enemy.x, enemy.y), 17)
+                let rnd = game.prng.get_f64() as f32;
+                for bullet in game.bullets.iter_mut() {
+                    // TODO: also check for (30. <= (bullet.sprites[0].additional_infos).height).
+                    if !bullet.is_active() || bullet.speed != 0. {
+                        continue;
+                    }
+                    //TODO: reverse the effect of bullet->field_0x5ba = 2.
+                    bullet.flags |= BulletFlags::ACCELERATION;
+                    bullet.manipulated = true;
+                    bullet.speed = 0.01;
+                    let angle = if function == 9 {
+                        // Bullets further away get a bigger angle, forming a spiral.
+                        let offset = enemy.pos - bullet.pos;
+                        let distance = offset.dx.hypot(offset.dy);
+                        let distance = if distance > 0.01 { distance.sqrt() } else { 0. };
+                        distance * PI / 256. + (rnd * 2. * PI - PI)
+                    } else {
+                        game.prng.get_f64() as f32 * 2. * PI - PI
+                    };
+                    bullet.attributes.0 = bullet.frame + 120;
+                    bullet.attributes.4 = 0.01;
+                    bullet.attributes.5 = angle;
+                }
+            }
+            12 => { // Patchouli’s bullets fired around her lasers, on each frame
+                let lasers: Vec<f32> = enemy.laser_by_id.values()
+                    .filter_map(|&laser| game.lasers.get(laser))
+                    .take(8)
+                    .map(|laser| laser.angle)
+                    .collect();
+                for angle in lasers {
+                    let x = angle.cos() * 64. + enemy.pos.x;
+                    // Yes, it reads x after it has been modified, and yes, this most likely is a
+                    // bug.
+                    let y = angle.cos() * x + enemy.pos.y;
+                    enemy.bullet_attributes.pos = Position::new(x, y);
+                    enemy.bullet_attributes.fire(game);
+                }
+            }
+            13 => {
+                let frame = self.frame.ints1[3];
+                self.frame.ints1[3] += 1;
+                if frame % 6 == 0 && arg > 0 {
+                    //TODO: distance is obtained directly by copying bullet attributes in memory.
+                    let distance = self.frame.floats[3];
+                    let mut attributes = enemy.bullet_attributes.clone();
+                    for i in 0..arg {
+                        let angle = i as f32 * 2. * PI / arg as f32 + self.frame.floats[2];
+                        attributes.pos = Position::new(192. + angle.cos() * distance, 224. + angle.sin() * distance);
+                        attributes.launch_angle = self.frame.floats[1] + angle;
+                        attributes.fire(game);
+                    }
+                }
+            }
+            14 => { // Lävatein
+                self.frame.ints1[3] = 0;
+                let lasers: Vec<_> = enemy.laser_by_id.values()
+                    .filter_map(|&laser| game.lasers.get(laser))
+                    .map(Laser::bullets_pos)
+                    .collect();
+                for positions in lasers {
+                    //TODO: the game checks for laser end_offset before firing
+                    for pos in positions {
+                        enemy.bullet_attributes.pos = pos;
+                        enemy.bullet_attributes.fire(game);
+                    }
+                    self.frame.ints1[3] += 1;
+                }
+            }
+            16 => { // QED: Ripples of 495 years
+                if arg == 0 {
+                    self.frame.floats[3] = 2. - (enemy.life as f32) / 6000.;
+                    self.frame.ints2[1] = ((enemy.life * 240) / 6000 + 40) as i32;
+                } else {
+                    let fx = (320. - ((enemy.life as f32) * 160.) / 6000.) as f64;
+                    let fy = (128. - ((enemy.life as f32) * 64.) / 6000.) as f64;
+                    let rand_x = game.prng.get_f64();
+                    let rand_y = game.prng.get_f64();
+                    self.frame.floats[2] = (rand_x * fx + (192. - fx / 2.)) as f32;
+                    self.frame.floats[3] = (rand_y * fy + (96. - fy / 2.)) as f32;
+                }
+            }
+            // TODO: Meiling’s (2), Sakuya’s (5), Remilia’s and Flandre’s (6, 10, 15) functions
+            // haven’t been reversed yet, neither in pytouhou, so they do nothing for now and the
+            // enemy keeps running its script.
+            2 | 5 | 6 | 10 | 15 => (),
+            _ => return Err(EclErrorKind::UnknownSpecialFunction(function)),
+        }
+        Ok(())
     }
 
-    fn run_instruction(&mut self, instruction: SubInstruction) {
-        println!("Running instruction {:?}", instruction);
-        match instruction {
+    fn run_instruction(&mut self, instruction: &SubInstruction, enemy: &mut Enemy, game: &mut Game) -> Result<(), EclErrorKind> {
+        match *instruction {
             SubInstruction::Noop() => {
                 // really
             }
             // 1
             SubInstruction::Destroy(_unused) => {
-                let mut enemy = self.enemy.borrow_mut();
                 enemy.removed = true;
             }
             // 2
             SubInstruction::RelativeJump(frame, ip) => {
+                let len = self.ecl.subs[self.frame.sub as usize].instructions.len();
+                if ip < 0 || ip as usize > len {
+                    return Err(EclErrorKind::InvalidJump(ip));
+                }
                 self.frame.frame = frame;
                 // ip = ip + flag in th06
                 self.frame.ip = ip;
@@ -256,133 +838,132 @@ impl EclRunner {
             // only the pointer is copied, not the value, thus we are safe
             SubInstruction::RelativeJumpEx(frame, ip, var_id) => {
                 // TODO: counter_value is a field of "enemy" in th06, to check
-                let counter_value = self.get_i32(var_id) - 1;
+                let counter_value = self.get_i32(var_id, enemy, game)? - 1;
                 if counter_value > 0 {
-                    self.run_instruction(SubInstruction::RelativeJump(frame, ip));
+                    self.set_i32(var_id, counter_value, enemy)?;
+                    self.run_instruction(&SubInstruction::RelativeJump(frame, ip), enemy, game)?;
                 }
             }
             // 4
             SubInstruction::SetInt(var_id, value) => {
-                self.set_i32(var_id, value);
+                self.set_i32(var_id, value, enemy)?;
             }
             // 5
             SubInstruction::SetFloat(var_id, value) => {
-                self.set_f32(var_id as f32, value);
+                self.set_f32(var_id as f32, value, enemy)?;
             }
             // 6
             SubInstruction::SetRandomInt(var_id, maxval) => {
-                let random = self.get_prng().borrow_mut().get_u32() as i32;
-                self.set_i32(var_id, random % self.get_i32(maxval));
+                let random = game.prng.get_u32() as i32;
+                self.set_i32(var_id, modulo(random, self.get_i32(maxval, enemy, game)?)?, enemy)?;
             }
             // 7
             SubInstruction::SetRandomIntMin(var_id, maxval, minval) => {
-                let random = self.get_prng().borrow_mut().get_u32() as i32;
-                self.set_i32(var_id, (random % self.get_i32(maxval)) + self.get_i32(minval));
+                let random = game.prng.get_u32() as i32;
+                let random = modulo(random, self.get_i32(maxval, enemy, game)?)?;
+                self.set_i32(var_id, random.wrapping_add(self.get_i32(minval, enemy, game)?), enemy)?;
             }
             // 8
             SubInstruction::SetRandomFloat(var_id, maxval) => {
-                let random = self.get_prng().borrow_mut().get_f64() as f32;
-                self.set_f32(var_id as f32, self.get_f32(maxval) * random)
+                let random = game.prng.get_f64() as f32;
+                self.set_f32(var_id as f32, self.get_f32(maxval, enemy, game)? * random, enemy)?
             }
             // 9
             SubInstruction::SetRandomFloatMin(var_id, maxval, minval) => {
-                let random = self.get_prng().borrow_mut().get_f64() as f32;
-                self.set_f32(var_id as f32, self.get_f32(maxval) * random + self.get_f32(minval))
+                let random = game.prng.get_f64() as f32;
+                self.set_f32(var_id as f32, self.get_f32(maxval, enemy, game)? * random + self.get_f32(minval, enemy, game)?, enemy)?
             }
             // 10
             SubInstruction::StoreX(var_id) => {
                 let x = {
-                    let enemy = self.enemy.borrow();
                     enemy.pos.x
                 };
                 // TODO: is this really an i32?
-                self.set_i32(var_id, x as i32);
+                self.set_i32(var_id, x as i32, enemy)?;
             }
             // 11
             SubInstruction::StoreY(var_id) => {
                 let y = {
-                    let enemy = self.enemy.borrow();
                     enemy.pos.y
                 };
-                self.set_i32(var_id, y as i32);
+                self.set_i32(var_id, y as i32, enemy)?;
             }
             // 12
             SubInstruction::StoreZ(var_id) => {
                 let z = {
-                    let enemy = self.enemy.borrow();
                     enemy.z
                 };
-                self.set_i32(var_id, z as i32);
+                self.set_i32(var_id, z as i32, enemy)?;
             }
             // 13(int), 20(float), same impl in th06
             SubInstruction::AddInt(var_id, a, b) => {
-                self.set_i32(var_id, self.get_i32(a) + self.get_i32(b));
+                self.set_i32(var_id, self.get_i32(a, enemy, game)?.wrapping_add(self.get_i32(b, enemy, game)?), enemy)?;
             }
             SubInstruction::AddFloat(var_id, a, b) => {
-                self.set_f32(var_id as f32, self.get_f32(a) + self.get_f32(b));
+                self.set_f32(var_id as f32, self.get_f32(a, enemy, game)? + self.get_f32(b, enemy, game)?, enemy)?;
             }
             // 14(int), 21(float), same impl in th06
             SubInstruction::SubstractInt(var_id, a, b) => {
-                self.set_i32(var_id, self.get_i32(a) - self.get_i32(b));
+                self.set_i32(var_id, self.get_i32(a, enemy, game)?.wrapping_sub(self.get_i32(b, enemy, game)?), enemy)?;
             }
             SubInstruction::SubstractFloat(var_id, a, b) => {
-                self.set_f32(var_id as f32, self.get_f32(a) - self.get_f32(b));
+                self.set_f32(var_id as f32, self.get_f32(a, enemy, game)? - self.get_f32(b, enemy, game)?, enemy)?;
             }
             // 15(int), 22(unused)
             SubInstruction::MultiplyInt(var_id, a, b) => {
-                self.set_i32(var_id, self.get_i32(a) * self.get_i32(b));
+                self.set_i32(var_id, self.get_i32(a, enemy, game)?.wrapping_mul(self.get_i32(b, enemy, game)?), enemy)?;
             }
             /*
             SubInstruction::MultiplyFloat(var_id, a, b) => {
-                self.set_f32(var_id as f32, self.get_f32(a) * self.get_f32(b));
+                self.set_f32(var_id as f32, self.get_f32(a, enemy, game)? * self.get_f32(b, enemy, game)?, enemy)?;
             }
             */
              // 16(int), 23(unused)
             SubInstruction::DivideInt(var_id, a, b) => {
-                self.set_i32(var_id, self.get_i32(a) / self.get_i32(b));
+                self.set_i32(var_id, divide(self.get_i32(a, enemy, game)?, self.get_i32(b, enemy, game)?)?, enemy)?;
             }
 
             SubInstruction::DivideFloat(var_id, a, b) => {
-                self.set_f32(var_id as f32, self.get_f32(a) / self.get_f32(b));
+                self.set_f32(var_id as f32, self.get_f32(a, enemy, game)? / self.get_f32(b, enemy, game)?, enemy)?;
             }
 
             // 17(int) 24(unused)
             SubInstruction::ModuloInt(var_id, a, b) => {
-                self.set_i32(var_id, self.get_i32(a) % self.get_i32(b));
+                self.set_i32(var_id, modulo(self.get_i32(a, enemy, game)?, self.get_i32(b, enemy, game)?)?, enemy)?;
             }
 
             SubInstruction::ModuloFloat(var_id, a, b) => {
-                self.set_f32(var_id as f32, self.get_f32(a) % self.get_f32(b));
+                self.set_f32(var_id as f32, self.get_f32(a, enemy, game)? % self.get_f32(b, enemy, game)?, enemy)?;
             }
 
             // 18
             // setval used by pytouhou, but not in game(???)
             SubInstruction::Increment(var_id) => {
-                self.set_i32(var_id, self.get_i32(var_id) + 1);
+                self.set_i32(var_id, self.get_i32(var_id, enemy, game)?.wrapping_add(1), enemy)?;
             }
 
             // 19
             SubInstruction::Decrement(var_id) => {
-                self.set_i32(var_id, self.get_i32(var_id) - 1);
+                self.set_i32(var_id, self.get_i32(var_id, enemy, game)?.wrapping_sub(1), enemy)?;
             }
 
             //25
             SubInstruction::GetDirection(var_id, x1, y1, x2, y2) => {
                 //__ctrandisp2 in ghidra, let's assume from pytouhou it's atan2
-                self.set_f32(var_id as f32, (self.get_f32(y2) - self.get_f32(y1)).atan2(self.get_f32(x2) - self.get_f32(x1)));
+                self.set_f32(var_id as f32, (self.get_f32(y2, enemy, game)? - self.get_f32(y1, enemy, game)?).atan2(self.get_f32(x2, enemy, game)? - self.get_f32(x1, enemy, game)?), enemy)?;
             }
 
             // 26
             SubInstruction::FloatToUnitCircle(var_id) => {
                 // TODO: atan2(var_id, ??) is used by th06, maybe ?? is pi?
                 // we suck at trigonometry so let's use pytouhou for now
-                self.set_f32(var_id as f32, (self.get_f32(var_id as f32) + std::f32::consts::PI) % (2. * std::f32::consts::PI) - std::f32::consts::PI);
+                self.set_f32(var_id as f32, (self.get_f32(var_id as f32, enemy, game)? + std::f32::consts::PI) % (2. * std::f32::consts::PI) - std::f32::consts::PI, enemy)?;
             }
 
             // 27(int), 28(float)
             SubInstruction::CompareInts(a, b) => {
-                let a = self.get_i32(a);
-                let b = self.get_i32(b);
+                let a = self.get_i32(a, enemy, game)?;
+                let b = self.get_i32(b, enemy, game)?;
                 if a < b {
                     self.frame.comparison_reg = -1;
                 }
@@ -394,8 +975,8 @@ impl EclRunner {
                 }
             }
             SubInstruction::CompareFloats(a, b) => {
-                let a = self.get_f32(a);
-                let b = self.get_f32(b);
+                let a = self.get_f32(a, enemy, game)?;
+                let b = self.get_f32(b, enemy, game)?;
                 if a < b {
                     self.frame.comparison_reg = -1;
                 }
@@ -409,43 +990,47 @@ impl EclRunner {
             // 29
             SubInstruction::RelativeJumpIfLowerThan(frame, ip) => {
                 if self.frame.comparison_reg == -1 {
-                    self.run_instruction(SubInstruction::RelativeJump(frame, ip));
+                    self.run_instruction(&SubInstruction::RelativeJump(frame, ip), enemy, game)?;
                 }
             }
             // 30
             SubInstruction::RelativeJumpIfLowerOrEqual(frame, ip) => {
                 if self.frame.comparison_reg != 1 {
-                    self.run_instruction(SubInstruction::RelativeJump(frame, ip));
+                    self.run_instruction(&SubInstruction::RelativeJump(frame, ip), enemy, game)?;
                 }
             }
             // 31
             SubInstruction::RelativeJumpIfEqual(frame, ip) => {
                 if self.frame.comparison_reg == 0 {
-                    self.run_instruction(SubInstruction::RelativeJump(frame, ip));
+                    self.run_instruction(&SubInstruction::RelativeJump(frame, ip), enemy, game)?;
                 }
             }
             // 32
             SubInstruction::RelativeJumpIfGreaterThan(frame, ip) => {
                 if self.frame.comparison_reg == 1 {
-                    self.run_instruction(SubInstruction::RelativeJump(frame, ip));
+                    self.run_instruction(&SubInstruction::RelativeJump(frame, ip), enemy, game)?;
                 }
             }
             // 33
             SubInstruction::RelativeJumpIfGreaterOrEqual(frame, ip) => {
                 if self.frame.comparison_reg != -1 {
-                    self.run_instruction(SubInstruction::RelativeJump(frame, ip));
+                    self.run_instruction(&SubInstruction::RelativeJump(frame, ip), enemy, game)?;
                 }
             }
             // 34
             SubInstruction::RelativeJumpIfNotEqual(frame, ip) => {
                 if self.frame.comparison_reg != 0 {
-                    self.run_instruction(SubInstruction::RelativeJump(frame, ip));
+                    self.run_instruction(&SubInstruction::RelativeJump(frame, ip), enemy, game)?;
                 }
             }
             // 35
             SubInstruction::Call(sub, param1, param2) => {
+                let sub = self.check_sub(sub)?;
+                if self.stack.len() >= MAX_STACK_DEPTH {
+                    return Err(EclErrorKind::StackOverflow);
+                }
                 self.stack.push(self.frame.clone());
-                self.frame.sub = sub as u16;
+                self.frame.sub = sub;
                 self.frame.ints1[0] = param1;
                 self.frame.floats[0] = param2;
                 self.frame.frame = 0;
@@ -454,105 +1039,95 @@ impl EclRunner {
 
             // 36
             SubInstruction::Return() => {
-                self.frame = self.stack.pop().unwrap();
+                self.frame = self.stack.pop().ok_or(EclErrorKind::StackUnderflow)?;
             }
             // 37
             SubInstruction::CallIfSuperior(sub, param1, param2, a, b) => {
-                if self.get_i32(a) < self.get_i32(b) {
-                    self.run_instruction(SubInstruction::Call(sub, param1, param2));
+                if self.get_i32(a, enemy, game)? < self.get_i32(b, enemy, game)? {
+                    self.run_instruction(&SubInstruction::Call(sub, param1, param2), enemy, game)?;
                 }
             }
             // 38
             SubInstruction::CallIfSuperiorOrEqual(sub, param1, param2, a, b) => {
-                if self.get_i32(a) <= self.get_i32(b) {
-                    self.run_instruction(SubInstruction::Call(sub, param1, param2));
+                if self.get_i32(a, enemy, game)? <= self.get_i32(b, enemy, game)? {
+                    self.run_instruction(&SubInstruction::Call(sub, param1, param2), enemy, game)?;
                 }
             }
             // 39
             SubInstruction::CallIfEqual(sub, param1, param2, a, b) => {
-                if self.get_i32(a) == self.get_i32(b) {
-                    self.run_instruction(SubInstruction::Call(sub, param1, param2));
+                if self.get_i32(a, enemy, game)? == self.get_i32(b, enemy, game)? {
+                    self.run_instruction(&SubInstruction::Call(sub, param1, param2), enemy, game)?;
                 }
             }
             // 40
             SubInstruction::CallIfInferior(sub, param1, param2, a, b) => {
-                if self.get_i32(b) < self.get_i32(a) {
-                    self.run_instruction(SubInstruction::Call(sub, param1, param2));
+                if self.get_i32(b, enemy, game)? < self.get_i32(a, enemy, game)? {
+                    self.run_instruction(&SubInstruction::Call(sub, param1, param2), enemy, game)?;
                 }
             }
 
             // 41
             SubInstruction::CallIfInferiorOrEqual(sub, param1, param2, a, b) => {
-                if self.get_i32(b) <= self.get_i32(a) {
-                    self.run_instruction(SubInstruction::Call(sub, param1, param2));
+                if self.get_i32(b, enemy, game)? <= self.get_i32(a, enemy, game)? {
+                    self.run_instruction(&SubInstruction::Call(sub, param1, param2), enemy, game)?;
                 }
             }
             //42
             SubInstruction::CallIfNotEqual(sub, param1, param2, a, b) => {
-                if self.get_i32(a) != self.get_i32(b) {
-                    self.run_instruction(SubInstruction::Call(sub, param1, param2));
+                if self.get_i32(a, enemy, game)? != self.get_i32(b, enemy, game)? {
+                    self.run_instruction(&SubInstruction::Call(sub, param1, param2), enemy, game)?;
                 }
             }
 
             // 43
             SubInstruction::SetPosition(x, y, z) => {
-                let (x, y, z) = (self.get_f32(x), self.get_f32(y), self.get_f32(z));
-                let mut enemy = self.enemy.borrow_mut();
+                let (x, y, z) = (self.get_f32(x, enemy, game)?, self.get_f32(y, enemy, game)?, self.get_f32(z, enemy, game)?);
                 enemy.set_pos(x, y, z);
             }
             // 44
             /*
             SubInstruction::SetAngularSpeed(x, y, z) => {
                 // same as above, except for angular speed
-                let mut enemy = self.enemy.borrow_mut();
-                enemy.set_angular_speed(self.get_f32(x), self.get_f32(y), self.get_f32(z));
+                enemy.set_angular_speed(self.get_f32(x, enemy, game)?, self.get_f32(y, enemy, game)?, self.get_f32(z, enemy, game)?);
             }
             */
             // 45
             SubInstruction::SetAngleAndSpeed(angle, speed) => {
-                let angle = self.get_f32(angle);
-                let speed = self.get_f32(speed);
-                let mut enemy = self.enemy.borrow_mut();
+                let angle = self.get_f32(angle, enemy, game)?;
+                let speed = self.get_f32(speed, enemy, game)?;
                 enemy.update_mode = 0;
                 enemy.angle = angle;
                 enemy.speed = speed;
             }
             // 46
             SubInstruction::SetRotationSpeed(speed) => {
-                let rotation_speed = self.get_f32(speed);
-                let mut enemy = self.enemy.borrow_mut();
+                let rotation_speed = self.get_f32(speed, enemy, game)?;
                 enemy.update_mode = 0;
                 enemy.rotation_speed = rotation_speed;
             }
             // 47
             SubInstruction::SetSpeed(speed) => {
-                let speed = self.get_f32(speed);
-                let mut enemy = self.enemy.borrow_mut();
+                let speed = self.get_f32(speed, enemy, game)?;
                 enemy.update_mode = 0;
                 enemy.speed = speed;
             }
             // 48
             SubInstruction::SetAcceleration(acceleration) => {
-                let acceleration = self.get_f32(acceleration);
-                let mut enemy = self.enemy.borrow_mut();
+                let acceleration = self.get_f32(acceleration, enemy, game)?;
                 enemy.update_mode = 0;
                 enemy.acceleration = acceleration;
             }
             // 49
             SubInstruction::SetRandomAngle(min_angle, max_angle) => {
-                let angle = self.get_prng().borrow_mut().get_f64() as f32 * (max_angle - min_angle) + min_angle;
-                let mut enemy = self.enemy.borrow_mut();
+                let angle = game.prng.get_f64() as f32 * (max_angle - min_angle) + min_angle;
                 enemy.angle = angle;
             }
             // 51
             SubInstruction::TargetPlayer(delta_angle, speed) => {
-                let speed = self.get_f32(speed);
-                let mut enemy = self.enemy.borrow_mut();
-                let game = enemy.game.upgrade().unwrap();
-                let player = game.borrow().get_player();
+                let speed = self.get_f32(speed, enemy, game)?;
                 enemy.update_mode = 0;
                 enemy.speed = speed;
-                enemy.angle = enemy.get_angle_to(player) + delta_angle;
+                enemy.angle = enemy.get_angle_to(game.player.pos) + delta_angle;
             }
 
             // 52 to 64 are different interlacing fields
@@ -562,12 +1137,10 @@ impl EclRunner {
             // it on top of setting our values. But we have a good engine and can detect if that's
             // changed without setting a flag :)
             SubInstruction::SetScreenBox(xmin, ymin, xmax, ymax) => {
-                let mut enemy = self.enemy.borrow_mut();
                 enemy.screen_box = Some((xmin, ymin, xmax, ymax));
             }
              // 66
             SubInstruction::ClearScreenBox() => {
-                let mut enemy = self.enemy.borrow_mut();
                 enemy.screen_box = None;
             }
 
@@ -575,82 +1148,77 @@ impl EclRunner {
             SubInstruction::SetBulletAttributes1(anim, sprite_index_offset, bullets_per_shot,
                                                  number_of_shots, speed, speed2, launch_angle,
                                                  angle, flags) => {
-                gen_SetBulletAttributes!(self, 67, anim, sprite_index_offset, bullets_per_shot,
+                gen_SetBulletAttributes!(self, enemy, game, 67, anim, sprite_index_offset, bullets_per_shot,
                                          number_of_shots, speed, speed2, launch_angle, angle,
                                          flags);
             }
             SubInstruction::SetBulletAttributes2(anim, sprite_index_offset, bullets_per_shot,
                                                  number_of_shots, speed, speed2, launch_angle,
                                                  angle, flags) => {
-                gen_SetBulletAttributes!(self, 68, anim, sprite_index_offset, bullets_per_shot,
+                gen_SetBulletAttributes!(self, enemy, game, 68, anim, sprite_index_offset, bullets_per_shot,
                                          number_of_shots, speed, speed2, launch_angle, angle,
                                          flags);
             }
             SubInstruction::SetBulletAttributes3(anim, sprite_index_offset, bullets_per_shot,
                                                  number_of_shots, speed, speed2, launch_angle,
                                                  angle, flags) => {
-                gen_SetBulletAttributes!(self, 69, anim, sprite_index_offset, bullets_per_shot,
+                gen_SetBulletAttributes!(self, enemy, game, 69, anim, sprite_index_offset, bullets_per_shot,
                                          number_of_shots, speed, speed2, launch_angle, angle,
                                          flags);
             }
             SubInstruction::SetBulletAttributes4(anim, sprite_index_offset, bullets_per_shot,
                                                  number_of_shots, speed, speed2, launch_angle,
                                                  angle, flags) => {
-                gen_SetBulletAttributes!(self, 70, anim, sprite_index_offset, bullets_per_shot,
+                gen_SetBulletAttributes!(self, enemy, game, 70, anim, sprite_index_offset, bullets_per_shot,
                                          number_of_shots, speed, speed2, launch_angle, angle,
                                          flags);
             }
             SubInstruction::SetBulletAttributes5(anim, sprite_index_offset, bullets_per_shot,
                                                  number_of_shots, speed, speed2, launch_angle,
                                                  angle, flags) => {
-                gen_SetBulletAttributes!(self, 71, anim, sprite_index_offset, bullets_per_shot,
+                gen_SetBulletAttributes!(self, enemy, game, 71, anim, sprite_index_offset, bullets_per_shot,
                                          number_of_shots, speed, speed2, launch_angle, angle,
                                          flags);
             }
             SubInstruction::SetBulletAttributes6(anim, sprite_index_offset, bullets_per_shot,
                                                  number_of_shots, speed, speed2, launch_angle,
                                                  angle, flags) => {
-                gen_SetBulletAttributes!(self, 74, anim, sprite_index_offset, bullets_per_shot,
+                gen_SetBulletAttributes!(self, enemy, game, 74, anim, sprite_index_offset, bullets_per_shot,
                                          number_of_shots, speed, speed2, launch_angle, angle,
                                          flags);
             }
             SubInstruction::SetBulletAttributes7(anim, sprite_index_offset, bullets_per_shot,
                                                  number_of_shots, speed, speed2, launch_angle,
                                                  angle, flags) => {
-                gen_SetBulletAttributes!(self, 75, anim, sprite_index_offset, bullets_per_shot,
+                gen_SetBulletAttributes!(self, enemy, game, 75, anim, sprite_index_offset, bullets_per_shot,
                                          number_of_shots, speed, speed2, launch_angle, angle,
                                          flags);
             }
 
             // 76
             SubInstruction::SetBulletInterval(interval) => {
-                let mut enemy = self.enemy.borrow_mut();
-                enemy.set_bullet_launch_interval(0, interval);
+                enemy.set_bullet_launch_interval(0, interval, game.difficulty());
             }
 
             // 77
             SubInstruction::SetBulletIntervalEx(interval) => {
-                let rand_start = self.get_prng().borrow_mut().get_u32();
+                let rand_start = game.prng.get_u32();
 
-                let mut enemy = self.enemy.borrow_mut();
-                enemy.set_bullet_launch_interval(rand_start, interval);
+                enemy.set_bullet_launch_interval(rand_start, interval, game.difficulty());
             }
 
             // 78-79 are more interpolation flags
             // 78
             SubInstruction::DelayAttack() => {
-                let mut enemy = self.enemy.borrow_mut();
                 enemy.delay_attack = true;
             }
             // 79
             SubInstruction::NoDelayAttack() => {
-                let mut enemy = self.enemy.borrow_mut();
                 enemy.delay_attack = false;
             }
             // 80
             /*
             SubInstruction::NoClue() => {
-                let mut enemy = self.enemy.borrow_mut();
                 //bullet_pos = launch offset
                 (enemy->bullet_attributes).bullets_per_shot = enemy.pos.x + enemy->bullet_pos.pos.x;
                 (enemy->bullet_attributes).number_of_shots = enemy.pos.pos.y + enemy.bullet_pos.pos.y;
@@ -661,90 +1229,78 @@ impl EclRunner {
 
             // 81
             SubInstruction::SetBulletLaunchOffset(dx, dy, dz) => {
-                let (dx, dy, dz) = (self.get_f32(dx), self.get_f32(dy), self.get_f32(dz));
-                let mut enemy = self.enemy.borrow_mut();
+                let (dx, dy, dz) = (self.get_f32(dx, enemy, game)?, self.get_f32(dy, enemy, game)?, self.get_f32(dz, enemy, game)?);
                 enemy.bullet_offset = Offset { dx, dy };
             }
 
             // 82
             SubInstruction::SetExtendedBulletAttributes(a, b, c, d, e, f, g, h) => {
-                let (a, b, c, d) = (self.get_i32(a), self.get_i32(b), self.get_i32(c), self.get_i32(d));
-                let (e, f, g, h) = (self.get_f32(e), self.get_f32(f), self.get_f32(g), self.get_f32(h));
-                let mut enemy = self.enemy.borrow_mut();
+                let (a, b, c, d) = (self.get_i32(a, enemy, game)?, self.get_i32(b, enemy, game)?, self.get_i32(c, enemy, game)?, self.get_i32(d, enemy, game)?);
+                let (e, f, g, h) = (self.get_f32(e, enemy, game)?, self.get_f32(f, enemy, game)?, self.get_f32(g, enemy, game)?, self.get_f32(h, enemy, game)?);
                 enemy.bullet_attributes.extended_attributes = (a, b, c, d, e, f, g, h);
             }
 
             // 83
-            /*
-            SubInstruction::ChangeBulletsIntoStarBonus() => {
-                let mut game = self.game.borrow_mut();
+            SubInstruction::ChangeBulletsInStarBonus() => {
                 game.change_bullets_into_star_items();
             }
-            */
 
             // 84
             SubInstruction::SetBulletSound(sound) => {
-                let mut enemy = self.enemy.borrow_mut();
                 if sound < 0 {
                     enemy.bullet_attributes.sound = None;
                 } else {
-                    // This assert isn’t part of the original engine, but it would crash on high
+                    // This check isn’t part of the original engine, but it would crash on high
                     // values anyway.
-                    assert!(sound <= 255);
+                    if sound > 255 {
+                        return Err(EclErrorKind::InvalidSound(sound));
+                    }
                     enemy.bullet_attributes.sound = Some(sound as u8);
                 }
             }
 
-            // 85-86 ire newlaser functions
+            // 85
+            SubInstruction::NewLaser(laser_type, sprite_idx_offset, angle, speed, start_offset, end_offset, max_length, width, start_duration, duration, end_duration, grazing_delay, grazing_extra_duration, _unknown) => {
+                gen_NewLaser!(self, enemy, game, false, laser_type, sprite_idx_offset, angle, speed, start_offset,
+                              end_offset, max_length, width, start_duration, duration, end_duration,
+                              grazing_delay, grazing_extra_duration);
+            }
+
+            // 86
+            SubInstruction::NewLaserTowardsPlayer(laser_type, sprite_idx_offset, angle, speed, start_offset, end_offset, max_length, width, start_duration, duration, end_duration, grazing_delay, grazing_extra_duration, _unknown) => {
+                gen_NewLaser!(self, enemy, game, true, laser_type, sprite_idx_offset, angle, speed, start_offset,
+                              end_offset, max_length, width, start_duration, duration, end_duration,
+                              grazing_delay, grazing_extra_duration);
+            }
 
             // 87
             SubInstruction::SetUpcomingLaserId(laser_id) => {
-                let mut enemy = self.enemy.borrow_mut();
                 enemy.current_laser_id = laser_id;
             }
 
             // 88
-            /*
             SubInstruction::AlterLaserAngle(laser_id, delta) => {
-                let mut enemy = self.enemy.borrow_mut();
-                if enemy.laser_by_id.contains_key(&laser_id) {
-                    let mut laser = enemy.laser_by_id.get(&laser_id);
-                    laser.angle += self.get_f32(delta);
-                }
-            }
-            */
-
-            // 89
-            /*
-            SubInstruction::AlterLaserAnglePlayer(laser_id, delta) => {
-                let mut enemy = self.enemy.borrow_mut();
-                if enemy.laser_by_id.contains_key(&laser_id) {
-                    let mut laser = enemy.laser_by_id.get(laser_id);
-                    let player = enemy.select_player();
-                    laser.angle = enemy.get_angle(player) + angle;
+                let delta = self.get_f32(delta, enemy, game)?;
+                if let Some(laser) = enemy.get_laser(laser_id, &mut game.lasers) {
+                    laser.angle += delta;
                 }
             }
-            */
 
             // 90
-            /*
-            SubInstruction::RepositionLaser(laser_id, ox, oy, oz) => {
-                let mut enemy = self.enemy.borrow_mut();
-                if enemy.laser_by_id.contains_key(&laser_id) {
-                    let mut laser = enemy.laser_by_id.get(&laser_id);
-                    laser.set_base_pos(enemy.pos.x + ox, enemy.pos.y + oy, enemy.z + oz)
+            SubInstruction::RepositionLaser(laser_id, ox, oy, _oz) => {
+                if let Some(laser) = enemy.get_laser(laser_id, &mut game.lasers) {
+                    laser.set_base_pos(enemy.pos + Offset::new(ox, oy));
                 }
             }
-            */
+
             // 91
             // wat
             SubInstruction::LaserSetCompare(laser_id) => {
-                let enemy = self.enemy.borrow_mut();
                 // in game it checks if either the laser exists OR if one of its member is set to 0
                 // which, uhhhh, we are not going to reimplement for obvious reasons
                 // the correct implementation would be: if this laser does not exist have a
                 // 1/100000 chance to continue, otherwise crash
-                if enemy.laser_by_id.contains_key(&laser_id) {
+                if enemy.get_laser(laser_id, &mut game.lasers).is_some() {
                     // let's assume we gud
                     self.frame.comparison_reg = 1;
                 }
@@ -754,56 +1310,48 @@ impl EclRunner {
             }
 
             // 92
-            /*
-            SubInstruction::RepositionLaser(laser_id, ox, oy, oz) => {
-                let mut enemy = self.enemy.borrow_mut();
-                if enemy.laser_by_id.contains_key(&laser_id) {
-                    let mut laser = enemy.laser_by_id.get(laser_id);
+            SubInstruction::CancelLaser(laser_id) => {
+                if let Some(laser) = enemy.get_laser(laser_id, &mut game.lasers) {
                     laser.cancel();
                 }
             }
-            */
+
             // 93
             // TODO: actually implement that hell
-            SubInstruction::SetSpellcard(face, number, name) => {
-                unimplemented!("spellcard start");
-
+            SubInstruction::SetSpellcard(face, number, ref name) => {
+                game.start_spellcard(face, number, name.clone());
             }
             // 94
             SubInstruction::EndSpellcard() => {
-                unimplemented!("spellcard end");
-
+                game.end_spellcard();
             }
 
             // 95
             SubInstruction::SpawnEnemy(sub, x, y, z, life, bonus, score) => {
-                let x = self.get_f32(x);
-                let y = self.get_f32(y);
-                let _z = self.get_f32(z);
-                let enemy = self.enemy.borrow_mut();
-                let anm0 = enemy.anm0.upgrade().unwrap();
-                let game = enemy.game.upgrade().unwrap();
-                let enemy = Enemy::new(Position::new(x, y), life, bonus, score as u32, false, Rc::downgrade(&anm0), Rc::downgrade(&game));
-                let ecl = self.ecl.clone().unwrap();
-                let mut runner = EclRunner::new(&ecl, enemy, sub as u16);
-                runner.run_frame();
+                let x = self.get_f32(x, enemy, game)?;
+                let y = self.get_f32(y, enemy, game)?;
+                let _z = self.get_f32(z, enemy, game)?;
+                // The child joins the game and keeps running its own sub, mirrored like its parent.
+                let sub = self.check_sub(sub)?;
+                if game.ecl.is_none() {
+                    return Err(EclErrorKind::NoStage);
+                }
+                game.spawn_enemy(sub, Position::new(x, y), life, bonus, score as u32, enemy.mirror)
+                    .map_err(|error| EclErrorKind::SpawnedEnemy(Box::new(error)))?;
             }
 
             // 96
-            /*
-            SubInstruction::KillEnemies() => {
-                let mut game = self.game.borrow_mut();
+            SubInstruction::KillAllEnemies() => {
+                // This enemy is out of the game while it runs.
                 game.kill_enemies();
+                enemy.kill();
             }
-            */
-
-
 
             // 97
             SubInstruction::SetAnim(index) => {
                 // seems correct, game internally gets base_addr =(iVar13 + 0x1c934), pointer_addr = iVar14 * 4
-                let mut enemy = self.enemy.borrow_mut();
-                enemy.set_anim(index as u8);
+                let index = u8::try_from(index).map_err(|_| EclErrorKind::InvalidAnim(index))?;
+                enemy.set_anim(index, &mut game.prng)?;
             }
             // 98
             SubInstruction::SetMultipleAnims(default, end_left, end_right, left, right, _unused) => {
@@ -811,506 +1359,350 @@ impl EclRunner {
                 // assigns it 0xff
                 // TODO: THIS DOES NOT CALL set_anim. this only assigns all parameters to their
                 // internal struct. To check if the anims are set somewhere else
-                let mut enemy = self.enemy.borrow_mut();
                 enemy.movement_dependant_sprites = if left == -1 {
                     None
                 } else {
-                    enemy.set_anim(default as u8);
+                    for anim in [default, end_left, end_right, left, right] {
+                        enemy.anm0_with_script(anim.into())?;
+                    }
+                    enemy.set_anim(default as u8, &mut game.prng)?;
                     Some((end_left as u8, end_right as u8, left as u8, right as u8))
                 };
             }
             // 99
             SubInstruction::SetAuxAnm(number, script) => {
-                assert!(number < 8);
-                let mut enemy = self.enemy.borrow_mut();
-                enemy.set_aux_anm(number, script);
+                if !(0..8).contains(&number) {
+                    return Err(EclErrorKind::InvalidAuxAnm(number));
+                }
+                let script = u8::try_from(script).map_err(|_| EclErrorKind::InvalidAnim(script))?;
+                enemy.set_aux_anm(number as usize, script, &mut game.prng)?;
             }
 
             // 100
-            SubInstruction::SetDeathAnim(index) => {
-                // TODO: takes 3 parameters in game as u8 unlike our single u32.
-                // To reverse!
-                let mut enemy = self.enemy.borrow_mut();
-                enemy.death_anim = index;
+            SubInstruction::SetDeathAnim(anim, _, _, _) => {
+                // TODO: find what the two other parameters are used for.
+                enemy.death_anim = anim;
             }
             // 101
             SubInstruction::SetBossMode(value) => {
-                let enemy = self.enemy.borrow_mut();
-                if value < 0 {
-                    enemy.set_boss(false);
-                }
-                else {
-                    // the boss pointer is written somewhere in memory and overwrote by a 0 when
-                    // the boss mode is false, might want to look into that
-                    enemy.set_boss(true);
-                }
+                // TODO: standard enemies should only be blocked until any of multiple bosses dies.
+                enemy.set_boss(self.enemy, value >= 0, game);
             }
 
             // 102
-            // TODO: title says it all
-            /*
-            SubInstruction::ParticlesVoodooMagic(unk1, unk2, unk3, unk4, unk5) => {
+            SubInstruction::CreateSquares(_, _, _, _, _) => {
+                // Nothing is known about what this creates, pytouhou ignores it too, so it isn’t
+                // part of the effects supported so far and stays listed in the TODO.  Unlike
+                // unknown opcodes it doesn’t fail, so that the stages using it keep running.
             }
-            */
 
             // 103
             SubInstruction::SetHitbox(width, height, depth) => {
-                let mut enemy = self.enemy.borrow_mut();
                 enemy.set_hitbox(width, height);
             }
 
             // 104
             SubInstruction::SetCollidable(collidable) => {
-                // TODO: me and my siblings(105, 107, 117) are implemented as a single variable in the touhou 6
+                // TODO: me and my siblings(105, 117) are implemented as a single variable in the touhou 6
                 // original engine. While our behaviour seems correct we might want to implement
                 // that as a single variable
+                // 107 isn’t one of them, its value is decoded as DeathFlags by the parser.
                 // TODO[2]: THE BITFLAG MIGHT BE INCORRECT FOR OTHER SIBLING INSTRUCTIONS, the
                 // behavior was DEFINITELY incorrect in pytouhou for SetTouchable at the very least
-                let mut enemy = self.enemy.borrow_mut();
                 enemy.collidable = (collidable&1) != 0;
             }
 
             // 105
             SubInstruction::SetDamageable(damageable) => {
-                let mut enemy = self.enemy.borrow_mut();
                 enemy.damageable = (damageable&1) != 0;
             }
 
             // 106
             SubInstruction::PlaySound(index) => {
-                let enemy = self.enemy.borrow_mut();
-                enemy.play_sound(index);
+                // Playing it is left to the frontend.
+                game.sounds.push(index);
             }
 
             // 107
             SubInstruction::SetDeathFlags(death_flags) => {
-                let mut enemy = self.enemy.borrow_mut();
                 enemy.death_flags = death_flags;
             }
             // 108
             SubInstruction::SetDeathCallback(sub) => {
-                let mut enemy = self.enemy.borrow_mut();
                 enemy.death_callback = Some(sub);
             }
 
             // 109
             SubInstruction::MemoryWriteInt(value, index) => {
-                unimplemented!("not again that damn foe corrupted my ret\\x41\\x41\\x41\\x41");
+                // Only the boss callback, run by ResumeEcl after a dialogue, is ever written.
+                if index != 0 {
+                    // not again that damn foe corrupted my ret\x41\x41\x41\x41
+                    return Err(EclErrorKind::UnknownMemoryWrite(index));
+                }
+                enemy.boss_callback = Some(value);
             }
 
-            // 110
-            /*
-            SubInstruction::KillEnemy(enemy) => {
-                let mut game = self.game.borrow_mut();
-                game.kill_enemy(enemy);
-            }
-            */
+            // 110 doesn’t exist in EoSD, enemies only get killed all at once by 96.
 
             // 111
-            /*
-            SubInstruction::SetLife(value) => {
-                let mut enemy = self.enemy.borrow_mut();
-                let mut game = self.game.borrow_mut();
-                enemy.life = value;
-                game.interface.set_boss_life();
+            SubInstruction::SetLife(life) => {
+                enemy.life = life.max(0) as u32;
+                game.set_boss_life(self.enemy, enemy.life);
             }
-            */
             // 112
             SubInstruction::SetElapsedTime(value) => {
-                let mut enemy = self.enemy.borrow_mut();
                 enemy.frame = value as u32;
             }
             // 113
-            /*
-            SubInstruction::SetLowLifeTrigger(value) => {
-                let mut enemy = self.enemy.borrow_mut();
-                let mut game = self.game.borrow_mut();
-                enemy.low_life_trigger = value;
-                game.interface.set_spell_life();
+            SubInstruction::SetLowLifeTrigger(trigger) => {
+                // TODO: the life bar fills in 100 frames, during which the ECL seems paused.
+                enemy.low_life_trigger = if trigger < 0 { None } else { Some(trigger as u32) };
             }
-            */
             // 114
-            /*
-             SubInstruction::SetLowLifeCallback(sub) => {
-                let mut enemy = self.enemy.borrow_mut();
-                enemy.low_life_callback.enable(self.switch_to_sub, (sub,));
+            SubInstruction::SetLowLifeCallback(sub) => {
+                enemy.low_life_callback = Some(sub);
             }
-            */
             // 115
-            /*
             SubInstruction::SetTimeout(timeout) => {
-                let mut enemy = self.enemy.borrow_mut();
-                enemy.frame = value;
-                enemy.timeout = timeout;
+                enemy.frame = 0;
+                enemy.timeout = if timeout < 0 { None } else { Some(timeout as u32) };
             }
-            */
             // 116
-            /*
-             SubInstruction::SetTimeoutCallback(sub) => {
-                let mut enemy = self.enemy.borrow_mut();
-                enemy.timeout_callback.enable(self.switch_to_sub, (sub,));
+            SubInstruction::SetTimeoutCallback(sub) => {
+                enemy.timeout_callback = Some(sub);
             }
-            */
-
 
             // 117
             SubInstruction::SetTouchable(touchable) => {
-                let mut enemy = self.enemy.borrow_mut();
                 enemy.touchable = touchable != 0;
             }
 
-            // 121
-            // Here lies the Di Sword of sadness
-            SubInstruction::CallSpecialFunction(function, arg) => {
-                match function {
-                    0 => {
-                        let mut enemy = self.enemy.borrow_mut();
-                        let game = enemy.game.upgrade().unwrap();
-                        let mut game = game.borrow_mut();
-                        //game.drop_particle(12, enemy.pos, 1, 0xffffffff);
-                        //game.iter_bullets(|mut bullet| {
-                        for bullet in game.bullets.iter() {
-                            //game.new_effect(bullet.sprite, TODO);
-                            let mut bullet = bullet.borrow_mut();
-                            if arg == 0 {
-                                bullet.speed = 0.;
-                                bullet.dpos = [0., 0., 0.];
-                            } else if arg == 1 {
-                                bullet.flags |= 0x10;
-                                bullet.frame = 220;
-                                let rand_angle = game.prng.borrow_mut().get_f64() * 2. * std::f64::consts::PI - std::f64::consts::PI;
-                                bullet.attributes[0] = (rand_angle.cos() * 0.01) as f32;
-                                bullet.attributes[1] = (rand_angle.sin() * 0.01) as f32;
-                            }
-                        }
-                    }
-                    1 => {
-                        let range_x = arg as f64;
-                        let range_y = (arg as f32 * 0.75) as f64;
-                        let rand_x = self.get_prng().borrow_mut().get_f64();
-                        let rand_y = self.get_prng().borrow_mut().get_f64();
-                        let mut enemy = self.enemy.borrow_mut();
-                        let pos = [rand_x * range_x + enemy.pos.x as f64 - range_x / 2.,
-                                   rand_y * range_y + enemy.pos.x as f64 - range_y / 2.];
-                        enemy.bullet_attributes.fire();
-                    }
-                    3 => { // Patchouli’s dual sign spellcard selector
-                        let mut enemy = self.enemy.borrow_mut();
-                        let mut knowledge: [[i32; 3]; 4] =
-                            [[0, 3, 1],
-                             [2, 3, 4],
-                             [1, 4, 0],
-                             [4, 2, 3]];
-
-                        //TODO: implement select_player and replace character by the correct one
-                        //let character = enemy.select_player().character;
-                        let character = 0;
-                        for i in 1..=3 {
-                            self.frame.ints1[i] = knowledge[character][i];
-                        }
-                    }
-                    4 => { // Sakuya random daggers and time stop
-                        /*
-                        if arg < 2 {
-                            drop_particle(&PARTICLES_ARRAY,0xc,enemy->pos,1,0xffffffff);
-                            //TODO: is that the timestop?
-                            LEVEL.field_0x2c = arg;
-                            return;
-                        }
-                        // this changes the orientation of random bullets
-                        let mut max_bullets = 0xe;
-                        if (LEVEL.rank >= 2) {max_bullets = 0x34;}
-                        i = 0;
-                        for bullet in game.bullets {
-                            if bullet->state != 0 && bullet->state != 5 && 30. <= (bullet->sprites[0].additional_infos)->height && bullet->field_0x5ba != 5 && (uVar3 = prng16(&PRNG_STATE), (uVar3 & 3) == 0) {
-                                bullet->field_0x5ba = 5;
-                                new_effect(GAME_OBJECT,(sprite *)bullet, (int)bullet->sprites[0].sometimes_copy_of_UNK1 + (int)bullet->field_0x5ba);
-                                x = bullet->pos[0] - PLAYER.pos[0];
-                                y = bullet->pos[1] - PLAYER.pos[1];
-                                if sqrt(x*x+y*y) > 128. {
-                                    if LEVEL.rank >= 2 {bullet->automatic_angle = prng_double() * 2*pi;}
-                                    else{bullet->automatic_angle = (prng_double() * ((pi/8)*6) + pi/4);}
-                                    else {
-                                        // TODO: check player_get_angle, might be what ST0 is
-                                        player_get_angle_to(&PLAYER,bullet->pos,local_38);
-                                        bullet->automatic_angle = (extraout_ST0_00 + pi/2 + (prng_double() * pi*2));
-                                    }
-                                    bullet->dpos[0] = cos(bullet->automatic_angle) * bullet->speed;
-                                    bullet->dpos[1] = sin(bullet->automatic_angle) * bullet->speed;
-                                    max_bullets -= 1;
-                                    if (max_bullets == 0) break;
-                                }
-                            }
-                            (enemy->ecl_frame).var_ints_1[2] = 0;*/
-                        }
-                    7 => { // Remilia's lazer maze
-                        // so what this does is kinda complex: 2 rounds of 3 subrounds of 8 shots, either
-                        // laser or standard bullets depending on the argument passed.
-                        // it is done in 2 steps: first we precalculate coordinates of the 8 shots for the first subround
-                        // set the shot properties depending on difficulties and current round and then
-                        // edit  the coordinates for the next round
-                        let rnd_pos = self.get_prng().borrow_mut().get_f64() * 2. * std::f64::consts::PI;
-                        let enemy = self.enemy.borrow();
-                        for i in 0..2 {
-                            let mut pos: [f64; 8*3] = [0.; 8*3];
-                            let mut offset = rnd_pos -((std::f64::consts::PI/8.)*7.);
-                            let mut next_offset = -std::f64::consts::PI/4.;
-                            if (i == 0) {
-                                offset = rnd_pos -std::f64::consts::PI;
-                                next_offset = std::f64::consts::PI/4.;
-                            }
-
-                            // we calculate angle, speed and offset for the 8 shots
-                            let mut offset_copy=offset;
-                            for y in 0..8 {
-                                pos[y * 3] = offset_copy.cos() * 32. + enemy.pos.x as f64;
-                                pos[y * 3 + 1] = offset_copy.sin() * 32. + enemy.pos.y as f64;
-                                pos[y * 3 + 2] = enemy.z as f64;
-                                offset_copy += std::f64::consts::PI/4.;
-                            }
-
-                            // 3 rounds of 8 shots
-                            for z in 0..3 {
-
-                                let mut length = 112.;
-                                // last subround
-                                if (z == 2) {length = 480.;}
-
-                                for y in 0..8 {
-                                    /*
-                                    if (arg == 0) {
-                                        let (mut si, mut ged, mut ed) = (8, 20.,ed=430.);
-                                        if (LEVEL.rank < 2) {si=2; ged=28.; ed=length;}
-                                        laser_args.angle = pos[y * 3];
-                                        laser_args.speed = pos[y * 3 + 1];
-                                        laser_args.start_offset = pos[y * 3 + 2];
-                                        laser_args.type = 1;
-                                        laser_args.sprite_index_offset = si;
-                                        laser_args.end_offset = offset;
-                                        laser_args.width = 0.;
-                                        laser_args.duration = 0;
-                                        laser_args.grazing_extra_duration = ged;
-                                        laser_args.end_duration = ed;
-                                        laser_args.UNK1 = z * 0x10 + 0x3c;
-                                        laser_args.grazing_delay = laser_args.end_duration;
-                                        fire_laser(&ETAMA_ARRAY,&laser_args);
-                                    }
-                                    else {
-                                        (enemy->bullet_attributes).pos[0] = pos[y * 3];
-                                        (enemy->bullet_attributes).pos[1] = pos[y*3+1];
-                                        (enemy->bullet_attributes).pos[2] = pos[y*3+2];
-                                        bullet_fire(&enemy->bullet_attributes,&ETAMA_ARRAY);
-                                    }
-                                    */
-                                    pos[y * 3] = offset.cos() * length + pos[y * 3];
-                                    pos[y * 3 + 1] = offset.sin() * length + pos[y * 3 + 1];
-                                    offset = offset + std::f64::consts::PI/4.;
-                                }
-                                offset = (next_offset - 2.*std::f64::consts::PI) + offset;
-                            }
-                        }
-                    }
-                    8 => { // Vampire Fantasy
-                        let n = {
-                            let enemy = self.enemy.borrow();
-                            let game = enemy.game.upgrade().unwrap();
-                            let mut game = game.borrow_mut();
-                            let mut n = 0;
-                            for bullet in game.bullets.iter() {
-                                let mut bullet = bullet.borrow();
-                                // TODO: uncomment that one.
-                                if bullet.state != 0 && bullet.state != 5 /* && (30. <= (bullet.sprites[0].additional_infos).height) */ {
-                                    let prng = enemy.prng.upgrade().unwrap();
-                                    let random = prng.borrow_mut().get_f64();
-                                    let launch_angle = (random * (2. * std::f64::consts::PI) - std::f64::consts::PI) as f32;
-                                    let mut attribs = BulletAttributes {
-                                        // TODO: check if the z value of this pos is really used.
-                                        pos: bullet.pos,
-                                        anim: 3,
-                                        sprite_index_offset: 1,
-                                        launch_angle,
-                                        speed: 0.,
-                                        angle: 0.,
-                                        speed2: 0.,
-                                        bullets_per_shot: 1,
-                                        number_of_shots: 1,
-                                        flags: 8,
-                                        bullet_type: 1,
-                                        extended_attributes: Default::default(),
-                                        sound: None,
-                                    };
-                                    attribs.fire();
-                                    n += 1
-                                }
-                            }
-                            n
-                        };
-                        //TODO: this variable might not always be correct! it uses the argument in
-                        //th06: *(int *)(param_1 + 0x9b0) = local_60;
-                        self.set_i32(-10004, n);
-                    }
+            // 118
+            SubInstruction::DropParticles(anim, number, _, _, _, _) => {
+                // TODO: find what the colour is used for.
+                let number = if number == 0 { 640 } else { number.min(640) } as usize;
+                match anim {
+                    0..=2 => game.new_effect(enemy.pos, anim as u8 + 3, number),
+                    // TODO: make them go back a bit at the end.
+                    3 => game.new_particles(enemy.pos, 6, 256., number),
+                    _ => (),
+                }
+            }
 
-                    9 => {
-                        let mut rnd = self.get_prng().borrow_mut().get_f64();
-                        //TODO: the game does that
-                        //drop_particle(&PARTICLES_ARRAY,0xc,enemy->pos,1,0xffffffff);
-                        //self._game.new_effect((enemy.x, enemy.y), 17)
-                        /*
-                        for bullet in game.bullets {
-                            if bullet._bullet_type.state != 0 && bullet._bullet_type.state != 5 && (30. <= (bullet.sprites[0].additional_infos)->height) && bullet.speed == 0. {
-                                bullet.flags = bullet.flags | 0x10;
-                                //TODO: reverse this field and effect
-                                bullet->field_0x5ba = 2;
-                                new_effect(GAME_OBJECT,(sprite *)bullet, (int)bullet->sprites[0].sometimes_copy_of_UNK1 + (int)bullet->field_0x5ba);
-                                bullet.speed=0.01;
-                                bullet.frame=0x78;
-
-                                let mut dx = bullet.x - enemy.x;
-                                let mut distance = dx.hypot(bullet.y - enemy.y);
-
-                                if distance > 0.01 {
-                                    distance = sqrt(distance);
-                                }else{distance = 0.;}
-                                let mut angle = (distance * std::f64::consts::PI) / 256. + (rnd * (2*std::f64::consts::PI) - std::f64::consts::PI);
-                                bullet->attributes[0] = cos(angle) * 0.01000000;
-                                bullet->attributes[1] = sin(angle) * 0.01000000;
-                            }
-                        }
-                        */
-                    }
-                    11 => {
-                        self.get_prng().borrow_mut().get_f64();
-                        //TODO: the game does that
-                        //drop_particle(&PARTICLES_ARRAY,0xc,enemy->pos,1,0xffffffff);
-                        //self._game.new_effect((enemy.x, enemy.y), 17)
-                        /*
-                        for bullet in game.bullets {
-                            if bullet._bullet_type.state != 0 && bullet._bullet_type.state != 5 && (30. <= (bullet.sprites[0].additional_infos)->height) && bullet.speed == 0. {
-                                bullet.flags = bullet.flags | 0x10;
-                                //TODO: reverse this field and effect
-                                bullet->field_0x5ba = 2;
-                                new_effect(GAME_OBJECT,(sprite *)bullet, (int)bullet->sprites[0].sometimes_copy_of_UNK1 + (int)bullet->field_0x5ba);
-                                bullet.speed=0.01;
-                                bullet.frame=0x78;
-                                let mut angle = self.get_prng().borrow_mut().get_f64() * (2*std::f64::consts::PI) - std::f64::consts::PI;
-                                bullet->attributes[0] = cos(angle) * 0.01000000;
-                                bullet->attributes[1] = sin(angle) * 0.01000000;
-
-
-                            }
-                        }
-                        */
-                    }
-                    13 => {
-                        if self.frame.ints1[3] % 6 == 0 {
-                            let mut _angle=self.frame.floats[2];
-                            /*
-                            (type_, anim, sprite_idx_offset, bullets_per_shot, number_of_shots,
-                            speed, speed2, launch_angle, angle, flags) = self._enemy.bullet_attributes
-                            for i in range(arg) {
-                                //TODO: distance is obtained directly by copying bullet attributes
-                                //in memory
-                                launch_pos = (192 + cos(_angle) * _distance,
-                                    224 + sin(_angle) * _distance);
-
-                                bullet_attributes = (type_, anim, sprite_idx_offset,
-                                     bullets_per_shot, number_of_shots,
-                                     speed, speed2,
-                                     _angle + self.frame.floats[1], angle, flags);
-                                enemy.fire(launch_pos=launch_pos,bullet_attributes=bullet_attributes);
-                                _angle += 2*std::f64::consts::PI/arg;
-                            }*/
-                        }
-                        self.frame.ints1[3] += 1;
-                    }
-                    14 => { // Lävatein
-                        let mut enemy = self.enemy.borrow_mut();
-                        self.frame.ints1[3] = 0;
-                        for laser in enemy.laser_by_id.values() {
-                            //for pos in laser.get_bullets_pos(){
-                            //TODO: the game checks for laser end_offset before firing
-                            //  enemy.fire(launch_pos=pos);
-                            //}
-                            self.frame.ints1[3] += 1;
-                        }
-                    }
-                    16 => { // QED: Ripples of 495 years
-                        let mut enemy = self.enemy.borrow_mut();
-                        let game = enemy.game.upgrade().unwrap();
-                        let mut game = game.borrow_mut();
-                        if arg == 0 {
-                            self.frame.floats[3] = 2. - (enemy.life as f32) / 6000.;
-                            self.frame.ints2[1] = ((enemy.life * 240) / 6000 + 40) as i32;
-                        } else {
-                            let fx = (320. - ((enemy.life as f32) * 160.) / 6000.) as f64;
-                            let fy = (128. - ((enemy.life as f32) * 64.) / 6000.) as f64;
-                            let rand_x = game.prng.borrow_mut().get_f64();
-                            let rand_y = game.prng.borrow_mut().get_f64();
-                            self.frame.floats[2] = (rand_x * fx + (192. - fx / 2.)) as f32;
-                            self.frame.floats[3] = (rand_y * fy + (96. - fy / 2.)) as f32;
-                        }
-                    }
-                    _ => unimplemented!("Special function {:?} not found!", function)
+            // 119
+            SubInstruction::DropBonus(number) => {
+                let full_power = game.player.power >= MAX_POWER;
+                for i in 0..number {
+                    let item_type = match (full_power, i) {
+                        (true, _) => ItemType::Point,
+                        (false, 0) => ItemType::BigPower,
+                        (false, _) => ItemType::Power,
+                    };
+                    // TODO: find the real formula in the binary.
+                    let (dx, dy) = {
+                        let prng = &mut game.prng;
+                        (prng.get_f64() as f32 * 128. - 64., prng.get_f64() as f32 * 128. - 64.)
+                    };
+                    game.drop_bonus(enemy.pos + Offset::new(dx, dy), item_type);
                 }
             }
 
+            // 121
+            // Here lies the Di Sword of sadness
+            SubInstruction::CallSpecialFunction(function, argument) => {
+                self.call_special_function(function, argument, enemy, game)?;
+            }
+
             // 122
             // Here lies the Di Sword of despair
             SubInstruction::SetSpecialFunctionCallback(function) => {
-                //TODO: those functions are executed at each frame and needs to be written to the
-                //callback function but so far i'm simply focusing on the implementation
-                //NB: the original engine doesn't differenciate between function blocks for ins 121
-                //and 122 but we do here, since it wouldn't make sense the other way around.
-                match function {
-                    12 => {
-                        for i in 0..8 {
-                            /*
-                            if ((enemy->lasers[i] != (laser *)0x0) && (enemy->lasers[i]->state != 0)) {
-                                (enemy->bullet_attributes).pos[0] = cos(enemy->lasers[i]->angle) * 64. + enemy.pos.x;
-                                // yes, it reads pos[0] after it has been modified and yes, this most
-                                // likely is a bug
-                                (enemy->bullet_attributes).pos[1] = cos(enemy->lasers[i]->angle) * (enemy->bullet_attributes).pos[0] + enemy.pos.y;
-                                (enemy->bullet_attributes).pos[2] = enemy.z;
-                                bullet_fire(&enemy->bullet_attributes,&ETAMA_ARRAY);
-                            }*/
-                        }
-                    }
-                    _ => unimplemented!("Special function {:?} not found!", function)
+                // The original engine shares its functions between 121 and 122, the latter running
+                // one on each frame.
+                enemy.special_callback = if function < 0 { None } else { Some(function) };
+            }
+
+            // 124
+            SubInstruction::DropSpecificBonus(type_) => {
+                // TODO: negative types “drop” a bullet animation instead, but are never used.
+                if let Some(item_type) = ItemType::from_index(type_) {
+                    game.drop_bonus(enemy.pos, item_type);
                 }
             }
-            _ => unimplemented!("{:?}", instruction)
-        }
 
+            // 126
+            SubInstruction::SetRemainingLives(lives) => {
+                enemy.remaining_lives = lives.max(0) as u32;
+            }
+
+            // 129
+            SubInstruction::InterruptAux(number, event) => {
+                if !(0..8).contains(&number) {
+                    return Err(EclErrorKind::InvalidAuxAnm(number));
+                }
+                enemy.interrupt_aux(number as usize, event);
+            }
+
+            // 131
+            SubInstruction::SetDifficultyCoeffs(speed_a, speed_b, nb_a, nb_b, shots_a, shots_b) => {
+                enemy.difficulty_coeffs = DifficultyCoeffs { speed_a, speed_b, nb_a, nb_b, shots_a, shots_b };
+            }
+
+            // 133
+            SubInstruction::CopyCallbacks() => {
+                enemy.timeout_callback = enemy.death_callback;
+            }
+
+            // 135
+            SubInstruction::EnableSpellcardBonus(_) => {
+                // TODO: find what the argument is used for.
+                game.enable_spellcard_bonus();
+            }
+            _ => return Err(EclErrorKind::UnknownOpcode(instruction.opcode())),
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::th06::anm0::Anm0;
-    use crate::th06::ecl::{Sub, CallSub, Rank};
-    use crate::th06::enemy::Game;
-    use std::io::{self, Read};
-    use std::fs::File;
-
-    fn setup() -> (Rc<RefCell<Game>>, Rc<RefCell<Enemy>>) {
-        let file = File::open("EoSD/ST/stg1enm.anm").unwrap();
-        let mut file = io::BufReader::new(file);
-        let mut buf = vec![];
-        file.read_to_end(&mut buf).unwrap();
-        let (_, mut anms) = Anm0::from_slice(&buf).unwrap();
-        let anm0 = anms.pop().unwrap();
-        let anm0 = Rc::new(RefCell::new(anm0));
-        let prng = Rc::new(RefCell::new(Prng::new(0)));
-        let game = Game::new(prng, Rank::EASY);
-        let game = Rc::new(RefCell::new(game));
-        let enemy = Enemy::new(Position::new(0., 0.), 500, 0, 640, Rc::downgrade(&anm0), Rc::downgrade(&game));
-        (game, enemy)
+    use crate::th06::player::Keys;
+    use crate::th06::bullet::Bullet;
+    use crate::th06::enemy::GameState;
+    use crate::th06::spellcard::SpellcardEvent;
+    use crate::th06::testing::{load_stage, load_stage_with_anm0, load_enemy, insert_enemy, enemy_runner};
+    use touhou_formats::th06::anm0::{Anm0, Script, Call, Instruction};
+    use touhou_formats::th06::ecl::{Sub, CallSub, CallMain, DeathFlags, Rank};
+    use touhou_utils::snapshot::Error as SnapshotError;
+    use touhou_utils::prng::Prng;
+
+    #[test]
+    fn integer_variables() {
+        let (mut runner, mut enemy, game) = enemy_runner();
+        let vars = [-10001, -10002, -10003, -10004, -10009, -10010, -10011, -10012];
+        for (i, &var) in vars.iter().enumerate() {
+            runner.set_i32(var, i as i32 + 1, &mut enemy).unwrap();
+        }
+        for (i, &var) in vars.iter().enumerate() {
+            assert_eq!(runner.get_i32(var, &enemy, &game), Ok(i as i32 + 1));
+            assert_eq!(runner.get_f32(var as f32, &enemy, &game), Ok(i as f32 + 1.));
+            runner.set_f32(var as f32, -2.75, &mut enemy).unwrap();
+            assert_eq!(runner.get_i32(var, &enemy, &game), Ok(-2));
+            assert_eq!(runner.get_f32(var as f32, &enemy, &game), Ok(-2.));
+        }
+    }
+
+    #[test]
+    fn float_variables() {
+        let (mut runner, mut enemy, game) = enemy_runner();
+        let vars = [-10005., -10006., -10007., -10008.];
+        for (i, &var) in vars.iter().enumerate() {
+            runner.set_f32(var, i as f32 + 0.5, &mut enemy).unwrap();
+        }
+        for (i, &var) in vars.iter().enumerate() {
+            assert_eq!(runner.get_f32(var, &enemy, &game), Ok(i as f32 + 0.5));
+            assert_eq!(runner.get_i32(var as i32, &enemy, &game), Ok(i as i32));
+            runner.set_i32(var as i32, 42, &mut enemy).unwrap();
+            assert_eq!(runner.get_f32(var, &enemy, &game), Ok(42.));
+        }
+        assert_eq!(runner.frame.ints1, [0; 4]);
+        assert_eq!(runner.frame.ints2, [0; 4]);
+    }
+
+    #[test]
+    fn rank_and_difficulty() {
+        let (runner, enemy, mut game) = enemy_runner();
+        assert_eq!(runner.get_i32(-10013, &enemy, &game), Ok(0));
+        assert_eq!(runner.get_i32(-10014, &enemy, &game), Ok(16));
+        game.difficulty.modify(-250);
+        assert_eq!(runner.get_i32(-10014, &enemy, &game), Ok(13));
+        let game = Game::new(Prng::new(0), Rank::LUNATIC);
+        assert_eq!(runner.get_i32(-10013, &enemy, &game), Ok(3));
+        assert_eq!(runner.get_f32(-10013., &enemy, &game), Ok(3.));
+    }
+
+    #[test]
+    fn difficulty_coeffs() {
+        let (mut runner, mut enemy, mut game) = enemy_runner();
+        enemy.delay_attack = true;
+        // The difficulty starts at 16, halfway through the coefficients.
+        runner.run_instruction(&SubInstruction::SetDifficultyCoeffs(-1., 3., -2, 6, 0, 4), &mut enemy, &mut game).unwrap();
+        let attributes = SubInstruction::SetBulletAttributes1(0, 0, 3, 1, 2., 1., 0., 0., BulletFlags::empty());
+        runner.run_instruction(&attributes, &mut enemy, &mut game).unwrap();
+        let bullet = &enemy.bullet_attributes;
+        assert_eq!((bullet.bullets_per_shot, bullet.number_of_shots), (5, 3));
+        assert_eq!((bullet.speed, bullet.speed2), (3., 1.5));
+    }
+
+    #[test]
+    fn enemy_position() {
+        let (mut runner, mut enemy, game) = enemy_runner();
+        runner.set_f32(-10015., 12.5, &mut enemy).unwrap();
+        runner.set_f32(-10016., 34.5, &mut enemy).unwrap();
+        runner.set_i32(-10017, 56, &mut enemy).unwrap();
+        assert_eq!(enemy.pos, Position::new(12.5, 34.5));
+        assert_eq!(enemy.z, 56.);
+        assert_eq!(runner.get_f32(-10015., &enemy, &game), Ok(12.5));
+        assert_eq!(runner.get_f32(-10016., &enemy, &game), Ok(34.5));
+        assert_eq!(runner.get_i32(-10017, &enemy, &game), Ok(56));
+    }
+
+    #[test]
+    fn player_position_and_angle() {
+        let (mut runner, mut enemy, mut game) = enemy_runner();
+        game.player.pos = Position::new(100., 200.);
+        assert_eq!(runner.get_f32(-10018., &enemy, &game), Ok(100.));
+        assert_eq!(runner.get_i32(-10019, &enemy, &game), Ok(200));
+        assert_eq!(runner.get_f32(-10020., &enemy, &game), Ok(0.));
+        assert_eq!(runner.get_f32(-10021., &enemy, &game), Ok(2f32.atan2(1.)));
+        assert_eq!(runner.set_f32(-10018., 0., &mut enemy), Err(EclErrorKind::ReadOnlyVariable(-10018)));
+        assert_eq!(runner.set_i32(-10019, 0, &mut enemy), Err(EclErrorKind::ReadOnlyVariable(-10019)));
+        assert_eq!(runner.set_i32(-10020, 1, &mut enemy), Err(EclErrorKind::ReadOnlyVariable(-10020)));
+        assert_eq!(runner.set_f32(-10021., 0., &mut enemy), Err(EclErrorKind::ReadOnlyVariable(-10021)));
+    }
+
+    #[test]
+    fn enemy_frame_and_life() {
+        let (mut runner, mut enemy, game) = enemy_runner();
+        assert_eq!(runner.get_i32(-10024, &enemy, &game), Ok(500));
+        runner.set_i32(-10022, 60, &mut enemy).unwrap();
+        runner.set_f32(-10024., 250., &mut enemy).unwrap();
+        assert_eq!(enemy.frame, 60);
+        assert_eq!(enemy.life, 250);
+        assert_eq!(runner.get_f32(-10022., &enemy, &game), Ok(60.));
+        assert_eq!(runner.get_i32(-10024, &enemy, &game), Ok(250));
+
+        // Neither can go below zero.
+        runner.set_i32(-10024, -5, &mut enemy).unwrap();
+        runner.set_f32(-10022., -5., &mut enemy).unwrap();
+        assert_eq!((enemy.frame, enemy.life), (0, 0));
+    }
+
+    #[test]
+    fn player_character() {
+        let (mut runner, mut enemy, mut game) = enemy_runner();
+        game.player.character = 2;
+        assert_eq!(runner.get_i32(-10025, &enemy, &game), Ok(2));
+        assert_eq!(runner.set_i32(-10025, 0, &mut enemy), Err(EclErrorKind::ReadOnlyVariable(-10025)));
+    }
+
+    #[test]
+    fn unknown_variables() {
+        let (mut runner, mut enemy, game) = enemy_runner();
+        assert_eq!(runner.get_i32(-10023, &enemy, &game), Err(EclErrorKind::UnknownVariable(-10023)));
+        assert_eq!(runner.get_f32(-10023., &enemy, &game), Err(EclErrorKind::UnknownVariable(-10023)));
+        assert_eq!(runner.set_i32(-10013, 1, &mut enemy), Err(EclErrorKind::ReadOnlyVariable(-10013)));
+        assert_eq!(runner.set_f32(-10014., 1., &mut enemy), Err(EclErrorKind::ReadOnlyVariable(-10014)));
+        assert_eq!(runner.set_i32(42, 1, &mut enemy), Err(EclErrorKind::UnknownVariable(42)));
+
+        // Anything else is an immediate value.
+        assert_eq!(runner.get_i32(-10000, &enemy, &game), Ok(-10000));
+        assert_eq!(runner.get_f32(-10015.5, &enemy, &game), Ok(-10015.5));
     }
 
     #[test]
     fn call_and_return() {
-        let (game, enemy) = setup();
+        let (mut game, enemy) = insert_enemy();
         let ecl = Ecl { mains: vec![], subs: vec![
             Sub { instructions: vec![
                 CallSub::new(0, Rank::EASY, SubInstruction::Call(1, 13, 12.)),
@@ -1320,14 +1712,599 @@ mod tests {
                 CallSub::new(1, Rank::EASY, SubInstruction::Return()),
             ]},
         ]};
-        let mut ecl_runner = EclRunner::new(&ecl, enemy, 0);
-        ecl_runner.run_frame();
+        let mut ecl_runner = EclRunner::new(Arc::new(ecl), enemy, 0);
+        ecl_runner.run_frame(&mut game).unwrap();
         assert_eq!(ecl_runner.frame.ints1[0], 13);
         assert_eq!(ecl_runner.frame.floats[0], 12.);
         assert_eq!(ecl_runner.stack.len(), 1);
-        ecl_runner.run_frame();
+        ecl_runner.run_frame(&mut game).unwrap();
         assert_eq!(ecl_runner.frame.ints1[0], 0);
         assert_eq!(ecl_runner.frame.floats[0], 0.);
         assert_eq!(ecl_runner.stack.len(), 0);
     }
+
+    fn run_sub(instructions: Vec<CallSub>) -> (EclRunner, Result<(), EclError>) {
+        let (mut game, enemy) = insert_enemy();
+        let ecl = Ecl { mains: vec![], subs: vec![Sub { instructions }] };
+        let mut ecl_runner = EclRunner::new(Arc::new(ecl), enemy, 0);
+        let result = ecl_runner.run_frame(&mut game);
+        (ecl_runner, result)
+    }
+
+    #[test]
+    fn script_errors() {
+        let (runner, result) = run_sub(vec![
+            CallSub::new(0, Rank::EASY, SubInstruction::Noop()),
+            CallSub::new(0, Rank::EASY, SubInstruction::Call(0, 0, 0.)),
+        ]);
+        assert_eq!(result, Err(EclError { sub: 0, ip: 1, frame: 0, kind: EclErrorKind::StackOverflow }));
+        assert_eq!(runner.stack.len(), MAX_STACK_DEPTH);
+        assert!(!runner.running);
+
+        let (_, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::Return())]);
+        assert_eq!(result.unwrap_err().kind, EclErrorKind::StackUnderflow);
+        let (_, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::Call(3, 0, 0.))]);
+        assert_eq!(result.unwrap_err().kind, EclErrorKind::InvalidSub(3));
+        let (_, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::RelativeJump(0, 2))]);
+        assert_eq!(result.unwrap_err().kind, EclErrorKind::InvalidJump(2));
+        let (_, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::MemoryWriteInt(1, 4))]);
+        assert_eq!(result.unwrap_err().kind, EclErrorKind::UnknownMemoryWrite(4));
+        let (_, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::CallSpecialFunction(42, 0))]);
+        assert_eq!(result.unwrap_err().kind, EclErrorKind::UnknownSpecialFunction(42));
+        let (_, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::SetAuxAnm(8, 0))]);
+        assert_eq!(result.unwrap_err().kind, EclErrorKind::InvalidAuxAnm(8));
+        let (_, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::SetAuxAnm(2, 0))]);
+        assert_eq!(result.unwrap_err().kind, EclErrorKind::InvalidAnim(0));
+        let (_, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::SetAnim(-1))]);
+        assert_eq!(result.unwrap_err().kind, EclErrorKind::InvalidAnim(-1));
+        let (_, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::SetMultipleAnims(0, 1, 2, 3, 4, 0))]);
+        assert_eq!(result.unwrap_err().kind, EclErrorKind::InvalidAnim(0));
+        let (_, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::UNK_ins134())]);
+        let error = result.unwrap_err();
+        assert_eq!(error.kind, EclErrorKind::UnknownOpcode(134));
+        assert_eq!(error.to_string(), "unknown opcode 134 (sub 0, instruction 0, frame 0)");
+
+        let (_, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::SetRandomInt(-10001, 0))]);
+        assert_eq!(result.unwrap_err().kind, EclErrorKind::DivisionByZero);
+        let (_, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::SetRandomIntMin(-10001, 0, 5))]);
+        assert_eq!(result.unwrap_err().kind, EclErrorKind::DivisionByZero);
+        let (_, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::DivideInt(-10001, 7, 0))]);
+        assert_eq!(result.unwrap_err().kind, EclErrorKind::DivisionByZero);
+        let (_, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::ModuloInt(-10001, 7, 0))]);
+        assert_eq!(result.unwrap_err().kind, EclErrorKind::DivisionByZero);
+
+        // Overflows wrap around instead.
+        let (runner, result) = run_sub(vec![
+            CallSub::new(0, Rank::EASY, SubInstruction::SetInt(-10001, i32::MIN)),
+            CallSub::new(0, Rank::EASY, SubInstruction::DivideInt(-10002, -10001, -1)),
+            CallSub::new(0, Rank::EASY, SubInstruction::ModuloInt(-10003, -10001, -1)),
+            CallSub::new(0, Rank::EASY, SubInstruction::Decrement(-10001)),
+            CallSub::new(0, Rank::EASY, SubInstruction::MultiplyInt(-10004, i32::MAX, 2)),
+        ]);
+        result.unwrap();
+        assert_eq!(runner.frame.ints1, [i32::MAX, i32::MIN, 0, -2]);
+
+        // Without any stage, there is no sub to spawn enemies with.
+        let (_, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::SpawnEnemy(0, 0., 0., 0., 10, -1, 100))]);
+        assert_eq!(result.unwrap_err().kind, EclErrorKind::NoStage);
+        let (mut game, _) = insert_enemy();
+        let error = game.spawn_enemy(3, Position::new(0., 0.), 10, -1, 100, false).unwrap_err();
+        assert_eq!(error, EclError { sub: 3, ip: 0, frame: 0, kind: EclErrorKind::NoStage });
+        assert_eq!(game.enemies().count(), 1);
+
+        // Jumping right after the last instruction just ends the sub.
+        let (runner, result) = run_sub(vec![CallSub::new(0, Rank::EASY, SubInstruction::RelativeJump(5, 1))]);
+        result.unwrap();
+        assert!(!runner.running);
+    }
+
+    #[test]
+    fn counted_loop() {
+        let (runner, result) = run_sub(vec![
+            CallSub::new(0, Rank::EASY, SubInstruction::SetInt(-10001, 3)),
+            CallSub::new(0, Rank::EASY, SubInstruction::AddInt(-10002, -10002, 1)),
+            CallSub::new(0, Rank::EASY, SubInstruction::RelativeJumpEx(0, 1, -10001)),
+        ]);
+        result.unwrap();
+        // The body runs once per count, the counter is left at 1 once the loop is over.
+        assert_eq!(runner.frame.ints1[1], 3);
+        assert_eq!(runner.frame.ints1[0], 1);
+        assert!(!runner.running);
+    }
+
+    #[test]
+    fn failing_enemies() {
+        let ecl = Ecl {
+            mains: vec![Main { instructions: vec![
+                CallMain { time: 0, sub: 0, instr: MainInstruction::SpawnEnemy(100., 100., 0., 10, -1, 100) },
+                CallMain { time: 0, sub: 1, instr: MainInstruction::SpawnEnemy(200., 100., 0., 10, -1, 100) },
+            ]}],
+            subs: vec![
+                Sub { instructions: vec![
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetSpeed(1.)),
+                    CallSub::new(2, Rank::EASY, SubInstruction::SetInt(-10018, 0)),
+                ]},
+                Sub { instructions: vec![
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetSpeed(1.)),
+                    CallSub::new(5, Rank::EASY, SubInstruction::SpawnEnemy(2, 0., 0., 0., 10, -1, 100)),
+                ]},
+                Sub { instructions: vec![
+                    CallSub::new(0, Rank::EASY, SubInstruction::Return()),
+                ]},
+            ],
+        };
+        let mut game = load_stage(0, ecl);
+        game.run_frame(Keys::empty()).unwrap();
+        let error = game.run_frame(Keys::empty()).unwrap_err();
+        assert_eq!(error, EclError { sub: 0, ip: 1, frame: 2, kind: EclErrorKind::ReadOnlyVariable(-10018) });
+
+        // The other enemy keeps running, and fails in its child.
+        game.run_frame(Keys::empty()).unwrap();
+        game.run_frame(Keys::empty()).unwrap();
+        let error = game.run_frame(Keys::empty()).unwrap_err();
+        let child = EclError { sub: 2, ip: 0, frame: 0, kind: EclErrorKind::StackUnderflow };
+        assert_eq!(error.kind, EclErrorKind::SpawnedEnemy(Box::new(child)));
+        assert_eq!(game.ecl_runners.len(), 3);
+        assert!(game.ecl_runners.iter().all(|runner| !runner.running));
+
+        // Stopped enemies keep moving.
+        let x = game.enemies().map(|enemy| enemy.pos.x).sum::<f32>();
+        game.run_frame(Keys::empty()).unwrap();
+        assert!(game.enemies().map(|enemy| enemy.pos.x).sum::<f32>() > x);
+    }
+
+    #[test]
+    fn main_spawns_enemies() {
+        let ecl = Ecl {
+            mains: vec![Main { instructions: vec![
+                CallMain { time: 1, sub: 0, instr: MainInstruction::SpawnEnemy(42., 64., 0., 10, -1, 100) },
+                CallMain { time: 1, sub: 0, instr: MainInstruction::SpawnEnemyMirrored(42., 64., 0., 10, -1, 100) },
+            ]}],
+            subs: vec![
+                Sub { instructions: vec![
+                    CallSub::new(0, Rank::EASY, SubInstruction::Noop()),
+                ]},
+            ],
+        };
+        let mut game = load_stage(0, ecl);
+        game.run_frame(Keys::empty()).unwrap();
+        assert_eq!(game.ecl_runners.len(), 0);
+        game.run_frame(Keys::empty()).unwrap();
+        assert_eq!(game.ecl_runners.len(), 2);
+        let enemy = &game.enemies[game.ecl_runners[0].enemy];
+        assert_eq!(enemy.pos, Position::new(42., 64.));
+        assert_eq!(enemy.life, 10);
+    }
+
+    #[test]
+    fn forked_games_stay_identical() {
+        let ecl = Ecl {
+            mains: vec![Main { instructions: vec![
+                CallMain { time: 0, sub: 0, instr: MainInstruction::SpawnEnemy(192., 100., 0., 100, -1, 100) },
+            ]}],
+            subs: vec![
+                Sub { instructions: vec![
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetRandomAngle(0., 3.)),
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetSpeed(0.5)),
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetBulletAttributes1(0, 0, 3, 1, 2., 1., 0., 0.1, BulletFlags::empty())),
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetBulletIntervalEx(7)),
+                    CallSub::new(30, Rank::EASY, SubInstruction::RelativeJump(0, 0)),
+                ]},
+            ],
+        };
+        let mut game = load_stage(1234, ecl);
+        for _ in 0..50 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+
+        // Run a copy of the game on another thread, it must end up in the same state.
+        let mut fork = game.clone();
+        let fork = std::thread::spawn(move || {
+            for _ in 0..100 {
+                fork.run_frame(Keys::empty()).unwrap();
+            }
+            fork
+        }).join().unwrap();
+        for _ in 0..100 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+
+        let positions = |game: &Game| -> Vec<Position> {
+            game.bullets().map(|bullet| bullet.pos)
+                .chain(game.enemies().map(|enemy| enemy.pos))
+                .chain(game.items().map(|item| item.pos))
+                .collect()
+        };
+        assert!(game.bullets().next().is_some());
+        assert_eq!(positions(&game), positions(&fork));
+        assert_eq!(game.player().score, fork.player().score);
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let ecl = Ecl {
+            mains: vec![Main { instructions: vec![
+                CallMain { time: 0, sub: 0, instr: MainInstruction::SpawnEnemy(192., 100., 0., 100, -1, 100) },
+                CallMain { time: 40, sub: 0, instr: MainInstruction::SpawnEnemyRandom(-999., 50., 0., 100, -1, 100) },
+            ]}],
+            subs: vec![
+                Sub { instructions: vec![
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetAnim(0)),
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetRandomAngle(0., 3.)),
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetSpeed(0.5)),
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetBulletAttributes1(0, 0, 3, 1, 2., 1., 0., 0.1, BulletFlags::empty())),
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetBulletIntervalEx(7)),
+                    CallSub::new(20, Rank::EASY, SubInstruction::Call(1, 3, 0.)),
+                    CallSub::new(30, Rank::EASY, SubInstruction::RelativeJump(0, 0)),
+                ]},
+                Sub { instructions: vec![
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetRandomAngle(3., 6.)),
+                    CallSub::new(15, Rank::EASY, SubInstruction::Return()),
+                ]},
+            ],
+        };
+        let mut anm0 = Anm0::default();
+        anm0.scripts.insert(0, Script { instructions: vec![
+            Call { time: 0, instr: Instruction::LoadRandomSprite(0, 4) },
+            Call { time: 0, instr: Instruction::Fade(0, 40) },
+            Call { time: 10, instr: Instruction::Jump(0) },
+        ], interrupts: Default::default() });
+        let mut game = load_stage_with_anm0(4321, ecl, anm0);
+        for _ in 0..30 {
+            game.run_frame(Keys::SHOOT).unwrap();
+        }
+
+        let state = game.snapshot();
+        for _ in 0..60 {
+            game.run_frame(Keys::SHOOT).unwrap();
+        }
+        let expected = game.snapshot();
+        assert!(game.bullets().next().is_some());
+        assert!(game.enemies().all(|enemy| enemy.anmrunner.is_some()));
+
+        // Going back in time, the same frames must lead to the same state again.
+        game.restore(&state).unwrap();
+        assert_eq!(game.snapshot(), state);
+        for _ in 0..60 {
+            game.run_frame(Keys::SHOOT).unwrap();
+        }
+        assert_eq!(game.snapshot(), expected);
+
+        // Broken snapshots get rejected, without changing the game.
+        let truncated = GameState::from_bytes(state.as_bytes()[..100].to_vec());
+        assert_eq!(game.restore(&truncated), Err(SnapshotError::UnexpectedEnd));
+        assert_eq!(game.snapshot(), expected);
+    }
+
+    #[test]
+    fn timeout_callback() {
+        let ecl = Ecl {
+            mains: vec![Main { instructions: vec![
+                CallMain { time: 0, sub: 0, instr: MainInstruction::SpawnEnemy(192., 100., 0., 100, -1, 100) },
+                CallMain { time: 0, sub: 2, instr: MainInstruction::SpawnEnemy(100., 100., 0., 100, -1, 100) },
+            ]}],
+            subs: vec![
+                Sub { instructions: vec![
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetLowLifeTrigger(30)),
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetTimeout(10)),
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetTimeoutCallback(1)),
+                ]},
+                Sub { instructions: vec![
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetInt(-10001, 7)),
+                ]},
+                Sub { instructions: vec![] },
+            ],
+        };
+        let mut game = load_stage(0, ecl);
+        for _ in 0..10 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+        assert_eq!(game.ecl_runners.len(), 2);
+        assert_eq!(game.ecl_runners[0].frame.ints1[0], 0);
+
+        game.run_frame(Keys::empty()).unwrap();
+        game.run_frame(Keys::empty()).unwrap();
+        // The other enemy got killed, and the timeout callback replaced the sub.
+        assert_eq!(game.ecl_runners.len(), 1);
+        let runner = &game.ecl_runners[0];
+        assert_eq!(runner.frame.sub, 1);
+        assert_eq!(runner.frame.ints1[0], 7);
+        let enemy = &game.enemies[runner.enemy];
+        assert_eq!(enemy.life, 30);
+        assert_eq!(enemy.timeout, None);
+        assert_eq!(enemy.low_life_trigger, None);
+    }
+
+    #[test]
+    fn timed_out_spellcard() {
+        let mut game = load_enemy(vec![
+            vec![
+                CallSub::new(0, Rank::EASY, SubInstruction::SetSpellcard(0, 3, String::from("Moon Sign 「Moonlight Ray」"))),
+                CallSub::new(0, Rank::EASY, SubInstruction::SetTimeout(5)),
+                CallSub::new(0, Rank::EASY, SubInstruction::SetTimeoutCallback(1)),
+            ],
+            vec![
+                CallSub::new(0, Rank::EASY, SubInstruction::EndSpellcard()),
+            ],
+        ]);
+        game.run_frame(Keys::empty()).unwrap();
+        assert!(game.spellcard().unwrap().capturing);
+        let events: Vec<_> = (0..8).flat_map(|_| {
+            game.run_frame(Keys::empty()).unwrap();
+            game.spellcard_events().to_vec()
+        }).collect();
+        assert_eq!(events, [SpellcardEvent::Failed(3)]);
+        assert!(game.spellcard().is_none());
+        assert_eq!(game.player().score, 0);
+    }
+
+    #[test]
+    fn low_life_and_death_callbacks() {
+        let mut game = load_enemy(vec![
+            vec![
+                CallSub::new(0, Rank::EASY, SubInstruction::SetLowLifeTrigger(50)),
+                CallSub::new(0, Rank::EASY, SubInstruction::SetLowLifeCallback(1)),
+                CallSub::new(0, Rank::EASY, SubInstruction::SetDeathCallback(2)),
+                CallSub::new(0, Rank::EASY, SubInstruction::CopyCallbacks()),
+                CallSub::new(5, Rank::EASY, SubInstruction::SetLife(20)),
+            ],
+            vec![
+                CallSub::new(0, Rank::EASY, SubInstruction::SetDeathFlags(DeathFlags::STAY_UNTOUCHABLE)),
+                CallSub::new(2, Rank::EASY, SubInstruction::SetLife(0)),
+            ],
+            vec![
+                CallSub::new(0, Rank::EASY, SubInstruction::SetInt(-10001, 2)),
+            ],
+        ]);
+        game.run_frame(Keys::empty()).unwrap();
+        let id = game.ecl_runners[0].enemy;
+        assert_eq!(game.enemies[id].timeout_callback, Some(2));
+
+        for _ in 0..4 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+        assert_eq!(game.enemies[id].life, 20);
+        assert_eq!(game.enemies[id].pending_callback, Some(1));
+        assert_eq!(game.enemies[id].timeout_callback, None);
+
+        for _ in 0..4 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+        assert_eq!(game.ecl_runners[0].frame.sub, 2);
+        assert_eq!(game.ecl_runners[0].frame.ints1[0], 2);
+        assert!(!game.enemies[id].touchable);
+    }
+
+    #[test]
+    fn kill_all_enemies() {
+        let (mut runner, mut enemy, mut game) = enemy_runner();
+        let mut untouchable = Enemy::new(Position::new(0., 0.), 100, 0, 0, false, None);
+        untouchable.touchable = false;
+        untouchable.death_callback = Some(3);
+        let untouchable = game.enemies.insert(untouchable);
+        let mut boss = Enemy::new(Position::new(0., 0.), 100, 0, 0, false, None);
+        boss.boss = true;
+        let boss = game.enemies.insert(boss);
+        runner.run_instruction(&SubInstruction::KillAllEnemies(), &mut enemy, &mut game).unwrap();
+        assert_eq!(enemy.life, 0);
+        assert_eq!(game.enemies[untouchable].life, 100);
+        assert_eq!(game.enemies[untouchable].pending_callback, Some(3));
+        assert_eq!(game.enemies[boss].life, 100);
+        assert_eq!(game.enemies[boss].pending_callback, None);
+    }
+
+    #[test]
+    fn boss_callback() {
+        let (mut runner, mut enemy, mut game) = enemy_runner();
+        runner.run_instruction(&SubInstruction::MemoryWriteInt(4, 0), &mut enemy, &mut game).unwrap();
+        assert_eq!(enemy.boss_callback, Some(4));
+        enemy.boss = true;
+        game.enemies.put_back(runner.enemy, enemy);
+        game.resume_boss();
+        assert_eq!(game.enemies[runner.enemy].pending_callback, Some(4));
+        assert_eq!(game.enemies[runner.enemy].boss_callback, None);
+    }
+
+    #[test]
+    fn child_enemies() {
+        let ecl = Ecl {
+            mains: vec![Main { instructions: vec![
+                CallMain { time: 0, sub: 0, instr: MainInstruction::SpawnEnemyMirrored(192., 100., 0., 100, -1, 100) },
+            ]}],
+            subs: vec![
+                Sub { instructions: vec![
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetTouchable(0)),
+                    CallSub::new(2, Rank::EASY, SubInstruction::SpawnEnemy(1, 100., 120., 0., 10, -1, 50)),
+                    CallSub::new(20, Rank::EASY, SubInstruction::KillAllEnemies()),
+                ]},
+                Sub { instructions: vec![
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetSpeed(1.)),
+                    CallSub::new(5, Rank::EASY, SubInstruction::SetInt(-10001, 5)),
+                ]},
+            ],
+        };
+        let mut game = load_stage(0, ecl);
+        for _ in 0..10 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+        assert_eq!(game.ecl_runners.len(), 2);
+        let child = &game.ecl_runners[1];
+        assert_eq!(child.frame.ints1[0], 5);
+        let child = &game.enemies[child.enemy];
+        assert!(child.mirror);
+        assert!(child.pos.x < 100.);
+        assert_eq!(child.pos.y, 120.);
+
+        for _ in 0..10 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+        assert_eq!(game.ecl_runners.len(), 1);
+        assert_eq!(game.enemies().count(), 1);
+    }
+
+    #[test]
+    fn aux_anms_and_particles() {
+        let ecl = Ecl {
+            mains: vec![Main { instructions: vec![
+                CallMain { time: 0, sub: 0, instr: MainInstruction::SpawnEnemy(192., 100., 0., 100, -1, 100) },
+            ]}],
+            subs: vec![
+                Sub { instructions: vec![
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetSpeed(1.)),
+                    CallSub::new(0, Rank::EASY, SubInstruction::SetAuxAnm(2, 0)),
+                    CallSub::new(0, Rank::EASY, SubInstruction::DropParticles(3, 5, 0, 0, 0, 0)),
+                    CallSub::new(10, Rank::EASY, SubInstruction::InterruptAux(2, 1)),
+                ]},
+            ],
+        };
+        let mut anm0 = Anm0::default();
+        anm0.scripts.insert(0, Script { instructions: vec![
+            Call { time: 0, instr: Instruction::LoadSprite(0) },
+            Call { time: 100, instr: Instruction::Delete() },
+            Call { time: 0, instr: Instruction::Delete() },
+        ], interrupts: [(1, 2)].iter().cloned().collect() });
+        let mut game = load_stage_with_anm0(0, ecl, anm0.clone());
+        let script = anm0.scripts[&0].clone();
+        anm0.scripts.insert(6, script);
+        game.load_effects(anm0);
+
+        for _ in 0..5 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+        let enemy = game.enemies().next().unwrap();
+        assert_eq!(game.get_sprites().len(), 1);
+        assert_eq!((game.get_sprites()[0].0, game.get_sprites()[0].1), (enemy.pos.x, enemy.pos.y));
+        assert_eq!(game.effects().count(), 5);
+
+        let state = game.snapshot();
+        let mut copy = game.clone();
+        copy.restore(&state).unwrap();
+        assert_eq!(copy.snapshot(), state);
+
+        for _ in 0..7 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+        assert!(game.get_sprites().is_empty());
+        assert_eq!(game.effects().count(), 5);
+    }
+
+    fn still_bullet(x: f32, y: f32) -> Bullet {
+        Bullet::new(Position::new(x, y), 0, 0, 0., 0., Default::default(), BulletFlags::empty())
+    }
+
+    #[test]
+    fn special_function_callback() {
+        let (runner, mut enemy, mut game) = enemy_runner();
+        let ecl = Arc::new(Ecl { mains: vec![], subs: vec![Sub { instructions: vec![] }] });
+        let mut runner = EclRunner::new(ecl, runner.enemy, 0);
+        runner.run_instruction(&SubInstruction::SetSpecialFunctionCallback(0), &mut enemy, &mut game).unwrap();
+        // The sub is over, but the callback keeps running on each frame, freezing new bullets.
+        for _ in 0..3 {
+            game.bullets.push(Bullet::new(Position::new(100., 100.), 0, 0, 0., 2., Default::default(), BulletFlags::empty()));
+            runner.run_enemy_frame(&mut enemy, &mut game).unwrap();
+        }
+        assert!(!runner.running);
+        assert!(game.bullets.iter().all(|bullet| bullet.speed == 0.));
+
+        runner.run_instruction(&SubInstruction::SetSpecialFunctionCallback(-1), &mut enemy, &mut game).unwrap();
+        game.bullets.push(Bullet::new(Position::new(100., 100.), 0, 0, 0., 2., Default::default(), BulletFlags::empty()));
+        runner.run_enemy_frame(&mut enemy, &mut game).unwrap();
+        assert_eq!(game.bullets[3].speed, 2.);
+
+        // The functions which haven’t been reversed yet do nothing, but keep getting called.
+        for function in [2, 5, 6, 10, 15] {
+            runner.run_instruction(&SubInstruction::SetSpecialFunctionCallback(function), &mut enemy, &mut game).unwrap();
+            runner.run_enemy_frame(&mut enemy, &mut game).unwrap();
+            assert_eq!(enemy.special_callback, Some(function));
+        }
+
+        // Unknown ones fail, once.
+        runner.run_instruction(&SubInstruction::SetSpecialFunctionCallback(42), &mut enemy, &mut game).unwrap();
+        let error = runner.run_enemy_frame(&mut enemy, &mut game).unwrap_err();
+        assert_eq!(error.kind, EclErrorKind::UnknownSpecialFunction(42));
+        assert_eq!(enemy.special_callback, None);
+        runner.run_enemy_frame(&mut enemy, &mut game).unwrap();
+    }
+
+    #[test]
+    fn play_sound() {
+        let (mut runner, mut enemy, mut game) = enemy_runner();
+        runner.run_instruction(&SubInstruction::PlaySound(3), &mut enemy, &mut game).unwrap();
+        runner.run_instruction(&SubInstruction::PlaySound(5), &mut enemy, &mut game).unwrap();
+        assert_eq!(game.sounds(), [3, 5]);
+        game.run_frame(Keys::empty()).unwrap();
+        assert!(game.sounds().is_empty());
+    }
+
+    #[test]
+    fn patchouli_elements() {
+        let (mut runner, mut enemy, mut game) = enemy_runner();
+        game.player.character = 3;
+        runner.run_instruction(&SubInstruction::CallSpecialFunction(3, 0), &mut enemy, &mut game).unwrap();
+        assert_eq!(runner.frame.ints1, [0, 4, 2, 3]);
+    }
+
+    #[test]
+    fn time_stop_and_daggers() {
+        let (mut runner, mut enemy, mut game) = enemy_runner();
+        for i in 0..40 {
+            let mut bullet = still_bullet(i as f32 * 8., 100.);
+            bullet.speed = 2.;
+            bullet.dpos = [2., 0., 0.];
+            game.bullets.push(bullet);
+        }
+        runner.run_instruction(&SubInstruction::CallSpecialFunction(4, 1), &mut enemy, &mut game).unwrap();
+        assert!(game.is_time_stopped());
+        let positions: Vec<_> = game.bullets.iter().map(|bullet| bullet.pos).collect();
+        game.run_frame(Keys::empty()).unwrap();
+        assert_eq!(game.bullets.iter().map(|bullet| bullet.pos).collect::<Vec<_>>(), positions);
+
+        runner.frame.ints1[2] = 1;
+        runner.run_instruction(&SubInstruction::CallSpecialFunction(4, 2), &mut enemy, &mut game).unwrap();
+        let manipulated = game.bullets.iter().filter(|bullet| bullet.manipulated).count();
+        assert!(manipulated > 0 && manipulated <= 14);
+        assert_eq!(runner.frame.ints1[2], 0);
+
+        runner.run_instruction(&SubInstruction::CallSpecialFunction(4, 0), &mut enemy, &mut game).unwrap();
+        assert!(!game.is_time_stopped());
+        game.run_frame(Keys::empty()).unwrap();
+        assert!(game.bullets.iter().zip(positions).all(|(bullet, pos)| bullet.pos != pos));
+    }
+
+    #[test]
+    fn frozen_bullets_restart() {
+        let (mut runner, mut enemy, mut game) = enemy_runner();
+        for i in 0..10 {
+            let mut bullet = still_bullet(i as f32 * 20., 100.);
+            bullet.speed = 3.;
+            bullet.dpos = [3., 0., 0.];
+            game.bullets.push(bullet);
+        }
+        runner.run_instruction(&SubInstruction::CallSpecialFunction(0, 0), &mut enemy, &mut game).unwrap();
+        assert!(game.bullets.iter().all(|bullet| bullet.speed == 0. && bullet.dpos == [0., 0., 0.]));
+        for function in [9, 11].iter() {
+            runner.run_instruction(&SubInstruction::CallSpecialFunction(*function, 0), &mut enemy, &mut game).unwrap();
+        }
+        for bullet in game.bullets.iter() {
+            assert!(bullet.flags.contains(BulletFlags::ACCELERATION));
+            assert!(bullet.manipulated);
+            assert_eq!(bullet.speed, 0.01);
+        }
+    }
+
+    #[test]
+    fn laser_webs() {
+        let (mut runner, mut enemy, mut game) = enemy_runner();
+        runner.run_instruction(&SubInstruction::CallSpecialFunction(7, 0), &mut enemy, &mut game).unwrap();
+        assert_eq!(game.lasers.len(), 48);
+        assert!(game.bullets.is_empty());
+        runner.run_instruction(&SubInstruction::CallSpecialFunction(7, 1), &mut enemy, &mut game).unwrap();
+        assert_eq!(game.bullets.len(), 48);
+
+        // Patchouli’s bullets get fired from around the lasers of this enemy.
+        let bullets = game.bullets.len();
+        runner.run_instruction(&SubInstruction::SetSpecialFunctionCallback(12), &mut enemy, &mut game).unwrap();
+        runner.call_special_function(12, 0, &mut enemy, &mut game).unwrap();
+        assert_eq!(game.bullets.len(), bullets + 1);
+    }
 }
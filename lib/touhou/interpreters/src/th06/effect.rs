@@ -0,0 +1,191 @@
+//! Module providing the effects displayed on top of the game, like explosions and particles.
+
+use crate::th06::anm0::{AnmRunner, Sprite};
+use crate::th06::bullet::MAX_BULLETS;
+use crate::th06::enemy::{Game, Position};
+use crate::th06::interpolator::{Formula, Interpolator2};
+use touhou_formats::th06::anm0::Anm0;
+use touhou_utils::prng::Prng;
+use touhou_utils::snapshot::{Error, Load, Reader, Save};
+use std::sync::Arc;
+
+/// Maximum amount of effects at the same time, the original game shares it with bullets.
+const MAX_EFFECTS: usize = MAX_BULLETS;
+
+/// Amount of frames a particle takes to reach its destination.
+const PARTICLE_DURATION: u32 = 24;
+
+/// Struct representing an animation played once, at a fixed position or moving as a particle.
+#[derive(Clone)]
+pub struct Effect {
+    /// Current position of the effect.
+    pub pos: Position,
+
+    /// Frames since this effect got created.
+    pub frame: u32,
+
+    pub(crate) removed: bool,
+    anmrunner: AnmRunner,
+    pos_interpolator: Option<Interpolator2<f32>>,
+}
+
+impl Effect {
+    fn new(pos: Position, anim: u8, anms: Arc<[Anm0]>, prng: &mut Prng) -> Effect {
+        Effect {
+            pos,
+            frame: 0,
+            removed: false,
+            anmrunner: AnmRunner::new(anms, anim, Sprite::new(), prng, 0),
+            pos_interpolator: None,
+        }
+    }
+
+    /// Returns the sprite of this effect.
+    pub fn get_sprite(&self) -> &Sprite {
+        self.anmrunner.get_sprite()
+    }
+
+    /// Run one frame of its animation and movement.
+    pub(crate) fn update(&mut self, prng: &mut Prng) {
+        self.anmrunner.run_frame(prng);
+        if self.anmrunner.get_sprite().is_removed() {
+            self.removed = true;
+        }
+
+        if let Some(interpolator) = &self.pos_interpolator {
+            let [x, y] = interpolator.values(self.frame);
+            self.pos = Position::new(x, y);
+        }
+
+        self.frame += 1;
+    }
+}
+
+impl Save for Effect {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.pos.save(out);
+        self.frame.save(out);
+        self.removed.save(out);
+        self.anmrunner.save(out);
+        self.pos_interpolator.save(out);
+    }
+}
+
+/// The scripts are taken from the effect ANM of the game.
+impl Load<Game> for Effect {
+    fn load(reader: &mut Reader, game: &Game) -> Result<Effect, Error> {
+        let anms = game.effects_anm0.clone().ok_or(Error::MissingData("effects anm0"))?;
+        Ok(Effect {
+            pos: Load::load(reader, game)?,
+            frame: Load::load(reader, game)?,
+            removed: Load::load(reader, game)?,
+            anmrunner: AnmRunner::load_with(reader, game, anms)?,
+            pos_interpolator: Load::load(reader, game)?,
+        })
+    }
+}
+
+impl Game {
+    /// Load the ANM containing the effects, etama4.anm in the original game.
+    pub fn load_effects(&mut self, anm0: Anm0) {
+        self.effects_anm0 = Some(Arc::new([anm0]));
+    }
+
+    /// Play this animation `number` times at this position.
+    ///
+    /// Nothing gets displayed until `load_effects` got called.
+    pub(crate) fn new_effect(&mut self, pos: Position, anim: u8, number: usize) {
+        let number = number.min(MAX_EFFECTS.saturating_sub(self.effects.len()));
+        if let Some(anms) = self.effects_anm0.clone() {
+            for _ in 0..number {
+                self.effects.push(Effect::new(pos, anim, anms.clone(), &mut self.prng));
+            }
+        }
+    }
+
+    /// Throw `number` particles from this position, each towards a random point in a square of
+    /// `amplitude` pixels around it.
+    pub(crate) fn new_particles(&mut self, pos: Position, anim: u8, amplitude: f32, number: usize) {
+        let number = number.min(MAX_EFFECTS.saturating_sub(self.effects.len()));
+        for _ in 0..number {
+            // The destination is drawn even without any effect loaded, to keep the game in sync.
+            let x = pos.x + amplitude * self.prng.get_f64() as f32 - amplitude / 2.;
+            let y = pos.y + amplitude * self.prng.get_f64() as f32 - amplitude / 2.;
+            if let Some(anms) = self.effects_anm0.clone() {
+                let mut effect = Effect::new(pos, anim, anms, &mut self.prng);
+                effect.pos_interpolator = Some(Interpolator2::new([pos.x, pos.y], 0, [x, y], PARTICLE_DURATION, Formula::InvertPower2));
+                self.effects.push(effect);
+            }
+        }
+    }
+
+    /// Play the explosion of a dying enemy.
+    pub(crate) fn enemy_death_effect(&mut self, pos: Position, death_anim: u8) {
+        // The original game crashes on any other value.
+        if death_anim <= 2 {
+            self.new_effect(pos, death_anim + 3, 1);
+        }
+    }
+
+    /// Throw the particles of a dying enemy dropping its bonus.
+    pub(crate) fn enemy_death_particles(&mut self, pos: Position) {
+        // TODO: find the real size, and use colour 3 on stages 1, 2 and 7.
+        self.new_particles(pos, 9, 256., 7);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::th06::player::Keys;
+    use crate::th06::testing::new_game;
+    use touhou_formats::th06::anm0::{Call, Instruction, Script};
+
+    fn effects_anm0() -> Anm0 {
+        let mut anm0 = Anm0::default();
+        for anim in 0..10 {
+            anm0.scripts.insert(anim, Script { instructions: vec![
+                Call { time: 0, instr: Instruction::LoadSprite(0) },
+                Call { time: 30, instr: Instruction::Delete() },
+            ], interrupts: Default::default() });
+        }
+        anm0
+    }
+
+    #[test]
+    fn death_effects() {
+        let mut game = new_game();
+        let pos = Position::new(100., 100.);
+        game.enemy_death_effect(pos, 1);
+        game.enemy_death_particles(pos);
+        assert_eq!(game.effects().count(), 0);
+
+        game.load_effects(effects_anm0());
+        game.enemy_death_effect(pos, 1);
+        game.enemy_death_effect(pos, 7);
+        game.enemy_death_particles(pos);
+        assert_eq!(game.effects().count(), 8);
+
+        for effect in game.effects.iter_mut() {
+            for _ in 0..PARTICLE_DURATION {
+                effect.update(&mut Prng::new(0));
+            }
+        }
+        assert_eq!(game.effects[0].pos, pos);
+        assert!(game.effects[1..].iter().all(|effect| effect.pos != pos && (effect.pos.x - pos.x).abs() <= 128.));
+
+        for _ in 0..30 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+        assert_eq!(game.effects().count(), 0);
+    }
+
+    #[test]
+    fn limit() {
+        let mut game = new_game();
+        game.load_effects(effects_anm0());
+        game.new_effect(Position::new(0., 0.), 4, MAX_EFFECTS - 10);
+        game.new_particles(Position::new(0., 0.), 9, 256., 20);
+        assert_eq!(game.effects().count(), MAX_EFFECTS);
+    }
+}
@@ -1,13 +1,29 @@
 //! Module providing an Enemy struct, to be changed by EclRunner.
 
 use touhou_formats::th06::anm0::Anm0;
-use touhou_formats::th06::ecl::Rank;
+use touhou_formats::th06::sht::Sht;
+use touhou_formats::th06::ecl::{Ecl, Rank, BulletFlags, DeathFlags, DifficultyCoeffs};
+use touhou_formats::th06::msg::Msg;
 use crate::th06::anm0::{Sprite, AnmRunner};
+use crate::th06::bullet::{Bullet, GAME_WIDTH, GAME_HEIGHT};
+use crate::th06::difficulty::Difficulty;
+use crate::th06::ecl::{EclRunner, EclError, EclErrorKind, MainRunner};
+use crate::th06::effect::Effect;
+use crate::th06::item::Item;
+use crate::th06::laser::{Laser, LaserAttributes, MAX_LASERS};
+use crate::th06::msg::MsgRunner;
+use crate::th06::player::{Player, PlayerBullet, PlayerEvent, Keys};
 use crate::th06::interpolator::{Interpolator1, Interpolator2};
+use crate::th06::spellcard::{Spellcard, SpellcardEvent};
+use crate::th06::std::StageRunner;
+use crate::th06::trace::SharedTracer;
+use touhou_utils::arena::{Arena, Id};
+use touhou_utils::impl_snapshot;
 use touhou_utils::prng::Prng;
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::{Rc, Weak};
+use touhou_utils::snapshot::{Save, Load, Reader, Error};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
 
 /// The 2D position of an object in the game.
 #[derive(Debug, Clone, Copy, Default, PartialEq)]
@@ -16,6 +32,8 @@ pub struct Position {
     pub(crate) y: f32,
 }
 
+impl_snapshot!(Position { x, y });
+
 /// An offset which can be added to a Position.
 #[derive(Debug, Clone, Copy, Default, PartialEq)]
 pub struct Offset {
@@ -23,11 +41,23 @@ pub struct Offset {
     pub(crate) dy: f32,
 }
 
+impl_snapshot!(Offset { dx, dy });
+
 impl Position {
     /// Create said position.
     pub fn new(x: f32, y: f32) -> Position {
         Position { x, y }
     }
+
+    /// Horizontal coordinate of this position.
+    pub fn x(&self) -> f32 {
+        self.x
+    }
+
+    /// Vertical coordinate of this position.
+    pub fn y(&self) -> f32 {
+        self.y
+    }
 }
 
 impl Offset {
@@ -59,142 +89,438 @@ impl std::ops::Sub<Position> for Position {
 
 type Callback = i32;
 
-#[derive(Debug, Clone)]
-/// XXX
-pub struct Laser {
-    /// XXX
-    pub placeholder: u32
+/// God struct of our game.
+///
+/// It only contains plain owned data, so it can be cloned to fork a simulation, or sent to
+/// another thread.
+#[derive(Clone)]
+pub struct Game {
+    pub(crate) enemies: Arena<Enemy>,
+    pub(crate) ecl: Option<Arc<Ecl>>,
+    pub(crate) anm0: Option<Arc<[Anm0; 2]>>,
+    pub(crate) effects_anm0: Option<Arc<[Anm0]>>,
+    pub(crate) msg: Option<Arc<Msg>>,
+    main_runner: Option<MainRunner>,
+    pub(crate) ecl_runners: Vec<EclRunner>,
+    stage_runner: Option<StageRunner>,
+    pub(crate) msg_runner: Option<MsgRunner>,
+    pub(crate) msg_wait: bool,
+    pub(crate) stage_cleared: bool,
+    pub(crate) bullets: Vec<Bullet>,
+    pub(crate) lasers: Arena<Laser>,
+    pub(crate) player: Player,
+    pub(crate) player_bullets: Vec<PlayerBullet>,
+    pub(crate) items: Vec<Item>,
+    pub(crate) effects: Vec<Effect>,
+    pub(crate) prng: Prng,
+    pub(crate) deaths_count: u32,
+    pub(crate) next_bonus: usize,
+    pub(crate) spellcard: Option<Spellcard>,
+    pub(crate) spellcard_effect: bool,
+    pub(crate) spellcard_events: Vec<SpellcardEvent>,
+    pub(crate) sounds: Vec<i32>,
+    pub(crate) time_stop: bool,
+    pub(crate) tracer: Option<SharedTracer>,
+    pub(crate) boss: Option<Id>,
+    pub(crate) boss_max_life: u32,
+    rank: Rank,
+    pub(crate) difficulty: Difficulty,
+    game_over: bool,
 }
 
-#[derive(Debug, Clone, Default)]
-struct Process;
-
-/// Struct representing the player.
-pub struct Player {
-    pos: Position,
+// The stage data is kept from the game being restored.
+impl_snapshot!(Game, |game: Game| {
+    enemies, main_runner, ecl_runners, stage_runner, msg_runner, msg_wait, stage_cleared, bullets, lasers, player, player_bullets,
+    items, effects, prng, deaths_count, next_bonus, spellcard, spellcard_effect, time_stop, boss,
+    boss_max_life, rank, difficulty, game_over,
+} {
+    ecl: game.ecl.clone(),
+    anm0: game.anm0.clone(),
+    effects_anm0: game.effects_anm0.clone(),
+    msg: game.msg.clone(),
+    spellcard_events: Vec::new(),
+    sounds: Vec::new(),
+    tracer: game.tracer.clone(),
+});
+
+/// Version of the format of `GameState`, to be bumped on each change.
+const SNAPSHOT_VERSION: u8 = 11;
+
+/// The whole state of a `Game` at a given frame, in a compact binary form.
+///
+/// It doesn’t contain the stage data, so it can only be restored into a game which loaded the
+/// same stage.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameState {
+    data: Vec<u8>,
 }
 
-/// Struct representing an enemy bullet.
-pub struct Bullet {
-    /// Current position of the bullet.
-    pub pos: Position,
-
-    /// Current speed of the bullet.
-    pub speed: f32,
-
-    /// Current XXX of the bullet.
-    pub dpos: [f32; 3],
-
-    /// Current XXX of the bullet.
-    pub flags: u32,
-
-    /// Current frame of the bullet.
-    pub frame: i32,
-
-    /// Current attributes of the bullet.
-    pub attributes: [f32; 2],
-
-    /// TODO: what are the values?
-    pub state: i8,
-}
+impl GameState {
+    /// Wrap bytes previously obtained from `as_bytes`.
+    pub fn from_bytes(data: Vec<u8>) -> GameState {
+        GameState {
+            data,
+        }
+    }
 
-/// God struct of our game.
-pub struct Game {
-    enemies: Vec<Rc<RefCell<Enemy>>>,
-    anmrunners: Vec<Rc<RefCell<AnmRunner>>>,
-    pub(crate) bullets: Vec<Rc<RefCell<Bullet>>>,
-    player: Rc<RefCell<Player>>,
-    pub(crate) prng: Rc<RefCell<Prng>>,
-    rank: Rank,
-    difficulty: i32,
+    /// Returns the serialised state.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
 }
 
 impl Game {
     /// Create said god struct.
-    pub fn new(prng: Rc<RefCell<Prng>>, rank: Rank) -> Game {
+    ///
+    /// The player can’t move nor shoot until `set_player` gives it the characteristics of a
+    /// character, see `touhou_formats::th06::exe::read_characters`.
+    pub fn new(mut prng: Prng, rank: Rank) -> Game {
+        // See 102h.exe@0x413220 if you think you’re brave enough.
+        let deaths_count = prng.get_u16() as u32 % 3;
+        let next_bonus = prng.get_u16() as usize % 8;
         Game {
-            enemies: Vec::new(),
-            anmrunners: Vec::new(),
+            enemies: Arena::new(),
+            ecl: None,
+            anm0: None,
+            effects_anm0: None,
+            msg: None,
+            main_runner: None,
+            ecl_runners: Vec::new(),
+            stage_runner: None,
+            msg_runner: None,
+            msg_wait: false,
+            stage_cleared: false,
             bullets: Vec::new(),
-            player: Rc::new(RefCell::new(Player { pos: Position { x: 192., y: 384. } })),
+            lasers: Arena::new(),
+            player: Player::new(Sht::default(), Sht::default()),
+            player_bullets: Vec::new(),
+            items: Vec::new(),
+            effects: Vec::new(),
             prng,
+            deaths_count,
+            next_bonus,
+            spellcard: None,
+            spellcard_effect: false,
+            spellcard_events: Vec::new(),
+            sounds: Vec::new(),
+            time_stop: false,
+            tracer: None,
+            boss: None,
+            boss_max_life: 0,
             rank,
-            difficulty: 0,
+            difficulty: Difficulty::new(rank),
+            game_over: false,
         }
     }
 
-    /// Run the simulation for a single frame.
-    pub fn run_frame(&mut self) {
-        /*
-        for eclrunner in self.eclrunners {
-            eclrunner.run_frame();
+    /// Save the current state of the simulation, to be resumed later with `restore`.
+    pub fn snapshot(&self) -> GameState {
+        let mut data = vec![SNAPSHOT_VERSION];
+        self.save(&mut data);
+        GameState::from_bytes(data)
+    }
+
+    /// Go back to a state saved by `snapshot`, on this game or a copy of it.
+    ///
+    /// The game is left untouched if this state can’t be restored.
+    pub fn restore(&mut self, state: &GameState) -> Result<(), Error> {
+        let mut reader = Reader::new(state.as_bytes());
+        if u8::load(&mut reader, self)? != SNAPSHOT_VERSION {
+            return Err(Error::InvalidValue("version"));
         }
-        */
+        let game = Game::load(&mut reader, self)?;
+        reader.finish()?;
+        *self = game;
+        Ok(())
+    }
+
+    /// Replace the player, to give it the characteristics of an actual character.
+    pub fn set_player(&mut self, player: Player) {
+        self.player = player;
+    }
+
+    /// Load the enemies of a stage, its main timeline will then spawn them on each `run_frame`.
+    ///
+    /// The enemy sprites are taken from `anm0`.
+    pub fn load_stage(&mut self, ecl: Ecl, anm0: Arc<[Anm0; 2]>) {
+        self.main_runner = ecl.mains.first().cloned().map(MainRunner::new);
+        self.ecl = Some(Arc::new(ecl));
+        self.anm0 = Some(anm0);
+    }
+
+    /// Set the background of the stage, to be run on each `run_frame`.
+    pub fn set_background(&mut self, stage_runner: StageRunner) {
+        self.stage_runner = Some(stage_runner);
+    }
+
+    /// Spawn a new enemy running this sub of the loaded ECL, and run its first frame.
+    ///
+    /// The enemy is kept even if this first frame fails, nothing gets spawned if no stage got
+    /// loaded.
+    pub fn spawn_enemy(&mut self, sub: u16, pos: Position, life: i16, bonus_dropped: i16, die_score: u32, mirror: bool) -> Result<Id, EclError> {
+        let ecl = self.ecl.clone().ok_or(EclError { sub, ip: 0, frame: 0, kind: EclErrorKind::NoStage })?;
+        let enemy = Enemy::new(pos, life, bonus_dropped, die_score, mirror, self.anm0.clone());
+        let id = self.enemies.insert(enemy);
+        let mut runner = EclRunner::new(ecl, id, sub);
+        let result = runner.run_frame(self);
+        self.ecl_runners.push(runner);
+        result.map(|()| id)
+    }
 
-        for anmrunner in self.anmrunners.iter() {
-            let mut anmrunner = anmrunner.borrow_mut();
-            anmrunner.run_frame();
+    /// Whether an enemy is currently a boss.
+    pub(crate) fn has_boss(&self) -> bool {
+        self.enemies.iter().any(|enemy| enemy.boss)
+    }
+
+    /// Run the boss callback of the bosses, once their dialogue is over.
+    pub(crate) fn resume_boss(&mut self) {
+        for enemy in self.enemies.iter_mut().filter(|enemy| enemy.boss) {
+            if let Some(sub) = enemy.boss_callback.take() {
+                enemy.pending_callback = Some(sub);
+            }
         }
     }
 
+    /// Kill every enemy but the bosses, as done by `KillAllEnemies` and timeouts.
+    pub(crate) fn kill_enemies(&mut self) {
+        for enemy in self.enemies.iter_mut() {
+            enemy.kill();
+        }
+    }
+
+    /// Cancel every bullet and laser currently alive.
+    pub(crate) fn cancel_bullets(&mut self) {
+        for bullet in self.bullets.iter_mut().filter(|bullet| bullet.is_active()) {
+            bullet.cancel();
+        }
+        for laser in self.lasers.iter_mut() {
+            laser.cancel();
+        }
+    }
+
+    /// Run the simulation for a single frame, with these keys pressed.
+    ///
+    /// An enemy whose script fails stops running it, the frame still gets completed and the first
+    /// error is then returned.
+    pub fn run_frame(&mut self, keys: Keys) -> Result<(), EclError> {
+        self.spellcard_events.clear();
+        self.sounds.clear();
+        let mut result = Ok(());
+        self.difficulty.update();
+        if let Some(mut main_runner) = self.main_runner.take() {
+            result = main_runner.run_frame(self);
+            self.main_runner = Some(main_runner);
+        }
+
+        if let Some(stage_runner) = self.stage_runner.as_mut() {
+            stage_runner.run_frame();
+        }
+
+        let keys = self.update_msg(keys);
+        let event = self.player.update(keys, &mut self.player_bullets);
+        let player_pos = self.player.pos;
+        match event {
+            Some(PlayerEvent::CancelBullets) => self.cancel_bullets(),
+            Some(PlayerEvent::Died) => {
+                self.difficulty.modify(-1600);
+                self.fail_spellcard();
+                self.drop_player_items(player_pos, false);
+            }
+            Some(PlayerEvent::Continued) => self.drop_player_items(player_pos, true),
+            Some(PlayerEvent::GameOver) => self.game_over = true,
+            // TODO: bombing lowers the difficulty too, but neither pytouhou nor the reversing
+            // notes know by how much, so it is left unchanged for now.
+            Some(PlayerEvent::Bomb) => self.fail_spellcard(),
+            None => (),
+        }
+        if self.player.is_bombing() {
+            self.cancel_bullets();
+        }
+        if let Some(spellcard) = self.spellcard.as_mut() {
+            spellcard.update();
+        }
+        let enemies = &self.enemies;
+        for bullet in self.player_bullets.iter_mut() {
+            // Homing bullets go for the closest enemy.
+            let pos = bullet.pos;
+            let distance = |target: &Position| {
+                let offset = pos - *target;
+                offset.dx.hypot(offset.dy)
+            };
+            let target = enemies
+                .iter()
+                .filter(|enemy| enemy.touchable && !enemy.removed)
+                .map(|enemy| enemy.pos)
+                .min_by(|a, b| distance(a).total_cmp(&distance(b)));
+            bullet.update(target);
+        }
+
+        // Enemies spawned during this loop will only get updated on the next frame.
+        let mut ecl_runners = std::mem::take(&mut self.ecl_runners);
+        for runner in ecl_runners.iter_mut() {
+            if let Err(error) = runner.run_frame(self) {
+                result = result.and(Err(error));
+            }
+            // The enemy is taken out of the arena while it updates, so that it can access the rest
+            // of the game.
+            if let Some(mut enemy) = self.enemies.take(runner.enemy) {
+                enemy.update(self);
+                if let Some(anmrunner) = enemy.anmrunner.as_mut() {
+                    anmrunner.run_frame(&mut self.prng);
+                }
+                self.enemies.put_back(runner.enemy, enemy);
+            }
+        }
+        ecl_runners.append(&mut self.ecl_runners);
+        self.ecl_runners = ecl_runners;
+
+        for effect in self.effects.iter_mut() {
+            effect.update(&mut self.prng);
+        }
+
+        // TODO: check whether the player should be frozen too, as pytouhou does.
+        if !self.time_stop {
+            let target = self.player.pos;
+            for bullet in self.bullets.iter_mut() {
+                bullet.update(target);
+            }
+            for item in self.items.iter_mut() {
+                item.update(target);
+                // Only items falling off the screen get removed while moving.
+                if item.removed {
+                    self.difficulty.modify(-3);
+                }
+            }
+
+            for laser in self.lasers.iter_mut() {
+                laser.update();
+            }
+        }
+
+        self.check_collisions();
+
+        for enemy in self.enemies.iter_mut() {
+            if enemy.is_visible() {
+                enemy.was_visible = true;
+            } else if enemy.was_visible {
+                enemy.removed = true;
+            }
+        }
+        self.enemies.retain(|enemy| !enemy.removed);
+        self.update_boss();
+        let enemies = &self.enemies;
+        self.ecl_runners.retain(|runner| enemies.get(runner.enemy).is_some());
+        self.bullets.retain(|bullet| !bullet.removed);
+        self.player_bullets.retain(|bullet| !bullet.removed);
+        self.lasers.retain(|laser| !laser.removed);
+        self.items.retain(|item| !item.removed);
+        self.effects.retain(|effect| !effect.removed);
+        result
+    }
+
     /// Returns a list of all sprites currently being displayed on screen.
-    pub fn get_sprites(&self) -> Vec<(f32, f32, f32, Rc<RefCell<Sprite>>)> {
+    ///
+    /// Only the ones taken from the enemy ANMs are listed, see `effects` for the others.
+    pub fn get_sprites(&self) -> Vec<(f32, f32, f32, &Sprite)> {
         let mut sprites = vec![];
         for enemy in self.enemies.iter() {
-            let enemy = enemy.borrow();
-            let anmrunner = enemy.anmrunner.upgrade().unwrap();
-            let anmrunner = anmrunner.borrow();
-            let sprite = anmrunner.get_sprite();
-            sprites.push((enemy.pos.x, enemy.pos.y, enemy.z, sprite));
+            for sprite in enemy.get_sprites() {
+                sprites.push((enemy.pos.x, enemy.pos.y, enemy.z, sprite));
+            }
         }
         sprites
     }
 
-    // TODO: Fix this function so we can stop making Game::bullets pub.
-    /*
-    /// Apply a function on all bullets.
-    pub fn iter_bullets(&mut self, mut f: impl FnMut(Bullet)) {
-        self.bullets.iter().map(|bullet| {
-            let mut bullet = bullet.borrow_mut();
-            f(*bullet)
-        });
+    /// Returns the background of the stage, if any.
+    pub fn stage_runner(&self) -> Option<&StageRunner> {
+        self.stage_runner.as_ref()
+    }
+
+    /// Returns an iterator over all enemies currently alive.
+    pub fn enemies(&self) -> impl Iterator<Item = &Enemy> {
+        self.enemies.iter()
+    }
+
+    /// Returns an iterator over all bullets currently alive.
+    pub fn bullets(&self) -> impl Iterator<Item = &Bullet> {
+        self.bullets.iter()
+    }
+
+    /// Returns an iterator over all lasers currently alive.
+    pub fn lasers(&self) -> impl Iterator<Item = &Laser> {
+        self.lasers.iter()
     }
-    */
 
-    pub(crate) fn get_player(&self) -> Rc<RefCell<Player>> {
-        self.player.clone()
+    /// Returns the player.
+    pub fn player(&self) -> &Player {
+        &self.player
+    }
+
+    /// Returns an iterator over all bullets fired by the player.
+    pub fn player_bullets(&self) -> impl Iterator<Item = &PlayerBullet> {
+        self.player_bullets.iter()
+    }
+
+    /// Returns an iterator over all items currently alive.
+    pub fn items(&self) -> impl Iterator<Item = &Item> {
+        self.items.iter()
+    }
+
+    /// Returns an iterator over all effects currently displayed, using the ANM given to
+    /// `load_effects`.
+    pub fn effects(&self) -> impl Iterator<Item = &Effect> {
+        self.effects.iter()
+    }
+
+    /// Returns the spellcard currently declared by a boss, if any.
+    pub fn spellcard(&self) -> Option<&Spellcard> {
+        self.spellcard.as_ref()
+    }
+
+    /// Whether the spellcard background should be displayed.
+    pub fn has_spellcard_effect(&self) -> bool {
+        self.spellcard_effect
+    }
+
+    /// Returns what happened to spellcards during the last frame.
+    pub fn spellcard_events(&self) -> &[SpellcardEvent] {
+        &self.spellcard_events
+    }
+
+    /// Returns the sounds played by the enemies during the last frame.
+    pub fn sounds(&self) -> &[i32] {
+        &self.sounds
+    }
+
+    /// Whether time is currently stopped, freezing every bullet.
+    pub fn is_time_stopped(&self) -> bool {
+        self.time_stop
+    }
+
+    /// Whether the player lost its last life.
+    pub fn is_game_over(&self) -> bool {
+        self.game_over
+    }
+
+    pub(crate) fn get_rank(&self) -> Rank {
+        self.rank
+    }
+
+    /// Returns the current difficulty, from 10 to 32, making the bullets denser and faster.
+    pub fn difficulty(&self) -> i32 {
+        self.difficulty.value()
     }
 }
 
-/// Common to all elements in game.
+/// Common to all elements in game, used for the auxiliary animations of enemies.
+#[derive(Clone)]
 struct Element {
     pos: Position,
     removed: bool,
     anmrunner: AnmRunner,
 }
 
-#[derive(PartialEq)]
-pub(crate) struct DifficultyCoeffs {
-    pub(crate) speed_a: f32,
-    pub(crate) speed_b: f32,
-    pub(crate) nb_a: i16,
-    pub(crate) nb_b: i16,
-    pub(crate) shots_a: i16,
-    pub(crate) shots_b: i16,
-}
-
-impl Default for DifficultyCoeffs {
-    fn default() -> DifficultyCoeffs {
-        DifficultyCoeffs {
-            speed_a: -0.5,
-            speed_b: 0.5,
-            nb_a: 0,
-            nb_b: 0,
-            shots_a: 0,
-            shots_b: 0,
-        }
-    }
-}
+impl_snapshot!(Element, |game: Game| { pos, removed, anmrunner });
 
 #[derive(Debug, Clone, Default, PartialEq)]
 pub(crate) struct BulletAttributes {
@@ -211,26 +537,26 @@ pub(crate) struct BulletAttributes {
     pub(crate) number_of_shots: i16,
     pub(crate) bullet_type: i16,
     // zero: x32,
-    pub(crate) flags: u32,
+    pub(crate) flags: BulletFlags,
 
     /// Which sound to play when the bullet gets fired.
     pub sound: Option<u8>,
 }
 
-impl BulletAttributes {
-    /// Fire!
-    pub fn fire(&mut self) {
-        println!("PAN!");
-    }
-}
+impl_snapshot!(BulletAttributes {
+    anim, sprite_index_offset, pos, launch_angle, angle, speed, speed2, extended_attributes,
+    bullets_per_shot, number_of_shots, bullet_type, flags, sound,
+});
 
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub(crate) enum Direction {
     Left,
     Center,
     Right,
 }
 
+impl_snapshot!(enum Direction { Left, Center, Right });
+
 impl Default for Direction {
     fn default() -> Direction {
         Direction::Center
@@ -238,12 +564,12 @@ impl Default for Direction {
 }
 
 /// The enemy struct, containing everything pertaining to an enemy.
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Enemy {
     // Common to all elements in game.
     pub(crate) pos: Position,
     pub(crate) removed: bool,
-    pub(crate) anmrunner: Weak<RefCell<AnmRunner>>,
+    pub(crate) anmrunner: Option<AnmRunner>,
 
     // Specific to enemy.
     // Floats.
@@ -255,19 +581,19 @@ pub struct Enemy {
 
     // Ints.
     pub(crate) type_: u32,
-    pub(crate) bonus_dropped: u32,
+    pub(crate) bonus_dropped: i16,
     pub(crate) die_score: u32,
     /// XXX
     pub frame: u32,
     pub(crate) life: u32,
-    pub(crate) death_flags: u32,
+    pub(crate) death_flags: DeathFlags,
     pub(crate) current_laser_id: u32,
     pub(crate) low_life_trigger: Option<u32>,
     pub(crate) timeout: Option<u32>,
     pub(crate) remaining_lives: u32,
     bullet_launch_interval: u32,
     bullet_launch_timer: u32,
-    pub(crate) death_anim: i32,
+    pub(crate) death_anim: u8,
     pub(crate) direction: Direction,
     pub(crate) update_mode: u32,
 
@@ -295,37 +621,46 @@ pub struct Enemy {
     pub(crate) boss_callback: Option<Callback>,
     pub(crate) low_life_callback: Option<Callback>,
     pub(crate) timeout_callback: Option<Callback>,
+    pub(crate) pending_callback: Option<Callback>,
+    pub(crate) special_callback: Option<i32>,
 
     // Laser.
-    pub(crate) laser_by_id: HashMap<u32, Laser>,
+    pub(crate) laser_by_id: BTreeMap<u32, Id>,
 
-    // Options.
-    // TODO: actually a 8 element array.
-    options: Vec<Element>,
+    // Auxiliary animations, following the enemy.
+    aux_anms: [Option<Element>; 8],
 
     // Interpolators.
     pub(crate) interpolator: Option<Interpolator2<f32>>,
     pub(crate) speed_interpolator: Option<Interpolator1<f32>>,
 
     // Misc stuff, do we need them?
-    pub(crate) anm0: Weak<RefCell<[Anm0; 2]>>,
-    process: Rc<RefCell<Process>>,
-    pub(crate) game: Weak<RefCell<Game>>,
-    pub(crate) prng: Weak<RefCell<Prng>>,
+    pub(crate) anm0: Option<Arc<[Anm0; 2]>>,
     pub(crate) hitbox_half_size: [f32; 2],
 }
 
+impl_snapshot!(Enemy, |game: Game| {
+    pos, removed, anmrunner, z, angle, speed, rotation_speed, acceleration, type_, bonus_dropped,
+    die_score, frame, life, death_flags, current_laser_id, low_life_trigger, timeout,
+    remaining_lives, bullet_launch_interval, bullet_launch_timer, death_anim, direction,
+    update_mode, visible, was_visible, touchable, collidable, damageable, boss,
+    automatic_orientation, delay_attack, mirror, difficulty_coeffs, bullet_attributes,
+    bullet_offset, movement_dependant_sprites, screen_box, death_callback, boss_callback,
+    low_life_callback, timeout_callback, pending_callback, special_callback, laser_by_id, aux_anms,
+    interpolator,
+    speed_interpolator, hitbox_half_size,
+} {
+    anm0: game.anm0.clone(),
+});
+
 impl Enemy {
     /// Create a new enemy.
-    pub fn new(pos: Position, life: i16, bonus_dropped: i16, die_score: u32, mirror: bool, anm0: Weak<RefCell<[Anm0; 2]>>, game: Weak<RefCell<Game>>) -> Rc<RefCell<Enemy>> {
-        let game_rc = game.upgrade().unwrap();
-        let mut enemy = Enemy {
+    pub fn new(pos: Position, life: i16, bonus_dropped: i16, die_score: u32, mirror: bool, anm0: Option<Arc<[Anm0; 2]>>) -> Enemy {
+        Enemy {
             pos,
             anm0,
-            game,
             visible: true,
-            // XXX: shouldn’t be u32, since that can be -1.
-            bonus_dropped: bonus_dropped as u32,
+            bonus_dropped,
             die_score,
             life: if life < 0 { 1 } else { life as u32 },
             touchable: true,
@@ -333,23 +668,23 @@ impl Enemy {
             damageable: true,
             mirror,
             ..Default::default()
-        };
-        let mut game = game_rc.borrow_mut();
-        enemy.prng = Rc::downgrade(&game.prng);
-        let enemy = Rc::new(RefCell::new(enemy));
-        game.enemies.push(enemy.clone());
-        enemy
+        }
+    }
+
+    /// The ANMs of this enemy, if they contain the script `index`.
+    pub(crate) fn anm0_with_script(&self, index: i32) -> Result<Arc<[Anm0]>, EclErrorKind> {
+        let script = u8::try_from(index).map_err(|_| EclErrorKind::InvalidAnim(index))?;
+        self.anm0.clone()
+            .map(|anm0| anm0 as Arc<[Anm0]>)
+            .filter(|anm0| AnmRunner::has_script(anm0, script))
+            .ok_or(EclErrorKind::InvalidAnim(index))
     }
 
     /// Sets the animation to the one indexed by index in the current anm0.
-    pub fn set_anim(&mut self, index: u8) {
-        let anm0 = self.anm0.upgrade().unwrap();
-        let game = self.game.upgrade().unwrap();
-        let sprite = Rc::new(RefCell::new(Sprite::new()));
-        let anmrunner = AnmRunner::new(anm0, index, sprite, self.prng.clone(), 0);
-        let anmrunner = Rc::new(RefCell::new(anmrunner));
-        self.anmrunner = Rc::downgrade(&anmrunner);
-        (*game.borrow_mut()).anmrunners.push(anmrunner);
+    pub fn set_anim(&mut self, index: u8, prng: &mut Prng) -> Result<(), EclErrorKind> {
+        let anm0 = self.anm0_with_script(index.into())?;
+        self.anmrunner = Some(AnmRunner::new(anm0, index, Sprite::new(), prng, 0));
+        Ok(())
     }
 
     /// Sets the current position of the enemy.
@@ -367,26 +702,23 @@ impl Enemy {
     /// Defines the attributes for the next bullet fired, and fire it if delay_attack isn’t set!
     pub fn set_bullet_attributes(&mut self, opcode: u16, anim: i16, sprite_index_offset: i16,
                                  bullets_per_shot: i16, number_of_shots: i16, speed: f32,
-                                 speed2: f32, launch_angle: f32, angle: f32, flags: u32) {
+                                 speed2: f32, launch_angle: f32, angle: f32, flags: BulletFlags,
+                                 game: &mut Game) {
         // Get the coeffs for the current difficulty.
-        let difficulty = self.get_difficulty() as i16;
-        let coeff_nb = self.difficulty_coeffs.nb_a + (self.difficulty_coeffs.nb_b - self.difficulty_coeffs.nb_a) * difficulty / 32;
-        let coeff_shots = self.difficulty_coeffs.shots_a + (self.difficulty_coeffs.shots_b - self.difficulty_coeffs.shots_a) * difficulty / 32;
-        let coeff_speed = self.difficulty_coeffs.speed_a + (self.difficulty_coeffs.speed_b - self.difficulty_coeffs.speed_a) * difficulty as f32 / 32.;
+        let (coeff_nb, coeff_shots, coeff_speed) = self.difficulty_coeffs.at(game.difficulty());
 
-        let opcode = 67;
-        let mut bullet = &mut self.bullet_attributes;
+        let bullet = &mut self.bullet_attributes;
 
         bullet.anim = anim;
-        bullet.bullet_type = opcode - 67;
+        bullet.bullet_type = opcode as i16 - 67;
         bullet.sprite_index_offset = sprite_index_offset;
 
-        bullet.bullets_per_shot = bullets_per_shot + coeff_nb;
+        bullet.bullets_per_shot = bullets_per_shot + coeff_nb as i16;
         if bullet.bullets_per_shot < 1 {
             bullet.bullets_per_shot = 1;
         }
 
-        bullet.number_of_shots = number_of_shots + coeff_shots;
+        bullet.number_of_shots = number_of_shots + coeff_shots as i16;
         if bullet.number_of_shots < 1 {
             bullet.number_of_shots = 1;
         }
@@ -403,41 +735,132 @@ impl Enemy {
             bullet.speed2 = 0.3;
         }
 
-        bullet.launch_angle = launch_angle.atan2(0.);
+        bullet.launch_angle = launch_angle;
         bullet.angle = angle;
         bullet.flags = flags;
 
         if !self.delay_attack {
-            bullet.fire();
+            self.fire(game);
         }
     }
 
-    /// Sets the bullet launch interval.
-    pub(crate) fn set_bullet_launch_interval(&mut self, rand_start: u32, interval: i32) {
-        let coeff_interval = interval / 5;
-        let difficulty_modifier = coeff_interval + (-coeff_interval * 2) * self.get_difficulty() / 32;
-        self.bullet_launch_interval = (interval + difficulty_modifier) as u32;
-        if self.bullet_launch_interval > 0 {
-            self.bullet_launch_timer = rand_start % self.bullet_launch_interval;
+    /// Fire the current bullet attributes from the position of this enemy.
+    pub(crate) fn fire(&mut self, game: &mut Game) {
+        self.bullet_attributes.pos = self.pos + self.bullet_offset;
+        self.bullet_attributes.fire(game);
+    }
+
+    /// Fire a new laser from the position of this enemy, and register it under the current laser id.
+    ///
+    /// `towards_player` adds the angle to the player to the one given in the attributes.
+    pub(crate) fn new_laser(&mut self, towards_player: bool, attributes: &LaserAttributes, game: &mut Game) {
+        let pos = self.pos + self.bullet_offset;
+        self.new_laser_at(pos, towards_player, attributes, game);
+    }
+
+    /// Same as `new_laser`, but from any position.
+    pub(crate) fn new_laser_at(&mut self, pos: Position, towards_player: bool, attributes: &LaserAttributes, game: &mut Game) {
+        if game.lasers.len() >= MAX_LASERS {
+            return;
+        }
+        let mut laser = Laser::new(pos, attributes);
+        if towards_player {
+            let player = &game.player;
+            laser.angle += (player.pos.y - pos.y).atan2(player.pos.x - pos.x);
+        }
+        let laser = game.lasers.insert(laser);
+        self.laser_by_id.insert(self.current_laser_id, laser);
+    }
+
+    /// Returns the laser registered under this id, if it is still alive.
+    pub(crate) fn get_laser<'a>(&self, laser_id: u32, lasers: &'a mut Arena<Laser>) -> Option<&'a mut Laser> {
+        self.laser_by_id.get(&laser_id).and_then(move |&laser| lasers.get_mut(laser))
+    }
+
+    /// Handle the death or low life of this enemy, once it took its damages for this frame.
+    ///
+    /// The callback to run, if any, is left in `pending_callback` for the `EclRunner`, and the
+    /// bonus to drop on death is returned for the game to drop.
+    pub(crate) fn handle_callbacks(&mut self, player: &mut Player) -> Option<i16> {
+        let mut bonus = None;
+        if self.life == 0 && self.touchable {
+            self.timeout = None;
+            self.timeout_callback = None;
+            player.score += self.die_score;
+
+            // TODO: drop particles, and turn the bullets into bonus for bosses.
+            let death_flags = self.death_flags & (DeathFlags::STAY_UNTOUCHABLE | DeathFlags::STAY | DeathFlags::NO_BONUS);
+            if !death_flags.contains(DeathFlags::NO_BONUS) {
+                bonus = Some(self.bonus_dropped);
+            }
+            if death_flags.is_empty() {
+                self.removed = true;
+                return bonus;
+            }
+            if death_flags == DeathFlags::STAY_UNTOUCHABLE {
+                self.boss = false;
+                self.touchable = false;
+            } else if death_flags == DeathFlags::STAY_UNTOUCHABLE | DeathFlags::STAY {
+                self.boss = false;
+                self.damageable = false;
+                self.life = 1;
+                self.death_flags = DeathFlags::empty();
+            }
+            self.pending_callback = self.death_callback.take();
+        } else if self.low_life_callback.is_some() && self.low_life_trigger.is_some_and(|trigger| self.life <= trigger) {
+            self.pending_callback = self.low_life_callback.take();
+            self.low_life_trigger = None;
+            self.timeout_callback = None;
+        }
+        bonus
+    }
+
+    /// Kill this enemy, unless it is a boss; an untouchable one runs its death callback instead.
+    pub(crate) fn kill(&mut self) {
+        if self.boss {
+            // Bosses are immune to it.
+        } else if self.touchable {
+            self.life = 0;
+        } else if let Some(sub) = self.death_callback.take() {
+            // TODO: check.
+            self.pending_callback = Some(sub);
         }
     }
 
-    /// Stubbed for now.
-    pub(crate) fn play_sound(&self, sound_index: i32) {
-        println!("Playing sound {}!", sound_index);
+    /// Handle the end of the timer, which ends the current phase of a boss.
+    fn handle_timeout(&mut self, game: &mut Game) {
+        self.frame = 0;
+        self.timeout = None;
+        game.kill_enemies();
+        game.cancel_bullets();
+        // Surviving until the end of a spellcard doesn’t capture it.
+        game.fail_spellcard();
+
+        if let Some(trigger) = self.low_life_trigger.take() {
+            self.life = trigger;
+        }
+
+        if let Some(sub) = self.timeout_callback.take() {
+            self.pending_callback = Some(sub);
+        } else if self.death_callback.is_some() {
+            // TODO: this is only done under certain (unknown) conditions!
+            self.life = 0;
+        }
     }
 
-    /// Stubbed for now.
-    pub(crate) fn set_boss(&self, enable: bool) {
-        match enable {
-            true => println!("Enemy is now boss!"),
-            false => println!("Enemy is not boss anymore."),
+    /// Sets the bullet launch interval.
+    pub(crate) fn set_bullet_launch_interval(&mut self, rand_start: u32, interval: i32, difficulty: i32) {
+        let coeff_interval = interval / 5;
+        let difficulty_modifier = coeff_interval + (-coeff_interval * 2) * difficulty / 32;
+        self.bullet_launch_interval = (interval + difficulty_modifier) as u32;
+        if self.bullet_launch_interval > 0 {
+            self.bullet_launch_timer = rand_start % self.bullet_launch_interval;
         }
     }
 
     /// Run all interpolators and such, and update internal variables once per
     /// frame.
-    pub fn update(&mut self) {
+    pub fn update(&mut self, game: &mut Game) {
         let Position { mut x, mut y } = self.pos;
 
         let speed = if self.update_mode == 1 {
@@ -468,12 +891,13 @@ impl Enemy {
         }
         y += dy;
 
+        // These animations got checked by 98, so setting them can’t fail.
         if let Some((end_left, end_right, left, right)) = self.movement_dependant_sprites {
             if x < self.pos.x && self.direction != Direction::Left {
-                self.set_anim(left);
+                let _ = self.set_anim(left, &mut game.prng);
                 self.direction = Direction::Left;
             } else if x > self.pos.x && self.direction != Direction::Right {
-                self.set_anim(right);
+                let _ = self.set_anim(right, &mut game.prng);
                 self.direction = Direction::Right;
             } else if x == self.pos.x && self.direction != Direction::Center {
                 let anim = if self.direction == Direction::Left {
@@ -481,7 +905,7 @@ impl Enemy {
                 } else {
                     end_right
                 };
-                self.set_anim(anim);
+                let _ = self.set_anim(anim, &mut game.prng);
                 self.direction = Direction::Center;
             }
         }
@@ -490,49 +914,81 @@ impl Enemy {
 
         if self.bullet_launch_interval != 0 {
             if self.bullet_launch_timer == 0 {
-                self.bullet_attributes.fire();
+                self.fire(game);
                 self.bullet_launch_timer = self.bullet_launch_interval;
             }
             self.bullet_launch_timer += 1;
             self.bullet_launch_timer %= self.bullet_launch_interval;
         }
 
-        self.frame += 1;
-    }
+        self.update_aux_anms(&mut game.prng);
 
-    pub(crate) fn get_rank(&self) -> Rank {
-        let game = self.game.upgrade().unwrap();
-        let game = game.borrow();
-        game.rank
+        if self.timeout == Some(self.frame) {
+            self.handle_timeout(game);
+        }
+
+        self.frame += 1;
     }
 
-    pub(crate) fn get_difficulty(&self) -> i32 {
-        let game = self.game.upgrade().unwrap();
-        let game = game.borrow();
-        game.difficulty
+    /// Whether any part of the sprite of this enemy is inside the game area.
+    pub(crate) fn is_visible(&self) -> bool {
+        let [width, height] = match self.anmrunner.as_ref() {
+            Some(anmrunner) => anmrunner.get_sprite().size(),
+            None => [0., 0.],
+        };
+        let Position { x, y } = self.pos;
+        (-width / 2. ..=GAME_WIDTH + width / 2.).contains(&x)
+            && (-height / 2. ..=GAME_HEIGHT + height / 2.).contains(&y)
     }
 
     // TODO: use a trait for positionable entities.
-    pub(crate) fn get_angle_to(&self, player: Rc<RefCell<Player>>) -> f32 {
-        let player = player.borrow();
-        let offset = self.pos - player.pos;
+    pub(crate) fn get_angle_to(&self, pos: Position) -> f32 {
+        let offset = self.pos - pos;
         offset.dy.atan2(offset.dx)
     }
 
-    pub(crate) fn set_aux_anm(&self, number: i32, script: i32) {
-        println!("TODO: Spawn aux anm {} with script {}.", number, script);
+    /// Play this script in the auxiliary animation `number`, replacing any previous one.
+    pub(crate) fn set_aux_anm(&mut self, number: usize, script: u8, prng: &mut Prng) -> Result<(), EclErrorKind> {
+        let anm0 = self.anm0_with_script(script.into())?;
+        self.aux_anms[number] = Some(Element {
+            pos: self.pos,
+            removed: false,
+            anmrunner: AnmRunner::new(anm0, script, Sprite::new(), prng, 0),
+        });
+        Ok(())
+    }
+
+    /// Trigger an interrupt in the auxiliary animation `number`, if there is one.
+    pub(crate) fn interrupt_aux(&mut self, number: usize, event: i32) {
+        if let Some(aux) = self.aux_anms[number].as_mut() {
+            aux.anmrunner.interrupt(event);
+        }
+    }
+
+    /// Move the auxiliary animations to the position of the enemy, and run them.
+    fn update_aux_anms(&mut self, prng: &mut Prng) {
+        for slot in self.aux_anms.iter_mut() {
+            if let Some(aux) = slot {
+                aux.pos = self.pos;
+                aux.anmrunner.run_frame(prng);
+                if aux.anmrunner.get_sprite().is_removed() {
+                    *slot = None;
+                }
+            }
+        }
     }
 }
 
 trait Renderable {
-    fn get_sprites(&self) -> Vec<Rc<RefCell<Sprite>>>;
+    fn get_sprites(&self) -> Vec<&Sprite>;
 }
 
 impl Renderable for Enemy {
-    fn get_sprites(&self) -> Vec<Rc<RefCell<Sprite>>> {
-        let anmrunner = self.anmrunner.upgrade().unwrap();
-        let anmrunner = anmrunner.borrow();
-        vec![anmrunner.get_sprite()]
+    fn get_sprites(&self) -> Vec<&Sprite> {
+        self.anmrunner.iter()
+            .chain(self.aux_anms.iter().flatten().map(|aux| &aux.anmrunner))
+            .map(AnmRunner::get_sprite)
+            .collect()
     }
 }
 
@@ -558,14 +1014,11 @@ mod tests {
         let (_, mut anms) = Anm0::from_slice(&buf).unwrap();
         let anm0_bis = anms.pop().unwrap();
 
-        let anm0 = Rc::new(RefCell::new([anm0, anm0_bis]));
-        let prng = Rc::new(RefCell::new(Prng::new(0)));
-        let game = Game::new(prng, Rank::EASY);
-        let game = Rc::new(RefCell::new(game));
-        let enemy = Enemy::new(Position::new(0., 0.), 500, 0, 640, Rc::downgrade(&anm0), Rc::downgrade(&game));
-        let mut enemy = enemy.borrow_mut();
-        assert!(enemy.anmrunner.upgrade().is_none());
-        enemy.set_anim(0);
-        assert!(enemy.anmrunner.upgrade().is_some());
+        let anm0 = Arc::new([anm0, anm0_bis]);
+        let mut prng = Prng::new(0);
+        let mut enemy = Enemy::new(Position::new(0., 0.), 500, 0, 640, false, Some(anm0));
+        assert!(enemy.anmrunner.is_none());
+        enemy.set_anim(0, &mut prng).unwrap();
+        assert!(enemy.anmrunner.is_some());
     }
 }
@@ -1,5 +1,8 @@
 //! Animation runner.
 
+use touhou_utils::impl_snapshot;
+use touhou_utils::snapshot::{Save, Load, Reader, Error};
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub(crate) enum Formula {
     Linear,
@@ -7,6 +10,8 @@ pub(crate) enum Formula {
     InvertPower2,
 }
 
+impl_snapshot!(enum Formula { Linear, Power2, InvertPower2 });
+
 impl Formula {
     fn apply(&self, x: f32) -> f32 {
         match self {
@@ -87,6 +92,28 @@ macro_rules! generate_interpolator {
                 }
             }
         }
+
+        impl<T: Save> Save for $name<T> {
+            fn save(&self, out: &mut Vec<u8>) {
+                self.start_values.save(out);
+                self.end_values.save(out);
+                self.start_frame.save(out);
+                self.end_frame.save(out);
+                self.formula.save(out);
+            }
+        }
+
+        impl<C: ?Sized, T: Load<C>> Load<C> for $name<T> {
+            fn load(reader: &mut Reader, context: &C) -> Result<$name<T>, Error> {
+                Ok($name {
+                    start_values: Load::load(reader, context)?,
+                    end_values: Load::load(reader, context)?,
+                    start_frame: Load::load(reader, context)?,
+                    end_frame: Load::load(reader, context)?,
+                    formula: Load::load(reader, context)?,
+                })
+            }
+        }
     };
 }
 
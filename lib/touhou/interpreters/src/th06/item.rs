@@ -0,0 +1,398 @@
+//! Module providing an Item struct, dropped by enemies and collected by the player.
+
+use crate::th06::bullet::{BulletState, GAME_HEIGHT, MAX_BULLETS};
+use crate::th06::difficulty::Difficulty;
+use crate::th06::enemy::{Game, Position};
+use crate::th06::interpolator::{Interpolator1, Interpolator2, Formula};
+use crate::th06::player::{Player, MAX_POWER};
+use touhou_utils::impl_snapshot;
+use std::f32::consts::PI;
+
+/// Maximum amount of items alive at the same time.
+// TODO: check, pytouhou reuses the bullet limit.
+const MAX_ITEMS: usize = MAX_BULLETS;
+
+/// Items dropped in turn by the enemies without a specific bonus, one kill in three.
+const BONUS_LIST: [i16; 32] = [
+    0, 0, 1, 0, 1, 0, 0, 1, 1, 1, 0, 0, 0, 1, 1, 0,
+    1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 0, 1, 1, 1, 0, 2,
+];
+
+/// Maximum amount of lives or bombs which can be gained from items.
+const MAX_STOCK: u32 = 8;
+
+/// Maximum power bonus, reached after collecting power items at full power.
+const MAX_POWER_BONUS: u32 = 30;
+
+/// Kind of item, as used by `DropSpecificBonus` and the bonus of `SpawnEnemy`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ItemType {
+    /// Gives one power point.
+    Power,
+
+    /// Gives points, more the higher it gets collected.
+    Point,
+
+    /// Gives eight power points.
+    BigPower,
+
+    /// Gives a bomb.
+    Bomb,
+
+    /// Sets the power to its maximum.
+    FullPower,
+
+    /// Gives a life.
+    OneUp,
+
+    /// Gives a few points, dropped by cancelled bullets.
+    Star,
+}
+
+impl_snapshot!(enum ItemType { Power, Point, BigPower, Bomb, FullPower, OneUp, Star });
+
+impl ItemType {
+    /// Returns the item type of this index, if any.
+    pub fn from_index(index: i32) -> Option<ItemType> {
+        Some(match index {
+            0 => ItemType::Power,
+            1 => ItemType::Point,
+            2 => ItemType::BigPower,
+            3 => ItemType::Bomb,
+            4 => ItemType::FullPower,
+            5 => ItemType::OneUp,
+            6 => ItemType::Star,
+            _ => return None,
+        })
+    }
+}
+
+/// Struct representing an item.
+#[derive(Debug, Clone)]
+pub struct Item {
+    /// Current position of the item.
+    pub pos: Position,
+
+    /// Kind of this item.
+    pub item_type: ItemType,
+
+    /// Current angle of the item.
+    pub angle: f32,
+
+    /// Current speed of the item.
+    pub speed: f32,
+
+    /// Frames since this item got dropped.
+    pub frame: u32,
+
+    pub(crate) removed: bool,
+    autocollected: bool,
+    player_only: bool,
+    pos_interpolator: Option<Interpolator2<f32>>,
+    speed_interpolator: Option<Interpolator1<f32>>,
+}
+
+impl_snapshot!(Item {
+    pos, item_type, angle, speed, frame, removed, autocollected, player_only, pos_interpolator,
+    speed_interpolator,
+});
+
+impl Item {
+    /// Create a new item, which first jumps a bit before falling down.
+    pub(crate) fn new(pos: Position, item_type: ItemType) -> Item {
+        // TODO: find the formulae in the binary.
+        Item {
+            pos,
+            item_type,
+            angle: PI / 2.,
+            speed: 0.,
+            frame: 0,
+            removed: false,
+            autocollected: false,
+            player_only: false,
+            pos_interpolator: None,
+            speed_interpolator: Some(Interpolator1::new([-2.], 0, [0.], 60, Formula::Linear)),
+        }
+    }
+
+    /// Create a new item dropped by the player on death, which first flies to `end_pos` and
+    /// never gets autocollected.
+    pub(crate) fn new_towards(pos: Position, end_pos: Position, item_type: ItemType) -> Item {
+        Item {
+            player_only: true,
+            pos_interpolator: Some(Interpolator2::new([pos.x, pos.y], 0, [end_pos.x, end_pos.y], 60, Formula::Linear)),
+            speed_interpolator: None,
+            ..Item::new(pos, item_type)
+        }
+    }
+
+    /// Make this item fly towards the player at `speed`, until it gets collected.
+    pub(crate) fn autocollect(&mut self, speed: f32) {
+        if !self.autocollected && !self.player_only {
+            self.autocollected = true;
+            self.speed = speed;
+        }
+    }
+
+    /// Give this item to the player, returns whether it just reached full power.
+    pub(crate) fn collect(&mut self, player: &mut Player, difficulty: &mut Difficulty) -> bool {
+        // TODO: display the score.
+        let old_power = player.power;
+        let mut score = 0;
+
+        match self.item_type {
+            ItemType::Power | ItemType::BigPower => {
+                let value = if self.item_type == ItemType::Power { 1 } else { 8 };
+                if old_power < MAX_POWER {
+                    player.power_bonus = 0;
+                    player.power = (player.power + value).min(MAX_POWER);
+                    score = 10;
+                } else {
+                    let bonus = (player.power_bonus + value).min(MAX_POWER_BONUS);
+                    score = match bonus {
+                        0..=8 => (bonus + 1) * 10,
+                        9..=17 => (bonus - 8) * 100,
+                        18..=29 => (bonus - 17) * 1000,
+                        _ => 51200,
+                    };
+                    player.power_bonus = bonus;
+                }
+                difficulty.modify(1);
+            }
+            ItemType::Point => {
+                player.points += 1;
+                score = if player.pos.y < player.sht().point_of_collection {
+                    difficulty.modify(30);
+                    100000
+                } else {
+                    difficulty.modify(3);
+                    // TODO: check the formula some more.
+                    (728 - self.pos.y as i32) as u32 * 100
+                };
+            }
+            ItemType::Bomb => {
+                if player.bombs < MAX_STOCK {
+                    player.bombs += 1;
+                }
+                difficulty.modify(5);
+            }
+            ItemType::FullPower => {
+                player.power = MAX_POWER;
+                score = 1000;
+            }
+            ItemType::OneUp => {
+                if player.lives < MAX_STOCK as i32 {
+                    player.lives += 1;
+                }
+                difficulty.modify(200);
+            }
+            ItemType::Star => score = 500,
+        }
+
+        player.score += score;
+        self.removed = true;
+        old_power < MAX_POWER && player.power == MAX_POWER
+    }
+
+    /// Move the item for a single frame, towards `target` if it is being autocollected.
+    pub(crate) fn update(&mut self, target: Position) {
+        if self.frame == 60 {
+            self.speed_interpolator = Some(Interpolator1::new([0.], 60, [3.], 180, Formula::Linear));
+        }
+
+        if self.autocollected {
+            self.angle = (target.y - self.pos.y).atan2(target.x - self.pos.x);
+            self.pos.x += self.angle.cos() * self.speed;
+            self.pos.y += self.angle.sin() * self.speed;
+        } else if let Some(ref interpolator) = self.speed_interpolator {
+            self.speed = interpolator.values(self.frame)[0];
+            self.pos.x += self.angle.cos() * self.speed;
+            self.pos.y += self.angle.sin() * self.speed;
+        } else if let Some(ref interpolator) = self.pos_interpolator {
+            let [x, y] = interpolator.values(self.frame);
+            self.pos = Position::new(x, y);
+        }
+
+        if self.pos.y >= GAME_HEIGHT {
+            self.removed = true;
+        }
+
+        self.frame += 1;
+    }
+}
+
+impl Game {
+    /// Drop an item at this position, unless there are too many already.
+    pub(crate) fn drop_bonus(&mut self, pos: Position, item_type: ItemType) {
+        if self.items.len() >= MAX_ITEMS {
+            return;
+        }
+        self.items.push(Item::new(pos, item_type));
+    }
+
+    /// Drop the bonus of a dying enemy, either a specific one or every third kill from the
+    /// bonus list when it is -1.
+    pub(crate) fn drop_enemy_bonus(&mut self, pos: Position, bonus_dropped: i16) {
+        if bonus_dropped > -1 {
+            self.enemy_death_particles(pos);
+            if let Some(item_type) = ItemType::from_index(bonus_dropped as i32) {
+                self.drop_bonus(pos, item_type);
+            }
+        } else if bonus_dropped == -1 {
+            if self.deaths_count.is_multiple_of(3) {
+                let item_type = ItemType::from_index(BONUS_LIST[self.next_bonus] as i32).unwrap();
+                self.drop_bonus(pos, item_type);
+                self.next_bonus = (self.next_bonus + 1) % BONUS_LIST.len();
+            }
+            self.deaths_count += 1;
+        }
+    }
+
+    /// Drop the items lost by the player on death, or on a continue.
+    pub(crate) fn drop_player_items(&mut self, pos: Position, continued: bool) {
+        let drop_item = |game: &mut Game, item_type| {
+            let x = game.prng.get_f64() as f32 * 288. + 48.;
+            let y = game.prng.get_f64() as f32 * 192. - 64.;
+            game.items.push(Item::new_towards(pos, Position::new(x, y), item_type));
+        };
+        if continued {
+            for _ in 0..5 {
+                drop_item(self, ItemType::FullPower);
+            }
+        } else {
+            drop_item(self, ItemType::BigPower);
+            for _ in 0..5 {
+                drop_item(self, ItemType::Power);
+            }
+        }
+    }
+
+    /// Make every item fly towards the player.
+    pub(crate) fn autocollect(&mut self, speed: f32) {
+        for item in self.items.iter_mut() {
+            item.autocollect(speed);
+        }
+    }
+
+    /// Turn every bullet and laser into star items, which get autocollected.
+    pub(crate) fn change_bullets_into_star_items(&mut self) {
+        let speed = self.player.sht().autocollection_speed;
+        let mut positions: Vec<_> = self.bullets.iter()
+            .filter(|bullet| bullet.state != BulletState::Cancelled)
+            .map(|bullet| bullet.pos)
+            .collect();
+        for laser in self.lasers.iter_mut() {
+            positions.extend(laser.bullets_pos());
+            laser.cancel();
+        }
+        for pos in positions {
+            let mut item = Item::new(pos, ItemType::Star);
+            item.autocollect(speed);
+            self.items.push(item);
+        }
+        self.bullets.retain(|bullet| bullet.state == BulletState::Cancelled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::th06::bullet::Bullet;
+    use crate::th06::testing::new_game;
+    use touhou_formats::th06::ecl::{BulletFlags, Rank};
+    use touhou_formats::th06::sht::Sht;
+
+    #[test]
+    fn fall() {
+        let mut item = Item::new(Position::new(100., 100.), ItemType::Power);
+        for _ in 0..60 {
+            item.update(Position::new(0., 0.));
+        }
+        assert!(item.pos.y < 100.);
+        let top = item.pos.y;
+        for _ in 0..200 {
+            item.update(Position::new(0., 0.));
+        }
+        assert!(item.pos.y > top + 100.);
+        assert_eq!(item.speed, 3.);
+        while !item.removed {
+            item.update(Position::new(0., 0.));
+        }
+        assert!(item.pos.y >= GAME_HEIGHT);
+    }
+
+    #[test]
+    fn autocollect() {
+        let mut item = Item::new(Position::new(100., 100.), ItemType::Point);
+        item.autocollect(8.);
+        item.update(Position::new(100., 200.));
+        assert_eq!(item.pos, Position::new(100., 108.));
+
+        let mut item = Item::new_towards(Position::new(100., 100.), Position::new(160., 40.), ItemType::Power);
+        item.autocollect(8.);
+        for _ in 0..60 {
+            item.update(Position::new(100., 200.));
+        }
+        assert_eq!(item.pos, Position::new(160., 40.));
+    }
+
+    #[test]
+    fn collect() {
+        let mut player = Player::new(Sht::default(), Sht::default());
+        let mut difficulty = Difficulty::new(Rank::NORMAL);
+        assert!(!Item::new(Position::new(0., 0.), ItemType::BigPower).collect(&mut player, &mut difficulty));
+        assert_eq!((player.power, player.score), (8, 10));
+
+        player.power = MAX_POWER - 1;
+        assert!(Item::new(Position::new(0., 0.), ItemType::Power).collect(&mut player, &mut difficulty));
+        for expected in [20, 30, 40] {
+            let score = player.score;
+            Item::new(Position::new(0., 0.), ItemType::Power).collect(&mut player, &mut difficulty);
+            assert_eq!(player.score - score, expected);
+        }
+
+        let score = player.score;
+        Item::new(Position::new(0., 300.), ItemType::Point).collect(&mut player, &mut difficulty);
+        assert_eq!(player.score - score, 42800);
+        player.pos.y = 100.;
+        Item::new(Position::new(0., 300.), ItemType::Point).collect(&mut player, &mut difficulty);
+        assert_eq!(player.score - score, 142800);
+        assert_eq!(player.points, 2);
+
+        player.lives = 7;
+        for _ in 0..2 {
+            Item::new(Position::new(0., 0.), ItemType::OneUp).collect(&mut player, &mut difficulty);
+        }
+        assert_eq!(player.lives, 8);
+        // Five power items, two point items of which one at the top, and two 1ups.
+        assert_eq!(difficulty.value(), 16 + 4);
+    }
+
+    #[test]
+    fn bonus_list() {
+        let mut game = new_game();
+        game.deaths_count = 0;
+        game.next_bonus = 0;
+        for _ in 0..6 {
+            game.drop_enemy_bonus(Position::new(0., 0.), -1);
+        }
+        game.drop_enemy_bonus(Position::new(0., 0.), 5);
+        game.drop_enemy_bonus(Position::new(0., 0.), -2);
+        let types: Vec<_> = game.items.iter().map(|item| item.item_type).collect();
+        assert_eq!(types, [ItemType::Power, ItemType::Power, ItemType::OneUp]);
+        assert_eq!(game.next_bonus, 2);
+    }
+
+    #[test]
+    fn star_items() {
+        let mut game = new_game();
+        for x in [10., 20., 30.] {
+            game.bullets.push(Bullet::new(Position::new(x, 50.), 0, 0, 0., 0., Default::default(), BulletFlags::empty()));
+        }
+        game.bullets[2].cancel();
+        game.change_bullets_into_star_items();
+        assert_eq!(game.bullets.len(), 1);
+        assert_eq!(game.items.len(), 2);
+        assert!(game.items.iter().all(|item| item.item_type == ItemType::Star && item.autocollected));
+    }
+}
@@ -0,0 +1,253 @@
+//! Module providing a Laser struct, fired by enemies with `NewLaser` and friends.
+
+use crate::th06::enemy::Position;
+use touhou_utils::impl_snapshot;
+
+/// Maximum amount of lasers alive at the same time.
+pub(crate) const MAX_LASERS: usize = 64;
+
+/// Distance between two of the bullets a laser can be split into.
+const BULLET_SPACING: f32 = 48.;
+
+/// Lifecycle of a laser.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LaserState {
+    /// The laser is growing to its full width, it can’t hit the player yet.
+    Starting,
+
+    /// The laser is at its full width.
+    Started,
+
+    /// The laser is shrinking until it disappears.
+    Stopping,
+}
+
+impl_snapshot!(enum LaserState { Starting, Started, Stopping });
+
+/// Parameters of `NewLaser` and `NewLaserTowardsPlayer`, once the variables have been resolved.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LaserAttributes {
+    pub(crate) anim: i16,
+    pub(crate) sprite_index_offset: i16,
+    pub(crate) angle: f32,
+    pub(crate) speed: f32,
+    pub(crate) start_offset: f32,
+    pub(crate) end_offset: f32,
+    pub(crate) max_length: f32,
+    pub(crate) width: f32,
+    pub(crate) start_duration: i32,
+    pub(crate) duration: i32,
+    pub(crate) end_duration: i32,
+    pub(crate) grazing_delay: i32,
+    pub(crate) grazing_extra_duration: i32,
+}
+
+/// Struct representing an enemy laser.
+#[derive(Debug, Clone)]
+pub struct Laser {
+    /// Position the laser is fired from.
+    pub base_pos: Position,
+
+    /// Current angle of the laser.
+    pub angle: f32,
+
+    /// Distance between the base position and the start of the laser.
+    pub start_offset: f32,
+
+    /// Distance between the base position and the end of the laser.
+    pub end_offset: f32,
+
+    /// Current width of the laser, which grows while starting and shrinks while stopping.
+    pub width: f32,
+
+    /// Current state of the laser.
+    pub state: LaserState,
+
+    /// Frame in the current state.
+    pub frame: i32,
+
+    /// Laser type, used to select its sprite.
+    pub anim: i16,
+
+    /// Offset into the sprites of this type, usually its colour.
+    pub sprite_index_offset: i16,
+
+    pub(crate) removed: bool,
+    speed: f32,
+    max_length: f32,
+    max_width: f32,
+    start_duration: i32,
+    duration: i32,
+    end_duration: i32,
+    grazing_delay: i32,
+    grazing_extra_duration: i32,
+}
+
+impl_snapshot!(Laser {
+    base_pos, angle, start_offset, end_offset, width, state, frame, anim, sprite_index_offset,
+    removed, speed, max_length, max_width, start_duration, duration, end_duration, grazing_delay,
+    grazing_extra_duration,
+});
+
+impl Laser {
+    /// Create a new laser, starting from `base_pos`.
+    pub(crate) fn new(base_pos: Position, attributes: &LaserAttributes) -> Laser {
+        Laser {
+            base_pos,
+            angle: attributes.angle,
+            start_offset: attributes.start_offset,
+            end_offset: attributes.end_offset,
+            width: 0.,
+            state: LaserState::Starting,
+            frame: 0,
+            anim: attributes.anim,
+            sprite_index_offset: attributes.sprite_index_offset,
+            removed: false,
+            speed: attributes.speed,
+            max_length: attributes.max_length,
+            max_width: attributes.width,
+            start_duration: attributes.start_duration,
+            duration: attributes.duration,
+            end_duration: attributes.end_duration,
+            grazing_delay: attributes.grazing_delay,
+            grazing_extra_duration: attributes.grazing_extra_duration,
+        }
+    }
+
+    /// Move the base of the laser.
+    pub(crate) fn set_base_pos(&mut self, pos: Position) {
+        self.base_pos = pos;
+    }
+
+    /// Stop this laser, it will then shrink until it disappears.
+    pub(crate) fn cancel(&mut self) {
+        self.grazing_extra_duration = 0;
+        if self.state != LaserState::Stopping {
+            self.frame = 0;
+            self.state = LaserState::Stopping;
+        }
+    }
+
+    /// Whether a square hitbox of `half_size` centered on `point` touches this laser.
+    pub fn collides(&self, point: Position, half_size: f32) -> bool {
+        // Express the point in the laser’s frame of reference, with x along its length.
+        let (dx, dy) = (point.x - self.base_pos.x, point.y - self.base_pos.y);
+        let (sin, cos) = self.angle.sin_cos();
+        let along = dx * cos + dy * sin;
+        let across = -dx * sin + dy * cos;
+        // Only the middle half of the sprite is harmful.
+        along >= self.start_offset - half_size
+            && along <= self.end_offset + half_size
+            && across.abs() <= self.width / 4. + half_size
+    }
+
+    /// Whether this laser can kill the player.
+    pub fn is_lethal(&self) -> bool {
+        self.state == LaserState::Started && !self.removed
+    }
+
+    /// Whether this laser can be grazed on this frame.
+    pub fn is_grazable(&self) -> bool {
+        if self.frame % 12 != 0 {
+            return false;
+        }
+        match self.state {
+            LaserState::Starting => self.frame > self.grazing_delay,
+            LaserState::Started => true,
+            LaserState::Stopping => self.frame < self.grazing_extra_duration,
+        }
+    }
+
+    /// Positions of the bullets this laser would turn into, spaced along its length.
+    pub fn bullets_pos(&self) -> Vec<Position> {
+        let (sin, cos) = self.angle.sin_cos();
+        let mut positions = vec![];
+        let mut offset = self.start_offset;
+        while offset < self.end_offset {
+            positions.push(Position::new(self.base_pos.x + offset * cos, self.base_pos.y + offset * sin));
+            offset += BULLET_SPACING;
+        }
+        positions
+    }
+
+    /// Grow and move the laser, and advance its lifecycle by one frame.
+    pub(crate) fn update(&mut self) {
+        self.end_offset += self.speed;
+        if self.end_offset - self.start_offset > self.max_length {
+            self.start_offset = self.end_offset - self.max_length;
+        }
+        if self.start_offset < 0. {
+            self.start_offset = 0.;
+        }
+
+        if self.state == LaserState::Starting {
+            if self.frame >= self.start_duration {
+                self.frame = 0;
+                self.state = LaserState::Started;
+            } else {
+                self.width = self.max_width * self.frame as f32 / self.start_duration as f32;
+            }
+        }
+        if self.state == LaserState::Started {
+            self.width = self.max_width;
+            if self.frame >= self.duration {
+                self.frame = 0;
+                self.state = LaserState::Stopping;
+            }
+        }
+        if self.state == LaserState::Stopping {
+            if self.frame >= self.end_duration {
+                self.width = 0.;
+                self.removed = true;
+            } else {
+                self.width = self.max_width * (1. - self.frame as f32 / self.end_duration as f32);
+            }
+        }
+
+        self.frame += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::th06::testing::falling_laser;
+
+    #[test]
+    fn lifecycle() {
+        let mut laser = falling_laser();
+        laser.update();
+        assert_eq!(laser.state, LaserState::Starting);
+        assert!(!laser.is_grazable());
+        for _ in 0..4 {
+            laser.update();
+        }
+        assert_eq!(laser.state, LaserState::Started);
+        assert_eq!(laser.width, 16.);
+        for _ in 0..10 {
+            laser.update();
+        }
+        assert_eq!(laser.state, LaserState::Stopping);
+        assert_eq!(laser.start_offset, 50.);
+        assert_eq!(laser.end_offset, 150.);
+        for _ in 0..4 {
+            laser.update();
+        }
+        assert!(laser.removed);
+    }
+
+    #[test]
+    fn collision() {
+        let mut laser = falling_laser();
+        for _ in 0..8 {
+            laser.update();
+        }
+        assert!(laser.is_lethal());
+        // The laser goes straight down from (100, 0), and is 80 pixels long.
+        assert!(laser.collides(Position::new(100., 40.), 1.25));
+        assert!(laser.collides(Position::new(105., 40.), 1.25));
+        assert!(!laser.collides(Position::new(106., 40.), 1.25));
+        assert!(!laser.collides(Position::new(100., 90.), 1.25));
+        assert_eq!(laser.bullets_pos().len(), 2);
+    }
+}
@@ -4,5 +4,20 @@ pub mod anm0;
 pub mod ecl;
 pub mod std;
 
+pub mod boss;
+pub mod bullet;
+pub mod collision;
+pub mod difficulty;
+pub mod effect;
 pub mod enemy;
 pub mod interpolator;
+pub mod item;
+pub mod laser;
+pub mod msg;
+pub mod player;
+pub mod spellcard;
+pub mod timeline;
+pub mod trace;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,314 @@
+//! Module providing the dialogues played before and after bosses.
+
+use touhou_formats::th06::msg::{Call, Instruction, Msg};
+use crate::th06::enemy::Game;
+use crate::th06::player::Keys;
+use touhou_utils::impl_snapshot;
+use touhou_utils::snapshot::Error;
+use std::sync::Arc;
+
+/// A character displayed on one side of the dialogue.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Face {
+    /// Face currently displayed, from the face ANM of this side.
+    pub index: i16,
+
+    /// Last animation played on this character.
+    pub effect: i16,
+}
+
+impl_snapshot!(Face { index, effect });
+
+/// What a dialogue currently displays.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dialogue {
+    /// Characters on the left (player) and right (boss) sides.
+    pub faces: [Option<Face>; 2],
+
+    /// The two lines of text currently displayed.
+    pub lines: [Option<String>; 2],
+
+    /// Side of the character saying these lines.
+    pub speaker: i16,
+
+    /// Name of the boss, once introduced.
+    pub boss_name: Option<String>,
+
+    /// Title of the boss, once introduced.
+    pub boss_title: Option<String>,
+}
+
+impl_snapshot!(Dialogue { faces, lines, speaker, boss_name, boss_title });
+
+/// Interpreter for a dialogue script, pausing the main timeline until it ends.
+#[derive(Clone)]
+pub struct MsgRunner {
+    msg: Arc<Msg>,
+    script: u8,
+    frame: u16,
+    ip: usize,
+    sleep_time: u32,
+    allow_skip: bool,
+    skipping: bool,
+    frozen: bool,
+    ended: bool,
+    // Whether a key able to skip was already pressed on the previous frame.
+    skip_held: bool,
+    dialogue: Dialogue,
+}
+
+impl_snapshot!(MsgRunner, |game: Game| {
+    script, frame, ip, sleep_time, allow_skip, skipping, frozen, ended, skip_held, dialogue,
+} {
+    msg: game.msg.clone().ok_or(Error::MissingData("msg"))?,
+});
+
+impl MsgRunner {
+    fn new(msg: Arc<Msg>, script: u8) -> MsgRunner {
+        MsgRunner {
+            msg,
+            script,
+            frame: 0,
+            ip: 0,
+            sleep_time: 0,
+            allow_skip: true,
+            skipping: false,
+            frozen: false,
+            ended: false,
+            skip_held: false,
+            dialogue: Dialogue::default(),
+        }
+    }
+
+    /// Returns what this dialogue currently displays.
+    pub fn dialogue(&self) -> &Dialogue {
+        &self.dialogue
+    }
+
+    /// Stop the current pause, as the player pressed a key.
+    fn skip(&mut self) {
+        self.sleep_time = 0;
+    }
+
+    /// Handle the keys pressed during this frame, then run it.
+    fn update(&mut self, keys: Keys, game: &mut Game) {
+        // Only new presses skip a pause, holding the skip key fast-forwards instead.
+        let pressed = keys.intersects(Keys::SHOOT | Keys::SKIP);
+        if pressed && !self.skip_held {
+            self.skip();
+        }
+        self.skip_held = pressed;
+        self.skipping = keys.contains(Keys::SKIP);
+        self.run_frame(game);
+    }
+
+    /// Run every instruction of the current frame, then advance unless paused.
+    fn run_frame(&mut self, game: &mut Game) {
+        let msg = self.msg.clone();
+        let calls = match msg.scripts.get(&self.script) {
+            Some(calls) => calls,
+            None => {
+                self.ended = true;
+                return;
+            }
+        };
+        while !self.ended {
+            let Call { time, instr } = match calls.get(self.ip) {
+                Some(call) => call,
+                None => {
+                    self.ended = true;
+                    return;
+                }
+            };
+            if *time > self.frame {
+                break;
+            }
+            self.ip += 1;
+            if *time == self.frame {
+                self.run_instruction(instr, game);
+            }
+        }
+
+        if !self.frozen {
+            if self.sleep_time > 0 {
+                self.sleep_time -= 1;
+            } else {
+                self.frame += 1;
+            }
+        }
+    }
+
+    fn face(&mut self, side: i16) -> Option<&mut Face> {
+        self.dialogue.faces.get_mut(side as usize).and_then(Option::as_mut)
+    }
+
+    fn run_instruction(&mut self, instruction: &Instruction, game: &mut Game) {
+        match instruction {
+            // The original game crashes here when the dialogue can be skipped.
+            Instruction::End() => self.ended = true,
+            &Instruction::Enter(side, effect) => {
+                if let Some(face) = self.dialogue.faces.get_mut(side as usize) {
+                    *face = Some(Face { index: 0, effect });
+                }
+            }
+            &Instruction::ChangeFace(side, index) => {
+                if let Some(face) = self.face(side) {
+                    face.index = index;
+                }
+            }
+            Instruction::DisplayText(side, index, text) => {
+                let lines = &mut self.dialogue.lines;
+                if *index == 0 {
+                    *lines = Default::default();
+                }
+                if let Some(line) = lines.get_mut(*index as usize) {
+                    *line = Some(text.clone());
+                }
+                self.dialogue.speaker = *side;
+            }
+            &Instruction::Pause(duration) => {
+                if !(self.skipping && self.allow_skip) {
+                    self.sleep_time = duration;
+                }
+            }
+            &Instruction::Animate(side, effect) => {
+                if let Some(face) = self.face(side) {
+                    face.effect = effect;
+                }
+            }
+            Instruction::SpawnEnemySprite() => game.msg_wait = false,
+            Instruction::DisplayDescription(_, index, text) => {
+                let description = if *index == 0 { &mut self.dialogue.boss_name } else { &mut self.dialogue.boss_title };
+                *description = Some(text.clone());
+            }
+            Instruction::Freeze() => self.frozen = true,
+            Instruction::NextStage() => game.stage_cleared = true,
+            &Instruction::SetAllowSkip(allow) => self.allow_skip = allow != 0,
+            // TODO: play the music, and display the scores.
+            Instruction::ChangeMusic(_) | Instruction::ShowScores(_) => (),
+            Instruction::Unknown12() | Instruction::Unknown14() | Instruction::Unknown(_, _) => (),
+        }
+    }
+}
+
+impl Game {
+    /// Load the dialogues of the current stage, msg{stage}.dat in the original game.
+    pub fn load_msg(&mut self, msg: Msg) {
+        self.msg = Some(Arc::new(msg));
+    }
+
+    /// Start this dialogue, picking the script of the current character.
+    ///
+    /// Nothing happens if no dialogue got loaded.
+    pub(crate) fn start_msg(&mut self, script: u16) {
+        let msg = match self.msg.clone() {
+            Some(msg) => msg,
+            None => return,
+        };
+        let script = script as u8 + 10 * (self.player.character / 2);
+        let mut runner = MsgRunner::new(msg, script);
+        runner.run_frame(self);
+        self.msg_runner = Some(runner);
+        self.end_msg_if_done();
+    }
+
+    /// Advance the current dialogue, if any, returning the keys left for the player.
+    pub(crate) fn update_msg(&mut self, keys: Keys) -> Keys {
+        let mut runner = match self.msg_runner.take() {
+            Some(runner) => runner,
+            None => return keys,
+        };
+        runner.update(keys, self);
+        self.msg_runner = Some(runner);
+        self.end_msg_if_done();
+        // The player can’t attack during a dialogue.
+        keys - (Keys::SHOOT | Keys::BOMB)
+    }
+
+    /// Pause the main timeline, as long as a dialogue is running.
+    pub(crate) fn wait_msg(&mut self) {
+        self.msg_wait = self.msg_runner.is_some();
+    }
+
+    /// Stop the current dialogue, and resume the main timeline.
+    pub(crate) fn end_msg(&mut self) {
+        self.msg_runner = None;
+        self.msg_wait = false;
+    }
+
+    fn end_msg_if_done(&mut self) {
+        if self.msg_runner.as_ref().is_some_and(|runner| runner.ended) {
+            self.end_msg();
+        }
+    }
+
+    /// Returns what the current dialogue displays, if any.
+    pub fn dialogue(&self) -> Option<&Dialogue> {
+        self.msg_runner.as_ref().map(MsgRunner::dialogue)
+    }
+
+    /// Whether a dialogue ended the stage.
+    pub fn is_stage_cleared(&self) -> bool {
+        self.stage_cleared
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::th06::testing::load_dialogue;
+
+    #[test]
+    fn dialogue() {
+        let mut game = load_dialogue();
+        for _ in 0..5 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+        let dialogue = game.dialogue().unwrap();
+        assert_eq!(dialogue.faces, [Some(Face { index: 0, effect: 1 }), None]);
+        assert_eq!(dialogue.lines, [Some(String::from("Is that so?")), None]);
+        assert_eq!(dialogue.boss_name, None);
+
+        // Shooting skips the pause, without firing anything.
+        game.run_frame(Keys::SHOOT).unwrap();
+        game.run_frame(Keys::SHOOT).unwrap();
+        assert_eq!(game.dialogue().unwrap().boss_name.as_deref(), Some("Rumia"));
+        assert_eq!(game.player_bullets().count(), 0);
+        assert_eq!(game.enemies().count(), 0);
+
+        // The main timeline resumed, and the dialogue ends.
+        game.run_frame(Keys::empty()).unwrap();
+        game.run_frame(Keys::empty()).unwrap();
+        assert_eq!(game.enemies().count(), 1);
+        assert!(game.dialogue().is_none());
+    }
+
+    #[test]
+    fn main_waits_for_dialogue() {
+        let mut game = load_dialogue();
+        for _ in 0..60 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+        assert_eq!(game.enemies().count(), 0);
+        let state = game.snapshot();
+        let mut copy = game.clone();
+        for _ in 0..60 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+        assert_eq!(game.enemies().count(), 1);
+        assert!(game.dialogue().is_none());
+
+        copy.restore(&state).unwrap();
+        assert!(copy.dialogue().is_some());
+    }
+
+    #[test]
+    fn no_msg_loaded() {
+        let mut game = load_dialogue();
+        game.msg = None;
+        game.run_frame(Keys::empty()).unwrap();
+        game.run_frame(Keys::empty()).unwrap();
+        assert!(game.dialogue().is_none());
+        assert_eq!(game.enemies().count(), 1);
+    }
+}
@@ -0,0 +1,581 @@
+//! Module providing a Player struct, driven by the keys pressed each frame.
+
+use touhou_formats::th06::sht::{Sht, Shot};
+use crate::th06::bullet::{GAME_WIDTH, GAME_HEIGHT, MAX_BULLETS};
+use crate::th06::enemy::{Game, Position};
+use touhou_utils::impl_snapshot;
+use bitflags::bitflags;
+
+bitflags! {
+    /// Keys pressed during a frame, using the same bits as pytouhou and its gym environment.
+    #[derive(Default)]
+    pub struct Keys: u16 {
+        /// Fire shots.
+        const SHOOT = 1;
+
+        /// Use a bomb.
+        const BOMB = 2;
+
+        /// Move slower, and switch to the focused shot type.
+        const FOCUS = 4;
+
+        /// Move up.
+        const UP = 16;
+
+        /// Move down.
+        const DOWN = 32;
+
+        /// Move left.
+        const LEFT = 64;
+
+        /// Move right.
+        const RIGHT = 128;
+
+        /// Fast-forward dialogues.
+        const SKIP = 256;
+    }
+}
+
+/// Amount of frames during which the player can’t be hit, after a bomb or a respawn.
+const INVULNERABLE_DURATION: u32 = 240;
+
+/// Amount of frames a bomb lasts.
+// TODO: this depends on the character.
+const BOMB_DURATION: u32 = 240;
+
+/// Amount of frames after being hit during which a bomb still saves the player.
+const DEATHBOMB_WINDOW: u32 = 6;
+
+/// Amount of frames the shots keep being fired after the shoot key got pressed.
+const FIRE_DURATION: u32 = 30;
+
+/// Amount of lasers a player can fire at once, indexed by the delay of their shot.
+const MAX_LASERS: usize = 2;
+
+/// Maximum angle a homing bullet turns by each frame.
+// TODO: find the actual behaviour in the binary, pytouhou doesn’t implement homing either.
+const HOMING_ANGLE: f32 = std::f32::consts::PI / 30.;
+
+/// Maximum power of the player.
+pub(crate) const MAX_POWER: u32 = 128;
+
+// TODO: use the right defaults, they can be changed in the options.
+const DEFAULT_LIVES: i32 = 2;
+const DEFAULT_BOMBS: u32 = 3;
+
+/// Something which happened to the player during a frame, for the game to react to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlayerEvent {
+    /// A bomb just started.
+    Bomb,
+
+    /// The player lost a life.
+    Died,
+
+    /// The player lost its last life, and used a continue.
+    Continued,
+
+    /// All enemy bullets should get cancelled, before the player respawns.
+    CancelBullets,
+
+    /// The player lost its last life, with no continue left.
+    GameOver,
+}
+
+/// A bullet fired by the player.
+#[derive(Debug, Clone)]
+pub struct PlayerBullet {
+    /// Current position of the bullet.
+    pub pos: Position,
+
+    /// Current velocity of the bullet.
+    pub dpos: [f32; 2],
+
+    /// Half-size of the hitbox of this bullet.
+    pub hitbox: [f32; 2],
+
+    /// Damage dealt to the enemy it hits.
+    pub damage: u16,
+
+    /// Sprite of this bullet.
+    pub sprite: i16,
+
+    pub(crate) removed: bool,
+    accelerating: bool,
+    homing: bool,
+}
+
+impl_snapshot!(PlayerBullet { pos, dpos, hitbox, damage, sprite, removed, accelerating, homing });
+
+impl PlayerBullet {
+    pub(crate) fn new(pos: Position, shot: &Shot) -> PlayerBullet {
+        PlayerBullet {
+            pos,
+            dpos: [shot.angle.cos() * shot.speed, shot.angle.sin() * shot.speed],
+            hitbox: [shot.hitbox.0 / 2., shot.hitbox.1 / 2.],
+            damage: shot.damage,
+            sprite: shot.sprite,
+            removed: false,
+            accelerating: shot.shot_type == 2,
+            homing: shot.shot_type == 1,
+        }
+    }
+
+    /// Move the bullet, a homing one turning towards `target`.
+    pub(crate) fn update(&mut self, target: Option<Position>) {
+        if let (true, Some(target)) = (self.homing, target) {
+            let [dx, dy] = self.dpos;
+            let speed = dx.hypot(dy);
+            let angle = dy.atan2(dx);
+            let mut diff = (target.y - self.pos.y).atan2(target.x - self.pos.x) - angle;
+            diff = (diff + std::f32::consts::PI).rem_euclid(std::f32::consts::TAU) - std::f32::consts::PI;
+            let angle = angle + diff.clamp(-HOMING_ANGLE, HOMING_ANGLE);
+            self.dpos = [angle.cos() * speed, angle.sin() * speed];
+        }
+        if self.accelerating {
+            // TODO: triple-check acceleration!
+            self.dpos[1] -= 0.15;
+        }
+        self.pos.x += self.dpos[0];
+        self.pos.y += self.dpos[1];
+        let Position { x, y } = self.pos;
+        if !(-32. ..=GAME_WIDTH + 32.).contains(&x) || !(-32. ..=GAME_HEIGHT + 32.).contains(&y) {
+            self.removed = true;
+        }
+    }
+}
+
+/// A laser fired by the player, following it until the end of its duration.
+#[derive(Debug, Clone)]
+pub struct PlayerLaser {
+    /// Position of the laser, its middle being at half the height of the player.
+    pub pos: Position,
+
+    /// Length of the laser, from the top of the screen to the player.
+    pub length: f32,
+
+    /// Half-width of the hitbox of this laser.
+    pub half_width: f32,
+
+    /// Damage dealt to each enemy it touches, every frame.
+    pub damage: u16,
+
+    /// Sprite of this laser.
+    pub sprite: i16,
+
+    angle: f32,
+    offset: f32,
+    duration: u32,
+    frame: u32,
+}
+
+impl_snapshot!(PlayerLaser { pos, length, half_width, damage, sprite, angle, offset, duration, frame });
+
+impl PlayerLaser {
+    fn new(shot: &Shot) -> PlayerLaser {
+        PlayerLaser {
+            pos: Position::new(0., 0.),
+            length: 0.,
+            // Like pytouhou, the width of the hitbox is used as its half-width.
+            half_width: shot.hitbox.0,
+            damage: shot.damage,
+            sprite: shot.sprite,
+            angle: shot.angle,
+            offset: shot.speed,
+            duration: shot.interval as u32,
+            frame: 0,
+        }
+    }
+
+    /// Follow the player at `origin`, returns whether the laser is over.
+    fn update(&mut self, origin: Position) -> bool {
+        self.length = origin.y;
+        self.pos.x = origin.x + self.offset * self.angle.cos();
+        self.pos.y = origin.y / 2. + self.offset * self.angle.sin();
+        let over = self.frame == self.duration;
+        self.frame += 1;
+        over
+    }
+
+    /// Whether this laser touches a hitbox.
+    pub(crate) fn collides(&self, pos: Position, half_size: [f32; 2]) -> bool {
+        // The laser goes from the player up to the top of the screen.
+        let bottom = self.pos.y * 2.;
+        (self.pos.x - pos.x).abs() <= self.half_width + half_size[0] && bottom >= pos.y - half_size[1]
+    }
+}
+
+/// Struct representing the player.
+#[derive(Debug, Clone)]
+pub struct Player {
+    pub(crate) pos: Position,
+
+    /// Current score.
+    pub score: u32,
+
+    /// Remaining lives, not counting the current one.
+    pub lives: i32,
+
+    /// Remaining bombs.
+    pub bombs: u32,
+
+    /// Current power, between 0 and 128.
+    pub power: u32,
+
+    /// Bonus gained from power items at full power, between 0 and 30.
+    pub power_bonus: u32,
+
+    /// Amount of bullets grazed.
+    pub graze: u32,
+
+    /// Amount of point items collected.
+    pub points: u32,
+
+    /// Amount of lives lost.
+    pub miss: u32,
+
+    /// Amount of bombs used.
+    pub bombs_used: u32,
+
+    /// Remaining continues, negative means infinite.
+    pub continues: i32,
+
+    /// Amount of continues used.
+    pub continues_used: u32,
+
+    /// Whether the focus key is being held.
+    pub focused: bool,
+
+    /// Character and shot type, from 0 to 3 for ReimuA, ReimuB, MarisaA and MarisaB.
+    pub character: u8,
+
+    pub(crate) touchable: bool,
+    pub(crate) invulnerable_time: u32,
+    fire_time: u32,
+    bomb_time: u32,
+    death_time: Option<u32>,
+    lasers: [Option<PlayerLaser>; MAX_LASERS],
+    sht: Sht,
+    focused_sht: Sht,
+}
+
+// The characteristics of the character are kept from the current player.
+impl_snapshot!(Player, |game: Game| {
+    pos, score, lives, bombs, power, power_bonus, graze, points, miss, bombs_used, continues,
+    continues_used, focused, character, touchable, invulnerable_time, fire_time, bomb_time,
+    death_time, lasers,
+} {
+    sht: game.player.sht.clone(),
+    focused_sht: game.player.focused_sht.clone(),
+});
+
+impl Player {
+    /// Create a new player, with the characteristics of its character when unfocused and focused.
+    pub fn new(sht: Sht, focused_sht: Sht) -> Player {
+        Player {
+            pos: Position::new(GAME_WIDTH / 2., 384.),
+            score: 0,
+            lives: DEFAULT_LIVES,
+            bombs: DEFAULT_BOMBS,
+            power: 0,
+            power_bonus: 0,
+            graze: 0,
+            points: 0,
+            miss: 0,
+            bombs_used: 0,
+            continues: 0,
+            continues_used: 0,
+            focused: false,
+            character: 0,
+            touchable: true,
+            invulnerable_time: INVULNERABLE_DURATION,
+            fire_time: 0,
+            bomb_time: 0,
+            death_time: None,
+            lasers: Default::default(),
+            sht,
+            focused_sht,
+        }
+    }
+
+    /// Current position of the player.
+    pub fn pos(&self) -> Position {
+        self.pos
+    }
+
+    /// Characteristics of the player in its current focus mode.
+    pub fn sht(&self) -> &Sht {
+        if self.focused { &self.focused_sht } else { &self.sht }
+    }
+
+    /// Returns an iterator over the lasers currently fired by the player.
+    pub fn lasers(&self) -> impl Iterator<Item = &PlayerLaser> {
+        self.lasers.iter().flatten()
+    }
+
+    /// Whether a bomb is currently active.
+    pub fn is_bombing(&self) -> bool {
+        self.bomb_time > 0
+    }
+
+    /// Whether the player can currently get hit.
+    pub fn is_vulnerable(&self) -> bool {
+        self.touchable && self.invulnerable_time == 0 && self.death_time.is_none()
+    }
+
+    /// Hit the player, who will die unless it bombs in the next few frames.
+    pub fn hit(&mut self) {
+        if self.is_vulnerable() {
+            self.death_time = Some(0);
+        }
+    }
+
+    /// Run the player for a single frame, firing new shots into `bullets`.
+    pub(crate) fn update(&mut self, keys: Keys, bullets: &mut Vec<PlayerBullet>) -> Option<PlayerEvent> {
+        let mut event = None;
+
+        if self.death_time.is_none_or(|time| time > 60) {
+            self.focused = keys.contains(Keys::FOCUS);
+            self.move_(keys);
+
+            if self.invulnerable_time > 0 {
+                self.invulnerable_time -= 1;
+            }
+
+            if keys.contains(Keys::SHOOT) && self.fire_time == 0 {
+                self.fire_time = FIRE_DURATION;
+            }
+            if self.fire_time > 0 {
+                self.fire(bullets);
+                self.fire_time -= 1;
+            }
+        }
+
+        if self.death_time.is_none_or(|time| time < DEATHBOMB_WINDOW) {
+            if keys.contains(Keys::BOMB) && self.bombs > 0 && self.bomb_time == 0 {
+                self.bomb_time = BOMB_DURATION;
+                self.bombs -= 1;
+                self.bombs_used += 1;
+                self.invulnerable_time = INVULNERABLE_DURATION;
+                // Deathbomb.
+                self.death_time = None;
+                event = Some(PlayerEvent::Bomb);
+            }
+            if self.bomb_time > 0 {
+                self.bomb_time -= 1;
+            }
+        }
+
+        let origin = self.pos;
+        for laser in self.lasers.iter_mut() {
+            if laser.as_mut().is_some_and(|laser| laser.update(origin)) {
+                *laser = None;
+            }
+        }
+
+        if let Some(time) = self.death_time {
+            let time = time + 1;
+            self.death_time = Some(time);
+            match time {
+                // Too late, you are dead. :(
+                DEATHBOMB_WINDOW => event = Some(self.die()),
+                31 => event = Some(PlayerEvent::CancelBullets),
+                32 => self.pos = Position::new(GAME_WIDTH / 2., 384.),
+                61 => {
+                    self.touchable = true;
+                    self.invulnerable_time = INVULNERABLE_DURATION;
+                }
+                // Start the bullet hell again.
+                91 => self.death_time = None,
+                _ => (),
+            }
+        }
+
+        event
+    }
+
+    fn move_(&mut self, keys: Keys) {
+        let sht = self.sht();
+        let (speed, diagonal_speed) = if self.focused {
+            (sht.horizontal_vertical_focused_speed, sht.diagonal_focused_speed)
+        } else {
+            (sht.horizontal_vertical_speed, sht.diagonal_speed)
+        };
+
+        let dx = match (keys.contains(Keys::LEFT), keys.contains(Keys::RIGHT)) {
+            (true, false) => -1.,
+            (false, true) => 1.,
+            _ => 0.,
+        };
+        let dy = match (keys.contains(Keys::UP), keys.contains(Keys::DOWN)) {
+            (true, false) => -1.,
+            (false, true) => 1.,
+            _ => 0.,
+        };
+        let speed = if dx != 0. && dy != 0. { diagonal_speed } else { speed };
+
+        self.pos.x = (self.pos.x + dx * speed).clamp(8., GAME_WIDTH - 8.);
+        self.pos.y = (self.pos.y + dy * speed).clamp(16., GAME_HEIGHT - 16.);
+    }
+
+    fn fire(&mut self, bullets: &mut Vec<PlayerBullet>) {
+        let sht = if self.focused { &self.focused_sht } else { &self.sht };
+        for shot in sht.get_shots(self.power) {
+            if shot.shot_type == 3 {
+                // Lasers are only fired once, and keep going until the end of their duration.
+                if self.fire_time != FIRE_DURATION {
+                    continue;
+                }
+                if let Some(laser @ None) = self.lasers.get_mut(shot.delay as usize) {
+                    *laser = Some(PlayerLaser::new(shot));
+                }
+                continue;
+            }
+            if shot.interval == 0 || !(self.fire_time + shot.delay as u32).is_multiple_of(shot.interval as u32) {
+                continue;
+            }
+            if bullets.len() >= MAX_BULLETS {
+                break;
+            }
+            // TODO: fire from the orbs once they are implemented.
+            let pos = Position::new(self.pos.x + shot.pos.0, self.pos.y + shot.pos.1);
+            bullets.push(PlayerBullet::new(pos, shot));
+        }
+    }
+
+    fn die(&mut self) -> PlayerEvent {
+        self.touchable = false;
+        self.lasers = Default::default();
+        self.power = self.power.saturating_sub(16);
+        self.bombs = DEFAULT_BOMBS;
+        self.miss += 1;
+        self.lives -= 1;
+        if self.lives >= 0 {
+            return PlayerEvent::Died;
+        }
+
+        if self.continues == 0 {
+            return PlayerEvent::GameOver;
+        }
+        // Don’t decrement if it’s infinite.
+        if self.continues > 0 {
+            self.continues -= 1;
+        }
+        self.continues_used += 1;
+        self.score = self.continues_used.min(9);
+        self.lives = DEFAULT_LIVES;
+        self.power = 0;
+        self.graze = 0;
+        self.points = 0;
+        PlayerEvent::Continued
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::th06::testing::shooting_player;
+
+    #[test]
+    fn movement() {
+        let mut player = shooting_player();
+        let mut bullets = vec![];
+        player.update(Keys::LEFT, &mut bullets);
+        assert_eq!(player.pos, Position::new(188., 384.));
+        player.update(Keys::LEFT | Keys::FOCUS, &mut bullets);
+        assert_eq!(player.pos, Position::new(186., 384.));
+        assert!(player.focused);
+        for _ in 0..100 {
+            player.update(Keys::DOWN | Keys::RIGHT, &mut bullets);
+        }
+        assert_eq!(player.pos, Position::new(GAME_WIDTH - 8., GAME_HEIGHT - 16.));
+        assert!(bullets.is_empty());
+    }
+
+    #[test]
+    fn shots() {
+        let mut player = shooting_player();
+        let mut bullets = vec![];
+        for _ in 0..FIRE_DURATION {
+            player.update(Keys::SHOOT, &mut bullets);
+        }
+        assert_eq!(bullets.len(), 6);
+        assert_eq!(bullets[0].damage, 4);
+        bullets[0].update(None);
+        assert_eq!(bullets[0].pos.y, 374.);
+    }
+
+    #[test]
+    fn lasers() {
+        let mut player = shooting_player();
+        let laser = Shot { interval: 40, delay: 1, hitbox: (8., 0.), speed: 0., angle: -std::f32::consts::FRAC_PI_2, damage: 2, shot_type: 3, ..Default::default() };
+        player.sht.shots.get_mut(&999).unwrap().push(laser);
+        let mut bullets = vec![];
+        player.update(Keys::SHOOT, &mut bullets);
+        let laser = player.lasers[1].as_ref().unwrap();
+        assert_eq!(laser.pos, Position::new(192., 192.));
+        assert_eq!(laser.length, 384.);
+
+        // It follows the player, and doesn’t get fired again while alive.
+        for _ in 0..FIRE_DURATION {
+            player.update(Keys::SHOOT | Keys::LEFT, &mut bullets);
+        }
+        assert_eq!(player.lasers().count(), 1);
+        assert_eq!(player.lasers().next().unwrap().pos.x, 192. - 4. * FIRE_DURATION as f32);
+        for _ in 0..10 {
+            player.update(Keys::empty(), &mut bullets);
+        }
+        assert_eq!(player.lasers().count(), 0);
+
+        player.invulnerable_time = 0;
+        player.update(Keys::SHOOT, &mut bullets);
+        player.hit();
+        for _ in 0..DEATHBOMB_WINDOW {
+            player.update(Keys::empty(), &mut bullets);
+        }
+        assert_eq!(player.lasers().count(), 0);
+    }
+
+    #[test]
+    fn homing() {
+        let shot = Shot { speed: 10., angle: -std::f32::consts::FRAC_PI_2, shot_type: 1, ..Default::default() };
+        let mut bullet = PlayerBullet::new(Position::new(192., 384.), &shot);
+        bullet.update(Some(Position::new(392., 384.)));
+        let angle = bullet.dpos[1].atan2(bullet.dpos[0]);
+        assert!((angle - (-std::f32::consts::FRAC_PI_2 + HOMING_ANGLE)).abs() < 1e-5);
+        assert!((bullet.dpos[0].hypot(bullet.dpos[1]) - 10.).abs() < 1e-5);
+
+        // Without any enemy, it keeps going straight.
+        let dpos = bullet.dpos;
+        bullet.update(None);
+        assert_eq!(bullet.dpos, dpos);
+    }
+
+    #[test]
+    fn death_and_deathbomb() {
+        let mut player = shooting_player();
+        let mut bullets = vec![];
+        player.hit();
+        assert!(player.death_time.is_none());
+        for _ in 0..INVULNERABLE_DURATION {
+            player.update(Keys::empty(), &mut bullets);
+        }
+
+        player.hit();
+        player.update(Keys::empty(), &mut bullets);
+        assert_eq!(player.update(Keys::BOMB, &mut bullets), Some(PlayerEvent::Bomb));
+        assert_eq!(player.lives, DEFAULT_LIVES);
+        assert_eq!(player.bombs, DEFAULT_BOMBS - 1);
+        assert!(!player.is_vulnerable());
+        for _ in 0..INVULNERABLE_DURATION {
+            player.update(Keys::empty(), &mut bullets);
+        }
+
+        player.hit();
+        let events: Vec<_> = (0..91).filter_map(|_| player.update(Keys::empty(), &mut bullets)).collect();
+        assert_eq!(events, [PlayerEvent::Died, PlayerEvent::CancelBullets]);
+        assert_eq!(player.lives, DEFAULT_LIVES - 1);
+        assert_eq!(player.bombs, DEFAULT_BOMBS);
+        assert!(player.death_time.is_none());
+    }
+}
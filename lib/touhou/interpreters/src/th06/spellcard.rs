@@ -0,0 +1,151 @@
+//! Module providing the spellcards declared by bosses, and whether they got captured.
+//!
+//! The bonus given on capture isn’t known yet, so capturing a spellcard doesn’t give any score.
+
+use crate::th06::enemy::Game;
+use touhou_utils::impl_snapshot;
+
+/// Something which happened to a spellcard, to be used as a reward.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpellcardEvent {
+    /// A boss declared this spellcard.
+    Started(i16),
+
+    /// The player died or used a bomb, or the timer ran out, it can’t be captured anymore.
+    Failed(i16),
+
+    /// The player survived this spellcard without bombing.
+    Captured(i16),
+}
+
+/// Struct representing the spellcard currently declared by a boss.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spellcard {
+    /// Face of the boss displayed while declaring it.
+    pub face: i16,
+
+    /// Number of this spellcard, unique across the game.
+    pub number: i16,
+
+    /// Name of this spellcard.
+    pub name: String,
+
+    /// Whether the player can still capture it.
+    pub capturing: bool,
+
+    /// Amount of frames since it got declared.
+    pub frame: u32,
+}
+
+impl_snapshot!(Spellcard { face, number, name, capturing, frame });
+
+impl Spellcard {
+    /// Create said spellcard, which can still be captured.
+    pub fn new(face: i16, number: i16, name: String) -> Spellcard {
+        Spellcard {
+            face,
+            number,
+            name,
+            capturing: true,
+            frame: 0,
+        }
+    }
+
+    /// Run one frame.
+    pub fn update(&mut self) {
+        self.frame += 1;
+    }
+}
+
+impl Game {
+    /// Declare a new spellcard, turning every bullet into star items.
+    pub(crate) fn start_spellcard(&mut self, face: i16, number: i16, name: String) {
+        self.change_bullets_into_star_items();
+        self.spellcard = Some(Spellcard::new(face, number, name));
+        self.spellcard_effect = true;
+        self.spellcard_events.push(SpellcardEvent::Started(number));
+    }
+
+    /// End the current spellcard, reporting whether it got captured.
+    // TODO: give the capture bonus to the player, once its value is known.
+    pub(crate) fn end_spellcard(&mut self) {
+        if let Some(spellcard) = self.spellcard.take() {
+            self.change_bullets_into_star_items();
+            if spellcard.capturing {
+                self.spellcard_events.push(SpellcardEvent::Captured(spellcard.number));
+            }
+        }
+        self.spellcard_effect = false;
+    }
+
+    /// Allow the current spellcard to be captured, even if it already failed.
+    pub(crate) fn enable_spellcard_bonus(&mut self) {
+        if let Some(spellcard) = self.spellcard.as_mut() {
+            spellcard.capturing = true;
+        }
+    }
+
+    /// The player died or bombed, or the timer ran out, the current spellcard can’t be captured
+    /// anymore.
+    pub(crate) fn fail_spellcard(&mut self) {
+        if let Some(spellcard) = self.spellcard.as_mut() {
+            if spellcard.capturing {
+                spellcard.capturing = false;
+                self.spellcard_events.push(SpellcardEvent::Failed(spellcard.number));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::th06::bullet::Bullet;
+    use crate::th06::enemy::Position;
+    use crate::th06::item::ItemType;
+    use crate::th06::player::Keys;
+    use crate::th06::testing::new_game;
+    use touhou_formats::th06::ecl::BulletFlags;
+
+    #[test]
+    fn capture() {
+        let mut game = new_game();
+        game.bullets.push(Bullet::new(Position::new(100., 100.), 0, 0, 0., 1., Default::default(), BulletFlags::empty()));
+        game.start_spellcard(0, 3, String::from("Moonlight Ray"));
+        assert!(game.has_spellcard_effect());
+        assert_eq!(game.spellcard().unwrap().number, 3);
+        assert_eq!(game.bullets().count(), 0);
+        assert_eq!(game.items().next().unwrap().item_type, ItemType::Star);
+        assert_eq!(game.spellcard_events(), &[SpellcardEvent::Started(3)]);
+
+        for _ in 0..10 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+        assert!(game.spellcard_events().is_empty());
+        assert_eq!(game.spellcard().unwrap().frame, 10);
+        let score = game.player.score;
+        game.end_spellcard();
+        assert!(!game.has_spellcard_effect());
+        assert!(game.spellcard().is_none());
+        assert_eq!(game.player.score, score);
+        assert_eq!(game.spellcard_events(), &[SpellcardEvent::Captured(3)]);
+    }
+
+    #[test]
+    fn failure() {
+        let mut game = new_game();
+        game.bullets.push(Bullet::new(Position::new(100., 100.), 0, 0, 0., 1., Default::default(), BulletFlags::empty()));
+        game.start_spellcard(0, 3, String::from("Moonlight Ray"));
+        game.fail_spellcard();
+        game.fail_spellcard();
+        assert_eq!(game.spellcard_events(), &[SpellcardEvent::Started(3), SpellcardEvent::Failed(3)]);
+        game.end_spellcard();
+        assert_eq!(game.spellcard_events().len(), 2);
+
+        game.start_spellcard(0, 4, String::from("Night Sign 「Night Bird」"));
+        game.fail_spellcard();
+        game.enable_spellcard_bonus();
+        game.end_spellcard();
+        assert_eq!(game.spellcard_events().last(), Some(&SpellcardEvent::Captured(4)));
+    }
+}
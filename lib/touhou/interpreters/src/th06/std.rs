@@ -2,14 +2,17 @@
 
 use touhou_formats::th06::std::{Stage, Call, Instruction};
 use crate::th06::interpolator::{Interpolator3, Formula};
+use crate::th06::enemy::Game;
 use touhou_utils::math::{Mat4, setup_camera};
-use std::cell::RefCell;
-use std::rc::Rc;
+use touhou_utils::impl_snapshot;
+use touhou_utils::snapshot::Error;
+use std::sync::Arc;
 
 /// Interpreter for Stage.
+#[derive(Clone)]
 pub struct StageRunner {
     /// XXX: no pub.
-    pub stage: Rc<RefCell<Stage>>,
+    pub stage: Arc<Stage>,
     frame: u32,
 
     position: Interpolator3<f32>,
@@ -23,9 +26,15 @@ pub struct StageRunner {
     pub fog_far: f32,
 }
 
+impl_snapshot!(StageRunner, |game: Game| {
+    frame, position, direction, fog_color, fog_near, fog_far,
+} {
+    stage: game.stage_runner().ok_or(Error::MissingData("stage"))?.stage.clone(),
+});
+
 impl StageRunner {
     /// Create a new StageRunner attached to a Stage.
-    pub fn new(stage: Rc<RefCell<Stage>>) -> StageRunner {
+    pub fn new(stage: Arc<Stage>) -> StageRunner {
         StageRunner {
             stage,
             frame: 0,
@@ -39,7 +48,7 @@ impl StageRunner {
 
     /// Advance the simulation one frame.
     pub fn run_frame(&mut self) {
-        let stage = self.stage.borrow();
+        let stage = self.stage.clone();
 
         for Call { time, instr } in stage.script.iter() {
             let time = *time;
@@ -47,8 +56,6 @@ impl StageRunner {
                 continue;
             }
 
-            println!("{} {:?}", time, instr);
-
             match *instr {
                 Instruction::SetViewpos(x, y, z) => {
                     self.position.set_start(time, [x, y, z]);
@@ -100,3 +107,41 @@ impl StageRunner {
         model * view
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::th06::player::Keys;
+    use crate::th06::testing::new_game;
+
+    #[test]
+    fn background() {
+        let stage = Stage {
+            name: String::new(),
+            musics: vec![],
+            models: vec![],
+            instances: vec![],
+            script: vec![
+                Call { time: 0, instr: Instruction::SetViewpos(0., 0., 0.) },
+                Call { time: 0, instr: Instruction::SetFog(0, 0, 255, 255, 100., 500.) },
+                Call { time: 10, instr: Instruction::SetViewpos(0., 100., 0.) },
+            ],
+        };
+        let mut game = new_game();
+        game.set_background(StageRunner::new(Arc::new(stage)));
+        for _ in 0..5 {
+            game.run_frame(Keys::empty()).unwrap();
+        }
+        let background = game.stage_runner().unwrap();
+        assert_eq!(background.frame, 5);
+        assert_eq!(background.position.values(background.frame), [0., 50., 0.]);
+        assert_eq!(background.fog_color, [1., 0., 0., 1.]);
+        assert_eq!(background.fog_far, 500.);
+
+        // It gets restored along with the rest of the game.
+        let state = game.snapshot();
+        game.run_frame(Keys::empty()).unwrap();
+        game.restore(&state).unwrap();
+        assert_eq!(game.stage_runner().unwrap().frame, 5);
+    }
+}
@@ -0,0 +1,122 @@
+//! Helpers shared by the tests of the EoSD interpreters.
+
+use crate::th06::ecl::EclRunner;
+use crate::th06::enemy::{Enemy, Game, Position};
+use crate::th06::laser::{Laser, LaserAttributes};
+use crate::th06::player::Player;
+use touhou_formats::th06::anm0::Anm0;
+use touhou_formats::th06::ecl::{Ecl, Main, CallMain, MainInstruction, Sub, CallSub, SubInstruction, Rank};
+use touhou_formats::th06::msg::{Call, Instruction, Msg};
+use touhou_formats::th06::sht::{Sht, Shot};
+use touhou_utils::arena::Id;
+use touhou_utils::prng::Prng;
+use std::sync::Arc;
+
+/// Create an easy game without any stage, with a fixed seed.
+pub(crate) fn new_game() -> Game {
+    Game::new(Prng::new(0), Rank::EASY)
+}
+
+/// Create an easy game whose player can get hit right away.
+pub(crate) fn vulnerable_game() -> Game {
+    let mut game = new_game();
+    game.player.invulnerable_time = 0;
+    game
+}
+
+/// Create an easy game running this stage, with empty enemy sprites.
+pub(crate) fn load_stage(seed: u16, ecl: Ecl) -> Game {
+    load_stage_with_anm0(seed, ecl, Anm0::default())
+}
+
+/// Create an easy game running this stage, with its enemy sprites taken from `anm0`.
+pub(crate) fn load_stage_with_anm0(seed: u16, ecl: Ecl, anm0: Anm0) -> Game {
+    let mut game = Game::new(Prng::new(seed), Rank::EASY);
+    game.load_stage(ecl, Arc::new([anm0, Anm0::default()]));
+    game
+}
+
+/// Create an easy game spawning a single enemy at the top of the screen on the first frame,
+/// running the first of these subs.
+pub(crate) fn load_enemy(subs: Vec<Vec<CallSub>>) -> Game {
+    let ecl = Ecl {
+        mains: vec![Main { instructions: vec![
+            CallMain { time: 0, sub: 0, instr: MainInstruction::SpawnEnemy(192., 100., 0., 100, -1, 100) },
+        ]}],
+        subs: subs.into_iter().map(|instructions| Sub { instructions }).collect(),
+    };
+    load_stage(0, ecl)
+}
+
+/// Create an easy game opening a short dialogue on the first frame, then spawning an enemy once
+/// it is over.
+pub(crate) fn load_dialogue() -> Game {
+    let ecl = Ecl {
+        mains: vec![Main { instructions: vec![
+            CallMain { time: 0, sub: 0, instr: MainInstruction::CallMessage() },
+            CallMain { time: 0, sub: 0, instr: MainInstruction::WaitMessage() },
+            CallMain { time: 1, sub: 0, instr: MainInstruction::SpawnEnemy(192., 100., 0., 100, -1, 100) },
+        ]}],
+        subs: vec![Sub { instructions: vec![CallSub::new(0, Rank::EASY, SubInstruction::Noop())] }],
+    };
+    let mut game = load_stage(0, ecl);
+    let mut msg = Msg::default();
+    msg.scripts.insert(0, vec![
+        Call { time: 0, instr: Instruction::Enter(0, 1) },
+        Call { time: 0, instr: Instruction::DisplayText(0, 0, String::from("Is that so?")) },
+        Call { time: 0, instr: Instruction::Pause(100) },
+        Call { time: 1, instr: Instruction::DisplayDescription(1, 0, String::from("Rumia")) },
+        Call { time: 1, instr: Instruction::SpawnEnemySprite() },
+        Call { time: 3, instr: Instruction::End() },
+    ]);
+    game.load_msg(msg);
+    game
+}
+
+/// Create an easy game with a single enemy, which doesn’t run any script.
+pub(crate) fn insert_enemy() -> (Game, Id) {
+    let mut game = new_game();
+    let enemy = game.enemies.insert(Enemy::new(Position::new(0., 0.), 500, 0, 640, false, None));
+    (game, enemy)
+}
+
+/// The enemy of `insert_enemy()`, taken out of its game so that instructions can be run on it.
+pub(crate) fn enemy_runner() -> (EclRunner, Enemy, Game) {
+    let (mut game, id) = insert_enemy();
+    let enemy = game.enemies.take(id).unwrap();
+    let runner = EclRunner::new(Arc::new(Ecl { mains: vec![], subs: vec![] }), id, 0);
+    (runner, enemy, game)
+}
+
+/// A player moving at four pixels per frame, or two when focused, firing a single shot every
+/// five frames.
+pub(crate) fn shooting_player() -> Player {
+    let mut sht = Sht {
+        horizontal_vertical_speed: 4.,
+        diagonal_speed: 4. * std::f32::consts::FRAC_1_SQRT_2,
+        horizontal_vertical_focused_speed: 2.,
+        diagonal_focused_speed: 2. * std::f32::consts::FRAC_1_SQRT_2,
+        ..Default::default()
+    };
+    let shot = Shot { interval: 5, speed: 10., angle: -std::f32::consts::FRAC_PI_2, damage: 4, ..Default::default() };
+    sht.shots.insert(999, vec![shot]);
+    Player::new(sht.clone(), sht)
+}
+
+/// A laser going down from the top of the screen, with short phases.
+pub(crate) fn falling_laser() -> Laser {
+    let attributes = LaserAttributes {
+        angle: std::f32::consts::FRAC_PI_2,
+        speed: 10.,
+        end_offset: 0.,
+        max_length: 100.,
+        width: 16.,
+        start_duration: 4,
+        duration: 10,
+        end_duration: 4,
+        grazing_delay: 2,
+        grazing_extra_duration: 2,
+        ..Default::default()
+    };
+    Laser::new(Position::new(100., 0.), &attributes)
+}
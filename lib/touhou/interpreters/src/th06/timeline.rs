@@ -0,0 +1,281 @@
+//! Per-frame timeline of a stage, obtained by simulating it.
+//!
+//! Every frame lists the enemies alive with their position and life, along with what happened
+//! during it: enemy spawns and removals, spellcards, dialogues and script errors.  Frames are
+//! game frames, so they keep counting while a dialogue is displayed or a boss is being fought.
+
+use crate::th06::enemy::{Game, Position};
+use crate::th06::player::Keys;
+use touhou_utils::arena::Id;
+use std::fmt::Write;
+
+/// State of an enemy at the end of a frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnemyState {
+    /// The enemy.
+    pub enemy: Id,
+
+    /// Its position.
+    pub pos: Position,
+
+    /// Its life.
+    pub life: u32,
+
+    /// Whether it is a boss.
+    pub boss: bool,
+}
+
+/// What happened during a frame.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// This enemy got spawned, either by the main timeline or by another enemy.
+    Spawn(Id),
+
+    /// This enemy died, destroyed itself or left the screen.
+    Removal(Id),
+
+    /// A boss declared this spellcard.
+    SpellcardStart {
+        /// Number of the spellcard, unique across the game.
+        number: i16,
+
+        /// Name of the spellcard.
+        name: String,
+    },
+
+    /// This spellcard ended, either captured or not.
+    SpellcardEnd(i16),
+
+    /// A dialogue started.
+    MessageStart,
+
+    /// The dialogue ended.
+    MessageEnd,
+
+    /// A script failed, its enemy stopped running it.
+    Error(String),
+}
+
+impl Event {
+    /// Short name of this event, used as a tag in the exported formats.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Spawn(_) => "spawn",
+            Event::Removal(_) => "removal",
+            Event::SpellcardStart { .. } => "spellcard_start",
+            Event::SpellcardEnd(_) => "spellcard_end",
+            Event::MessageStart => "message_start",
+            Event::MessageEnd => "message_end",
+            Event::Error(_) => "error",
+        }
+    }
+}
+
+/// Everything recorded for a single frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Index of this frame, starting at 0.
+    pub frame: u32,
+
+    /// The enemies alive at the end of this frame, in spawn order.
+    pub enemies: Vec<EnemyState>,
+
+    /// What happened during this frame.
+    pub events: Vec<Event>,
+}
+
+/// The frames of a stage, as simulated by a `Game`.
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    /// List of frames.
+    pub frames: Vec<Frame>,
+}
+
+impl Timeline {
+    /// Run this game for this amount of frames with these keys pressed, recording each of them.
+    ///
+    /// Script errors get recorded, and the other enemies keep running.
+    pub fn record(game: &mut Game, frames: u32, keys: Keys) -> Timeline {
+        let mut timeline = Timeline::default();
+        let mut enemies: Vec<Id> = game.enemies.ids().collect();
+        let mut spellcard = game.spellcard().map(|spellcard| spellcard.number);
+        let mut message = game.dialogue().is_some();
+        for frame in 0..frames {
+            let mut events = Vec::new();
+            if let Err(error) = game.run_frame(keys) {
+                events.push(Event::Error(error.to_string()));
+            }
+
+            let ids: Vec<Id> = game.enemies.ids().collect();
+            events.extend(ids.iter().filter(|id| !enemies.contains(id)).map(|&id| Event::Spawn(id)));
+            events.extend(enemies.iter().filter(|id| !ids.contains(id)).map(|&id| Event::Removal(id)));
+            enemies = ids;
+
+            let current = game.spellcard().map(|spellcard| spellcard.number);
+            if current != spellcard {
+                if let Some(number) = spellcard {
+                    events.push(Event::SpellcardEnd(number));
+                }
+                if let Some(spellcard) = game.spellcard() {
+                    events.push(Event::SpellcardStart { number: spellcard.number, name: spellcard.name.clone() });
+                }
+                spellcard = current;
+            }
+
+            if game.dialogue().is_some() != message {
+                message = !message;
+                events.push(if message { Event::MessageStart } else { Event::MessageEnd });
+            }
+
+            let enemies = enemies.iter()
+                .filter_map(|&id| game.enemies.get(id).map(|enemy| EnemyState {
+                    enemy: id,
+                    pos: enemy.pos,
+                    life: enemy.life,
+                    boss: enemy.boss,
+                }))
+                .collect();
+            timeline.frames.push(Frame { frame, enemies, events });
+        }
+        timeline
+    }
+
+    /// Export this timeline as CSV, with a header line.
+    ///
+    /// Each frame has a line per event, then a line per enemy alive.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("frame,event,enemy,x,y,life,boss,spellcard,name\n");
+        for frame in self.frames.iter() {
+            for event in frame.events.iter() {
+                let (enemy, number, name) = event.fields();
+                writeln!(csv, "{},{},{},,,,,{},\"{}\"",
+                         frame.frame,
+                         event.name(),
+                         optional(enemy),
+                         optional(number),
+                         name.unwrap_or("").replace('"', "\"\"")).unwrap();
+            }
+            for state in frame.enemies.iter() {
+                writeln!(csv, "{},enemy,{},{},{},{},{},,\"\"",
+                         frame.frame, state.enemy, state.pos.x, state.pos.y, state.life, state.boss).unwrap();
+            }
+        }
+        csv
+    }
+
+    /// Export this timeline as a JSON array of frames.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("[\n");
+        for (i, frame) in self.frames.iter().enumerate() {
+            write!(json, "  {{\"frame\": {}, \"enemies\": [", frame.frame).unwrap();
+            for (j, state) in frame.enemies.iter().enumerate() {
+                if j > 0 {
+                    json.push_str(", ");
+                }
+                write!(json, "{{\"enemy\": {}, \"x\": {}, \"y\": {}, \"life\": {}, \"boss\": {}}}",
+                       state.enemy, state.pos.x, state.pos.y, state.life, state.boss).unwrap();
+            }
+            json.push_str("], \"events\": [");
+            for (j, event) in frame.events.iter().enumerate() {
+                if j > 0 {
+                    json.push_str(", ");
+                }
+                let (enemy, number, name) = event.fields();
+                write!(json, "{{\"event\": \"{}\"", event.name()).unwrap();
+                if let Some(enemy) = enemy {
+                    write!(json, ", \"enemy\": {}", enemy).unwrap();
+                }
+                if let Some(number) = number {
+                    write!(json, ", \"spellcard\": {}", number).unwrap();
+                }
+                if let Some(name) = name {
+                    write!(json, ", \"name\": \"{}\"", escape_json(name)).unwrap();
+                }
+                json.push('}');
+            }
+            json.push_str("]}");
+            if i + 1 < self.frames.len() {
+                json.push(',');
+            }
+            json.push('\n');
+        }
+        json.push(']');
+        json
+    }
+}
+
+impl Event {
+    /// Enemy, spellcard number and name or error message of this event, when relevant.
+    fn fields(&self) -> (Option<Id>, Option<i16>, Option<&str>) {
+        match *self {
+            Event::Spawn(enemy) | Event::Removal(enemy) => (Some(enemy), None, None),
+            Event::SpellcardStart { number, ref name } => (None, Some(number), Some(name)),
+            Event::SpellcardEnd(number) => (None, Some(number), None),
+            Event::Error(ref message) => (None, None, Some(message)),
+            Event::MessageStart | Event::MessageEnd => (None, None, None),
+        }
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn escape_json(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for c in string.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::th06::testing::load_stage;
+    use touhou_formats::th06::ecl::{Ecl, Main, CallMain, MainInstruction, Sub, CallSub, SubInstruction, Rank};
+
+    #[test]
+    fn spawn_move_and_spellcard() {
+        let ecl = Ecl {
+            mains: vec![Main { instructions: vec![
+                CallMain { time: 2, sub: 0, instr: MainInstruction::SpawnEnemy(192., 100., 0., 500, -1, 100) },
+            ]}],
+            subs: vec![
+                Sub { instructions: vec![
+                    CallSub::new(0, Rank::ALL, SubInstruction::SetAngleAndSpeed(std::f32::consts::FRAC_PI_2, 2.)),
+                    CallSub::new(0, Rank::ALL, SubInstruction::SetSpellcard(0, 3, String::from("Moon Sign \"Moonlight Ray\""))),
+                    CallSub::new(3, Rank::ALL, SubInstruction::SetLife(200)),
+                    CallSub::new(4, Rank::ALL, SubInstruction::EndSpellcard()),
+                    CallSub::new(5, Rank::ALL, SubInstruction::Destroy(0)),
+                ]},
+            ],
+        };
+        let mut game = load_stage(0, ecl);
+        let timeline = Timeline::record(&mut game, 10, Keys::empty());
+        assert_eq!(timeline.frames.len(), 10);
+        assert!(timeline.frames[1].enemies.is_empty());
+
+        let spawned = &timeline.frames[2];
+        let enemy = spawned.enemies[0].enemy;
+        assert_eq!(spawned.events[0], Event::Spawn(enemy));
+        assert_eq!(spawned.events[1], Event::SpellcardStart { number: 3, name: String::from("Moon Sign \"Moonlight Ray\"") });
+
+        // The enemy moves two pixels down per frame, and its script sets its life.
+        let states: Vec<_> = timeline.frames[2..6].iter().map(|frame| (frame.enemies[0].pos.y, frame.enemies[0].life)).collect();
+        assert_eq!(states, [(102., 500), (104., 500), (106., 200), (108., 200)]);
+        assert_eq!(timeline.frames[5].events, [Event::SpellcardEnd(3)]);
+        assert_eq!(timeline.frames[6].events, [Event::Removal(enemy)]);
+        assert!(timeline.frames[6].enemies.is_empty());
+
+        assert!(timeline.to_json().contains(r#""name": "Moon Sign \"Moonlight Ray\"""#));
+        // Header, two events and four enemy lines, then two more events.
+        assert_eq!(timeline.to_csv().lines().count(), 1 + 2 + 4 + 2);
+    }
+}
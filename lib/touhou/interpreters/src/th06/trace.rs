@@ -0,0 +1,221 @@
+//! Module providing hooks to trace the ECL interpreter, and pause it on breakpoints.
+
+use touhou_formats::th06::ecl::SubInstruction;
+use crate::th06::ecl::EclError;
+use crate::th06::enemy::{Enemy, Game};
+use touhou_utils::arena::Id;
+use std::sync::{Arc, Mutex};
+
+/// Value written into an ECL variable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    /// Written as an integer.
+    Int(i32),
+
+    /// Written as a float.
+    Float(f32),
+}
+
+/// Place in the scripts where the interpreter should pause.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Breakpoint {
+    /// Before running this instruction of this sub.
+    Instruction {
+        /// Sub containing the instruction.
+        sub: u16,
+
+        /// Index of the instruction in this sub.
+        ip: i32,
+    },
+
+    /// Before running any instruction with this opcode.
+    Opcode(u16),
+}
+
+/// What to do once paused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resume {
+    /// Run until the next breakpoint.
+    Continue,
+
+    /// Pause again before the next instruction, whichever enemy runs it.
+    Step,
+}
+
+/// An instruction run by an enemy.
+#[derive(Debug)]
+pub struct InstructionEvent<'a> {
+    /// Enemy running it.
+    pub enemy: Id,
+
+    /// Sub containing it.
+    pub sub: u16,
+
+    /// Index of the instruction in this sub.
+    pub ip: i32,
+
+    /// Frame of this sub.
+    pub frame: i32,
+
+    /// The instruction itself.
+    pub instruction: &'a SubInstruction,
+
+    /// Variables written by this instruction, in order; always empty before it ran.
+    pub writes: &'a [(i32, Value)],
+}
+
+/// Hooks called by the ECL interpreter of a game, see `Game::set_observer`.
+///
+/// Every method does nothing by default.
+pub trait EclObserver: Send {
+    /// Places where `pause` should be called.
+    fn breakpoints(&self) -> &[Breakpoint] {
+        &[]
+    }
+
+    /// Called once an instruction has been run.
+    fn instruction(&mut self, _event: &InstructionEvent) {
+    }
+
+    /// Called when the script of an enemy fails, it then stops running.
+    fn error(&mut self, _enemy: Id, _error: &EclError) {
+    }
+
+    /// Called before running an instruction on a breakpoint, or after a step; the whole game
+    /// stays paused until it returns.
+    fn pause(&mut self, _event: &InstructionEvent, _enemy: &Enemy, _game: &Game) -> Resume {
+        Resume::Continue
+    }
+}
+
+/// An observer along with the state of its debugger.
+pub(crate) struct Tracer {
+    observer: Box<dyn EclObserver>,
+    stepping: bool,
+}
+
+/// Shared between the copies of a game, so that forks keep being traced.
+pub(crate) type SharedTracer = Arc<Mutex<Tracer>>;
+
+impl Tracer {
+    pub(crate) fn new(observer: Box<dyn EclObserver>) -> SharedTracer {
+        Arc::new(Mutex::new(Tracer {
+            observer,
+            stepping: false,
+        }))
+    }
+
+    /// Pause if this instruction is on a breakpoint, or if the previous one asked for a step.
+    pub(crate) fn before(&mut self, event: &InstructionEvent, enemy: &Enemy, game: &Game) {
+        let opcode = event.instruction.opcode();
+        let hit = self.stepping || self.observer.breakpoints().iter().any(|breakpoint| match *breakpoint {
+            Breakpoint::Instruction { sub, ip } => sub == event.sub && ip == event.ip,
+            Breakpoint::Opcode(value) => value == opcode,
+        });
+        if hit {
+            self.stepping = self.observer.pause(event, enemy, game) == Resume::Step;
+        }
+    }
+
+    pub(crate) fn after(&mut self, event: &InstructionEvent) {
+        self.observer.instruction(event);
+    }
+
+    pub(crate) fn error(&mut self, enemy: Id, error: &EclError) {
+        self.observer.error(enemy, error);
+    }
+}
+
+impl Game {
+    /// Start notifying this observer of everything the enemy scripts do.
+    ///
+    /// Copies of this game made afterwards notify the same observer.
+    pub fn set_observer<O: EclObserver + 'static>(&mut self, observer: O) {
+        self.tracer = Some(Tracer::new(Box::new(observer)));
+    }
+
+    /// Stop notifying the current observer, if any.
+    pub fn remove_observer(&mut self) {
+        self.tracer = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::th06::player::Keys;
+    use crate::th06::testing::load_stage;
+    use touhou_formats::th06::ecl::{CallMain, CallSub, Ecl, Main, MainInstruction, Rank, Sub};
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    struct Recorder {
+        log: Log,
+        breakpoints: Vec<Breakpoint>,
+        resume: Resume,
+    }
+
+    impl EclObserver for Recorder {
+        fn breakpoints(&self) -> &[Breakpoint] {
+            &self.breakpoints
+        }
+
+        fn instruction(&mut self, event: &InstructionEvent) {
+            self.log.lock().unwrap().push(format!("{}:{} {:?}", event.sub, event.ip, event.writes));
+        }
+
+        fn error(&mut self, _enemy: Id, error: &EclError) {
+            self.log.lock().unwrap().push(error.to_string());
+        }
+
+        fn pause(&mut self, event: &InstructionEvent, _enemy: &Enemy, game: &Game) -> Resume {
+            assert_eq!(game.enemies().count(), 0);
+            self.log.lock().unwrap().push(format!("pause {}:{}", event.sub, event.ip));
+            self.resume
+        }
+    }
+
+    fn run(breakpoints: Vec<Breakpoint>, resume: Resume) -> Vec<String> {
+        let ecl = Ecl {
+            mains: vec![Main { instructions: vec![
+                CallMain { time: 0, sub: 0, instr: MainInstruction::SpawnEnemy(100., 100., 0., 10, -1, 100) },
+            ]}],
+            subs: vec![Sub { instructions: vec![
+                CallSub::new(0, Rank::EASY, SubInstruction::SetInt(-10001, 5)),
+                CallSub::new(0, Rank::EASY, SubInstruction::SetFloat(-10005, 1.5)),
+                CallSub::new(1, Rank::EASY, SubInstruction::Noop()),
+                CallSub::new(1, Rank::EASY, SubInstruction::SetInt(-10018, 0)),
+            ]}],
+        };
+        let mut game = load_stage(0, ecl);
+        let log = Log::default();
+        game.set_observer(Recorder { log: log.clone(), breakpoints, resume });
+        // Enemies run their first two frames when spawned.
+        game.run_frame(Keys::empty()).unwrap_err();
+        let entries = log.lock().unwrap().clone();
+        entries
+    }
+
+    #[test]
+    fn trace() {
+        assert_eq!(run(vec![], Resume::Continue), [
+            "0:0 [(-10001, Int(5))]",
+            "0:1 [(-10005, Float(1.5))]",
+            "0:2 []",
+            "0:3 []",
+            "variable -10018 is read-only (sub 0, instruction 3, frame 1)",
+        ]);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let log = run(vec![Breakpoint::Opcode(0), Breakpoint::Instruction { sub: 0, ip: 1 }], Resume::Continue);
+        assert_eq!(log[1], "pause 0:1");
+        assert_eq!(log[3], "pause 0:2");
+        assert_eq!(log.len(), 7);
+
+        let log = run(vec![Breakpoint::Instruction { sub: 0, ip: 1 }], Resume::Step);
+        assert_eq!(log.iter().filter(|entry| entry.starts_with("pause")).count(), 3);
+        assert_eq!(log[3], "pause 0:2");
+    }
+}
@@ -12,8 +12,7 @@ use touhou_formats::th06::anm0::Anm0;
 use touhou_interpreters::th06::anm0::{AnmRunner, Sprite, Vertex as FakeVertex};
 use touhou_utils::math::{perspective, setup_camera};
 use touhou_utils::prng::Prng;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::env;
 use std::path::Path;
 
@@ -84,14 +83,14 @@ struct ShaderInterface {
     mvp: Uniform<[[f32; 4]; 4]>,
 }
 
-fn fill_vertices_ptr(sprite: Rc<RefCell<Sprite>>, vertices: *mut Vertex) {
+fn fill_vertices_ptr(sprite: &Sprite, vertices: *mut Vertex) {
     let mut fake_vertices = unsafe { std::mem::transmute::<*mut Vertex, &mut [FakeVertex; 4]>(vertices) };
-    sprite.borrow().fill_vertices(&mut fake_vertices, 0., 0., 0.);
+    sprite.fill_vertices(&mut fake_vertices, 0., 0., 0.);
 }
 
-fn fill_vertices(sprite: Rc<RefCell<Sprite>>, vertices: &mut [Vertex; 4]) {
+fn fill_vertices(sprite: &Sprite, vertices: &mut [Vertex; 4]) {
     let mut fake_vertices = unsafe { std::mem::transmute::<&mut [Vertex; 4], &mut [FakeVertex; 4]>(vertices) };
-    sprite.borrow().fill_vertices(&mut fake_vertices, 0., 0., 0.);
+    sprite.fill_vertices(&mut fake_vertices, 0., 0., 0.);
 }
 
 fn main() {
@@ -114,11 +113,8 @@ fn main() {
         return;
     }
 
-    // Create the sprite.
-    let sprite = Rc::new(RefCell::new(Sprite::new()));
-
     // TODO: seed this PRNG with a valid seed.
-    let prng = Rc::new(RefCell::new(Prng::new(0)));
+    let mut prng = Prng::new(0);
 
     let mut surface = GlfwSurface::new(WindowDim::Windowed(384, 448), "Touhou", WindowOpt::default()).unwrap();
 
@@ -126,15 +122,15 @@ fn main() {
     let tex = load_anm_image(&mut surface, &anm0, anm_filename).expect("image loading");
 
     // Create the AnmRunner from the ANM and the sprite.
-    let anms = Rc::new(RefCell::new([anm0]));
-    let mut anm_runner = AnmRunner::new(anms, script, sprite.clone(), Rc::downgrade(&prng), 0);
+    let anms = Arc::new([anm0]);
+    let mut anm_runner = AnmRunner::new(anms, script, Sprite::new(), &mut prng, 0);
 
     assert_eq!(std::mem::size_of::<Vertex>(), std::mem::size_of::<FakeVertex>());
     let mut vertices: [Vertex; 4] = {
         let data = std::mem::MaybeUninit::uninit();
         unsafe { data.assume_init() }
     };
-    fill_vertices(sprite.clone(), &mut vertices);
+    fill_vertices(anm_runner.get_sprite(), &mut vertices);
 
     // set the uniform interface to our type so that we can read textures from the shader
     let program =
@@ -172,8 +168,8 @@ fn main() {
                 .as_slice_mut()
                 .unwrap();
 
-            anm_runner.run_frame();
-            fill_vertices_ptr(sprite.clone(), slice.as_mut_ptr());
+            anm_runner.run_frame(&mut prng);
+            fill_vertices_ptr(anm_runner.get_sprite(), slice.as_mut_ptr());
         }
 
         // here, we need to bind the pipeline variable; it will enable us to bind the texture to the GPU
@@ -0,0 +1,64 @@
+use touhou_formats::th06::anm0::Anm0;
+use touhou_formats::th06::ecl::{Ecl, Rank};
+use touhou_formats::th06::msg::Msg;
+use touhou_interpreters::th06::enemy::Game;
+use touhou_interpreters::th06::player::Keys;
+use touhou_interpreters::th06::timeline::Timeline;
+use touhou_utils::prng::Prng;
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+
+use touhou_runners::common::load_file_into_vec;
+
+fn load_anm(filename: &Path) -> Anm0 {
+    let buf = load_file_into_vec(filename).unwrap();
+    let (_, mut anms) = Anm0::from_slice(&buf).unwrap();
+    anms.pop().unwrap()
+}
+
+fn main() {
+    // Parse arguments.
+    let args: Vec<_> = env::args().collect();
+    let parsed = if args.len() == 6 {
+        match (args[2].parse::<u8>(), args[3].parse::<Rank>(), args[4].parse::<u32>(), args[5].as_str()) {
+            (Ok(stage_number), Ok(rank), Ok(frames), format @ ("csv" | "json")) => Some((stage_number, rank, frames, format)),
+            _ => None,
+        }
+    } else {
+        None
+    };
+    let (stage_number, rank, frames, format) = match parsed {
+        Some(parsed) => parsed,
+        None => {
+            eprintln!("Usage: {} <unarchived ST.DAT directory> <stage number> <easy|normal|hard|lunatic> <frames> <csv|json>", args[0]);
+            return;
+        }
+    };
+    let directory = Path::new(&args[1]);
+
+    // Open the ECL file.
+    let buf = load_file_into_vec(directory.join(format!("ecldata{}.ecl", stage_number))).unwrap();
+    let (_, ecl) = Ecl::from_slice(&buf).unwrap();
+
+    // Open both ANM files, they are only needed for the sprites of the enemies.
+    let anm0 = load_anm(&directory.join(format!("stg{}enm.anm", stage_number)));
+    let anm0_bis = load_anm(&directory.join(format!("stg{}enm2.anm", stage_number)));
+
+    // Use a fixed seed, so that timelines can be compared between runs.
+    let mut game = Game::new(Prng::new(0), rank);
+    game.load_stage(ecl, Arc::new([anm0, anm0_bis]));
+
+    // Open the MSG file, the dialogues get skipped without it.
+    if let Ok(buf) = load_file_into_vec(directory.join(format!("msg{}.dat", stage_number))) {
+        let (_, msg) = Msg::from_slice(&buf).unwrap();
+        game.load_msg(msg);
+    }
+
+    let timeline = Timeline::record(&mut game, frames, Keys::empty());
+    if format == "csv" {
+        print!("{}", timeline.to_csv());
+    } else {
+        println!("{}", timeline.to_json());
+    }
+}
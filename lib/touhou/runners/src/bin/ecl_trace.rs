@@ -0,0 +1,155 @@
+use touhou_formats::th06::anm0::Anm0;
+use touhou_formats::th06::ecl::{Ecl, Rank};
+use touhou_formats::th06::msg::Msg;
+use touhou_interpreters::th06::ecl::EclError;
+use touhou_interpreters::th06::enemy::{Enemy, Game};
+use touhou_interpreters::th06::player::Keys;
+use touhou_interpreters::th06::trace::{Breakpoint, EclObserver, InstructionEvent, Resume, Value};
+use touhou_utils::arena::Id;
+use touhou_utils::prng::Prng;
+use std::env;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use touhou_runners::common::load_file_into_vec;
+
+/// Writes one JSON object per line on stdout, and asks on stderr what to do on breakpoints.
+struct JsonTracer {
+    out: BufWriter<io::Stdout>,
+    frame: Arc<AtomicU32>,
+    breakpoints: Vec<Breakpoint>,
+}
+
+fn escape(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for c in string.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn value(value: Value) -> String {
+    match value {
+        Value::Int(value) => value.to_string(),
+        Value::Float(value) if value.is_finite() => value.to_string(),
+        // JSON has no representation for these.
+        Value::Float(_) => String::from("null"),
+    }
+}
+
+impl EclObserver for JsonTracer {
+    fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    fn instruction(&mut self, event: &InstructionEvent) {
+        let writes: Vec<_> = event.writes.iter()
+            .map(|&(var, written)| format!("[{},{}]", var, value(written)))
+            .collect();
+        writeln!(self.out, r#"{{"frame":{},"enemy":{},"sub":{},"ip":{},"time":{},"instruction":"{}","writes":[{}]}}"#,
+                 self.frame.load(Ordering::Relaxed), event.enemy, event.sub, event.ip, event.frame,
+                 escape(&format!("{:?}", event.instruction)), writes.join(",")).unwrap();
+    }
+
+    fn error(&mut self, enemy: Id, error: &EclError) {
+        writeln!(self.out, r#"{{"frame":{},"enemy":{},"sub":{},"ip":{},"time":{},"error":"{}"}}"#,
+                 self.frame.load(Ordering::Relaxed), enemy, error.sub, error.ip, error.frame,
+                 escape(&error.kind.to_string())).unwrap();
+    }
+
+    fn pause(&mut self, event: &InstructionEvent, _enemy: &Enemy, _game: &Game) -> Resume {
+        self.out.flush().unwrap();
+        eprintln!("Enemy {} paused in sub {}, instruction {}, frame {}: {:?}",
+                  event.enemy, event.sub, event.ip, event.frame, event.instruction);
+        eprint!("[c]ontinue or [s]tep? ");
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line).unwrap();
+        if line.trim() == "s" {
+            Resume::Step
+        } else {
+            Resume::Continue
+        }
+    }
+}
+
+fn parse_breakpoints(args: &[String]) -> Option<Vec<Breakpoint>> {
+    let mut breakpoints = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next()?;
+        breakpoints.push(match arg.as_str() {
+            "--break" => {
+                let (sub, ip) = value.split_once(':')?;
+                Breakpoint::Instruction { sub: sub.parse().ok()?, ip: ip.parse().ok()? }
+            }
+            "--break-opcode" => Breakpoint::Opcode(value.parse().ok()?),
+            _ => return None,
+        });
+    }
+    Some(breakpoints)
+}
+
+fn load_anm(filename: &Path) -> Anm0 {
+    let buf = load_file_into_vec(filename).unwrap();
+    let (_, mut anms) = Anm0::from_slice(&buf).unwrap();
+    anms.pop().unwrap()
+}
+
+fn main() {
+    // Parse arguments.
+    let args: Vec<_> = env::args().collect();
+    let breakpoints = if args.len() >= 5 { parse_breakpoints(&args[5..]) } else { None };
+    let breakpoints = match breakpoints {
+        Some(breakpoints) => breakpoints,
+        None => {
+            eprintln!("Usage: {} <unarchived ST.DAT directory> <stage number> <easy|normal|hard|lunatic> <frames> [--break <sub>:<ip>]… [--break-opcode <opcode>]…", args[0]);
+            return;
+        }
+    };
+    let directory = Path::new(&args[1]);
+    let stage_number: u8 = args[2].parse().expect("stage");
+    let rank: Rank = args[3].parse().expect("rank");
+    let frames: u32 = args[4].parse().expect("frames");
+
+    // Open the ECL file.
+    let buf = load_file_into_vec(directory.join(format!("ecldata{}.ecl", stage_number))).unwrap();
+    let (_, ecl) = Ecl::from_slice(&buf).unwrap();
+    assert_eq!(ecl.mains.len(), 1);
+
+    // Open both ANM files, they are only needed for the sprites of the enemies.
+    let anm0 = load_anm(&directory.join(format!("stg{}enm.anm", stage_number)));
+    let anm0_bis = load_anm(&directory.join(format!("stg{}enm2.anm", stage_number)));
+
+    // Use a fixed seed, so that traces can be compared between runs.
+    let mut game = Game::new(Prng::new(0), rank);
+    game.load_stage(ecl, Arc::new([anm0, anm0_bis]));
+
+    // Open the MSG file, the dialogues get skipped without it.
+    if let Ok(buf) = load_file_into_vec(directory.join(format!("msg{}.dat", stage_number))) {
+        let (_, msg) = Msg::from_slice(&buf).unwrap();
+        game.load_msg(msg);
+    }
+
+    let frame = Arc::new(AtomicU32::new(0));
+    game.set_observer(JsonTracer {
+        out: BufWriter::new(io::stdout()),
+        frame: frame.clone(),
+        breakpoints,
+    });
+
+    for i in 0..frames {
+        frame.store(i, Ordering::Relaxed);
+        // Errors already got traced, and the other enemies keep running.
+        let _ = game.run_frame(Keys::empty());
+    }
+
+    // Flush the trace.
+    game.remove_observer();
+}
@@ -11,12 +11,11 @@ use luminance_glfw::{Action, Key, WindowEvent, GlfwSurface, Surface, WindowDim,
 use touhou_formats::th06::anm0::Anm0;
 use touhou_formats::th06::ecl::{Ecl, Rank};
 use touhou_interpreters::th06::anm0::{Sprite, Vertex as FakeVertex};
-use touhou_interpreters::th06::ecl::EclRunner;
-use touhou_interpreters::th06::enemy::{Enemy, Game, Position};
+use touhou_interpreters::th06::enemy::{Game, Position};
+use touhou_interpreters::th06::player::Keys;
 use touhou_utils::math::{perspective, setup_camera};
 use touhou_utils::prng::Prng;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::env;
 use std::path::Path;
 
@@ -106,7 +105,7 @@ fn main() {
     let buf = load_file_into_vec(anm_filename).unwrap();
     let (_, mut anms) = Anm0::from_slice(&buf).unwrap();
     let anm0 = anms.pop().unwrap();
-    let anm0 = Rc::new(RefCell::new([anm0.clone(), anm0]));
+    let anm0 = Arc::new([anm0.clone(), anm0]);
 
     if ecl.subs.len() < sub as usize {
         eprintln!("This ecl doesn’t contain a sub named {}.", sub);
@@ -115,15 +114,14 @@ fn main() {
 
     // Get the time since January 1970 as a seed for the PRNG.
     let time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap();
-    let prng = Rc::new(RefCell::new(Prng::new(time.subsec_micros() as u16)));
+    let prng = Prng::new(time.subsec_micros() as u16);
 
     // Create the Game god object.
-    let game = Game::new(prng, rank);
-    let game = Rc::new(RefCell::new(game));
+    let mut game = Game::new(prng, rank);
+    game.load_stage(Ecl { mains: vec![], ..ecl }, anm0.clone());
 
     // And the enemy object.
-    let enemy = Enemy::new(Position::new(0., 0.), 500, 0, 640, false, Rc::downgrade(&anm0), Rc::downgrade(&game));
-    let mut ecl_runner = EclRunner::new(&ecl, enemy.clone(), sub);
+    game.spawn_enemy(sub, Position::new(0., 0.), 500, 0, 640, false).expect("first frame of the sub");
 
     assert_eq!(std::mem::size_of::<Vertex>(), std::mem::size_of::<FakeVertex>());
     let vertices: [Vertex; 4] = {
@@ -134,7 +132,7 @@ fn main() {
     let mut surface = GlfwSurface::new(WindowDim::Windowed(384, 448), "Touhou", WindowOpt::default()).unwrap();
 
     // Open the image atlas matching this ANM.
-    let tex = load_anm_image(&mut surface, &anm0.borrow()[0], &anm_filename).expect("image loading");
+    let tex = load_anm_image(&mut surface, &anm0[0], &anm_filename).expect("image loading");
 
     // set the uniform interface to our type so that we can read textures from the shader
     let program =
@@ -167,7 +165,7 @@ fn main() {
             resize = false;
         }
 
-        if ecl_runner.running == false {
+        if game.enemies().next().is_none() {
             break;
         }
 
@@ -176,13 +174,9 @@ fn main() {
                 .as_slice_mut()
                 .unwrap();
 
-            ecl_runner.run_frame();
-            {
-                let mut enemy = enemy.borrow_mut();
-                enemy.update();
+            if let Err(error) = game.run_frame(Keys::empty()) {
+                eprintln!("ECL error: {}", error);
             }
-            let mut game = game.borrow_mut();
-            game.run_frame();
             let sprites = game.get_sprites();
             fill_vertices_ptr(sprites, slice.as_mut_ptr());
         }
@@ -226,10 +220,9 @@ fn main() {
     }
 }
 
-fn fill_vertices_ptr(sprites: Vec<(f32, f32, f32, Rc<RefCell<Sprite>>)>, vertices: *mut Vertex) {
+fn fill_vertices_ptr(sprites: Vec<(f32, f32, f32, &Sprite)>, vertices: *mut Vertex) {
     let mut fake_vertices = unsafe { std::mem::transmute::<*mut Vertex, &mut [FakeVertex; 4]>(vertices) };
     for (x, y, z, sprite) in sprites {
-        let sprite = sprite.borrow();
         sprite.fill_vertices(&mut fake_vertices, x, y, z);
     }
 }
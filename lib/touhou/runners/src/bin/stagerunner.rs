@@ -9,14 +9,17 @@ use luminance::texture::Dim2Array;
 use luminance_derive::{Semantics, Vertex, UniformInterface};
 use luminance_glfw::{Action, Key, WindowEvent, GlfwSurface, Surface, WindowDim, WindowOpt};
 use touhou_formats::th06::anm0::Anm0;
-use touhou_formats::th06::ecl::{Ecl, Rank, MainInstruction};
+use touhou_formats::th06::ecl::{Ecl, Rank};
+use touhou_formats::th06::exe::read_characters;
+use touhou_formats::th06::msg::Msg;
+use touhou_formats::th06::std::Stage;
 use touhou_interpreters::th06::anm0::Vertex as FakeVertex;
-use touhou_interpreters::th06::ecl::EclRunner;
-use touhou_interpreters::th06::enemy::{Enemy, Game, Position};
+use touhou_interpreters::th06::enemy::Game;
+use touhou_interpreters::th06::player::{Keys, Player};
+use touhou_interpreters::th06::std::StageRunner;
 use touhou_utils::math::{perspective, setup_camera};
 use touhou_utils::prng::Prng;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::env;
 use std::path::Path;
 
@@ -98,19 +101,32 @@ struct ShaderInterface {
 fn main() {
     // Parse arguments.
     let args: Vec<_> = env::args().collect();
-    if args.len() != 4 {
-        eprintln!("Usage: {} <unarchived ST.DAT directory> <stage number> <easy|normal|hard|lunatic>", args[0]);
+    if args.len() != 4 && args.len() != 6 {
+        eprintln!("Usage: {} <unarchived ST.DAT directory> <stage number> <easy|normal|hard|lunatic> [<EoSD executable> <character, from 0 to 3>]", args[0]);
         return;
     }
     let directory = Path::new(&args[1]);
     let stage_number: u8 = args[2].parse().expect("stage");
     let rank: Rank = args[3].parse().expect("rank");
 
+    // Read the characteristics of the character from the executable, the player can’t move nor
+    // shoot without them.
+    let player = if args.len() == 6 {
+        let buf = load_file_into_vec(&args[4]).unwrap();
+        let character: u8 = args[5].parse().expect("character");
+        let mut characters = read_characters(&buf).expect("no character found in this executable");
+        let (sht, focused_sht) = characters.swap_remove(character as usize);
+        let mut player = Player::new(sht, focused_sht);
+        player.character = character;
+        Some(player)
+    } else {
+        None
+    };
+
     // Open the ECL file.
     let buf = load_file_into_vec(directory.join(format!("ecldata{}.ecl", stage_number))).unwrap();
     let (_, ecl) = Ecl::from_slice(&buf).unwrap();
     assert_eq!(ecl.mains.len(), 1);
-    let main = ecl.mains[0].clone();
 
     // Open the ANM file.
     let anm_filename = directory.join(format!("stg{}enm.anm", stage_number));
@@ -128,11 +144,24 @@ fn main() {
 
     // Get the time since January 1970 as a seed for the PRNG.
     let time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap();
-    let prng = Rc::new(RefCell::new(Prng::new(time.subsec_micros() as u16)));
+    let prng = Prng::new(time.subsec_micros() as u16);
 
     // Create the Game god object.
-    let game = Game::new(prng, rank);
-    let game = Rc::new(RefCell::new(game));
+    let mut game = Game::new(prng, rank);
+    if let Some(player) = player {
+        game.set_player(player);
+    }
+
+    // Open the MSG file, the dialogues get skipped without it.
+    if let Ok(buf) = load_file_into_vec(directory.join(format!("msg{}.dat", stage_number))) {
+        let (_, msg) = Msg::from_slice(&buf).unwrap();
+        game.load_msg(msg);
+    }
+
+    // Open the STD file, the game runs the background along with the enemies.
+    let buf = load_file_into_vec(directory.join(format!("stage{}.std", stage_number))).unwrap();
+    let (_, stage) = Stage::from_slice(&buf).unwrap();
+    game.set_background(StageRunner::new(Arc::new(stage)));
 
     assert_eq!(std::mem::size_of::<Vertex>(), std::mem::size_of::<FakeVertex>());
     let vertices: [Vertex; 4] = {
@@ -144,7 +173,7 @@ fn main() {
 
     // Open the image atlas matching this ANM.
     let tex = load_multiple_anm_images(&mut surface, &anms, &anm_filename).expect("image loading");
-    let anms = Rc::new(RefCell::new(anms));
+    game.load_stage(ecl, Arc::new(anms));
 
     // set the uniform interface to our type so that we can read textures from the shader
     let program =
@@ -158,14 +187,32 @@ fn main() {
 
     let mut back_buffer = surface.back_buffer().unwrap();
     let mut resize = false;
-    let mut frame = 0;
-    let mut ecl_runners = vec![];
+    let mut keys = Keys::empty();
 
     'app: loop {
         for event in surface.poll_events() {
             match event {
                 WindowEvent::Close | WindowEvent::Key(Key::Escape, _, Action::Release, _) => break 'app,
 
+                WindowEvent::Key(key, _, action, _) => {
+                    let key = match key {
+                        Key::Z => Keys::SHOOT,
+                        Key::X => Keys::BOMB,
+                        Key::LeftShift => Keys::FOCUS,
+                        Key::Up => Keys::UP,
+                        Key::Down => Keys::DOWN,
+                        Key::Left => Keys::LEFT,
+                        Key::Right => Keys::RIGHT,
+                        Key::LeftControl => Keys::SKIP,
+                        _ => continue,
+                    };
+                    match action {
+                        Action::Press => keys.insert(key),
+                        Action::Release => keys.remove(key),
+                        Action::Repeat => (),
+                    }
+                }
+
                 WindowEvent::FramebufferSize(..) => {
                     resize = true;
                 }
@@ -179,27 +226,8 @@ fn main() {
             resize = false;
         }
 
-        for call in main.instructions.iter() {
-            if call.time == frame {
-                let sub = call.sub;
-                let instr = call.instr;
-                let (x, y, _z, life, bonus, score, mirror) = match instr {
-                    MainInstruction::SpawnEnemy(x, y, z, life, bonus, score) => (x, y, z, life, bonus, score, false),
-                    MainInstruction::SpawnEnemyMirrored(x, y, z, life, bonus, score) => (x, y, z, life, bonus, score, true),
-                    MainInstruction::SpawnEnemyRandom(x, y, z, life, bonus, score) => (x, y, z, life, bonus, score, false),
-                    MainInstruction::SpawnEnemyMirroredRandom(x, y, z, life, bonus, score) => (x, y, z, life, bonus, score, true),
-                    _ => continue,
-                };
-                let enemy = Enemy::new(Position::new(x, y), life, bonus, score, mirror, Rc::downgrade(&anms), Rc::downgrade(&game));
-                let runner = EclRunner::new(&ecl, enemy, sub);
-                ecl_runners.push(runner);
-            }
-        }
-
-        for runner in ecl_runners.iter_mut() {
-            runner.run_frame();
-            let mut enemy = runner.enemy.borrow_mut();
-            enemy.update();
+        if let Err(error) = game.run_frame(keys) {
+            eprintln!("ECL error: {}", error);
         }
 
         // here, we need to bind the pipeline variable; it will enable us to bind the texture to the GPU
@@ -231,16 +259,12 @@ fn main() {
                         .set_blending((Equation::Additive, Factor::SrcAlpha, Factor::SrcAlphaComplement));
 
                     rdr_gate.render(&render_state, |mut tess_gate| {
-                        let mut game = game.borrow_mut();
-                        game.run_frame();
-
                         for (x, y, z, sprite) in game.get_sprites() {
                             {
                                 let mut slice = tess
                                     .as_slice_mut()
                                     .unwrap();
 
-                                let sprite = sprite.borrow();
                                 let fake_vertices = unsafe { std::mem::transmute::<*mut Vertex, &mut [FakeVertex; 4]>(slice.as_mut_ptr()) };
                                 sprite.fill_vertices(fake_vertices, x, y, z);
                             }
@@ -254,6 +278,5 @@ fn main() {
             });
 
         surface.swap_buffers();
-        frame += 1;
     }
 }
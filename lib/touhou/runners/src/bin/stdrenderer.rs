@@ -9,13 +9,15 @@ use luminance::texture::Dim2;
 use luminance_derive::{Semantics, Vertex, UniformInterface};
 use luminance_glfw::{Action, Key, WindowEvent, GlfwSurface, Surface, WindowDim, WindowOpt};
 use touhou_formats::th06::anm0::Anm0;
+use touhou_formats::th06::ecl::Rank;
 use touhou_formats::th06::std::{Stage, Position, Box2D};
 use touhou_interpreters::th06::anm0::{AnmRunner, Sprite, Vertex as FakeVertex};
+use touhou_interpreters::th06::enemy::Game;
+use touhou_interpreters::th06::player::Keys;
 use touhou_interpreters::th06::std::StageRunner;
 use touhou_utils::prng::Prng;
 use touhou_utils::math::perspective;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::env;
 use std::path::Path;
 
@@ -125,7 +127,7 @@ fn main() {
     let anm0 = anms.pop().unwrap();
 
     // TODO: seed this PRNG with a valid seed.
-    let prng = Rc::new(RefCell::new(Prng::new(0)));
+    let mut prng = Prng::new(0);
 
     let mut surface = GlfwSurface::new(WindowDim::Windowed(384, 448), "Touhou", WindowOpt::default()).unwrap();
 
@@ -137,7 +139,7 @@ fn main() {
     let mut indices = vec![];
 
     {
-        let anms = Rc::new(RefCell::new([anm0]));
+        let anms = Arc::new([anm0]);
         for model in stage.models.iter() {
             let begin = vertices.len();
             for quad in model.quads.iter() {
@@ -145,13 +147,13 @@ fn main() {
                 let Box2D { width, height } = quad.size_override;
 
                 // Create the AnmRunner from the ANM and the sprite.
-                let sprite = Rc::new(RefCell::new(Sprite::with_size(width, height)));
-                let _anm_runner = AnmRunner::new(anms.clone(), quad.anm_script as u8, sprite.clone(), Rc::downgrade(&prng), 0);
+                let sprite = Sprite::with_size(width, height);
+                let anm_runner = AnmRunner::new(anms.clone(), quad.anm_script as u8, sprite, &mut prng, 0);
                 let mut new_vertices: [Vertex; 6] = {
                     let data = std::mem::MaybeUninit::uninit();
                     unsafe { data.assume_init() }
                 };
-                fill_vertices(sprite.clone(), &mut new_vertices, x, y, z);
+                fill_vertices(anm_runner.get_sprite(), &mut new_vertices, x, y, z);
                 new_vertices[4] = new_vertices[0];
                 new_vertices[5] = new_vertices[2];
                 vertices.extend(&new_vertices);
@@ -161,7 +163,9 @@ fn main() {
         }
     }
 
-    let mut stage_runner = StageRunner::new(Rc::new(RefCell::new(stage)));
+    // The game only runs the background here, no enemy gets spawned.
+    let mut game = Game::new(prng, Rank::EASY);
+    game.set_background(StageRunner::new(Arc::new(stage)));
 
     // set the uniform interface to our type so that we can read textures from the shader
     let program =
@@ -195,10 +199,11 @@ fn main() {
         }
 
         {
-            stage_runner.run_frame();
+            game.run_frame(Keys::empty()).unwrap();
             //let sprites = stage.get_sprites();
             //fill_vertices_ptr(sprites, slice.as_mut_ptr());
         }
+        let stage_runner = game.stage_runner().unwrap();
 
         // here, we need to bind the pipeline variable; it will enable us to bind the texture to the GPU
         // and use it in the shader
@@ -232,7 +237,7 @@ fn main() {
                     let render_state = RenderState::default()
                         .set_blending((Equation::Additive, Factor::SrcAlpha, Factor::SrcAlphaComplement));
 
-                    let stage = stage_runner.stage.borrow();
+                    let stage = &stage_runner.stage;
                     for instance in stage.instances.iter() {
                         iface.instance_position.update([instance.pos.x, instance.pos.y, instance.pos.z]);
 
@@ -248,8 +253,7 @@ fn main() {
     }
 }
 
-fn fill_vertices(sprite: Rc<RefCell<Sprite>>, vertices: &mut [Vertex; 6], x: f32, y: f32, z: f32) {
+fn fill_vertices(sprite: &Sprite, vertices: &mut [Vertex; 6], x: f32, y: f32, z: f32) {
     let mut fake_vertices = unsafe { std::mem::transmute::<&mut [Vertex; 6], &mut [FakeVertex; 4]>(vertices) };
-    let sprite = sprite.borrow();
     sprite.fill_vertices(&mut fake_vertices, x, y, z);
 }
@@ -1,8 +1,9 @@
-use image::{GenericImageView, DynamicImage, GrayImage, ImageError};
+use image::{GenericImageView, DynamicImage, ImageError, RgbaImage};
 use luminance::pixel::{NormRGB8UI, NormRGBA8UI};
 use luminance::texture::{Dim2, Dim2Array, Sampler, Texture, GenMipmaps};
 use luminance_glfw::GlfwSurface;
 use touhou_formats::th06::anm0::Anm0;
+use touhou_formats::th06::texture;
 use std::fs::File;
 use std::io::{self, BufReader, Read};
 use std::path::Path;
@@ -25,7 +26,7 @@ pub enum LoadedTexture {
 pub enum TextureLoadError {
     CannotOpenRgb(String, ImageError),
     CannotOpenAlpha(String, ImageError),
-    AlphaToGrayscale(String),
+    CannotOpenAnm(String, ImageError),
 }
 
 fn open_rgb_png(path: &Path) -> Result<DynamicImage, TextureLoadError> {
@@ -38,11 +39,16 @@ fn open_alpha_png(path: &Path) -> Result<DynamicImage, TextureLoadError> {
     image::open(&path).map_err(|e| TextureLoadError::CannotOpenAlpha(path.to_str().unwrap().to_owned(), e))
 }
 
-fn merge_rgb_alpha(rgb: &DynamicImage, alpha: &GrayImage) -> Vec<(u8, u8, u8, u8)> {
-    rgb
+fn open_rgb_a_pngs(rgb: &Path, alpha: &Path) -> Result<RgbaImage, TextureLoadError> {
+    let img = open_alpha_png(&alpha)?;
+    texture::merge_alpha(open_rgb_png(&rgb)?, &img)
+        .map_err(|e| TextureLoadError::CannotOpenAlpha(alpha.to_str().unwrap().to_owned(), e))
+}
+
+fn rgba_texels(img: &RgbaImage) -> Vec<(u8, u8, u8, u8)> {
+    img
         .pixels()
-        .zip(alpha.pixels())
-        .map(|((_x, _y, rgb), luma)| (rgb[0], rgb[1], rgb[2], luma[0]))
+        .map(|rgba| (rgba[0], rgba[1], rgba[2], rgba[3]))
         .collect::<Vec<_>>()
 }
 
@@ -81,18 +87,9 @@ pub fn load_rgb_texture(surface: &mut GlfwSurface, path: &Path) -> Result<Loaded
     upload_texture_from_rgb_image(surface, img)
 }
 
-fn load_rgb_a_pngs(surface: &mut GlfwSurface, rgb: &Path, alpha: &Path) -> Result<LoadedTexture, TextureLoadError> {
-    let img = open_alpha_png(&alpha)?;
-    let alpha = match img.grayscale() {
-        DynamicImage::ImageLuma8(img) => img,
-        _ => {
-            return Err(TextureLoadError::AlphaToGrayscale(alpha.to_str().unwrap().to_owned()))
-        }
-    };
+fn upload_texture_from_rgba_image(surface: &mut GlfwSurface, img: &RgbaImage) -> Result<LoadedTexture, TextureLoadError> {
     let (width, height) = img.dimensions();
-    let img = open_rgb_png(&rgb)?;
-    assert_eq!((width, height), img.dimensions());
-    let texels = merge_rgb_alpha(&img, &alpha);
+    let texels = rgba_texels(img);
 
     // create the luminance texture; the third argument is the number of mipmaps we want (leave it
     // to 0 for now) and the latest is a the sampler to use when sampling the texels in the
@@ -106,36 +103,20 @@ fn load_rgb_a_pngs(surface: &mut GlfwSurface, rgb: &Path, alpha: &Path) -> Resul
     Ok(LoadedTexture::Rgba(tex))
 }
 
-pub fn load_anm_image<P: AsRef<Path>>(mut surface: &mut GlfwSurface, anm0: &Anm0, anm_filename: P) -> Result<LoadedTexture, TextureLoadError> {
+pub fn load_anm_image<P: AsRef<Path>>(surface: &mut GlfwSurface, anm0: &Anm0, anm_filename: P) -> Result<LoadedTexture, TextureLoadError> {
     let anm_filename = anm_filename.as_ref();
-    let png_filename = anm_filename.with_file_name(Path::new(&anm0.png_filename).file_name().unwrap());
-    match anm0.alpha_filename {
-        Some(ref filename) => {
-            let alpha_filename = anm_filename.with_file_name(Path::new(filename).file_name().unwrap());
-            load_rgb_a_pngs(&mut surface, &png_filename, &alpha_filename)
-        },
-        None => {
-            load_rgb_texture(&mut surface, &png_filename)
-        }
-    }
+    let img = texture::load_anm_image(anm0, anm_filename)
+        .map_err(|e| TextureLoadError::CannotOpenAnm(anm_filename.to_str().unwrap().to_owned(), e))?;
+    upload_texture_from_rgba_image(surface, &img)
 }
 
 fn load_array_texture(surface: &mut GlfwSurface, images: &[(&Path, &Path)]) -> Result<LoadedTexture, TextureLoadError> {
     let mut decoded = vec![];
     let dimensions = (256, 256);
     for (rgb, alpha) in images {
-        let img = open_alpha_png(&alpha)?;
-        assert_eq!(dimensions, img.dimensions());
-        let alpha = match img.grayscale() {
-            DynamicImage::ImageLuma8(img) => img,
-            _ => {
-                return Err(TextureLoadError::AlphaToGrayscale(alpha.to_str().unwrap().to_owned()))
-            }
-        };
-        let img = open_rgb_png(&rgb)?;
+        let img = open_rgb_a_pngs(rgb, alpha)?;
         assert_eq!(dimensions, img.dimensions());
-        let texels = merge_rgb_alpha(&img, &alpha);
-        decoded.push(texels);
+        decoded.push(rgba_texels(&img));
     }
 
     // create the luminance texture; the third argument is the number of mipmaps we want (leave it
@@ -0,0 +1,193 @@
+//! Storage for game entities, referenced by ids instead of pointers.
+
+use crate::snapshot::{Save, Load, Reader, Error};
+
+/// Identifier of a value in an `Arena`, never reused for another value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Id(u32);
+
+/// A list of values kept in insertion order, each of them reachable from its `Id`.
+///
+/// A value can be taken out of its slot for a while, so that it can be mutated alongside the
+/// rest of the arena, and then put back.
+#[derive(Debug, Clone)]
+pub struct Arena<T> {
+    next_id: u32,
+    entries: Vec<(Id, Option<T>)>,
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Arena<T> {
+        Arena {
+            next_id: 0,
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<T> Arena<T> {
+    /// Create an empty arena.
+    pub fn new() -> Arena<T> {
+        Arena::default()
+    }
+
+    /// Add a value at the end of this arena, and return its id.
+    pub fn insert(&mut self, value: T) -> Id {
+        let id = Id(self.next_id);
+        self.next_id += 1;
+        self.entries.push((id, Some(value)));
+        id
+    }
+
+    fn position(&self, id: Id) -> Option<usize> {
+        // Ids are always increasing, so the entries stay sorted.
+        self.entries.binary_search_by_key(&id, |&(id, _)| id).ok()
+    }
+
+    /// Returns the value with this id, unless it got removed or is currently taken out.
+    pub fn get(&self, id: Id) -> Option<&T> {
+        self.position(id).and_then(|index| self.entries[index].1.as_ref())
+    }
+
+    /// Returns the value with this id, unless it got removed or is currently taken out.
+    pub fn get_mut(&mut self, id: Id) -> Option<&mut T> {
+        self.position(id).and_then(move |index| self.entries[index].1.as_mut())
+    }
+
+    /// Take the value with this id out of the arena, keeping its place for `put_back`.
+    pub fn take(&mut self, id: Id) -> Option<T> {
+        self.position(id).and_then(|index| self.entries[index].1.take())
+    }
+
+    /// Put a value previously taken out back at its place.
+    ///
+    /// Does nothing if its place got removed in the meantime.
+    pub fn put_back(&mut self, id: Id, value: T) {
+        if let Some(index) = self.position(id) {
+            self.entries[index].1 = Some(value);
+        }
+    }
+
+    /// Number of values in this arena, including the ones currently taken out.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether this arena contains no value.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over all values currently present, in insertion order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> {
+        self.entries.iter().filter_map(|(_, value)| value.as_ref())
+    }
+
+    /// Iterate over all values currently present, in insertion order.
+    pub fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut T> {
+        self.entries.iter_mut().filter_map(|(_, value)| value.as_mut())
+    }
+
+    /// Iterate over the ids of all values, including the ones currently taken out.
+    pub fn ids(&self) -> impl DoubleEndedIterator<Item = Id> + '_ {
+        self.entries.iter().map(|&(id, _)| id)
+    }
+
+    /// Only keep the values for which `f` returns true, taken out ones are always kept.
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        self.entries.retain(|(_, value)| value.as_ref().is_none_or(&mut f));
+    }
+
+    /// Remove every value from this arena, ids won’t get reused.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl<T> std::ops::Index<Id> for Arena<T> {
+    type Output = T;
+    fn index(&self, id: Id) -> &T {
+        self.get(id).expect("no such value in this arena")
+    }
+}
+
+impl<T> std::ops::IndexMut<Id> for Arena<T> {
+    fn index_mut(&mut self, id: Id) -> &mut T {
+        self.get_mut(id).expect("no such value in this arena")
+    }
+}
+
+/// Ids are displayed as their raw value, to identify values in logs.
+impl std::fmt::Display for Id {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "{}", self.0)
+    }
+}
+
+impl Save for Id {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.0.save(out);
+    }
+}
+
+impl<C: ?Sized> Load<C> for Id {
+    fn load(reader: &mut Reader, context: &C) -> Result<Id, Error> {
+        u32::load(reader, context).map(Id)
+    }
+}
+
+impl<T: Save> Save for Arena<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.next_id.save(out);
+        self.entries.save(out);
+    }
+}
+
+impl<C: ?Sized, T: Load<C>> Load<C> for Arena<T> {
+    fn load(reader: &mut Reader, context: &C) -> Result<Arena<T>, Error> {
+        let next_id = u32::load(reader, context)?;
+        let entries: Vec<(Id, Option<T>)> = Load::load(reader, context)?;
+        if entries.windows(2).any(|pair| pair[0].0 >= pair[1].0) || entries.last().is_some_and(|&(Id(id), _)| id >= next_id) {
+            return Err(Error::InvalidValue("arena"));
+        }
+        Ok(Arena {
+            next_id,
+            entries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_remove() {
+        let mut arena = Arena::new();
+        let a = arena.insert(1);
+        let b = arena.insert(2);
+        let c = arena.insert(3);
+        arena.retain(|&value| value != 2);
+        assert_eq!(arena.get(b), None);
+        assert_eq!(arena[a], 1);
+        assert_eq!(arena[c], 3);
+        let d = arena.insert(4);
+        assert_ne!(b, d);
+        assert_eq!(arena.iter().copied().collect::<Vec<_>>(), [1, 3, 4]);
+    }
+
+    #[test]
+    fn take_and_put_back() {
+        let mut arena = Arena::new();
+        let a = arena.insert(1);
+        let b = arena.insert(2);
+        let mut value = arena.take(a).unwrap();
+        assert_eq!(arena.get(a), None);
+        arena.retain(|_| false);
+        assert_eq!(arena.len(), 1);
+        assert_eq!(arena.get(b), None);
+        value += 10;
+        arena.put_back(a, value);
+        assert_eq!(arena[a], 11);
+    }
+}
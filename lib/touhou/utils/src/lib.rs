@@ -1,6 +1,8 @@
 //! Module containing a bunch of helper modules.
 
+pub mod arena;
 pub mod bitstream;
 pub mod lzss;
 pub mod math;
 pub mod prng;
+pub mod snapshot;
@@ -1,7 +1,9 @@
 //! Random number generator extracted from EoSD.
 
+use crate::snapshot::{Save, Load, Reader, Error};
+
 /// Pseudo-random number generator from EoSD.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Prng {
     seed: u16,
 }
@@ -37,3 +39,15 @@ impl Prng {
         self.get_u32() as f64 / (0x100000000u64 as f64)
     }
 }
+
+impl Save for Prng {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.seed.save(out);
+    }
+}
+
+impl<C: ?Sized> Load<C> for Prng {
+    fn load(reader: &mut Reader, context: &C) -> Result<Prng, Error> {
+        u16::load(reader, context).map(Prng::new)
+    }
+}
@@ -0,0 +1,401 @@
+//! Compact binary serialisation of the state of a simulation.
+//!
+//! Only the changing state gets saved, the data it refers to (scripts, sprites…) is instead
+//! provided again as a context when loading it back.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fmt;
+
+/// Error which can happen while loading a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The snapshot ended before the value was complete.
+    UnexpectedEnd,
+
+    /// Some data was left after the value.
+    TrailingData,
+
+    /// This value couldn’t be decoded.
+    InvalidValue(&'static str),
+
+    /// The snapshot refers to data which isn’t loaded.
+    MissingData(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnexpectedEnd => write!(fmt, "unexpected end of snapshot"),
+            Error::TrailingData => write!(fmt, "trailing data after snapshot"),
+            Error::InvalidValue(name) => write!(fmt, "invalid {} in snapshot", name),
+            Error::MissingData(name) => write!(fmt, "no {} loaded for this snapshot", name),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Cursor over the bytes of a snapshot.
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Start reading these bytes.
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader {
+            data,
+        }
+    }
+
+    /// Read the next `N` bytes.
+    pub fn read_bytes<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        if self.data.len() < N {
+            return Err(Error::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.data.split_at(N);
+        self.data = rest;
+        let mut array = [0; N];
+        array.copy_from_slice(bytes);
+        Ok(array)
+    }
+
+    /// Check that every byte has been read.
+    pub fn finish(self) -> Result<(), Error> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::TrailingData)
+        }
+    }
+}
+
+/// A value which can be written into a snapshot.
+pub trait Save {
+    /// Append this value to `out`.
+    fn save(&self, out: &mut Vec<u8>);
+}
+
+/// A value which can be read back from a snapshot, `C` providing the data it refers to.
+pub trait Load<C: ?Sized>: Sized {
+    /// Read this value from `reader`.
+    fn load(reader: &mut Reader, context: &C) -> Result<Self, Error>;
+}
+
+macro_rules! impl_number {
+    ($($type:ty),*) => {
+        $(
+            impl Save for $type {
+                fn save(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl<C: ?Sized> Load<C> for $type {
+                fn load(reader: &mut Reader, _: &C) -> Result<$type, Error> {
+                    reader.read_bytes().map(<$type>::from_le_bytes)
+                }
+            }
+        )*
+    };
+}
+
+impl_number!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl Save for bool {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as u8).save(out);
+    }
+}
+
+impl<C: ?Sized> Load<C> for bool {
+    fn load(reader: &mut Reader, context: &C) -> Result<bool, Error> {
+        match u8::load(reader, context)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::InvalidValue("bool")),
+        }
+    }
+}
+
+// Sizes are saved as u32, nothing in a game gets anywhere near that.
+impl Save for usize {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as u32).save(out);
+    }
+}
+
+impl<C: ?Sized> Load<C> for usize {
+    fn load(reader: &mut Reader, context: &C) -> Result<usize, Error> {
+        u32::load(reader, context).map(|value| value as usize)
+    }
+}
+
+impl Save for String {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.as_bytes().save(out);
+    }
+}
+
+impl<C: ?Sized> Load<C> for String {
+    fn load(reader: &mut Reader, context: &C) -> Result<String, Error> {
+        String::from_utf8(Vec::load(reader, context)?)
+            .map_err(|_| Error::InvalidValue("string"))
+    }
+}
+
+impl<T: Save> Save for Option<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        match self {
+            None => false.save(out),
+            Some(value) => {
+                true.save(out);
+                value.save(out);
+            }
+        }
+    }
+}
+
+impl<C: ?Sized, T: Load<C>> Load<C> for Option<T> {
+    fn load(reader: &mut Reader, context: &C) -> Result<Option<T>, Error> {
+        Ok(if bool::load(reader, context)? {
+            Some(T::load(reader, context)?)
+        } else {
+            None
+        })
+    }
+}
+
+impl<T: Save> Save for [T] {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.len().save(out);
+        for value in self {
+            value.save(out);
+        }
+    }
+}
+
+impl<T: Save> Save for Vec<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.as_slice().save(out);
+    }
+}
+
+impl<C: ?Sized, T: Load<C>> Load<C> for Vec<T> {
+    fn load(reader: &mut Reader, context: &C) -> Result<Vec<T>, Error> {
+        let len = usize::load(reader, context)?;
+        // Don’t trust the length before having read the values.
+        let mut values = Vec::with_capacity(len.min(1024));
+        for _ in 0..len {
+            values.push(T::load(reader, context)?);
+        }
+        Ok(values)
+    }
+}
+
+impl<T: Save, const N: usize> Save for [T; N] {
+    fn save(&self, out: &mut Vec<u8>) {
+        for value in self {
+            value.save(out);
+        }
+    }
+}
+
+impl<C: ?Sized, T: Load<C>, const N: usize> Load<C> for [T; N] {
+    fn load(reader: &mut Reader, context: &C) -> Result<[T; N], Error> {
+        let mut values = Vec::with_capacity(N);
+        for _ in 0..N {
+            values.push(T::load(reader, context)?);
+        }
+        match values.try_into() {
+            Ok(array) => Ok(array),
+            Err(_) => unreachable!(),
+        }
+    }
+}
+
+impl<K: Save, V: Save> Save for BTreeMap<K, V> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.len().save(out);
+        for (key, value) in self {
+            key.save(out);
+            value.save(out);
+        }
+    }
+}
+
+impl<C: ?Sized, K: Load<C> + Ord, V: Load<C>> Load<C> for BTreeMap<K, V> {
+    fn load(reader: &mut Reader, context: &C) -> Result<BTreeMap<K, V>, Error> {
+        let len = usize::load(reader, context)?;
+        let mut map = BTreeMap::new();
+        for _ in 0..len {
+            let key = K::load(reader, context)?;
+            let value = V::load(reader, context)?;
+            map.insert(key, value);
+        }
+        Ok(map)
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident)*) => {
+        #[allow(non_snake_case)]
+        impl<$($name: Save),*> Save for ($($name,)*) {
+            fn save(&self, out: &mut Vec<u8>) {
+                let ($($name,)*) = self;
+                $($name.save(out);)*
+            }
+        }
+
+        impl<C: ?Sized, $($name: Load<C>),*> Load<C> for ($($name,)*) {
+            fn load(reader: &mut Reader, context: &C) -> Result<($($name,)*), Error> {
+                Ok(($($name::load(reader, context)?,)*))
+            }
+        }
+    };
+}
+
+impl_tuple!(A B);
+impl_tuple!(A B C_);
+impl_tuple!(A B C_ D);
+impl_tuple!(A B C_ D E F G H);
+
+/// Implement `Save` and `Load` for a struct, saving the listed fields in this order.
+///
+/// With a context, `Load` is only implemented for this context, and the fields listed in the
+/// second block aren’t saved but computed from it instead.
+#[macro_export]
+macro_rules! impl_snapshot {
+    (enum $name:ident { $($variant:ident),* $(,)? }) => {
+        impl $crate::snapshot::Save for $name {
+            fn save(&self, out: &mut Vec<u8>) {
+                let index = [$($name::$variant),*].iter().position(|variant| variant == self).unwrap();
+                $crate::snapshot::Save::save(&(index as u8), out);
+            }
+        }
+
+        impl<C: ?Sized> $crate::snapshot::Load<C> for $name {
+            fn load(reader: &mut $crate::snapshot::Reader, context: &C) -> Result<$name, $crate::snapshot::Error> {
+                let index: u8 = $crate::snapshot::Load::load(reader, context)?;
+                IntoIterator::into_iter([$($name::$variant),*]).nth(index as usize)
+                    .ok_or($crate::snapshot::Error::InvalidValue(stringify!($name)))
+            }
+        }
+    };
+
+    (bits $name:ident) => {
+        impl $crate::snapshot::Save for $name {
+            fn save(&self, out: &mut Vec<u8>) {
+                $crate::snapshot::Save::save(&self.bits(), out);
+            }
+        }
+
+        impl<C: ?Sized> $crate::snapshot::Load<C> for $name {
+            fn load(reader: &mut $crate::snapshot::Reader, context: &C) -> Result<$name, $crate::snapshot::Error> {
+                $name::from_bits($crate::snapshot::Load::load(reader, context)?)
+                    .ok_or($crate::snapshot::Error::InvalidValue(stringify!($name)))
+            }
+        }
+    };
+
+    (raw bits $name:ident) => {
+        impl $crate::snapshot::Save for $name {
+            fn save(&self, out: &mut Vec<u8>) {
+                $crate::snapshot::Save::save(&self.bits(), out);
+            }
+        }
+
+        impl<C: ?Sized> $crate::snapshot::Load<C> for $name {
+            fn load(reader: &mut $crate::snapshot::Reader, context: &C) -> Result<$name, $crate::snapshot::Error> {
+                // Unknown bits read from the game files are kept as is.
+                Ok(unsafe { $name::from_bits_unchecked($crate::snapshot::Load::load(reader, context)?) })
+            }
+        }
+    };
+
+    ($name:ident { $($field:ident),* $(,)? }) => {
+        impl $crate::snapshot::Save for $name {
+            fn save(&self, out: &mut Vec<u8>) {
+                $($crate::snapshot::Save::save(&self.$field, out);)*
+            }
+        }
+
+        impl<C: ?Sized> $crate::snapshot::Load<C> for $name {
+            fn load(reader: &mut $crate::snapshot::Reader, context: &C) -> Result<$name, $crate::snapshot::Error> {
+                Ok($name {
+                    $($field: $crate::snapshot::Load::load(reader, context)?,)*
+                })
+            }
+        }
+    };
+
+    ($name:ident, |$context:ident: $context_type:ty| { $($field:ident),* $(,)? } $({ $($computed:ident: $value:expr),* $(,)? })?) => {
+        impl $crate::snapshot::Save for $name {
+            fn save(&self, out: &mut Vec<u8>) {
+                $($crate::snapshot::Save::save(&self.$field, out);)*
+            }
+        }
+
+        impl $crate::snapshot::Load<$context_type> for $name {
+            fn load(reader: &mut $crate::snapshot::Reader, $context: &$context_type) -> Result<$name, $crate::snapshot::Error> {
+                Ok($name {
+                    $($field: $crate::snapshot::Load::load(reader, $context)?,)*
+                    $($($computed: $value,)*)?
+                })
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Shape {
+        Circle,
+        Square,
+    }
+
+    impl_snapshot!(enum Shape { Circle, Square });
+
+    #[derive(Debug, PartialEq)]
+    struct Thing {
+        shape: Shape,
+        pos: (f32, f32),
+        frames: Vec<u16>,
+        parent: Option<u32>,
+        name: String,
+    }
+
+    impl_snapshot!(Thing { shape, pos, frames, parent, name });
+
+    #[test]
+    fn round_trip() {
+        let thing = Thing {
+            shape: Shape::Square,
+            pos: (-0.5, f32::MAX),
+            frames: vec![1, 2, 3],
+            parent: Some(42),
+            name: String::from("Ω"),
+        };
+        let mut data = Vec::new();
+        thing.save(&mut data);
+        assert_eq!(data.len(), 1 + 8 + 4 + 6 + 5 + 4 + 2);
+        let mut reader = Reader::new(&data);
+        assert_eq!(Thing::load(&mut reader, &()).unwrap(), thing);
+        reader.finish().unwrap();
+    }
+
+    #[test]
+    fn invalid() {
+        let mut data = Vec::new();
+        (2u8, 0u8).save(&mut data);
+        assert_eq!(Shape::load(&mut Reader::new(&data), &()), Err(Error::InvalidValue("Shape")));
+        assert_eq!(<Vec<u32>>::load(&mut Reader::new(&data), &()), Err(Error::UnexpectedEnd));
+        let mut reader = Reader::new(&data);
+        u8::load(&mut reader, &()).unwrap();
+        assert_eq!(reader.finish(), Err(Error::TrailingData));
+    }
+}